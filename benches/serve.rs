@@ -0,0 +1,133 @@
+//! Benchmarks of the streaming and buffering layers, against synthetic files so results don't
+//! depend on disks: `cargo bench --features test-util`
+
+#[macro_use]
+extern crate criterion;
+
+use criterion::{Benchmark, Criterion, Throughput};
+use std::{thread, time::Duration};
+use tide_static_file::{test_util::client, vfs::Synthetic, StaticFiles};
+
+const MIB: u64 = 1024 * 1024;
+
+fn files(latency: Duration) -> StaticFiles {
+    let synthetic = Synthetic::new()
+        .file("small.bin", 4 * 1024)
+        .file("large.bin", 64 * MIB)
+        .latency(latency);
+    StaticFiles::from_backend(synthetic)
+}
+
+/// Benchmark GET of `path` with given `Range` header, `bytes` is the expected body size
+fn bench(
+    c: &mut Criterion,
+    name: &str,
+    latency: Duration,
+    path: &str,
+    range: Option<&str>,
+    bytes: u32,
+) {
+    let path = path.to_string();
+    let range = range.map(str::to_string);
+    let mut client = client(files(latency));
+    let benchmark = Benchmark::new(name, move |b| {
+        b.iter(|| {
+            let mut request = client.get(&path);
+            if let Some(range) = &range {
+                request = request.header("range", range);
+            }
+            let response = request.send();
+            assert_eq!(bytes as usize, response.body.len());
+        })
+    })
+    .throughput(Throughput::Bytes(bytes));
+    c.bench("serve", benchmark);
+}
+
+fn whole_file(c: &mut Criterion) {
+    let no_latency = Duration::from_secs(0);
+    bench(c, "small", no_latency, "/small.bin", None, 4 * 1024);
+    bench(c, "large", no_latency, "/large.bin", None, 64 * MIB as u32);
+}
+
+fn ranges(c: &mut Criterion) {
+    let no_latency = Duration::from_secs(0);
+    bench(
+        c,
+        "single range",
+        no_latency,
+        "/large.bin",
+        Some("bytes=1048576-9437183"),
+        8 * MIB as u32,
+    );
+    // 3 parts of 1MiB, plus part headers and the final boundary line
+    let multi = "bytes=0-1048575,4194304-5242879,16777216-17825791";
+    let client = &mut client(files(no_latency));
+    let body = client
+        .get("/large.bin")
+        .header("range", multi)
+        .send()
+        .body
+        .len();
+    bench(
+        c,
+        "multi ranges",
+        no_latency,
+        "/large.bin",
+        Some(multi),
+        body as u32,
+    );
+}
+
+fn slow_backend(c: &mut Criterion) {
+    let latency = Duration::from_micros(200);
+    bench(
+        c,
+        "small, slow backend",
+        latency,
+        "/small.bin",
+        None,
+        4 * 1024,
+    );
+    bench(
+        c,
+        "large, slow backend",
+        latency,
+        "/large.bin",
+        None,
+        64 * MIB as u32,
+    );
+}
+
+/// Many small reads at once, to measure contention in the read pool hand-off; every iteration
+/// includes spawning threads and building clients, compare runs of the same machine only
+fn concurrent(c: &mut Criterion) {
+    const THREADS: usize = 32;
+    const REQUESTS: usize = 16;
+    let files = files(Duration::from_secs(0));
+    let benchmark = Benchmark::new("concurrent small", move |b| {
+        b.iter(|| {
+            let handles: Vec<_> = (0..THREADS)
+                .map(|_| {
+                    let files = files.clone();
+                    thread::spawn(move || {
+                        let mut client = client(files);
+                        for _ in 0..REQUESTS {
+                            assert_eq!(4 * 1024, client.get("/small.bin").send().body.len());
+                        }
+                    })
+                })
+                .collect();
+            handles.into_iter().for_each(|x| x.join().unwrap());
+        })
+    })
+    .throughput(Throughput::Bytes((THREADS * REQUESTS * 4 * 1024) as u32));
+    c.bench("serve", benchmark);
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = whole_file, ranges, slow_backend, concurrent
+}
+criterion_main!(benches);
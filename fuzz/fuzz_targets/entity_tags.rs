@@ -0,0 +1,11 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use tide_static_file::fuzzing::check_entity_tags;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(list) = std::str::from_utf8(data) {
+        check_entity_tags(list);
+    }
+});
@@ -0,0 +1,28 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use std::ops::Range;
+use tide_static_file::fuzzing::*;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 16 {
+        return;
+    }
+    // file size, boundary and mime length first, then pairs of offsets relative to file size
+    let mut size = [0; 8];
+    size.copy_from_slice(&data[..8]);
+    let file_size = u64::from_le_bytes(size);
+    let boundary = "x".repeat(usize::from(data[8] % 71));
+    let mime = std::str::from_utf8(&data[10..10 + usize::from(data[9] % 7)]).unwrap_or("");
+
+    let step = file_size / 255;
+    let ranges: Vec<Range<u64>> = data[16..]
+        .chunks_exact(2)
+        .filter_map(|x| {
+            let range = ByteRange::FromToAll(step * u64::from(x[0]), step * u64::from(x[1]));
+            actual_range(range, file_size)
+        })
+        .collect();
+    check_multipart_length(&merge_ranges(ranges), mime, &boundary, file_size);
+});
@@ -0,0 +1,35 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+
+use tide_static_file::fuzzing::*;
+
+/// Every 17 bytes of input is a range spec: a kind byte and two numbers
+fn spec(chunk: &[u8]) -> ByteRange {
+    let mut a = [0; 8];
+    let mut b = [0; 8];
+    a.copy_from_slice(&chunk[1..9]);
+    b.copy_from_slice(&chunk[9..17]);
+    let (a, b) = (u64::from_le_bytes(a), u64::from_le_bytes(b));
+    match chunk[0] % 3 {
+        0 => ByteRange::FromTo(a),
+        1 => ByteRange::FromToAll(a, b),
+        _ => ByteRange::Last(a),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 8 {
+        return;
+    }
+    let mut size = [0; 8];
+    size.copy_from_slice(&data[..8]);
+    let file_size = u64::from_le_bytes(size);
+
+    let mut ranges = Vec::new();
+    for chunk in data[8..].chunks_exact(17) {
+        check_actual_range(spec(chunk), file_size);
+        ranges.extend(actual_range(spec(chunk), file_size));
+    }
+    check_merge_ranges(ranges);
+});
@@ -0,0 +1,41 @@
+//! Explicit aliases of url paths, e.g. `favicon.ico` served from `assets/icons/fav.ico`
+
+use std::collections::BTreeMap;
+
+/// Path to serve for `url_path` if it's an alias; both sides of `aliases` are logical paths,
+/// leading slashes don't matter
+pub(crate) fn resolve_alias<'a>(
+    aliases: &'a BTreeMap<String, String>,
+    url_path: &str,
+) -> Option<&'a str> {
+    let url_path = url_path.trim_start_matches('/');
+    aliases
+        .iter()
+        .find(|(from, _)| from.trim_start_matches('/') == url_path)
+        .map(|(_, to)| to.trim_start_matches('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_alias() {
+        let mut aliases = BTreeMap::new();
+        aliases.insert(
+            "/favicon.ico".to_string(),
+            "assets/icons/fav.ico".to_string(),
+        );
+        aliases.insert("legacy/app.js".to_string(), "/dist/app.1234.js".to_string());
+        assert_eq!(
+            Some("assets/icons/fav.ico"),
+            resolve_alias(&aliases, "favicon.ico")
+        );
+        assert_eq!(
+            Some("dist/app.1234.js"),
+            resolve_alias(&aliases, "/legacy/app.js")
+        );
+        assert_eq!(None, resolve_alias(&aliases, "legacy/app.css"));
+        assert_eq!(None, resolve_alias(&aliases, "legacy"));
+    }
+}
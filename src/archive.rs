@@ -0,0 +1,917 @@
+//! Downloads of directories as ZIP or tar archives, built while they are streamed, see
+//! [`StaticFiles::archive_downloads`](struct.StaticFiles.html#method.archive_downloads)
+//!
+//! Tar archives are deterministic and can be resumed, see [`tar`](../tar/index.html). In ZIP
+//! archives, files are stored uncompressed, with their CRC-32 and sizes in data descriptors following
+//! their content, so that nothing is read twice. ZIP64 fields are used where a size, an offset or
+//! the number of entries doesn't fit the classic ones, ref:
+//! https://pkware.cachefly.net/webdocs/casestudies/APPNOTE.TXT
+
+use crate::{
+    extensions::mark_generated,
+    options::Options,
+    range::ContentRange,
+    range_parser::parse_ranges,
+    tar::TarPlan,
+    utils::{
+        decide_ranges, get_header, range_header, ContentDisposition, DispositionType,
+        ErrorResponse, RangeDecision,
+    },
+    validators::EntityTag,
+    warm::glob_match,
+    StaticFiles,
+};
+use bytes::Bytes;
+use crossbeam_channel::bounded;
+use futures::{
+    channel::{
+        mpsc::{channel, Sender},
+        oneshot,
+    },
+    executor::block_on,
+    future::FutureObj,
+    SinkExt,
+};
+use http::{header, StatusCode};
+use http_service::Body;
+use lazy_static::lazy_static;
+use log::{error, warn};
+use std::{
+    fs::{self, File},
+    io::{self, ErrorKind, Read, Write},
+    mem,
+    ops::Range,
+    panic::{catch_unwind, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tide::{IntoResponse, Request, Response};
+
+/// Bytes sent to the body at once
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// Chunks built ahead of the client
+const CHUNKS_BUFFERED: usize = 4;
+
+/// Version needed to extract, 2.0 for stored files, 4.5 for ZIP64
+const VERSION: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+
+/// Sizes and CRC-32 in a data descriptor (bit 3), names in UTF-8 (bit 11)
+const FLAGS: u16 = 0x0808;
+
+/// Made by Unix, so that extractors take external attributes as permissions
+const MADE_BY_UNIX: u16 = 3 << 8;
+
+/// Regular file readable by anyone
+const FILE_MODE: u32 = 0o100_644;
+
+/// Header ID of the ZIP64 extended information extra field
+const ZIP64_EXTRA: u16 = 0x0001;
+
+/// Format of a directory archive
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+/// Archive format a request asks for, i.e. `?download=zip` or `?download=tar`
+pub(crate) fn archive_format(req: &Request) -> Option<ArchiveFormat> {
+    req.uri().query()?.split('&').find_map(|x| match x {
+        "download=zip" => Some(ArchiveFormat::Zip),
+        "download=tar" => Some(ArchiveFormat::Tar),
+        _ => None,
+    })
+}
+
+/// A file of a directory archive
+#[derive(Debug, Clone)]
+pub(crate) struct ArchiveEntry {
+    /// relative to the archived directory, `/` separated
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+/// Whether an entry named `name` (relative to the archived directory) matches one of
+/// `excludes`: patterns without `/` match the last segment at any depth (e.g. `node_modules`),
+/// others the whole name (e.g. `docs/*.pdf`)
+fn is_excluded(excludes: &[String], name: &str) -> bool {
+    let last = name.rsplit('/').next().unwrap_or(name);
+    excludes.iter().any(|pattern| {
+        if pattern.contains('/') {
+            glob_match(pattern, name)
+        } else {
+            glob_match(pattern, last)
+        }
+    })
+}
+
+/// Size of the archive of `entries`, exact unless ZIP64 fields are needed
+fn estimated_size(entries: &[ArchiveEntry]) -> u64 {
+    let headers = |x: &ArchiveEntry| 30 + 16 + 46 + 2 * x.name.len() as u64;
+    entries.iter().map(|x| x.size + headers(x)).sum::<u64>() + 22
+}
+
+/// Files below `dir` by name; hidden (dot) entries, names which are not valid UTF-8 and
+/// `excludes` (see [`is_excluded`]) are skipped, symbolic links are followed to files only
+pub(crate) fn collect_entries(dir: &Path, excludes: &[String]) -> io::Result<Vec<ArchiveEntry>> {
+    let mut entries = Vec::new();
+    let mut dirs = vec![(dir.to_path_buf(), String::new())];
+    while let Some((dir, prefix)) = dirs.pop() {
+        for item in fs::read_dir(&dir)? {
+            let item = item?;
+            let name = match item.file_name().into_string() {
+                Ok(x) if !x.starts_with('.') => format!("{}{}", prefix, x),
+                _ => continue,
+            };
+            if is_excluded(excludes, &name) {
+                continue;
+            }
+            if item.file_type()?.is_dir() {
+                dirs.push((item.path(), format!("{}/", name)));
+                continue;
+            }
+            let metadata = match fs::metadata(item.path()) {
+                Ok(x) if x.is_file() => x,
+                _ => continue,
+            };
+            entries.push(ArchiveEntry {
+                name,
+                path: item.path(),
+                size: metadata.len(),
+                modified: metadata.modified().unwrap_or(UNIX_EPOCH),
+            });
+        }
+    }
+    entries.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(entries)
+}
+
+/// Threads building archives, a download takes one while it is streamed
+const ARCHIVE_THREADS: usize = 4;
+
+lazy_static! {
+    /// Jobs are handed to idle threads only (the channel has no capacity), downloads beyond
+    /// `ARCHIVE_THREADS` at once are answered with `503`
+    static ref ARCHIVE_POOL: crossbeam_channel::Sender<ArchiveJob> = {
+        let (sender, receiver) = bounded(0);
+        for index in 0..ARCHIVE_THREADS {
+            let receiver: crossbeam_channel::Receiver<ArchiveJob> = receiver.clone();
+            thread::Builder::new()
+                .name(format!("tsf-archive-{}", index))
+                .spawn(move || {
+                    for job in receiver {
+                        if catch_unwind(AssertUnwindSafe(|| job.run())).is_err() {
+                            error!("archive download panicked");
+                        }
+                    }
+                })
+                .expect("failed to spawn archive thread");
+        }
+        sender
+    };
+}
+
+impl StaticFiles {
+    /// Archive of directory `dir` in `format`, walked and streamed by a thread of
+    /// `ARCHIVE_POOL`, `413 Payload Too Large` if it would be larger than allowed and
+    /// `503 Service Unavailable` if all threads are busy
+    pub(crate) fn archive_response(
+        &self,
+        options: &Options,
+        dir: &Path,
+        format: ArchiveFormat,
+        req: &Request,
+    ) -> FutureObj<'static, Response> {
+        let (sender, receiver) = oneshot::channel();
+        let job = ArchiveJob {
+            files: self.clone(),
+            dir: dir.to_path_buf(),
+            excludes: options.archive_excludes.clone(),
+            max_size: options.max_archive_size,
+            format,
+            if_range: get_header(req, header::IF_RANGE),
+            range: range_header(req, options.range_units),
+            response: sender,
+        };
+        if ARCHIVE_POOL.try_send(job).is_err() {
+            warn!("all archive threads are busy");
+            let response = ErrorResponse::Busy.into_response();
+            return FutureObj::new(Box::new(async move { response }));
+        }
+        FutureObj::new(Box::new(
+            async move {
+                await!(receiver).unwrap_or_else(|_| ErrorResponse::Unexpected.into_response())
+            },
+        ))
+    }
+}
+
+/// Download of an archive, run by a thread of `ARCHIVE_POOL` from walking the directory to the
+/// last chunk
+struct ArchiveJob {
+    files: StaticFiles,
+    dir: PathBuf,
+    excludes: Vec<String>,
+    max_size: Option<u64>,
+    format: ArchiveFormat,
+    if_range: Option<String>,
+    range: Option<String>,
+    response: oneshot::Sender<Response>,
+}
+
+/// What the body of an archive response is made of
+enum Writing {
+    Zip(Vec<ArchiveEntry>),
+    Tar(TarPlan, Range<u64>),
+}
+
+impl ArchiveJob {
+    /// Send the response, then write its body until it's complete or dropped; a file failing to
+    /// be read (or changing its size) fails the body
+    fn run(self) {
+        let (head, writing) = match self.prepare() {
+            Ok(x) => x,
+            Err(response) => {
+                let _ = self.response.send(response);
+                return;
+            }
+        };
+        let (sender, receiver) = channel(CHUNKS_BUFFERED);
+        let (parts, ()) = head.into_parts();
+        let response = http::Response::from_parts(parts, Body::from_stream(receiver));
+        let response = match writing {
+            // its length isn't known up front
+            Writing::Zip(_) => mark_generated(response),
+            Writing::Tar(..) => response,
+        };
+        if self.response.send(response).is_err() {
+            return;
+        }
+
+        let mut chunks = Chunks {
+            sender,
+            buffer: Vec::with_capacity(CHUNK_SIZE),
+        };
+        let written = match writing {
+            Writing::Zip(entries) => write_zip(&entries, &mut chunks),
+            Writing::Tar(plan, range) => plan.write(range, &mut chunks),
+        };
+        match written.and_then(|_| chunks.flush()) {
+            Err(ref error) if error.kind() == ErrorKind::BrokenPipe => {}
+            Err(error) => {
+                warn!("failed to archive: {}", error);
+                let _ = block_on(chunks.sender.send(Err(error)));
+            }
+            Ok(_) => {}
+        }
+    }
+
+    /// Head of the response and what its body is made of, or a whole response if there's no
+    /// archive to send
+    fn prepare(&self) -> Result<(http::Response<()>, Writing), Response> {
+        let entries = collect_entries(&self.dir, &self.excludes).map_err(|error| {
+            error!("unexpected error occurred: {:?}", error);
+            ErrorResponse::Unexpected.into_response()
+        })?;
+        let name = self
+            .dir
+            .file_name()
+            .and_then(|x| x.to_str())
+            .unwrap_or("download");
+        let too_large = |size| self.max_size.map_or(false, |max| size > max);
+        match self.format {
+            ArchiveFormat::Zip if too_large(estimated_size(&entries)) => Err(too_large_response()),
+            ArchiveFormat::Zip => {
+                let head = http::Response::builder()
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_TYPE, "application/zip")
+                    .header(header::CONTENT_DISPOSITION, attachment(name, "zip"))
+                    .body(())
+                    .unwrap();
+                Ok((head, Writing::Zip(entries)))
+            }
+            ArchiveFormat::Tar => {
+                let plan = TarPlan::new(entries);
+                if too_large(plan.length()) {
+                    Err(too_large_response())
+                } else {
+                    self.tar_head(plan, name)
+                }
+            }
+        }
+    }
+
+    /// Head of a tar archive of `plan`, or of the single range asked for (`If-Range` matches its
+    /// `ETag` only, several ranges make the whole archive)
+    fn tar_head(
+        &self,
+        plan: TarPlan,
+        name: &str,
+    ) -> Result<(http::Response<()>, Writing), Response> {
+        let length = plan.length();
+        let etag = plan.etag();
+        let should_range = self
+            .if_range
+            .as_ref()
+            .map_or(true, |x| EntityTag::parse(x).strong_eq(&etag));
+        // no valid range set makes the whole archive too
+        let ranges = self
+            .range
+            .as_ref()
+            .filter(|_| should_range)
+            .map(|x| parse_ranges(x))
+            .filter(|x| !x.is_empty());
+        let range = match ranges.map(|x| decide_ranges(x, length)) {
+            Some(RangeDecision::Unsatisfiable) => {
+                return Err(self.files.range_not_satisfiable_response(length))
+            }
+            Some(RangeDecision::Partial(ref x)) if x.len() == 1 => Some(x[0].clone()),
+            _ => None,
+        };
+
+        let mut response = http::Response::builder();
+        response
+            .header(header::CONTENT_TYPE, "application/x-tar")
+            .header(header::CONTENT_DISPOSITION, attachment(name, "tar"))
+            .header(header::ETAG, etag.as_str());
+        let range = match range {
+            Some(range) => {
+                response
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(
+                        header::CONTENT_RANGE,
+                        ContentRange::new(range.clone(), length).to_string(),
+                    )
+                    .header(header::CONTENT_LENGTH, range.end - range.start);
+                range
+            }
+            None => {
+                response
+                    .status(StatusCode::OK)
+                    .header(header::CONTENT_LENGTH, length);
+                0..length
+            }
+        };
+        Ok((response.body(()).unwrap(), Writing::Tar(plan, range)))
+    }
+}
+
+fn too_large_response() -> Response {
+    http::Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+        .body("archive too large".into())
+        .unwrap()
+}
+
+/// `Content-Disposition` of an archive of directory `name`
+fn attachment(name: &str, extension: &str) -> String {
+    let disposition = ContentDisposition {
+        ty: DispositionType::Attachment,
+        filename: Some(format!("{}.{}", name, extension)),
+    };
+    disposition.to_string()
+}
+
+fn write_zip(entries: &[ArchiveEntry], writer: impl Write) -> io::Result<()> {
+    let mut zip = ZipWriter::new(writer);
+    for entry in entries {
+        let file = File::open(&entry.path)?;
+        zip.add(&entry.name, entry.size, entry.modified, file)?;
+    }
+    zip.finish().map(|_| ())
+}
+
+/// Writer sending what is written to a body in chunks, `BrokenPipe` once the body is dropped
+struct Chunks {
+    sender: Sender<io::Result<Bytes>>,
+    buffer: Vec<u8>,
+}
+
+impl Write for Chunks {
+    fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(bytes);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.flush()?;
+        }
+        Ok(bytes.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE));
+        block_on(self.sender.send(Ok(Bytes::from(chunk))))
+            .map_err(|_| io::Error::new(ErrorKind::BrokenPipe, "archive download aborted"))
+    }
+}
+
+/// A file already written, for the central directory
+struct CentralEntry {
+    name: String,
+    crc: u32,
+    size: u64,
+    offset: u64,
+    time: u16,
+    date: u16,
+}
+
+/// Streaming writer of a ZIP archive of stored files
+pub(crate) struct ZipWriter<W> {
+    inner: W,
+    /// bytes written so far
+    offset: u64,
+    central: Vec<CentralEntry>,
+    /// values from this on don't fit a 32 bit field, lower in tests
+    max32: u64,
+    /// entries from this on don't fit a 16 bit field, lower in tests
+    max16: u64,
+}
+
+impl<W: Write> ZipWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self::with_limits(
+            inner,
+            u64::from(u32::max_value()),
+            u64::from(u16::max_value()),
+        )
+    }
+
+    fn with_limits(inner: W, max32: u64, max16: u64) -> Self {
+        Self {
+            inner,
+            offset: 0,
+            central: Vec::new(),
+            max32,
+            max16,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.inner.write_all(bytes)?;
+        self.offset += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Add a file named `name` of `size` bytes read from `content`, which must have that size
+    pub fn add(
+        &mut self,
+        name: &str,
+        size: u64,
+        modified: SystemTime,
+        content: impl Read,
+    ) -> io::Result<()> {
+        let zip64 = size >= self.max32;
+        let (time, date) = dos_date_time(modified);
+        let offset = self.offset;
+
+        let mut header = Vec::with_capacity(30 + name.len() + 20);
+        put32(&mut header, 0x0403_4b50);
+        put16(&mut header, if zip64 { VERSION_ZIP64 } else { VERSION });
+        put16(&mut header, FLAGS);
+        put16(&mut header, 0);
+        put16(&mut header, time);
+        put16(&mut header, date);
+        // CRC-32 and sizes follow in the data descriptor
+        put32(&mut header, 0);
+        let unknown = if zip64 { u32::max_value() } else { 0 };
+        put32(&mut header, unknown);
+        put32(&mut header, unknown);
+        put16(&mut header, name.len() as u16);
+        put16(&mut header, if zip64 { 20 } else { 0 });
+        header.extend_from_slice(name.as_bytes());
+        if zip64 {
+            // announces 8 byte sizes in the data descriptor
+            put16(&mut header, ZIP64_EXTRA);
+            put16(&mut header, 16);
+            put64(&mut header, 0);
+            put64(&mut header, 0);
+        }
+        self.write(&header)?;
+
+        let mut content = content.take(size);
+        let mut buffer = vec![0; size.min(CHUNK_SIZE as u64) as usize];
+        let (mut crc, mut copied) = (!0, 0);
+        loop {
+            let read = match content.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(x) => x,
+                Err(ref error) if error.kind() == ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            crc = crc32_update(crc, &buffer[..read]);
+            self.write(&buffer[..read])?;
+            copied += read as u64;
+        }
+        if copied != size {
+            let message = format!("{} changed while archived", name);
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, message));
+        }
+        let crc = !crc;
+
+        let mut descriptor = Vec::with_capacity(24);
+        put32(&mut descriptor, 0x0807_4b50);
+        put32(&mut descriptor, crc);
+        if zip64 {
+            put64(&mut descriptor, size);
+            put64(&mut descriptor, size);
+        } else {
+            put32(&mut descriptor, size as u32);
+            put32(&mut descriptor, size as u32);
+        }
+        self.write(&descriptor)?;
+
+        self.central.push(CentralEntry {
+            name: name.to_string(),
+            crc,
+            size,
+            offset,
+            time,
+            date,
+        });
+        Ok(())
+    }
+
+    /// Write the central directory and end records, giving back the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        let start = self.offset;
+        let mut central = Vec::new();
+        for entry in &self.central {
+            let mut extra = Vec::new();
+            if entry.size >= self.max32 {
+                put64(&mut extra, entry.size);
+                put64(&mut extra, entry.size);
+            }
+            if entry.offset >= self.max32 {
+                put64(&mut extra, entry.offset);
+            }
+            let version = if extra.is_empty() {
+                VERSION
+            } else {
+                VERSION_ZIP64
+            };
+            put32(&mut central, 0x0201_4b50);
+            put16(&mut central, MADE_BY_UNIX | version);
+            put16(&mut central, version);
+            put16(&mut central, FLAGS);
+            put16(&mut central, 0);
+            put16(&mut central, entry.time);
+            put16(&mut central, entry.date);
+            put32(&mut central, entry.crc);
+            put32(&mut central, saturate32(entry.size, self.max32));
+            put32(&mut central, saturate32(entry.size, self.max32));
+            put16(&mut central, entry.name.len() as u16);
+            put16(
+                &mut central,
+                if extra.is_empty() {
+                    0
+                } else {
+                    4 + extra.len() as u16
+                },
+            );
+            // comment length, disk number start, internal attributes
+            put16(&mut central, 0);
+            put16(&mut central, 0);
+            put16(&mut central, 0);
+            put32(&mut central, FILE_MODE << 16);
+            put32(&mut central, saturate32(entry.offset, self.max32));
+            central.extend_from_slice(entry.name.as_bytes());
+            if !extra.is_empty() {
+                put16(&mut central, ZIP64_EXTRA);
+                put16(&mut central, extra.len() as u16);
+                central.extend_from_slice(&extra);
+            }
+        }
+        self.write(&central)?;
+
+        let count = self.central.len() as u64;
+        let size = central.len() as u64;
+        let mut end = Vec::with_capacity(98);
+        if count >= self.max16 || size >= self.max32 || start >= self.max32 {
+            let record = self.offset;
+            put32(&mut end, 0x0606_4b50);
+            // size of the rest of the record
+            put64(&mut end, 44);
+            put16(&mut end, VERSION_ZIP64);
+            put16(&mut end, VERSION_ZIP64);
+            put32(&mut end, 0);
+            put32(&mut end, 0);
+            put64(&mut end, count);
+            put64(&mut end, count);
+            put64(&mut end, size);
+            put64(&mut end, start);
+            // locator
+            put32(&mut end, 0x0706_4b50);
+            put32(&mut end, 0);
+            put64(&mut end, record);
+            put32(&mut end, 1);
+        }
+        put32(&mut end, 0x0605_4b50);
+        put16(&mut end, 0);
+        put16(&mut end, 0);
+        let count = if count >= self.max16 {
+            u16::max_value()
+        } else {
+            count as u16
+        };
+        put16(&mut end, count);
+        put16(&mut end, count);
+        put32(&mut end, saturate32(size, self.max32));
+        put32(&mut end, saturate32(start, self.max32));
+        put16(&mut end, 0);
+        self.write(&end)?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+fn put16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put64(buffer: &mut Vec<u8>, value: u64) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// `value` of a 32 bit field, `0xFFFFFFFF` if it's found in the ZIP64 extra field instead
+fn saturate32(value: u64, max32: u64) -> u32 {
+    if value >= max32 {
+        u32::max_value()
+    } else {
+        value as u32
+    }
+}
+
+/// MS-DOS time and date of `time` in UTC, clamped to the years 1980 to 2107 they can tell
+fn dos_date_time(time: SystemTime) -> (u16, u16) {
+    const MIN: u64 = 315_532_800; // 1980-01-01
+    const MAX: u64 = 4_354_819_198; // 2107-12-31 23:59:58
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let secs = secs.max(MIN).min(MAX);
+    let (days, secs) = (secs / 86400, secs % 86400);
+    // civil date of days since epoch, ref: http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719_468;
+    let (era, doe) = (z / 146_097, z % 146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    let time = (secs / 3600) << 11 | (secs % 3600 / 60) << 5 | (secs % 60) / 2;
+    let date = (year - 1980) << 9 | month << 5 | day;
+    (time as u16, date as u16)
+}
+
+lazy_static! {
+    static ref CRC32_TABLE: [u32; 256] = {
+        let mut table = [0; 256];
+        for (index, value) in table.iter_mut().enumerate() {
+            *value = (0..8).fold(index as u32, |x, _| {
+                if x & 1 == 1 {
+                    0xEDB8_8320 ^ (x >> 1)
+                } else {
+                    x >> 1
+                }
+            });
+        }
+        table
+    };
+}
+
+/// Update CRC-32 (IEEE) `crc`, starting from `!0` and inverted once done
+fn crc32_update(crc: u32, bytes: &[u8]) -> u32 {
+    let table = &*CRC32_TABLE;
+    bytes.iter().fold(crc, |crc, &byte| {
+        table[((crc ^ u32::from(byte)) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::{convert::TryInto, io::repeat};
+
+    fn get16(bytes: &[u8], at: usize) -> u64 {
+        u64::from(u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap()))
+    }
+
+    fn get32(bytes: &[u8], at: usize) -> u64 {
+        u64::from(u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap()))
+    }
+
+    fn get64(bytes: &[u8], at: usize) -> u64 {
+        u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap())
+    }
+
+    /// Entries of the central directory as (name, CRC-32, size, offset of local header),
+    /// checking that end records agree and offsets point at local headers of the same names
+    fn read_central(zip: &[u8]) -> Vec<(String, u32, u64, u64)> {
+        let end = zip.len() - 22;
+        assert_eq!(0x0605_4b50, get32(zip, end));
+        let (mut count, mut size, mut start) = (
+            get16(zip, end + 10),
+            get32(zip, end + 12),
+            get32(zip, end + 16),
+        );
+        if count == 0xFFFF || size == 0xFFFF_FFFF || start == 0xFFFF_FFFF {
+            let locator = end - 20;
+            assert_eq!(0x0706_4b50, get32(zip, locator));
+            let record = get64(zip, locator + 8) as usize;
+            assert_eq!(locator - 56, record);
+            assert_eq!(0x0606_4b50, get32(zip, record));
+            assert_eq!(get64(zip, record + 24), get64(zip, record + 32));
+            count = get64(zip, record + 32);
+            size = get64(zip, record + 40);
+            start = get64(zip, record + 48);
+            assert_eq!(record as u64, start + size);
+        } else {
+            assert_eq!(end as u64, start + size);
+        }
+
+        let mut entries = Vec::new();
+        let mut at = start as usize;
+        for _ in 0..count {
+            assert_eq!(0x0201_4b50, get32(zip, at));
+            let name_len = get16(zip, at + 28) as usize;
+            let extra_len = get16(zip, at + 30) as usize;
+            let name = String::from_utf8(zip[at + 46..at + 46 + name_len].to_vec()).unwrap();
+            let (mut size, mut offset) = (get32(zip, at + 24), get32(zip, at + 42));
+            let mut extra = at + 46 + name_len;
+            if extra_len > 0 {
+                assert_eq!(u64::from(ZIP64_EXTRA), get16(zip, extra));
+                extra += 4;
+                if size == 0xFFFF_FFFF {
+                    assert_eq!(get64(zip, extra), get64(zip, extra + 8));
+                    size = get64(zip, extra);
+                    extra += 16;
+                }
+                if offset == 0xFFFF_FFFF {
+                    offset = get64(zip, extra);
+                }
+            }
+            let local = offset as usize;
+            assert_eq!(0x0403_4b50, get32(zip, local));
+            assert_eq!(name_len as u64, get16(zip, local + 26));
+            assert_eq!(name.as_bytes(), &zip[local + 30..local + 30 + name_len]);
+            entries.push((name, get32(zip, at + 16) as u32, size, offset));
+            at += 46 + name_len + extra_len;
+        }
+        assert_eq!(start + size, at as u64);
+        entries
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0xCBF4_3926, !crc32_update(!0, b"123456789"));
+    }
+
+    #[test]
+    fn test_dos_date_time() {
+        // 2019-04-01 12:34:56 UTC
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1_554_122_096);
+        assert_eq!(
+            (12 << 11 | 34 << 5 | 28, 39 << 9 | 4 << 5 | 1),
+            dos_date_time(time)
+        );
+        assert_eq!((0, 1 << 5 | 1), dos_date_time(UNIX_EPOCH));
+    }
+
+    #[test]
+    fn test_zip() {
+        let mut zip = ZipWriter::new(Vec::new());
+        zip.add("a.txt", 9, UNIX_EPOCH, &b"123456789"[..]).unwrap();
+        zip.add("b/c.txt", 0, UNIX_EPOCH, &b""[..]).unwrap();
+        let error = zip.add("d.txt", 4, UNIX_EPOCH, &b"12"[..]).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+        let zip = zip.finish().unwrap();
+        let entries = read_central(&zip);
+        assert_eq!(
+            vec![
+                ("a.txt".to_string(), 0xCBF4_3926, 9, 0),
+                ("b/c.txt".to_string(), 0, 0, 30 + 5 + 9 + 16),
+            ],
+            entries
+        );
+        // no ZIP64 end records
+        assert_eq!(0x0201_4b50, get32(&zip, zip.len() - 22 - 7 - 46));
+    }
+
+    /// ZIP64 of sizes and offsets beyond 4 GiB, with limits lowered so that no gigabytes are
+    /// written
+    #[test]
+    fn test_zip64_offsets() {
+        let mut zip = ZipWriter::with_limits(Vec::new(), 100, u64::from(u16::max_value()));
+        zip.add("small", 10, UNIX_EPOCH, repeat(1)).unwrap();
+        zip.add("large", 150, UNIX_EPOCH, repeat(2)).unwrap();
+        zip.add("after", 10, UNIX_EPOCH, repeat(3)).unwrap();
+        let zip = zip.finish().unwrap();
+        let entries = read_central(&zip);
+        let sizes: Vec<_> = entries.iter().map(|x| x.2).collect();
+        assert_eq!(vec![10, 150, 10], sizes);
+        let offsets: Vec<_> = entries.iter().map(|x| x.3).collect();
+        let large = 30 + 5 + 10 + 16;
+        assert_eq!(vec![0, large, large + 30 + 5 + 20 + 150 + 24], offsets);
+        // ZIP64 end records, as the central directory starts beyond the limit
+        assert_eq!(0x0606_4b50, get32(&zip, zip.len() - 22 - 20 - 56));
+        // 8 byte sizes in the data descriptor of the large file
+        let descriptor = (large + 30 + 5 + 20 + 150) as usize;
+        assert_eq!(0x0807_4b50, get32(&zip, descriptor));
+        assert_eq!(150, get64(&zip, descriptor + 8));
+    }
+
+    #[test]
+    fn test_zip64_entries() {
+        let mut zip = ZipWriter::new(Vec::new());
+        for index in 0..70_000 {
+            zip.add(&index.to_string(), 0, UNIX_EPOCH, &b""[..])
+                .unwrap();
+        }
+        let zip = zip.finish().unwrap();
+        assert_eq!(0xFFFF, get16(&zip, zip.len() - 22 + 10));
+        let entries = read_central(&zip);
+        assert_eq!(70_000, entries.len());
+        assert_eq!("69999", entries[69_999].0);
+    }
+
+    #[test]
+    fn test_collect_entries() {
+        let root = TempDir::new("archive");
+        fs::create_dir_all(root.join("b/c")).unwrap();
+        fs::create_dir_all(root.join(".git")).unwrap();
+        fs::write(root.join("a.txt"), b"a").unwrap();
+        fs::write(root.join("b/c/d.txt"), b"dd").unwrap();
+        fs::write(root.join(".git/config"), b"").unwrap();
+        let entries = collect_entries(&root, &[]).unwrap();
+        let names: Vec<_> = entries.iter().map(|x| (x.name.as_str(), x.size)).collect();
+        assert_eq!(vec![("a.txt", 1), ("b/c/d.txt", 2)], names);
+
+        let mut zip = Vec::new();
+        write_zip(&entries, &mut zip).unwrap();
+        let names: Vec<_> = read_central(&zip).into_iter().map(|x| x.0).collect();
+        assert_eq!(vec!["a.txt", "b/c/d.txt"], names);
+        assert_eq!(zip.len() as u64, estimated_size(&entries));
+
+        let excludes = vec!["c".to_string()];
+        assert_eq!(1, collect_entries(&root, &excludes).unwrap().len());
+        let excludes = vec!["b/**/*.txt".to_string()];
+        assert_eq!(1, collect_entries(&root, &excludes).unwrap().len());
+    }
+
+    #[test]
+    fn test_is_excluded() {
+        let excludes = vec![
+            "node_modules".to_string(),
+            "*.log".to_string(),
+            "docs/*.pdf".to_string(),
+        ];
+        assert!(is_excluded(&excludes, "node_modules"));
+        assert!(is_excluded(&excludes, "web/node_modules"));
+        assert!(is_excluded(&excludes, "a/b/debug.log"));
+        assert!(is_excluded(&excludes, "docs/manual.pdf"));
+        assert!(!is_excluded(&excludes, "docs/old/manual.pdf"));
+        assert!(!is_excluded(&excludes, "node_modules.txt"));
+    }
+
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn test_tar_download() {
+        let temp = TempDir::new("tar-download");
+        fs::create_dir_all(temp.join("dir")).unwrap();
+        fs::write(temp.join("dir/a.txt"), b"abc").unwrap();
+        let files = StaticFiles::new(&temp)
+            .unwrap()
+            .listing(true)
+            .archive_downloads(true);
+        let mut client = crate::test_util::client(files);
+
+        let whole = client.get("/dir?download=tar").send();
+        assert_eq!(200, whole.status.as_u16());
+        let range = client
+            .get("/dir?download=tar")
+            .header("range", "bytes=0-9")
+            .send();
+        assert_eq!(206, range.status.as_u16());
+        assert_eq!(&whole.body[..10], &range.body[..]);
+        // no valid range set
+        let malformed = client
+            .get("/dir?download=tar")
+            .header("range", "bytes=nothing")
+            .send();
+        assert_eq!(200, malformed.status.as_u16());
+    }
+}
@@ -0,0 +1,79 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Generate boundary of `multipart/byteranges` responses
+///
+/// A boundary must be 1 to 70 characters out of letters, digits and `'()+_,-./:=?`, and
+/// should not appear in served content. Override it (e.g. by
+/// [`FixedBoundary`](struct.FixedBoundary.html)) to make responses reproducible in tests.
+pub trait BoundaryGenerator: Send + Sync {
+    fn boundary(&self) -> String;
+}
+
+/// Random 16 hex digits per response (default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomBoundary;
+
+impl BoundaryGenerator for RandomBoundary {
+    fn boundary(&self) -> String {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        format!("{:016x}", hasher.finish())
+    }
+}
+
+/// Always the same boundary
+#[derive(Debug, Clone)]
+pub struct FixedBoundary(String);
+
+impl FixedBoundary {
+    /// Panic if `boundary` is not a valid boundary
+    pub fn new(boundary: &str) -> Self {
+        assert!(is_valid(boundary), "invalid boundary: {:?}", boundary);
+        FixedBoundary(boundary.to_string())
+    }
+}
+
+impl BoundaryGenerator for FixedBoundary {
+    fn boundary(&self) -> String {
+        self.0.clone()
+    }
+}
+
+/// ref: https://tools.ietf.org/html/rfc2046#section-5.1.1
+///
+/// Space is allowed by RFC but not accepted here, as it requires quoting in `Content-Type`.
+fn is_valid(boundary: &str) -> bool {
+    !boundary.is_empty()
+        && boundary.len() <= 70
+        && boundary
+            .bytes()
+            .all(|x| x.is_ascii_alphanumeric() || b"'()+_,-./:=?".contains(&x))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_random_boundary() {
+        let first = RandomBoundary.boundary();
+        assert_eq!(16, first.len());
+        assert!(is_valid(&first));
+        assert_ne!(first, RandomBoundary.boundary());
+    }
+
+    #[test]
+    fn test_is_valid() {
+        assert!(is_valid("DCjanus"));
+        assert!(is_valid("a'()+_,-./:=?z"));
+        assert!(!is_valid(""));
+        assert!(!is_valid("a b"));
+        assert!(!is_valid("a\r\nb"));
+        assert!(!is_valid(&"a".repeat(71)));
+    }
+}
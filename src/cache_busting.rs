@@ -0,0 +1,60 @@
+//! Query parameters templates append to bust caches, e.g. `app.js?v=3`, see
+//! [`StaticFiles::cache_busting_param`](struct.StaticFiles.html#method.cache_busting_param)
+
+/// Decides whether the file at a logical path is current at a version given by query, e.g.
+/// by comparing it with the file's build number
+pub(crate) type CacheBustingRule = dyn Fn(&str, &str) -> bool + Send + Sync;
+
+/// Whether query pair `pair` is one of `params`, whatever its value
+fn is_busting(params: &[String], pair: &str) -> bool {
+    let name = pair.splitn(2, '=').next().unwrap_or("");
+    params.iter().any(|x| x == name)
+}
+
+/// `query` without cache-busting parameters, `None` if nothing else is left
+pub(crate) fn strip_params(query: Option<&str>, params: &[String]) -> Option<String> {
+    let query = query?;
+    if params.is_empty() {
+        return Some(query.to_string());
+    }
+    let rest: Vec<&str> = query
+        .split('&')
+        .filter(|x| !x.is_empty() && !is_busting(params, x))
+        .collect();
+    Some(rest.join("&")).filter(|x| !x.is_empty())
+}
+
+/// Value of the first cache-busting parameter of `query` which has one
+pub(crate) fn version<'a>(query: Option<&'a str>, params: &[String]) -> Option<&'a str> {
+    query?
+        .split('&')
+        .filter(|x| is_busting(params, x))
+        .filter_map(|x| x.splitn(2, '=').nth(1))
+        .find(|x| !x.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_busting() {
+        let params = vec!["v".to_string(), "hash".to_string()];
+        assert_eq!(None, strip_params(Some("v=3"), &params));
+        assert_eq!(
+            Some("w=320".to_string()),
+            strip_params(Some("v=3&w=320&hash"), &params)
+        );
+        assert_eq!(
+            Some("vv=1".to_string()),
+            strip_params(Some("vv=1"), &params)
+        );
+        assert_eq!(Some("v=3".to_string()), strip_params(Some("v=3"), &[]));
+        assert_eq!(None, strip_params(None, &params));
+
+        assert_eq!(Some("3"), version(Some("w=320&v=3"), &params));
+        assert_eq!(Some("abc"), version(Some("v=&hash=abc"), &params));
+        assert_eq!(None, version(Some("v&vv=1"), &params));
+        assert_eq!(None, version(Some("v=3"), &[]));
+    }
+}
@@ -0,0 +1,208 @@
+use crate::{error::InvalidCacheControl, TSFResult};
+use http::HeaderValue;
+use std::time::Duration;
+
+/// Compose a `Cache-Control` value (also usable for `Surrogate-Control` and
+/// `CDN-Cache-Control`) out of directives, instead of writing it by hand
+///
+/// ref:
+/// + https://tools.ietf.org/html/rfc7234#section-5.2.2
+/// + https://tools.ietf.org/html/rfc5861
+/// + https://tools.ietf.org/html/rfc8246
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tide_static_file::{CacheControl, StaticFiles};
+/// let value = CacheControl::new()
+///     .public()
+///     .max_age(Duration::from_secs(300))
+///     .stale_while_revalidate(Duration::from_secs(60))
+///     .stale_if_error(Duration::from_secs(600))
+///     .build()
+///     .unwrap();
+/// let files = StaticFiles::new("./").unwrap().header(http::header::CACHE_CONTROL, value);
+/// ```
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct CacheControl {
+    public: bool,
+    private: bool,
+    no_cache: bool,
+    no_store: bool,
+    no_transform: bool,
+    must_revalidate: bool,
+    immutable: bool,
+    max_age: Option<u64>,
+    s_maxage: Option<u64>,
+    stale_while_revalidate: Option<u64>,
+    stale_if_error: Option<u64>,
+}
+
+impl CacheControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// `public`, shared caches may store responses
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// `private`, only browser caches may store responses
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    /// `no-cache`, revalidate before every reuse
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// `no-store`, never store responses, excludes any lifetime
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// `no-transform`, intermediaries must not recompress or convert responses
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    /// `must-revalidate`, never serve stale responses, excludes `stale-*` directives
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// `immutable`, don't revalidate fresh responses on reload, requires `max-age`
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// `max-age`, in whole seconds
+    pub fn max_age(mut self, age: Duration) -> Self {
+        self.max_age = Some(age.as_secs());
+        self
+    }
+
+    /// `s-maxage`, `max-age` of shared caches, in whole seconds
+    pub fn s_maxage(mut self, age: Duration) -> Self {
+        self.s_maxage = Some(age.as_secs());
+        self
+    }
+
+    /// `stale-while-revalidate`, serve stale responses for given time while revalidating in
+    /// background
+    pub fn stale_while_revalidate(mut self, age: Duration) -> Self {
+        self.stale_while_revalidate = Some(age.as_secs());
+        self
+    }
+
+    /// `stale-if-error`, serve stale responses for given time if revalidation fails
+    pub fn stale_if_error(mut self, age: Duration) -> Self {
+        self.stale_if_error = Some(age.as_secs());
+        self
+    }
+
+    /// Check directives and compose header value
+    pub fn build(&self) -> TSFResult<HeaderValue> {
+        self.validate()?;
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+        ];
+        let ages = [
+            ("max-age", self.max_age),
+            ("s-maxage", self.s_maxage),
+            ("stale-while-revalidate", self.stale_while_revalidate),
+            ("stale-if-error", self.stale_if_error),
+        ];
+        let directives: Vec<String> = flags
+            .iter()
+            .filter(|(on, _)| *on)
+            .map(|(_, name)| name.to_string())
+            .chain(
+                ages.iter()
+                    .filter_map(|(name, age)| age.map(|x| format!("{}={}", name, x))),
+            )
+            .chain(Some("immutable".to_string()).filter(|_| self.immutable))
+            .collect();
+        if directives.is_empty() {
+            return Err(InvalidCacheControl("no directive".to_string()).into());
+        }
+        Ok(HeaderValue::from_str(&directives.join(", ")).unwrap())
+    }
+
+    fn validate(&self) -> Result<(), InvalidCacheControl> {
+        let error = |x: &str| Err(InvalidCacheControl(x.to_string()));
+        let stale = self.stale_while_revalidate.is_some() || self.stale_if_error.is_some();
+        let lifetime = self.max_age.is_some() || self.s_maxage.is_some();
+        if self.public && self.private {
+            return error("both public and private");
+        }
+        if self.no_store && (lifetime || stale || self.immutable) {
+            return error("no-store with a lifetime");
+        }
+        if self.must_revalidate && stale {
+            return error("must-revalidate with stale-* directives");
+        }
+        if self.immutable && self.max_age.is_none() {
+            return error("immutable without max-age");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let minute = Duration::from_secs(60);
+        let value = CacheControl::new()
+            .public()
+            .max_age(minute * 5)
+            .stale_while_revalidate(minute)
+            .stale_if_error(minute * 10)
+            .build()
+            .unwrap();
+        assert_eq!(
+            "public, max-age=300, stale-while-revalidate=60, stale-if-error=600",
+            value
+        );
+        let value = CacheControl::new()
+            .public()
+            .max_age(Duration::from_secs(31_536_000))
+            .immutable()
+            .build()
+            .unwrap();
+        assert_eq!("public, max-age=31536000, immutable", value);
+        assert_eq!("no-store", CacheControl::new().no_store().build().unwrap());
+
+        assert!(CacheControl::new().build().is_err());
+        assert!(CacheControl::new().public().private().build().is_err());
+        assert!(CacheControl::new()
+            .no_store()
+            .max_age(minute)
+            .build()
+            .is_err());
+        assert!(CacheControl::new()
+            .must_revalidate()
+            .stale_if_error(minute)
+            .build()
+            .is_err());
+        assert!(CacheControl::new().immutable().build().is_err());
+    }
+}
@@ -0,0 +1,156 @@
+use crate::StaticFiles;
+use futures::future::FutureObj;
+use http::{header, HeaderValue};
+use tide::{configuration::Store, Request, Response, RouteMatch};
+
+/// Which requests are served by the canary build
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum CanaryRule {
+    /// Header `name` equals `value`, e.g. `X-Canary: 1`
+    Header { name: String, value: String },
+    /// Cookie `name` equals `value`, e.g. `canary=1`
+    Cookie { name: String, value: String },
+}
+
+impl CanaryRule {
+    pub fn header(name: impl Into<String>, value: impl Into<String>) -> Self {
+        CanaryRule::Header {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    pub fn cookie(name: impl Into<String>, value: impl Into<String>) -> Self {
+        CanaryRule::Cookie {
+            name: name.into(),
+            value: value.into(),
+        }
+    }
+
+    fn matches(&self, req: &Request) -> bool {
+        match self {
+            CanaryRule::Header { name, value } => req
+                .headers()
+                .get_all(name.as_str())
+                .iter()
+                .any(|x| x.to_str().map(|x| x.trim() == value).unwrap_or(false)),
+            CanaryRule::Cookie { name, value } => req
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|x| x.to_str().ok())
+                .any(|x| cookie_values(x, name).any(|x| x == value)),
+        }
+    }
+
+    /// Request header responses depend on, for `Vary`
+    fn vary(&self) -> &str {
+        match self {
+            CanaryRule::Header { name, .. } => name,
+            CanaryRule::Cookie { .. } => "cookie",
+        }
+    }
+}
+
+/// Serve two builds of a site from one endpoint, e.g. for canary rollouts of a frontend: requests
+/// matching the rule get the canary build, others the stable one
+///
+/// Each build keeps its own root and asset manifest, both share the content hash cache.
+/// Responses carry `Vary` naming the header (or `Cookie`) the rule looks at, so that shared
+/// caches keep the builds apart.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::{Canary, CanaryRule, StaticFiles};
+/// let files = Canary::new(
+///     CanaryRule::header("x-canary", "1"),
+///     StaticFiles::new("./dist").unwrap(),
+///     StaticFiles::new("./dist-canary").unwrap(),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct Canary {
+    rule: CanaryRule,
+    stable: StaticFiles,
+    canary: StaticFiles,
+}
+
+impl Canary {
+    pub fn new(rule: CanaryRule, stable: StaticFiles, mut canary: StaticFiles) -> Self {
+        canary.hash_cache = stable.hash_cache.clone();
+        Self {
+            rule,
+            stable,
+            canary,
+        }
+    }
+}
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for Canary {
+    type Fut = FutureObj<'static, Response>;
+
+    fn call(
+        &self,
+        data: Data,
+        req: Request,
+        params: Option<RouteMatch<'_>>,
+        store: &Store,
+    ) -> Self::Fut {
+        let files = if self.rule.matches(&req) {
+            &self.canary
+        } else {
+            &self.stable
+        };
+        let response =
+            <StaticFiles as tide::Endpoint<Data, ()>>::call(files, data, req, params, store);
+        let vary = HeaderValue::from_str(self.rule.vary()).ok();
+        FutureObj::new(Box::new(
+            async move {
+                let mut response = await!(response);
+                if let Some(vary) = vary {
+                    response.headers_mut().append(header::VARY, vary);
+                }
+                response
+            },
+        ))
+    }
+}
+
+/// Values of cookies named `name` in a `Cookie` header
+fn cookie_values<'a>(cookies: &'a str, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+    cookies.split(';').filter_map(move |pair| {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) if key.trim() == name => Some(value.trim().trim_matches('"')),
+            _ => None,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(name: &str, value: &str) -> Request {
+        http::Request::builder()
+            .header(name, value)
+            .body(http_service::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_rule() {
+        let rule = CanaryRule::header("x-canary", "1");
+        assert!(rule.matches(&request("X-Canary", " 1")));
+        assert!(!rule.matches(&request("x-canary", "0")));
+        assert!(!rule.matches(&request("cookie", "x-canary=1")));
+
+        let rule = CanaryRule::cookie("canary", "1");
+        assert!(rule.matches(&request("cookie", "a=b; canary=1")));
+        assert!(rule.matches(&request("cookie", "canary=\"1\"")));
+        assert!(!rule.matches(&request("cookie", "no-canary=1; canary=0")));
+        assert!(!rule.matches(&request("x-canary", "1")));
+        assert_eq!("cookie", rule.vary());
+    }
+}
@@ -0,0 +1,201 @@
+//! Files addressed by SHA-256 of their content, e.g. `cas/9f86d08...`, see
+//! [`StaticFiles::content_addressed`](struct.StaticFiles.html#method.content_addressed)
+
+use crate::{
+    archive::collect_entries,
+    etag::HashCache,
+    path::join_canonical,
+    vfs::Target,
+    watch::{ChangeEvent, ChangeEvents},
+};
+use futures::{executor::block_on, future, StreamExt};
+use log::warn;
+use std::{
+    collections::{BTreeSet, HashMap},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+/// SHA-256 (lower case hex) a request below `prefix` asks for, e.g. `cas/<sha256>`
+pub(crate) fn requested_hash(prefix: &str, url_path: &str) -> Option<String> {
+    let prefix = prefix.trim_matches('/');
+    let url_path = url_path.trim_start_matches('/');
+    if !url_path.starts_with(prefix) || url_path.as_bytes().get(prefix.len()) != Some(&b'/') {
+        return None;
+    }
+    let hash = &url_path[prefix.len() + 1..];
+    Some(hash.to_ascii_lowercase())
+        .filter(|x| x.len() == 64 && x.bytes().all(|x| x.is_ascii_hexdigit()))
+}
+
+#[derive(Default)]
+struct Index {
+    /// logical paths by SHA-256, files of the same content share one
+    paths: HashMap<String, BTreeSet<String>>,
+    /// SHA-256 by logical path
+    hashes: HashMap<String, String>,
+}
+
+/// SHA-256 to logical path index of files below a root, digests come from (and fill) the
+/// [`HashCache`] of `ETag`s
+#[derive(Default)]
+pub(crate) struct CasIndex {
+    scanned: AtomicBool,
+    index: Mutex<Index>,
+}
+
+impl CasIndex {
+    /// Index files below `root` by a background thread, only the first time it's called
+    ///
+    /// Hidden (dot) files are left out. Files are found by their hash once hashed.
+    pub fn scan(index: &Arc<Self>, root: PathBuf, cache: Arc<HashCache>) {
+        if index.scanned.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        let index = Arc::downgrade(index);
+        let scanning = thread::Builder::new()
+            .name("tsf-cas-scan".to_string())
+            .spawn(move || {
+                let entries = match collect_entries(&root, &[]) {
+                    Ok(x) => x,
+                    Err(error) => {
+                        warn!("failed to index {:?}: {}", root, error);
+                        return;
+                    }
+                };
+                for entry in entries {
+                    match index.upgrade() {
+                        Some(index) => index.update(&root, &entry.name, &cache),
+                        None => return,
+                    }
+                }
+            });
+        if let Err(error) = scanning {
+            warn!("failed to index content: {}", error);
+        }
+    }
+
+    /// Hash file at `logical_path` again, or forget it if it's gone
+    pub fn update(&self, root: &Path, logical_path: &str, cache: &HashCache) {
+        let digest = join_canonical(root, logical_path)
+            .and_then(|path| {
+                let metadata = path.metadata().ok().filter(|x| x.is_file())?;
+                let target = Target::Local(path);
+                cache
+                    .digests(&target, metadata.len(), metadata.modified().ok()?)
+                    .ok()
+            })
+            .map(|x| x.sha256);
+        let mut index = self.index.lock().unwrap();
+        if let Some(old) = index.hashes.remove(logical_path) {
+            let paths = index.paths.get_mut(&old).unwrap();
+            paths.remove(logical_path);
+            if paths.is_empty() {
+                index.paths.remove(&old);
+            }
+        }
+        if let Some(hash) = digest {
+            let paths = index.paths.entry(hash.clone()).or_default();
+            paths.insert(logical_path.to_string());
+            index.hashes.insert(logical_path.to_string(), hash);
+        }
+    }
+
+    /// Path of a file below `root` with content of SHA-256 `hash`, checked to still have it;
+    /// logical paths `accept` rejects are skipped
+    pub fn lookup(
+        &self,
+        root: &Path,
+        hash: &str,
+        cache: &HashCache,
+        accept: impl Fn(&str) -> bool,
+    ) -> Option<PathBuf> {
+        let candidates: Vec<String> = match self.index.lock().unwrap().paths.get(hash) {
+            Some(x) => x.iter().filter(|x| accept(x)).cloned().collect(),
+            None => return None,
+        };
+        for logical_path in candidates {
+            // digests are cached until a file changes, so this is cheap if it didn't
+            self.update(root, &logical_path, cache);
+            let index = self.index.lock().unwrap();
+            if index.hashes.get(&logical_path).map(String::as_str) == Some(hash) {
+                return join_canonical(root, &logical_path);
+            }
+        }
+        None
+    }
+
+    /// Hash files again as `events` report changes of files below `root`
+    pub fn watch(index: &Arc<Self>, root: PathBuf, cache: Arc<HashCache>, events: &ChangeEvents) {
+        let (index, receiver) = (Arc::downgrade(index), events.subscribe());
+        let watching = thread::Builder::new()
+            .name("tsf-cas-watch".to_string())
+            .spawn(move || {
+                block_on(receiver.for_each(|event: ChangeEvent| {
+                    if let Some(index) = index.upgrade() {
+                        index.update(&root, &event.path, &cache);
+                    }
+                    future::ready(())
+                }))
+            });
+        if let Err(error) = watching {
+            warn!("failed to watch for content index: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::fs;
+
+    const HELLO: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_requested_hash() {
+        let url_path = format!("cas/{}", HELLO);
+        assert_eq!(Some(HELLO.to_string()), requested_hash("cas", &url_path));
+        assert_eq!(Some(HELLO.to_string()), requested_hash("/cas/", &url_path));
+        let upper = format!("cas/{}", HELLO.to_uppercase());
+        assert_eq!(Some(HELLO.to_string()), requested_hash("cas", &upper));
+        assert_eq!(None, requested_hash("cas", &format!("cast/{}", HELLO)));
+        assert_eq!(None, requested_hash("cas", "cas/abc"));
+        assert_eq!(None, requested_hash("cas", &format!("cas/{}/x", HELLO)));
+    }
+
+    #[test]
+    fn test_cas_index() {
+        let temp = TempDir::new("cas");
+        let root = temp.canonicalize().unwrap();
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/hello.txt"), b"hello").unwrap();
+        fs::write(root.join("copy.txt"), b"hello").unwrap();
+        let cache = HashCache::default();
+        let index = CasIndex::default();
+        index.update(&root, "a/hello.txt", &cache);
+        index.update(&root, "copy.txt", &cache);
+        assert_eq!(
+            Some(root.join("a/hello.txt")),
+            index.lookup(&root, HELLO, &cache, |_| true)
+        );
+        assert_eq!(
+            Some(root.join("copy.txt")),
+            index.lookup(&root, HELLO, &cache, |x| !x.starts_with("a/"))
+        );
+
+        // changed files are found by their new content only, copies still by the old one
+        fs::write(root.join("a/hello.txt"), b"hello world").unwrap();
+        assert_eq!(
+            Some(root.join("copy.txt")),
+            index.lookup(&root, HELLO, &cache, |_| true)
+        );
+        fs::remove_file(root.join("copy.txt")).unwrap();
+        assert_eq!(None, index.lookup(&root, HELLO, &cache, |_| true));
+        assert_eq!(1, index.index.lock().unwrap().paths.len());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::{
+    error::TSFResult,
+    etag::HashCache,
+    options::Settings,
+    path::{join_canonical, normalize_path},
+    utils::{get_header, ErrorResponse},
+    vfs::Target,
+    StaticFiles,
+};
+use http::{header, StatusCode};
+use http_service::Body;
+use log::error;
+use sha2::{Digest, Sha256};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use tide::{IntoResponse, Request, Response};
+
+/// Name of the generated checksum manifest of a directory
+pub const SHA256SUMS: &str = "SHA256SUMS";
+
+impl StaticFiles {
+    /// Return the directory whose checksum manifest is asked by given url path, if enabled
+    ///
+    /// A real file named `SHA256SUMS` takes precedence, so this is only checked when no
+    /// file was found.
+    pub(crate) fn checksums_dir(
+        &self,
+        settings: &Settings,
+        root: &Path,
+        url_path: &str,
+    ) -> Option<PathBuf> {
+        if !settings.options.sha256sums || self.backend.is_some() {
+            return None;
+        }
+        let path = normalize_path(url_path)?;
+        let parent = match path.rfind('/') {
+            Some(index) if &path[index + 1..] == SHA256SUMS => &path[..index],
+            None if path == SHA256SUMS => "",
+            _ => return None,
+        };
+        join_canonical(root, parent).filter(|x| x.is_dir())
+    }
+
+    pub(crate) fn checksums_response(&self, dir: &Path, req: &Request) -> Response {
+        let sums = match sha256sums(dir, &self.hash_cache) {
+            Ok(x) => x,
+            Err(error) => {
+                error!("unexpected error occurred: {:?}", error);
+                return ErrorResponse::Unexpected.into_response();
+            }
+        };
+        let etag = format!("{:x}", Sha256::digest(sums.as_bytes()));
+
+        let mut response = http::Response::builder();
+        response
+            .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.to_string())
+            .header(header::ETAG, etag.as_str());
+        let not_modified = Self::should_cache(
+            req.method(),
+            None,
+            get_header(req, header::IF_NONE_MATCH),
+            UNIX_EPOCH,
+            &etag,
+        );
+        if not_modified {
+            return response
+                .status(StatusCode::NOT_MODIFIED)
+                .body(Body::empty())
+                .unwrap();
+        }
+        response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, sums.len() as u64)
+            .body(sums.into())
+            .unwrap()
+    }
+}
+
+/// Generate checksum manifest of regular files directly inside `dir`, in the format of
+/// `sha256sum`, digests are cached until files change
+///
+/// Symbolic links and files whose name is not printable as one line are skipped.
+fn sha256sums(dir: &Path, cache: &HashCache) -> TSFResult<String> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() {
+            continue;
+        }
+        let name = match entry.file_name().into_string() {
+            Ok(ref x) if x.contains(|c: char| c == '\n' || c == '\r' || c == '\\') => continue,
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        files.push((name, entry.path()));
+    }
+    files.sort();
+
+    let mut sums = String::new();
+    for (name, path) in files {
+        let target = Target::Local(path);
+        let meta = target.metadata()?;
+        let digests = cache.digests(&target, meta.size, meta.modified)?;
+        sums.push_str(&format!("{}  {}\n", digests.sha256, name));
+    }
+    Ok(sums)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_sha256sums() {
+        let dir = TempDir::new("sums");
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("b.txt"), b"abc").unwrap();
+        fs::write(dir.join("a.txt"), b"").unwrap();
+        fs::write(dir.join("sub/c.txt"), b"ignored").unwrap();
+
+        let cache = HashCache::default();
+        assert_eq!(
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855  a.txt\n\
+             ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad  b.txt\n",
+            sha256sums(&dir, &cache).unwrap()
+        );
+    }
+}
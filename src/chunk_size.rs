@@ -0,0 +1,135 @@
+//! Size of chunks read for a response body, adapted to how fast the client consumes them
+//!
+//! The time from handing out a chunk until the body is polled again is how long the consumer
+//! (socket, proxy, client) took for it. Chunks are sized to be consumed in about
+//! [`TARGET_CONSUME_TIME`], so that slow clients don't pin megabytes of buffers per connection
+//! while fast ones aren't slowed down by many small reads.
+
+use crate::utils::MAX_BUFFER_SIZE;
+use std::time::{Duration, Instant};
+
+/// Default smallest chunk
+pub(crate) const MIN_CHUNK_SIZE: usize = 16 * 1024;
+
+/// Size of the first chunk, before anything is known about the consumer
+const INITIAL_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Time a chunk should take to be consumed
+const TARGET_CONSUME_TIME: Duration = Duration::from_millis(50);
+
+/// Factor a chunk grows or shrinks by at most, so that a single hiccup doesn't swing it
+const MAX_STEP: usize = 4;
+
+#[derive(Debug, Clone)]
+pub(crate) struct ChunkSizer {
+    min: usize,
+    max: usize,
+    next: usize,
+    /// size of the last chunk handed out and when
+    yielded: Option<(usize, Instant)>,
+}
+
+impl Default for ChunkSizer {
+    fn default() -> Self {
+        Self::new(MIN_CHUNK_SIZE, MAX_BUFFER_SIZE)
+    }
+}
+
+impl ChunkSizer {
+    /// Chunks between `min` and `max` bytes, a `max` below `min` is raised to it
+    pub fn new(min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        let max = max.max(min);
+        Self {
+            min,
+            max,
+            next: INITIAL_CHUNK_SIZE.max(min).min(max),
+            yielded: None,
+        }
+    }
+
+    /// Smallest chunk
+    pub fn min(&self) -> usize {
+        self.min
+    }
+
+    /// Record a chunk of `size` bytes handed out to the consumer at `now`
+    pub fn yielded(&mut self, size: usize, now: Instant) {
+        self.yielded = Some((size, now));
+    }
+
+    /// Size of the next chunk, asked for at `now` as the consumer wants more
+    pub fn next_size(&mut self, now: Instant) -> usize {
+        let (size, yielded) = match self.yielded.take() {
+            Some(x) => x,
+            None => return self.next,
+        };
+        let elapsed = now.duration_since(yielded).as_nanos().max(1);
+        let estimate = size as u128 * TARGET_CONSUME_TIME.as_nanos() / elapsed;
+        let estimate = estimate.min(self.max as u128) as usize;
+        self.next = estimate
+            .max(self.next / MAX_STEP)
+            .min(self.next.saturating_mul(MAX_STEP))
+            .max(self.min)
+            .min(self.max);
+        self.next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sizes of chunks consumed in `elapsed` each
+    fn sizes(sizer: &mut ChunkSizer, elapsed: Duration, count: usize) -> Vec<usize> {
+        let mut now = Instant::now();
+        (0..count)
+            .map(|_| {
+                let size = sizer.next_size(now);
+                sizer.yielded(size, now);
+                now += elapsed;
+                size
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_chunk_sizer() {
+        // fast consumers ramp up to the maximum, by steps
+        let mut sizer = ChunkSizer::default();
+        assert_eq!(
+            vec![64 << 10, 256 << 10, 1 << 20, 4 << 20, 4 << 20],
+            sizes(&mut sizer, Duration::from_micros(100), 5)
+        );
+
+        // slow ones settle at what they consume in the target time, 20 KiB/s here
+        let mut sizer = ChunkSizer::default();
+        assert_eq!(
+            vec![64 << 10, 16 << 10, 16 << 10],
+            sizes(&mut sizer, Duration::from_millis(3200), 3)
+        );
+        let mut sizer = ChunkSizer::new(1024, 1 << 20);
+        let settled = *sizes(&mut sizer, Duration::from_millis(3200), 6)
+            .last()
+            .unwrap();
+        assert_eq!(1024, settled);
+
+        // a consumer of 1 MiB/s gets chunks of ~50 KiB
+        let mut sizer = ChunkSizer::new(1024, 1 << 20);
+        let mut now = Instant::now();
+        for _ in 0..8 {
+            let size = sizer.next_size(now);
+            sizer.yielded(size, now);
+            now += Duration::from_micros(size as u64 * 1_000_000 / (1 << 20));
+        }
+        let size = sizer.next_size(now);
+        assert!(size > 51_000 && size < 53_000, "{}", size);
+
+        // bounds are kept, whatever they are
+        let mut sizer = ChunkSizer::new(4096, 10);
+        assert_eq!(
+            vec![4096, 4096],
+            sizes(&mut sizer, Duration::from_secs(1), 2)
+        );
+    }
+}
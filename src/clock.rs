@@ -0,0 +1,29 @@
+use std::time::SystemTime;
+
+/// Source of current time, used for `Date` header and validator strength
+///
+/// Override it (e.g. by [`FixedClock`](struct.FixedClock.html)) to make responses
+/// reproducible in tests.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> SystemTime;
+}
+
+/// Current system time (default)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// Always the same time
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub SystemTime);
+
+impl Clock for FixedClock {
+    fn now(&self) -> SystemTime {
+        self.0
+    }
+}
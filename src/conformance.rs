@@ -0,0 +1,727 @@
+//! Table driven conformance suite for conditional (RFC 7232) and range (RFC 7233) requests
+//!
+//! Every case is a request against one file with fixed content and validators, and the expected
+//! status, headers and byte-exact body. A header expected as `None` must be absent.
+
+use crate::{
+    test_util::client, vfs::Embedded, DotfilePolicy, FixedBoundary, FixedClock, RandomBoundary,
+    RangeUnitPolicy, StaticFiles, WidthVariants,
+};
+use http::{header::HeaderName, HeaderValue};
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+const CONTENT: &[u8] = b"0123456789abcdef";
+
+/// SHA-256 of content, as a macro to be usable in `concat!`
+macro_rules! etag {
+    () => {
+        "9f9f5111f7b27a781f1f1ddde5ebc2dd2b796bfc7365c9c28b548e564176929f"
+    };
+}
+
+const ETAG: &str = etag!();
+const LAST_MODIFIED: &str = "Sun, 09 Sep 2001 01:46:40 GMT";
+const EARLIER: &str = "Sat, 08 Sep 2001 01:46:40 GMT";
+const NOW: &str = "Fri, 13 Feb 2009 23:31:30 GMT";
+
+struct Case {
+    name: &'static str,
+    request: &'static [(&'static str, &'static str)],
+    status: u16,
+    headers: &'static [(&'static str, Option<&'static str>)],
+    body: &'static str,
+}
+
+const CASES: &[Case] = &[
+    Case {
+        name: "plain",
+        request: &[],
+        status: 200,
+        headers: &[
+            ("date", Some(NOW)),
+            ("etag", Some(ETAG)),
+            ("last-modified", Some(LAST_MODIFIED)),
+            ("accept-ranges", Some("bytes")),
+            ("content-type", Some("text/plain")),
+            ("content-length", Some("16")),
+            (
+                "content-disposition",
+                Some("inline; filename*=\"hello.txt\""),
+            ),
+            ("content-range", None),
+        ],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-none-match matched",
+        request: &[("if-none-match", ETAG)],
+        status: 304,
+        headers: &[("date", Some(NOW)), ("etag", Some(ETAG))],
+        body: "",
+    },
+    Case {
+        name: "if-none-match matched in list",
+        request: &[("if-none-match", concat!("\"x\", ", etag!()))],
+        status: 304,
+        headers: &[],
+        body: "",
+    },
+    Case {
+        name: "if-none-match not matched",
+        request: &[("if-none-match", "other")],
+        status: 200,
+        headers: &[],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-modified-since matched",
+        request: &[("if-modified-since", LAST_MODIFIED)],
+        status: 304,
+        headers: &[],
+        body: "",
+    },
+    Case {
+        name: "if-modified-since earlier",
+        request: &[("if-modified-since", EARLIER)],
+        status: 200,
+        headers: &[],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-none-match takes precedence over if-modified-since",
+        request: &[
+            ("if-none-match", "other"),
+            ("if-modified-since", LAST_MODIFIED),
+        ],
+        status: 200,
+        headers: &[],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-none-match matched with range",
+        request: &[("if-none-match", ETAG), ("range", "bytes=0-3")],
+        status: 304,
+        headers: &[("etag", Some(ETAG)), ("content-range", None)],
+        body: "",
+    },
+    Case {
+        name: "if-none-match weakly matched with range",
+        request: &[
+            ("if-none-match", concat!("W/\"", etag!(), "\"")),
+            ("range", "bytes=0-3"),
+        ],
+        status: 304,
+        headers: &[("content-range", None)],
+        body: "",
+    },
+    Case {
+        name: "if-none-match matched with range and if-range",
+        request: &[
+            ("if-none-match", ETAG),
+            ("range", "bytes=0-3"),
+            ("if-range", ETAG),
+        ],
+        status: 304,
+        headers: &[("content-range", None)],
+        body: "",
+    },
+    Case {
+        name: "if-none-match matched with unsatisfiable range",
+        request: &[("if-none-match", ETAG), ("range", "bytes=100-")],
+        status: 304,
+        headers: &[("content-range", None)],
+        body: "",
+    },
+    Case {
+        name: "if-modified-since matched with range",
+        request: &[("if-modified-since", LAST_MODIFIED), ("range", "bytes=0-3")],
+        status: 304,
+        headers: &[("content-range", None)],
+        body: "",
+    },
+    Case {
+        name: "if-none-match not matched with range",
+        request: &[("if-none-match", "other"), ("range", "bytes=0-3")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-3/16"))],
+        body: "0123",
+    },
+    Case {
+        name: "single range",
+        request: &[("range", "bytes=0-3")],
+        status: 206,
+        headers: &[
+            ("content-range", Some("bytes 0-3/16")),
+            ("content-length", Some("4")),
+            ("content-type", Some("text/plain")),
+            ("etag", Some(ETAG)),
+        ],
+        body: "0123",
+    },
+    Case {
+        name: "suffix range",
+        request: &[("range", "bytes=-4")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 12-15/16"))],
+        body: "cdef",
+    },
+    Case {
+        name: "open range",
+        request: &[("range", "bytes=10-")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 10-15/16"))],
+        body: "abcdef",
+    },
+    Case {
+        name: "range end clamped",
+        request: &[("range", "bytes=12-100")],
+        status: 206,
+        headers: &[
+            ("content-range", Some("bytes 12-15/16")),
+            ("content-length", Some("4")),
+        ],
+        body: "cdef",
+    },
+    Case {
+        name: "range end at u64::MAX",
+        request: &[("range", "bytes=2-18446744073709551615")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 2-15/16"))],
+        body: "23456789abcdef",
+    },
+    Case {
+        name: "range start beyond u64::MAX",
+        request: &[("range", "bytes=99999999999999999999-")],
+        status: 416,
+        headers: &[("content-range", Some("bytes */16"))],
+        body: "requested range not satisfiable",
+    },
+    Case {
+        name: "range covering whole file",
+        request: &[("range", "bytes=0-")],
+        status: 200,
+        headers: &[("content-range", None), ("content-length", Some("16"))],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "overlapping ranges merged",
+        request: &[("range", "bytes=0-3,2-5")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-5/16"))],
+        body: "012345",
+    },
+    Case {
+        name: "multiple ranges",
+        request: &[("range", "bytes=0-1,4-5")],
+        status: 206,
+        headers: &[
+            (
+                "content-type",
+                Some("multipart/byteranges; boundary=DCjanus"),
+            ),
+            ("content-length", Some("159")),
+            ("content-range", None),
+        ],
+        body: concat!(
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 0-1/16\r\n",
+            "\r\n",
+            "01",
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 4-5/16\r\n",
+            "\r\n",
+            "45",
+            "\r\n--DCjanus--\r\n",
+        ),
+    },
+    Case {
+        name: "multiple range header fields",
+        request: &[("range", "bytes=0-1"), ("range", "bytes=4-5")],
+        status: 206,
+        headers: &[("content-length", Some("159"))],
+        body: concat!(
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 0-1/16\r\n",
+            "\r\n",
+            "01",
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 4-5/16\r\n",
+            "\r\n",
+            "45",
+            "\r\n--DCjanus--\r\n",
+        ),
+    },
+    Case {
+        name: "multiple range header fields merged",
+        request: &[("range", "bytes=0-3"), ("range", "bytes=2-5")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-5/16"))],
+        body: "012345",
+    },
+    Case {
+        name: "unsatisfiable range",
+        request: &[("range", "bytes=16-")],
+        status: 416,
+        headers: &[("content-range", Some("bytes */16"))],
+        body: "requested range not satisfiable",
+    },
+    Case {
+        name: "empty suffix range",
+        request: &[("range", "bytes=-0")],
+        status: 416,
+        headers: &[("content-range", Some("bytes */16"))],
+        body: "requested range not satisfiable",
+    },
+    Case {
+        name: "all ranges unsatisfiable",
+        request: &[("range", "bytes=16-20,-0,30-")],
+        status: 416,
+        headers: &[("content-range", Some("bytes */16"))],
+        body: "requested range not satisfiable",
+    },
+    Case {
+        name: "unsatisfiable ranges ignored in set",
+        request: &[("range", "bytes=16-20,-0,2-3")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 2-3/16"))],
+        body: "23",
+    },
+    Case {
+        name: "suffix range longer than file",
+        request: &[("range", "bytes=-100")],
+        status: 206,
+        headers: &[
+            ("content-range", Some("bytes 0-15/16")),
+            ("content-length", Some("16")),
+        ],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "suffix range as long as file",
+        request: &[("range", "bytes=-16")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-15/16"))],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "unknown range unit ignored",
+        request: &[("range", "lines=1-2")],
+        status: 200,
+        headers: &[("content-range", None)],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "unknown range unit ignored next to bytes",
+        request: &[("range", "lines=1-2"), ("range", "bytes=0-3")],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-3/16"))],
+        body: "0123",
+    },
+    Case {
+        name: "malformed byte range",
+        request: &[("range", "bytes=nothing")],
+        status: 400,
+        headers: &[],
+        body: "failed to parse request header: Range",
+    },
+    Case {
+        name: "if-range entity tag matched",
+        request: &[("range", "bytes=0-3"), ("if-range", ETAG)],
+        status: 206,
+        headers: &[("content-range", Some("bytes 0-3/16"))],
+        body: "0123",
+    },
+    Case {
+        name: "if-range entity tag stale",
+        request: &[("range", "bytes=0-3"), ("if-range", "stale")],
+        status: 200,
+        headers: &[("content-range", None)],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-range weak entity tag",
+        request: &[("range", "bytes=0-3"), ("if-range", concat!("W/", etag!()))],
+        status: 200,
+        headers: &[],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-range date matched",
+        request: &[("range", "bytes=0-3"), ("if-range", LAST_MODIFIED)],
+        status: 206,
+        headers: &[],
+        body: "0123",
+    },
+    Case {
+        name: "if-range date stale",
+        request: &[("range", "bytes=0-3"), ("if-range", EARLIER)],
+        status: 200,
+        headers: &[],
+        body: "0123456789abcdef",
+    },
+    Case {
+        name: "if-match failed",
+        request: &[("range", "bytes=0-3"), ("if-match", "other")],
+        status: 412,
+        headers: &[],
+        body: "precondition failed",
+    },
+    Case {
+        name: "if-unmodified-since failed",
+        request: &[("range", "bytes=0-3"), ("if-unmodified-since", EARLIER)],
+        status: 412,
+        headers: &[],
+        body: "precondition failed",
+    },
+    Case {
+        name: "if-match passed",
+        request: &[("range", "bytes=0-3"), ("if-match", ETAG)],
+        status: 206,
+        headers: &[],
+        body: "0123",
+    },
+];
+
+fn files() -> StaticFiles {
+    let embedded = Embedded::new()
+        .file("hello.txt", CONTENT)
+        .file("empty.txt", b"")
+        .modified(UNIX_EPOCH + Duration::from_secs(1_000_000_000));
+    StaticFiles::from_backend(embedded)
+        .clock(Arc::new(FixedClock(
+            UNIX_EPOCH + Duration::from_secs(1_234_567_890),
+        )))
+        .boundary_generator(Arc::new(FixedBoundary::new("DCjanus")))
+}
+
+#[test]
+fn test_conformance() {
+    let mut client = client(files());
+    for case in CASES {
+        let mut request = client.get("/hello.txt");
+        for (name, value) in case.request {
+            request = request.header(*name, value);
+        }
+        let response = request.send();
+
+        assert_eq!(case.status, response.status.as_u16(), "{}", case.name);
+        for (name, value) in case.headers {
+            assert_eq!(*value, response.header(name), "{}: {}", case.name, name);
+        }
+        assert_eq!(case.body, response.text(), "{}", case.name);
+    }
+}
+
+#[test]
+fn test_reject_unknown_range_unit() {
+    let mut client = client(files().range_units(RangeUnitPolicy::Reject));
+    let response = client.get("/hello.txt").header("range", "lines=1-2").send();
+    assert_eq!(400, response.status.as_u16());
+    let response = client.get("/hello.txt").header("range", "bytes=0-1").send();
+    assert_eq!(206, response.status.as_u16());
+    let response = client
+        .get("/hello.txt")
+        .header("range", "bytes=0-1")
+        .header("range", "lines=1-2")
+        .send();
+    assert_eq!(400, response.status.as_u16());
+}
+
+#[test]
+fn test_empty_file() {
+    const EMPTY_ETAG: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+    let mut client = client(files());
+
+    let response = client.get("/empty.txt").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!(Some("0"), response.header("content-length"));
+    assert_eq!(Some(EMPTY_ETAG), response.header("etag"));
+    assert_eq!(Some(LAST_MODIFIED), response.header("last-modified"));
+    assert!(response.body.is_empty());
+
+    let response = client.head("/empty.txt").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!(Some("0"), response.header("content-length"));
+
+    let response = client
+        .get("/empty.txt")
+        .header("if-none-match", EMPTY_ETAG)
+        .send();
+    assert_eq!(304, response.status.as_u16());
+
+    let response = client.get("/empty.txt").header("range", "bytes=0-0").send();
+    assert_eq!(416, response.status.as_u16());
+    assert_eq!(Some("bytes */0"), response.header("content-range"));
+
+    let response = client.get("/empty.txt").header("range", "bytes=-5").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!(Some("0"), response.header("content-length"));
+    assert!(response.body.is_empty());
+}
+
+#[test]
+fn test_robots_and_dotfiles() {
+    let files = StaticFiles::from_backend(
+        Embedded::new()
+            .file("robots.txt", b"ignored")
+            .file(".env", b"secret")
+            .file(".well-known/security.txt", b"Contact: x"),
+    )
+    .robots_txt("User-agent: *\nDisallow: /\n")
+    .dotfiles(DotfilePolicy::DenyExceptWellKnown);
+    let mut client = client(files);
+
+    let response = client.get("/robots.txt").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!("User-agent: *\nDisallow: /\n", response.text());
+    assert_eq!(404, client.get("/.env").send().status.as_u16());
+    assert_eq!(404, client.get("/%2eenv").send().status.as_u16());
+    let response = client.get("/.well-known/security.txt").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!("Contact: x", response.text());
+}
+
+#[test]
+fn test_map_response() {
+    let files = files().map_response(|path, parts| {
+        let value = format!("{}; {}", path.display(), parts.status.as_u16());
+        parts.headers.insert("x-mapped", value.parse().unwrap());
+    });
+    let mut client = client(files);
+
+    let response = client.get("/hello.txt").header("range", "bytes=0-1").send();
+    assert_eq!(Some("hello.txt; 206"), response.header("x-mapped"));
+    assert_eq!("01", response.text());
+    let response = client
+        .get("/hello.txt")
+        .header("if-none-match", ETAG)
+        .send();
+    assert_eq!(Some("hello.txt; 304"), response.header("x-mapped"));
+    assert_eq!(None, client.get("/no-such-file").send().header("x-mapped"));
+}
+
+#[test]
+fn test_guard() {
+    let files = files().guard(|_: &(), req| req.headers().contains_key("x-allow"));
+    let mut client = client(files);
+    assert_eq!(403, client.get("/hello.txt").send().status.as_u16());
+    let response = client.get("/hello.txt").header("x-allow", "1").send();
+    assert_eq!(200, response.status.as_u16());
+
+    // app data of another type than expected
+    let files = files().guard(|_: &u32, _| true);
+    let response = client(files)
+        .get("/hello.txt")
+        .header("x-allow", "1")
+        .send();
+    assert_eq!(403, response.status.as_u16());
+}
+
+#[test]
+fn test_common_headers() {
+    let files = files().security_headers(true).header(
+        HeaderName::from_static("x-served-by"),
+        HeaderValue::from_static("tsf"),
+    );
+    let mut client = client(files);
+    let responses = vec![
+        client.get("/hello.txt").send(),
+        client
+            .get("/hello.txt")
+            .header("if-none-match", ETAG)
+            .send(),
+        client.get("/no-such-file").send(),
+        client
+            .get("/hello.txt")
+            .header("range", "bytes=100-")
+            .send(),
+    ];
+    let statuses: Vec<_> = responses.iter().map(|x| x.status.as_u16()).collect();
+    assert_eq!(vec![200, 304, 404, 416], statuses);
+    for response in responses {
+        assert_eq!(Some("bytes"), response.header("accept-ranges"));
+        assert_eq!(Some("nosniff"), response.header("x-content-type-options"));
+        assert_eq!(Some("tsf"), response.header("x-served-by"));
+    }
+}
+
+#[test]
+fn test_clamp_last_modified() {
+    // one second before files were modified
+    let clock = Arc::new(FixedClock(UNIX_EPOCH + Duration::from_secs(999_999_999)));
+    const CLAMPED: &str = "Sun, 09 Sep 2001 01:46:39 GMT";
+    const WEAK_ETAG: &str = concat!("W/", etag!());
+
+    let response = client(files().clock(clock.clone()))
+        .get("/hello.txt")
+        .send();
+    assert_eq!(Some(LAST_MODIFIED), response.header("last-modified"));
+    assert_eq!(Some(ETAG), response.header("etag"));
+
+    let mut client = client(files().clock(clock).clamp_last_modified(true));
+    let response = client.get("/hello.txt").send();
+    assert_eq!(200, response.status.as_u16());
+    assert_eq!(Some(CLAMPED), response.header("last-modified"));
+    assert_eq!(Some(WEAK_ETAG), response.header("etag"));
+
+    let response = client
+        .get("/hello.txt")
+        .header("if-none-match", WEAK_ETAG)
+        .send();
+    assert_eq!(304, response.status.as_u16());
+
+    // strong comparisons fail on weak tags
+    let response = client
+        .get("/hello.txt")
+        .header("range", "bytes=0-3")
+        .header("if-range", ETAG)
+        .send();
+    assert_eq!(200, response.status.as_u16());
+    let response = client
+        .get("/hello.txt")
+        .header("range", "bytes=0-3")
+        .header("if-match", ETAG)
+        .send();
+    assert_eq!(412, response.status.as_u16());
+}
+
+#[test]
+fn test_random_boundary() {
+    let files = files().boundary_generator(Arc::new(RandomBoundary));
+    let response = client(files)
+        .get("/hello.txt")
+        .header("range", "bytes=0-1,-2")
+        .send();
+    assert_eq!(206, response.status.as_u16());
+    let boundary = response.boundary().unwrap();
+    assert_eq!(
+        Some(format!("multipart/byteranges; boundary={}", boundary).as_str()),
+        response.header("content-type")
+    );
+    let expected = format!(
+        concat!(
+            "\r\n--{0}\r\ncontent-type: text/plain\r\ncontent-range: bytes 0-1/16\r\n\r\n01",
+            "\r\n--{0}\r\ncontent-type: text/plain\r\ncontent-range: bytes 14-15/16\r\n\r\nef",
+            "\r\n--{0}--\r\n",
+        ),
+        boundary
+    );
+    assert_eq!(expected, response.text());
+}
+
+#[test]
+fn test_media() {
+    let embedded = Embedded::new()
+        .file("live/index.m3u8", b"#EXTM3U\n")
+        .file("live/0.ts", CONTENT);
+    let mut client = client(StaticFiles::from_backend(embedded).media_cors(true));
+    let response = client.get("/live/index.m3u8").send();
+    assert_eq!(
+        Some("application/vnd.apple.mpegurl"),
+        response.header("content-type")
+    );
+    assert_eq!(Some("no-cache"), response.header("cache-control"));
+    assert_eq!(Some("*"), response.header("access-control-allow-origin"));
+    assert!(response
+        .header("content-disposition")
+        .unwrap()
+        .starts_with("inline"));
+
+    let response = client.get("/live/0.ts").header("range", "bytes=0-3").send();
+    assert_eq!(206, response.status.as_u16());
+    assert_eq!(Some("video/mp2t"), response.header("content-type"));
+    assert_eq!(None, response.header("cache-control"));
+    assert_eq!(Some("*"), response.header("access-control-allow-origin"));
+
+    let response = client.get("/no-such-file.ts").send();
+    assert_eq!(None, response.header("access-control-allow-origin"));
+}
+
+#[test]
+fn test_width_variants() {
+    let embedded = Embedded::new()
+        .file("photos/cat.jpg", b"original")
+        .file("photos/cat.w320.jpg", b"small");
+    let files = StaticFiles::from_backend(embedded)
+        .variant_resolver(Arc::new(WidthVariants::new(vec![320, 640])));
+    let mut client = client(files);
+
+    let response = client.get("/photos/cat.jpg?w=320").send();
+    assert_eq!("small", response.text());
+    assert_eq!(
+        Some("/photos/cat.w320.jpg"),
+        response.header("content-location")
+    );
+    assert_eq!(None, response.header("cache-control"));
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+
+    let response = client.get("/photos/cat.jpg?w=640").send();
+    assert_eq!("original", response.text());
+    assert_eq!(Some("no-cache"), response.header("cache-control"));
+
+    let response = client.get("/photos/cat.jpg").send();
+    assert_eq!("original", response.text());
+    assert_eq!(None, response.header("cache-control"));
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+
+    let response = client
+        .get("/photos/cat.jpg")
+        .header("sec-ch-width", "200")
+        .send();
+    assert_eq!("small", response.text());
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+}
+
+#[test]
+fn test_cache_busting() {
+    let embedded = Embedded::new()
+        .file("app.js", b"app")
+        .file("photos/cat.jpg", b"original")
+        .file("photos/cat.w320.jpg", b"small");
+    let files = StaticFiles::from_backend(embedded)
+        .cache_busting_param("v")
+        .cache_busting_rule(|_, version| version == "3")
+        .variant_resolver(Arc::new(WidthVariants::new(vec![320, 640])));
+    let mut client = client(files);
+
+    let response = client.get("/app.js?v=3").send();
+    assert_eq!("app", response.text());
+    assert_eq!(
+        Some("public, max-age=31536000, immutable"),
+        response.header("cache-control")
+    );
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+
+    let response = client.get("/app.js?v=2").send();
+    assert_eq!("app", response.text());
+    assert_eq!(None, response.header("cache-control"));
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+
+    let response = client
+        .get("/photos/cat.jpg?v=3")
+        .header("sec-ch-width", "200")
+        .send();
+    assert_eq!("small", response.text());
+    assert_eq!(
+        Some("public, max-age=31536000, immutable"),
+        response.header("cache-control")
+    );
+    assert_eq!(Some("sec-ch-width"), response.header("vary"));
+
+    let response = client.get("/photos/cat.jpg?v=3&w=640").send();
+    assert_eq!("original", response.text());
+    assert_eq!(Some("no-cache"), response.header("cache-control"));
+}
+
+#[test]
+fn test_not_found() {
+    let response = client(files()).get("/no-such-file").send();
+    assert_eq!(404, response.status.as_u16());
+}
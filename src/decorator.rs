@@ -0,0 +1,284 @@
+use crate::{
+    error::{InvalidCacheAge, InvalidHeader},
+    extensions::Generated,
+    options::Options,
+    Metrics, TSFResult,
+};
+use http::{
+    header::{self, HeaderName},
+    HeaderValue, StatusCode,
+};
+use log::warn;
+use tide::Response;
+
+/// Headers sent when `Options::security_headers` is on
+pub(crate) const SECURITY_HEADERS: &[(&str, &str)] = &[
+    ("x-content-type-options", "nosniff"),
+    ("x-frame-options", "SAMEORIGIN"),
+    ("referrer-policy", "strict-origin-when-cross-origin"),
+];
+
+/// `Cache-Control` of shared caches, honored by Fastly, Akamai and Varnish and stripped before
+/// the browser
+const SURROGATE_CONTROL: &str = "surrogate-control";
+
+/// `Cache-Control` of CDNs, honored by Cloudflare and CloudFront,
+/// ref: https://tools.ietf.org/html/rfc9213
+const CDN_CACHE_CONTROL: &str = "cdn-cache-control";
+
+/// Headers every response of a file request carries, whatever its status: `Accept-Ranges`
+/// (omitted for [`Generated`] content), security headers and configured extra headers
+///
+/// Headers already set by the response are kept, e.g. `Cache-Control` of fingerprinted files.
+/// Cache lifetimes are clamped to configured bounds last, whoever set them.
+pub(crate) struct ResponseDecorator<'a> {
+    options: &'a Options,
+    metrics: Option<&'a dyn Metrics>,
+}
+
+impl<'a> ResponseDecorator<'a> {
+    pub fn new(options: &'a Options, metrics: Option<&'a dyn Metrics>) -> Self {
+        Self { options, metrics }
+    }
+
+    pub fn decorate(&self, mut response: Response) -> Response {
+        // edges should only keep what browsers may keep
+        let cacheable =
+            response.status().is_success() || response.status() == StatusCode::NOT_MODIFIED;
+        let generated = response.extensions().get::<Generated>().is_some();
+        let headers = response.headers_mut();
+        if generated {
+            headers.remove(header::ACCEPT_RANGES);
+        } else {
+            headers
+                .entry(header::ACCEPT_RANGES)
+                .unwrap()
+                .or_insert_with(|| HeaderValue::from_static("bytes"));
+        }
+        let security_headers = SECURITY_HEADERS
+            .iter()
+            .filter(|_| self.options.security_headers);
+        let extra_headers = self
+            .options
+            .headers
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()));
+        // configured headers override security headers of same name
+        for (name, value) in extra_headers.chain(security_headers.cloned()) {
+            match parse_header(name, value) {
+                Ok((name, value)) => {
+                    headers.entry(name).unwrap().or_insert(value);
+                }
+                // validated when loading options, unreachable
+                Err(error) => warn!("skip header: {}", error),
+            }
+        }
+        let edge_headers = vec![
+            (SURROGATE_CONTROL, &self.options.surrogate_control),
+            (CDN_CACHE_CONTROL, &self.options.cdn_cache_control),
+        ];
+        for (name, value) in edge_headers.into_iter().filter(|_| cacheable) {
+            let value = match value {
+                Some(x) => x,
+                None => continue,
+            };
+            match parse_header(name, value) {
+                Ok((name, value)) => {
+                    headers.entry(name).unwrap().or_insert(value);
+                }
+                // validated when loading options, unreachable
+                Err(error) => warn!("skip header: {}", error),
+            }
+        }
+        if self.options.min_max_age.is_some() || self.options.max_max_age.is_some() {
+            for name in &["cache-control", SURROGATE_CONTROL, CDN_CACHE_CONTROL] {
+                if let Some(value) = headers.get_mut(*name) {
+                    if let Some(clamped) = self.clamp_cache_control(value) {
+                        *value = clamped;
+                    }
+                }
+            }
+        }
+        response
+    }
+
+    /// Cache control value with `max-age` and `s-maxage` within bounds, `None` if already within
+    fn clamp_cache_control(&self, value: &HeaderValue) -> Option<HeaderValue> {
+        let min = self.options.min_max_age.unwrap_or(0);
+        let max = self.options.max_max_age.unwrap_or(u64::max_value());
+        let mut clamped = false;
+        let directives: Vec<String> = value
+            .to_str()
+            .ok()?
+            .split(',')
+            .map(|directive| {
+                let directive = directive.trim();
+                let mut parts = directive.splitn(2, '=');
+                let (name, age) = match (parts.next(), parts.next()) {
+                    (Some(name), Some(age)) => (name.trim(), age.trim().trim_matches('"')),
+                    _ => return directive.to_string(),
+                };
+                let is_age =
+                    name.eq_ignore_ascii_case("max-age") || name.eq_ignore_ascii_case("s-maxage");
+                let age: u64 = match age.parse() {
+                    Ok(x) if is_age => x,
+                    _ => return directive.to_string(),
+                };
+                let bounded = age.max(min).min(max);
+                if bounded == age {
+                    return directive.to_string();
+                }
+                warn!(
+                    "clamp cache lifetime {} from {}s to {}s",
+                    name, age, bounded
+                );
+                if let Some(metrics) = self.metrics {
+                    metrics.cache_age_clamped(age, bounded);
+                }
+                clamped = true;
+                format!("{}={}", name, bounded)
+            })
+            .collect();
+        if !clamped {
+            return None;
+        }
+        HeaderValue::from_str(&directives.join(", ")).ok()
+    }
+}
+
+/// Check configured bounds of cache lifetimes
+pub(crate) fn validate_cache_ages(options: &Options) -> TSFResult<()> {
+    match (options.min_max_age, options.max_max_age) {
+        (Some(min), Some(max)) if min > max => Err(InvalidCacheAge(min, max).into()),
+        _ => Ok(()),
+    }
+}
+
+/// Check configured extra headers
+pub(crate) fn validate_headers(options: &Options) -> TSFResult<()> {
+    for (name, value) in &options.headers {
+        parse_header(name, value)?;
+    }
+    if let Some(value) = &options.surrogate_control {
+        parse_header(SURROGATE_CONTROL, value)?;
+    }
+    if let Some(value) = &options.cdn_cache_control {
+        parse_header(CDN_CACHE_CONTROL, value)?;
+    }
+    Ok(())
+}
+
+fn parse_header(name: &str, value: &str) -> Result<(HeaderName, HeaderValue), InvalidHeader> {
+    let name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| InvalidHeader(format!("bad name {:?}", name)))?;
+    let value = HeaderValue::from_str(value)
+        .map_err(|_| InvalidHeader(format!("bad value {:?} of {}", value, name)))?;
+    Ok((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Served;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_decorate() {
+        let mut options = Options::default();
+        options.security_headers = true;
+        options
+            .headers
+            .insert("X-Frame-Options".to_string(), "DENY".to_string());
+        options
+            .headers
+            .insert("cache-control".to_string(), "no-cache".to_string());
+        let response = http::Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .header(header::CACHE_CONTROL, "max-age=60")
+            .body(http_service::Body::empty())
+            .unwrap();
+        let response = ResponseDecorator::new(&options, None).decorate(response);
+        let headers = response.headers();
+        assert_eq!("bytes", headers[header::ACCEPT_RANGES]);
+        assert!(!headers.contains_key(SURROGATE_CONTROL));
+        assert_eq!("nosniff", headers["x-content-type-options"]);
+        assert_eq!("DENY", headers["x-frame-options"]);
+        assert_eq!("max-age=60", headers[header::CACHE_CONTROL]);
+
+        let mut response = http::Response::builder()
+            .header(header::ACCEPT_RANGES, "bytes")
+            .body(http_service::Body::empty())
+            .unwrap();
+        response.extensions_mut().insert(Generated);
+        let response = ResponseDecorator::new(&options, None).decorate(response);
+        assert!(!response.headers().contains_key(header::ACCEPT_RANGES));
+
+        assert!(validate_headers(&options).is_ok());
+        options
+            .headers
+            .insert("bad name".to_string(), "x".to_string());
+        assert!(validate_headers(&options).is_err());
+        options.headers.clear();
+        options.surrogate_control = Some("max-age=60\n".to_string());
+        assert!(validate_headers(&options).is_err());
+    }
+
+    #[test]
+    fn test_clamp_cache_age() {
+        #[derive(Default)]
+        struct Clamped(Mutex<Vec<(u64, u64)>>);
+
+        impl Metrics for Clamped {
+            fn response(&self, _served: Served) {}
+
+            fn cache_age_clamped(&self, from: u64, to: u64) {
+                self.0.lock().unwrap().push((from, to));
+            }
+        }
+
+        let mut options = Options::default();
+        options.min_max_age = Some(60);
+        options.max_max_age = Some(86400);
+        let metrics = Clamped::default();
+        let decorator = ResponseDecorator::new(&options, Some(&metrics));
+        let decorate = |value: &'static str| {
+            let response = http::Response::builder()
+                .header(header::CACHE_CONTROL, value)
+                .body(http_service::Body::empty())
+                .unwrap();
+            let response = decorator.decorate(response);
+            response.headers()[header::CACHE_CONTROL].clone()
+        };
+        assert_eq!(
+            "public, max-age=86400, immutable",
+            decorate("public, max-age=31536000, immutable")
+        );
+        assert_eq!(
+            "max-age=60, s-maxage=600",
+            decorate("max-age=0,s-maxage=600")
+        );
+        assert_eq!("no-cache,max-age=600", decorate("no-cache,max-age=600"));
+        assert_eq!("no-store", decorate("no-store"));
+        assert_eq!(
+            vec![(31_536_000, 86400), (0, 60)],
+            *metrics.0.lock().unwrap()
+        );
+
+        options.surrogate_control = Some("max-age=31536000".to_string());
+        options.cdn_cache_control = Some("max-age=30".to_string());
+        let decorator = ResponseDecorator::new(&options, None);
+        let response = http::Response::builder()
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(http_service::Body::empty())
+            .unwrap();
+        let response = decorator.decorate(response);
+        let headers = response.headers();
+        assert_eq!("no-cache", headers[header::CACHE_CONTROL]);
+        assert_eq!("max-age=86400", headers[SURROGATE_CONTROL]);
+        assert_eq!("max-age=60", headers[CDN_CACHE_CONTROL]);
+
+        assert!(validate_cache_ages(&options).is_ok());
+        options.min_max_age = Some(86401);
+        assert!(validate_cache_ages(&options).is_err());
+    }
+}
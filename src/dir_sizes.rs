@@ -0,0 +1,185 @@
+//! Total size and file count of directories for listings, walked in background threads and
+//! cached, so that a large tree never blocks a request
+
+use crate::watch::{ChangeEvent, ChangeEvents};
+use futures::{executor::block_on, future, StreamExt};
+use log::warn;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Entries a walk visits at most, larger trees get no summary
+const MAX_WALK_ENTRIES: usize = 100_000;
+
+/// Walks running at once, further directories are walked once one finishes
+const MAX_WALKS: usize = 2;
+
+/// Age after which a summary is walked again, unless invalidated earlier by a watcher
+const SUMMARY_TTL: Duration = Duration::from_secs(300);
+
+/// Size and number of files below a directory
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct DirSummary {
+    pub size: u64,
+    pub files: usize,
+}
+
+#[derive(Debug)]
+enum State {
+    Walking,
+    /// `None` if the tree has too many entries
+    Done(Option<DirSummary>, Instant),
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct DirSizes {
+    states: Mutex<HashMap<PathBuf, State>>,
+    walks: AtomicUsize,
+}
+
+impl DirSizes {
+    /// Summary of `dir` if known, otherwise start walking it (if few enough walks are running)
+    /// and return `None` for now
+    pub fn get(sizes: &Arc<Self>, dir: &Path) -> Option<DirSummary> {
+        let mut states = sizes.states.lock().unwrap();
+        match states.get(dir) {
+            Some(State::Walking) => return None,
+            Some(State::Done(summary, walked)) if walked.elapsed() < SUMMARY_TTL => {
+                return *summary
+            }
+            _ => {}
+        }
+        if sizes.walks.fetch_add(1, Ordering::SeqCst) >= MAX_WALKS {
+            sizes.walks.fetch_sub(1, Ordering::SeqCst);
+            return None;
+        }
+        let (weak, owned) = (Arc::downgrade(sizes), dir.to_path_buf());
+        let spawned = thread::Builder::new()
+            .name("tsf-dir-size".to_string())
+            .spawn(move || walked(&weak, owned));
+        match spawned {
+            Ok(_) => {
+                states.insert(dir.to_path_buf(), State::Walking);
+            }
+            Err(error) => {
+                warn!("failed to walk {:?}: {}", dir, error);
+                sizes.walks.fetch_sub(1, Ordering::SeqCst);
+            }
+        }
+        None
+    }
+
+    /// Forget summaries of `path` and directories above it, e.g. once a file below changed
+    pub fn invalidate(&self, path: &Path) {
+        let mut states = self.states.lock().unwrap();
+        // walks in progress may miss the change, they're kept to not walk the same tree twice
+        states.retain(|dir, state| match state {
+            State::Walking => true,
+            State::Done(..) => !path.starts_with(dir),
+        });
+    }
+
+    /// Invalidate summaries as `events` report changes of files below `root`
+    pub fn invalidate_on(sizes: &Arc<Self>, root: PathBuf, events: &ChangeEvents) {
+        let (sizes, receiver) = (Arc::downgrade(sizes), events.subscribe());
+        let watching = thread::Builder::new()
+            .name("tsf-dir-size-watch".to_string())
+            .spawn(move || {
+                block_on(receiver.for_each(|event: ChangeEvent| {
+                    if let Some(sizes) = sizes.upgrade() {
+                        sizes.invalidate(&root.join(&event.path));
+                    }
+                    future::ready(())
+                }))
+            });
+        if let Err(error) = watching {
+            warn!("failed to watch for directory size invalidation: {}", error);
+        }
+    }
+}
+
+fn walked(sizes: &Weak<DirSizes>, dir: PathBuf) {
+    let summary = walk(&dir, MAX_WALK_ENTRIES);
+    if let Some(sizes) = sizes.upgrade() {
+        sizes
+            .states
+            .lock()
+            .unwrap()
+            .insert(dir, State::Done(summary, Instant::now()));
+        sizes.walks.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Summary of files below `dir` visiting at most `limit` entries, `None` beyond; symbolic links
+/// and hidden (dot) entries are skipped, like listings do
+pub(crate) fn walk(dir: &Path, limit: usize) -> Option<DirSummary> {
+    let mut summary = DirSummary { size: 0, files: 0 };
+    let mut visited = 0;
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        for entry in entries.filter_map(Result::ok) {
+            visited += 1;
+            if visited > limit {
+                return None;
+            }
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            match entry.metadata() {
+                Ok(x) if x.file_type().is_dir() => dirs.push(entry.path()),
+                Ok(x) if x.file_type().is_file() => {
+                    summary.size += x.len();
+                    summary.files += 1;
+                }
+                _ => {}
+            }
+        }
+    }
+    Some(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_dir_sizes() {
+        let root = TempDir::new("dir-sizes");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/one.bin"), vec![0; 10]).unwrap();
+        fs::write(root.join("a/b/two.bin"), vec![0; 20]).unwrap();
+        fs::write(root.join("a/.hidden"), vec![0; 40]).unwrap();
+
+        let expected = DirSummary { size: 30, files: 2 };
+        assert_eq!(Some(expected), walk(&root.join("a"), 16));
+        assert_eq!(None, walk(&root.join("a"), 3));
+
+        let sizes = Arc::new(DirSizes::default());
+        let dir = root.join("a");
+        let mut summary = DirSizes::get(&sizes, &dir);
+        for _ in 0..100 {
+            if summary.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+            summary = DirSizes::get(&sizes, &dir);
+        }
+        assert_eq!(Some(expected), summary);
+
+        sizes.invalidate(&root.join("a/b/two.bin"));
+        assert!(sizes.states.lock().unwrap().is_empty());
+    }
+}
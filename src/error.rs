@@ -7,3 +7,39 @@ pub type TSFResult<T> = std::result::Result<T, failure::Error>;
 #[derive(Debug, Fail)]
 #[fail(display = "no such directory found: {:?}", _0)]
 pub struct NoSuchDirectory(pub PathBuf);
+
+#[derive(Debug, Fail)]
+#[fail(display = "file read pool already started")]
+pub struct ReadPoolStarted;
+
+#[derive(Debug, Fail)]
+#[fail(display = "read pool already exists: {}", _0)]
+pub struct ReadPoolExists(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "no such read pool: {}", _0)]
+pub struct NoSuchReadPool(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid header: {}", _0)]
+pub struct InvalidHeader(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid cache control: {}", _0)]
+pub struct InvalidCacheControl(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "minimum cache age {}s exceeds maximum {}s", _0, _1)]
+pub struct InvalidCacheAge(pub u64, pub u64);
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid asset manifest: {}", _0)]
+pub struct InvalidManifest(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "invalid redirect: {}", _0)]
+pub struct InvalidRedirect(pub String);
+
+#[derive(Debug, Fail)]
+#[fail(display = "preflight found {} issues in root", _0)]
+pub struct PreflightFailed(pub usize);
@@ -0,0 +1,115 @@
+//! Custom bodies of error responses, loaded from a directory of pages named after their status
+
+use crate::error::TSFResult;
+use http::{header, HeaderValue};
+use http_service::Body;
+use log::warn;
+use std::{collections::BTreeMap, fs, path::Path};
+use tide::Response;
+
+/// Pages by status code, with their `Content-Type`, read once into memory
+#[derive(Debug, Default)]
+pub(crate) struct ErrorPages {
+    pages: BTreeMap<u16, (HeaderValue, Vec<u8>)>,
+}
+
+impl ErrorPages {
+    /// Read pages like `404.html` or `416.txt` of `dir`, for statuses from 400 to 599
+    ///
+    /// Files named otherwise are skipped, so are other pages of a status already found.
+    pub fn load(dir: &Path) -> TSFResult<Self> {
+        let mut pages = BTreeMap::new();
+        let mut entries: Vec<_> = fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|x| x.path())
+            .collect();
+        entries.sort();
+        for path in entries {
+            let status = match path
+                .file_stem()
+                .and_then(|x| x.to_str())
+                .and_then(|x| x.parse::<u16>().ok())
+            {
+                Some(x) if x >= 400 && x < 600 => x,
+                _ => continue,
+            };
+            if pages.contains_key(&status) || !path.is_file() {
+                continue;
+            }
+            let mime = mime_guess::guess_mime_type(&path);
+            let content_type = match mime.type_() {
+                mime::TEXT if mime.get_param(mime::CHARSET).is_none() => {
+                    format!("{}; charset=utf-8", mime)
+                }
+                _ => mime.to_string(),
+            };
+            let content_type = match HeaderValue::from_str(&content_type) {
+                Ok(x) => x,
+                Err(_) => {
+                    warn!("skip error page of unusable type: {:?}", path);
+                    continue;
+                }
+            };
+            pages.insert(status, (content_type, fs::read(&path)?));
+        }
+        Ok(Self { pages })
+    }
+
+    /// Replace the built-in plain text body of an error response by the page of its status
+    pub fn apply(&self, response: Response) -> Response {
+        let plain = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map_or(false, |x| x.as_bytes().starts_with(b"text/plain"));
+        let (content_type, page) = match self.pages.get(&response.status().as_u16()) {
+            Some(x) if plain => x,
+            _ => return response,
+        };
+        let (mut parts, _) = response.into_parts();
+        parts
+            .headers
+            .insert(header::CONTENT_TYPE, content_type.clone());
+        parts.headers.remove(header::CONTENT_LANGUAGE);
+        parts
+            .headers
+            .insert(header::CONTENT_LENGTH, page.len().into());
+        Response::from_parts(parts, Body::from(page.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{ErrorResponse, TempDir};
+    use futures::executor::block_on;
+    use http::StatusCode;
+    use tide::IntoResponse;
+
+    #[test]
+    fn test_error_pages() {
+        let dir = TempDir::new("errors");
+        fs::write(dir.join("404.html"), b"<h1>gone</h1>").unwrap();
+        fs::write(dir.join("416.txt"), b"bad range").unwrap();
+        fs::write(dir.join("200.html"), b"").unwrap();
+        fs::write(dir.join("style.css"), b"").unwrap();
+        let pages = ErrorPages::load(&dir).unwrap();
+        assert_eq!(
+            vec![404, 416],
+            pages.pages.keys().cloned().collect::<Vec<_>>()
+        );
+
+        let response = pages.apply(ErrorResponse::NotFound.into_response());
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(
+            "text/html; charset=utf-8",
+            response.headers()[header::CONTENT_TYPE]
+        );
+        assert_eq!("13", response.headers()[header::CONTENT_LENGTH]);
+        let body = block_on(response.into_body().into_vec()).unwrap();
+        assert_eq!(b"<h1>gone</h1>", &body[..]);
+
+        // no page of this status
+        let response = pages.apply(ErrorResponse::Forbidden.into_response());
+        assert_eq!("text/plain", response.headers()[header::CONTENT_TYPE]);
+    }
+}
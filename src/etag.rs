@@ -0,0 +1,230 @@
+use crate::{
+    error::TSFResult,
+    vfs::{Metadata, Target},
+};
+use serde::Deserialize;
+use sha2::{Digest, Sha256, Sha384};
+use std::{collections::HashMap, io::Read, path::PathBuf, sync::Mutex, time::SystemTime};
+
+/// Strategy used to generate the `ETag` of a file
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EtagMode {
+    /// Derived from last modified time and file size (default)
+    ///
+    /// Cheap, but differs between machines which got the same files at different time.
+    Metadata,
+    /// Derived from SHA-256 of file content, the digest is cached until the file changes
+    Content,
+    /// Derived from a build identifier (e.g. hash of a build manifest) and file size
+    Build(String),
+}
+
+impl Default for EtagMode {
+    fn default() -> Self {
+        EtagMode::Metadata
+    }
+}
+
+/// Upper bound of entries remembered by a `HashCache`
+const MAX_DIGESTS: usize = 65536;
+
+/// Digests of file content
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub(crate) struct Digests {
+    /// lower case hex encoded SHA-256
+    pub sha256: String,
+    /// [Subresource Integrity](https://www.w3.org/TR/SRI/) string, e.g. `sha384-...`
+    pub integrity: String,
+}
+
+struct CacheEntry {
+    last_modified: SystemTime,
+    size: u64,
+    digests: Digests,
+}
+
+/// Content digests keyed by path, invalidated when size or last modified time changed, all
+/// forgotten once [`MAX_DIGESTS`] are remembered
+///
+/// Digests are computed by the caller, which runs on the read pool when serving.
+pub(crate) struct HashCache {
+    max_entries: usize,
+    entries: Mutex<HashMap<(usize, PathBuf), CacheEntry>>,
+}
+
+impl Default for HashCache {
+    fn default() -> Self {
+        Self::bounded(MAX_DIGESTS)
+    }
+}
+
+impl HashCache {
+    fn bounded(max_entries: usize) -> Self {
+        Self {
+            max_entries,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn digests(
+        &self,
+        target: &Target,
+        size: u64,
+        last_modified: SystemTime,
+    ) -> TSFResult<Digests> {
+        let key = target.cache_key();
+        if let Some(entry) = self.entries.lock().unwrap().get(&key) {
+            if entry.size == size && entry.last_modified == last_modified {
+                return Ok(entry.digests.clone());
+            }
+        }
+
+        // compute without holding the lock, hashing a big file may take a while
+        let digests = digests(target.open()?.0)?;
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.max_entries && !entries.contains_key(&key) {
+            entries.clear();
+        }
+        entries.insert(
+            key,
+            CacheEntry {
+                last_modified,
+                size,
+                digests: digests.clone(),
+            },
+        );
+        Ok(digests)
+    }
+
+    /// Number of cached entries
+    pub fn entries(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    /// Return SRI integrity string of given entry
+    pub fn integrity(&self, target: &Target) -> TSFResult<String> {
+        let meta = target.metadata()?;
+        Ok(self.digests(target, meta.size, meta.modified)?.integrity)
+    }
+}
+
+/// Generate `ETag` of given entry with given strategy
+///
+/// `ETag` given by backend is used as is, unless strategy is `EtagMode::Build`.
+pub(crate) fn etag(
+    mode: &EtagMode,
+    cache: &HashCache,
+    target: &Target,
+    meta: &Metadata,
+) -> TSFResult<String> {
+    let etag = match (mode, &meta.etag) {
+        (EtagMode::Build(build), _) => build_etag(build, meta.size),
+        (_, Some(etag)) => etag.clone(),
+        (EtagMode::Metadata, None) => metadata_etag(meta)?,
+        (EtagMode::Content, None) => cache.digests(target, meta.size, meta.modified)?.sha256,
+    };
+    Ok(etag)
+}
+
+/// Modification time and size, with sub-second precision if available, so that a file replaced
+/// within the same second still gets a new `ETag`
+pub(crate) fn metadata_etag(meta: &Metadata) -> TSFResult<String> {
+    let modified = meta.modified.duration_since(::std::time::UNIX_EPOCH)?;
+    Ok(match modified.subsec_nanos() {
+        0 => format!("{:x}-{:x}", modified.as_secs(), meta.size),
+        nanos => format!("{:x}.{:x}-{:x}", modified.as_secs(), nanos, meta.size),
+    })
+}
+
+fn build_etag(build: &str, size: u64) -> String {
+    format!("{}-{:x}", build, size)
+}
+
+/// Compute all digests of data in `reader` in one pass
+fn digests(mut reader: impl Read) -> TSFResult<Digests> {
+    let mut sha256 = Sha256::new();
+    let mut sha384 = Sha384::new();
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let size = reader.read(&mut buffer)?;
+        if size == 0 {
+            break;
+        }
+        sha256.input(&buffer[..size]);
+        sha384.input(&buffer[..size]);
+    }
+    Ok(Digests {
+        sha256: format!("{:x}", sha256.result()),
+        integrity: format!("sha384-{}", base64::encode(&sha384.result())),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::fs;
+
+    #[test]
+    fn test_digests() {
+        assert_eq!(
+            Digests {
+                sha256: "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+                    .to_string(),
+                integrity:
+                    "sha384-OLBgp1GsljhM2TJ+sbHjaiH9txEUvgdDTAzHv2P24donTt6/529l+9Ua0vFImLlb"
+                        .to_string(),
+            },
+            digests(&b""[..]).unwrap()
+        );
+        assert_eq!(
+            Digests {
+                sha256: "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+                    .to_string(),
+                integrity:
+                    "sha384-ywB1P0WjXou1oD1pmsZQBycsMqsO3tFjGotgWkP/W+2AhgcroefMI1i67KE0yCWn"
+                        .to_string(),
+            },
+            digests(&b"abc"[..]).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bounded() {
+        let temp = TempDir::new("hash-cache");
+        let cache = HashCache::bounded(2);
+        let digests = |name: &str| {
+            let path = temp.join(name);
+            fs::write(&path, name).unwrap();
+            let meta = fs::metadata(&path).unwrap();
+            cache.digests(&Target::Local(path), meta.len(), meta.modified().unwrap())
+        };
+        digests("a").unwrap();
+        digests("b").unwrap();
+        digests("b").unwrap();
+        assert_eq!(2, cache.entries());
+        digests("c").unwrap();
+        assert_eq!(1, cache.entries());
+    }
+
+    #[test]
+    fn test_build_etag() {
+        assert_eq!("v1-ff", build_etag("v1", 255));
+        assert_ne!(build_etag("v1", 255), build_etag("v2", 255));
+    }
+
+    #[test]
+    fn test_metadata_etag() {
+        use std::time::{Duration, UNIX_EPOCH};
+        let meta = |modified| Metadata {
+            size: 255,
+            modified,
+            etag: None,
+        };
+        let etag = |x| metadata_etag(&meta(UNIX_EPOCH + x)).unwrap();
+        assert_eq!("10-ff", etag(Duration::from_secs(16)));
+        assert_eq!("10.1f4-ff", etag(Duration::new(16, 500)));
+        assert_ne!(etag(Duration::new(16, 500)), etag(Duration::new(16, 600)));
+    }
+}
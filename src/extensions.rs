@@ -0,0 +1,120 @@
+//! Request extensions honored when building responses, inserted by middleware running before
+//! the endpoint, e.g. `req.extensions_mut().insert(ForceDownload::new("report.csv"))`, and
+//! response extensions for middleware running after it
+
+use crate::utils::{ContentDisposition, DispositionType};
+use mime::Mime;
+use std::time::{Duration, Instant};
+use tide::{Request, Response};
+
+/// Serve the file with given MIME type instead of the one guessed from its name
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct OverrideMime(pub Mime);
+
+/// Serve the file as attachment, named as given, or after the file if empty
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ForceDownload(pub String);
+
+impl ForceDownload {
+    pub fn new(filename: impl Into<String>) -> Self {
+        ForceDownload(filename.into())
+    }
+}
+
+/// Time by which the response to a file request must be ready (resolved, metadata read and
+/// body about to stream), otherwise `503 Service Unavailable` is answered instead
+///
+/// It's checked between steps, a step running over isn't interrupted but its result dropped.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// Deadline `budget` from now
+    pub fn after(budget: Duration) -> Self {
+        Deadline(Instant::now() + budget)
+    }
+
+    pub fn is_exceeded(self) -> bool {
+        Instant::now() >= self.0
+    }
+}
+
+/// Boundary of a `multipart/byteranges` response, as in its `Content-Type`
+///
+/// Handy for tests with random boundaries: `response.extensions().get::<MultipartBoundary>()`.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MultipartBoundary(pub String);
+
+/// Marks a response of content generated on request (listings, compressed, transformed or
+/// rendered content) instead of stored bytes
+///
+/// Offsets into such content aren't stable, so `Range` is ignored (`200 OK` with everything) and
+/// no `Accept-Ranges` is sent. Middleware generating bodies should insert it as well.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Generated;
+
+pub(crate) fn mark_generated(mut response: Response) -> Response {
+    response.extensions_mut().insert(Generated);
+    response
+}
+
+/// Apply overrides of request to MIME type and disposition of a response
+pub(crate) fn apply_overrides(
+    req: &Request,
+    mime: &mut Mime,
+    disposition: &mut ContentDisposition,
+) {
+    if let Some(OverrideMime(x)) = req.extensions().get() {
+        *mime = x.clone();
+    }
+    if let Some(ForceDownload(filename)) = req.extensions().get() {
+        disposition.ty = DispositionType::Attachment;
+        if !filename.is_empty() {
+            disposition.filename = Some(filename.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn test_apply_overrides() {
+        let apply = |req: &Request| {
+            let mut mime = mime::TEXT_PLAIN;
+            let mut disposition = ContentDisposition::new(&mime, Path::new("a.txt"));
+            apply_overrides(req, &mut mime, &mut disposition);
+            (mime.to_string(), disposition.to_string())
+        };
+        let mut req = http::Request::new(http_service::Body::empty());
+        assert_eq!(
+            (
+                "text/plain".to_string(),
+                "inline; filename*=\"a.txt\"".to_string()
+            ),
+            apply(&req)
+        );
+
+        req.extensions_mut().insert(OverrideMime(mime::TEXT_CSV));
+        req.extensions_mut().insert(ForceDownload::default());
+        assert_eq!(
+            (
+                "text/csv".to_string(),
+                "attachment; filename*=\"a.txt\"".to_string()
+            ),
+            apply(&req)
+        );
+
+        req.extensions_mut()
+            .insert(ForceDownload::new("report.csv"));
+        assert_eq!("attachment; filename*=\"report.csv\"", apply(&req).1);
+    }
+
+    #[test]
+    fn test_deadline() {
+        assert!(!Deadline::after(Duration::from_secs(60)).is_exceeded());
+        assert!(Deadline::after(Duration::from_secs(0)).is_exceeded());
+    }
+}
@@ -1,33 +1,356 @@
-use crate::utils::{buffer_size, MAX_BUFFER_SIZE};
+use crate::{
+    chunk_size::ChunkSizer,
+    error::{ReadPoolExists, ReadPoolStarted, TSFResult},
+    memory_budget::{self, Reservation},
+    utils::buffer_size,
+    vfs::Reader,
+};
 use bytes::{Bytes, BytesMut};
-use crossbeam_channel::{bounded, Receiver, Sender, TrySendError};
-use futures::io::ErrorKind;
+use crossbeam_channel::{bounded, Receiver, Sender};
+use futures::{
+    channel::oneshot::{self, Canceled},
+    future::FutureObj,
+    io::ErrorKind,
+    Future,
+};
 use lazy_static::lazy_static;
+use log::{error, warn};
+use serde::Serialize;
 use std::{
-    fs::File,
+    collections::HashMap,
     io::{Error as IoError, Read, Seek, SeekFrom},
     ops::Range,
-    sync::{Arc, Mutex},
+    panic::{catch_unwind, AssertUnwindSafe},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
     task::{Poll, Waker},
+    thread,
+    time::{Duration, Instant},
 };
 
+/// Settings of a thread pool reading files, the default one is shared by all instances in a
+/// process unless they're assigned to [their own](fn.add_read_pool.html)
+///
+/// With feature `tokio` or `async-std`, operations run on the blocking threads of the runtime
+/// instead of threads of the pool, queue size and timeout apply alike.
+#[derive(Debug, Clone)]
+pub struct ReadPoolConfig {
+    /// number of threads, default 8, unused with feature `tokio` or `async-std`
+    pub threads: usize,
+    /// pending reads before requests are answered with `500`, default 1024
+    pub queue_size: usize,
+    /// threads are named `{thread_name}-{index}`, default `tsf-read`
+    pub thread_name: String,
+    /// time an open, stat or read may take, e.g. on a hung network mount, default none
+    ///
+    /// Opening (and hashing for `ETag`s) then runs on the pool too, a request whose file isn't
+    /// opened in time is answered with `504`, a read taking too long fails its response body.
+    /// Threads stuck on an operation are replaced, they exit once it returns.
+    pub timeout: Option<Duration>,
+}
+
+impl Default for ReadPoolConfig {
+    fn default() -> Self {
+        Self {
+            threads: 8,
+            queue_size: 1024,
+            thread_name: "tsf-read".to_string(),
+            timeout: None,
+        }
+    }
+}
+
+/// Point-in-time state of the read pool, see [`Metrics::read_pool`](trait.Metrics.html#method.read_pool)
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize)]
+pub struct ReadPoolHealth {
+    /// whether the pool has been started by a first read
+    pub started: bool,
+    /// threads of the pool, or blocking threads of the runtime busy with its operations
+    pub threads: usize,
+    /// reads waiting for a thread
+    pub queued: usize,
+    /// reads which panicked (e.g. in a storage backend) since start, their workers are kept
+    pub panics: u64,
+    /// operations which exceeded [`ReadPoolConfig::timeout`](struct.ReadPoolConfig.html#structfield.timeout)
+    /// since start, their threads were replaced
+    pub timeouts: u64,
+}
+
+lazy_static! {
+    static ref CONFIG: Mutex<ReadPoolConfig> = Mutex::new(ReadPoolConfig::default());
+    static ref DEFAULT_POOL: Arc<ReadPool> = {
+        STARTED.store(true, Ordering::SeqCst);
+        Arc::new(ReadPool::start(&CONFIG.lock().unwrap()))
+    };
+    static ref NAMED_POOLS: Mutex<HashMap<String, Arc<ReadPool>>> = Mutex::default();
+}
+
+static STARTED: AtomicBool = AtomicBool::new(false);
+
+/// Configure the default read pool, fails once the pool has been started by a first read
+pub fn configure_read_pool(config: ReadPoolConfig) -> TSFResult<()> {
+    let mut current = CONFIG.lock().unwrap();
+    if STARTED.load(Ordering::SeqCst) {
+        return Err(ReadPoolStarted.into());
+    }
+    *current = config;
+    Ok(())
+}
+
+/// Current state of the default read pool
+pub fn read_pool_health() -> ReadPoolHealth {
+    if !STARTED.load(Ordering::SeqCst) {
+        return ReadPoolHealth::default();
+    }
+    DEFAULT_POOL.health()
+}
+
+/// Start a read pool of its own named `name`, for instances assigned to it by
+/// [`StaticFiles::read_pool`](struct.StaticFiles.html#method.read_pool), e.g. so that a mount
+/// of bulk downloads can't starve one of latency-sensitive assets
+///
+/// Its threads are started right away. Fails if a pool of this name exists.
+pub fn add_read_pool(name: impl Into<String>, config: ReadPoolConfig) -> TSFResult<()> {
+    let name = name.into();
+    let mut pools = NAMED_POOLS.lock().unwrap();
+    if pools.contains_key(&name) {
+        return Err(ReadPoolExists(name).into());
+    }
+    pools.insert(name, Arc::new(ReadPool::start(&config)));
+    Ok(())
+}
+
+/// Current state of the read pool named `name`, `None` if there's none
+pub fn named_read_pool_health(name: &str) -> Option<ReadPoolHealth> {
+    NAMED_POOLS.lock().unwrap().get(name).map(|x| x.health())
+}
+
+/// Read pool named `name`, see [`add_read_pool`](fn.add_read_pool.html)
+pub(crate) fn named_read_pool(name: &str) -> Option<Arc<ReadPool>> {
+    NAMED_POOLS.lock().unwrap().get(name).cloned()
+}
+
+/// State of `pool`, the default one if `None`
+pub(crate) fn pool_health(pool: Option<&ReadPool>) -> ReadPoolHealth {
+    match pool {
+        Some(pool) => pool.health(),
+        None => read_pool_health(),
+    }
+}
+
+/// Counters of a pool, shared with its workers
+#[derive(Default)]
+struct PoolStats {
+    panics: AtomicU64,
+    timeouts: AtomicU64,
+}
+
+/// Workers of a pool, or runners of the runtime busy with one of its operations
+type Workers = Arc<Mutex<Vec<Arc<WorkerState>>>>;
+
+/// A started thread pool reading files
+pub(crate) struct ReadPool {
+    sender: Sender<Job>,
+    /// taken from by runners of the runtime, see [`Spawner`]
+    receiver: Receiver<Job>,
+    workers: Workers,
+    stats: Arc<PoolStats>,
+}
+
+impl ReadPool {
+    fn start(config: &ReadPoolConfig) -> Self {
+        let stats = Arc::new(PoolStats::default());
+        let (sender, receiver) = bounded(config.queue_size);
+        let workers = Workers::default();
+        // no threads of its own if the runtime runs its operations
+        if runtime().is_none() {
+            workers.lock().unwrap().extend(
+                (0..config.threads).map(|_| spawn_worker(&config.thread_name, &receiver, &stats)),
+            );
+        }
+        if let Some(timeout) = config.timeout {
+            let (name, receiver) = (config.thread_name.clone(), receiver.clone());
+            let (stats, workers) = (stats.clone(), workers.clone());
+            thread::Builder::new()
+                .name(format!("{}-watchdog", name))
+                .spawn(move || watchdog(timeout, &name, &receiver, &stats, &workers))
+                .expect("failed to spawn file read watchdog");
+        }
+        Self {
+            sender,
+            receiver,
+            workers,
+            stats,
+        }
+    }
+
+    fn health(&self) -> ReadPoolHealth {
+        ReadPoolHealth {
+            started: true,
+            threads: self.workers.lock().unwrap().len(),
+            queued: self.sender.len(),
+            panics: self.stats.panics.load(Ordering::Relaxed),
+            timeouts: self.stats.timeouts.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Queue `job`, give it back if the queue is full
+    fn submit(&self, job: Job) -> Result<(), Job> {
+        if let Err(error) = self.sender.try_send(job) {
+            return Err(error.into_inner());
+        }
+        if let Some(runtime) = runtime() {
+            let (receiver, workers) = (self.receiver.clone(), self.workers.clone());
+            let stats = self.stats.clone();
+            runtime.spawn_blocking(Box::new(move |can_block| {
+                run_one(&receiver, &workers, &stats, can_block)
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// Index of the next worker thread, replacements get new ones
+static WORKER_INDEX: AtomicUsize = AtomicUsize::new(0);
+
+fn spawn_worker(name: &str, receiver: &Receiver<Job>, stats: &Arc<PoolStats>) -> Arc<WorkerState> {
+    let state = Arc::new(WorkerState::default());
+    let (receiver, worker_state, stats) = (receiver.clone(), state.clone(), stats.clone());
+    let index = WORKER_INDEX.fetch_add(1, Ordering::Relaxed);
+    thread::Builder::new()
+        .name(format!("{}-{}", name, index))
+        .spawn(move || worker(&receiver, &worker_state, &stats))
+        .expect("failed to spawn file read thread");
+    state
+}
+
+/// Replace workers busy with one operation for longer than `timeout`, failing their read;
+/// runners of the runtime are only failed, the runtime has threads of its own
+fn watchdog(
+    timeout: Duration,
+    name: &str,
+    receiver: &Receiver<Job>,
+    stats: &Arc<PoolStats>,
+    workers: &Mutex<Vec<Arc<WorkerState>>>,
+) {
+    let interval = (timeout / 4).min(Duration::from_secs(1));
+    loop {
+        thread::sleep(interval);
+        let mut workers = workers.lock().unwrap();
+        let stuck = abandon_stuck(&workers, timeout, stats);
+        workers.retain(|x| !x.abandoned.load(Ordering::SeqCst));
+        if runtime().is_none() {
+            workers.extend((0..stuck).map(|_| spawn_worker(name, receiver, stats)));
+        }
+    }
+}
+
+/// Mark workers busy for longer than `timeout` abandoned and fail their operation, return how
+/// many
+fn abandon_stuck(workers: &[Arc<WorkerState>], timeout: Duration, stats: &PoolStats) -> usize {
+    let mut stuck = 0;
+    for state in workers {
+        // checked and taken under one lock, so that the worker can't have moved on to another
+        // operation which would be failed instead
+        let fail = match &mut *state.current.lock().unwrap() {
+            Some(operation) if operation.since.elapsed() >= timeout => {
+                state.abandoned.store(true, Ordering::SeqCst);
+                operation.fail.take()
+            }
+            _ => continue,
+        };
+        stats.timeouts.fetch_add(1, Ordering::Relaxed);
+        warn!("file operation exceeded {:?}, replace its thread", timeout);
+        if let Some(fail) = fail {
+            fail.send(IoError::new(
+                ErrorKind::TimedOut,
+                "file operation timed out",
+            ));
+        }
+        stuck += 1;
+    }
+    stuck
+}
+
+/// Run blocking file system operation `f` (open, stat, resolving paths) on `pool` (the default one
+/// if `None`), `TimedOut` once the watchdog finds it exceeded the pool's
+/// [timeout](struct.ReadPoolConfig.html#structfield.timeout)
+pub(crate) fn run_blocking<T, F>(
+    pool: Option<&ReadPool>,
+    f: F,
+) -> FutureObj<'static, Result<T, IoError>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    // answered by whichever of the operation and the watchdog comes first
+    let (sender, receiver) = oneshot::channel();
+    let sender = Arc::new(Mutex::new(Some(sender)));
+    let timed_out = sender.clone();
+    let job = Job::Call {
+        call: Box::new(move |()| {
+            let result = f();
+            if let Some(sender) = sender.lock().unwrap().take() {
+                let _ = sender.send(Ok(result));
+            }
+        }),
+        fail: Box::new(move |error| {
+            if let Some(sender) = timed_out.lock().unwrap().take() {
+                let _ = sender.send(Err(error));
+            }
+        }),
+    };
+    let pool = pool.unwrap_or(&**DEFAULT_POOL);
+    if pool.submit(job).is_err() {
+        let error = ErrorKind::WouldBlock.into();
+        return FutureObj::new(Box::new(async move { Err(error) }));
+    }
+    FutureObj::new(Box::new(
+        async move {
+            await!(receiver).unwrap_or_else(|Canceled| {
+                Err(IoError::new(ErrorKind::Other, "file operation panicked"))
+            })
+        },
+    ))
+}
+
 pub(crate) struct FileReadStream {
+    /// the default pool if `None`
+    pool: Option<Arc<ReadPool>>,
     range: Range<u64>,
     state: StreamState,
+    sizer: ChunkSizer,
+    /// memory of the buffer of the last read, until the next one
+    reservation: Option<Reservation>,
 }
 
 impl FileReadStream {
-    pub fn new(mut file: File, range: Range<u64>) -> Result<Self, (File, IoError)> {
+    pub fn new(
+        pool: Option<Arc<ReadPool>>,
+        mut file: Reader,
+        range: Range<u64>,
+    ) -> Result<Self, (Reader, IoError)> {
         assert!(range.start <= range.end);
         if let Err(error) = file.seek(SeekFrom::Start(range.start)) {
             return Err((file, error));
         }
         Ok(Self {
+            pool,
             range,
             state: StreamState::Init(file),
+            sizer: ChunkSizer::default(),
+            reservation: None,
         })
     }
 
+    /// Read chunks sized by `sizer` instead of default bounds
+    pub fn chunk_sizer(&mut self, sizer: ChunkSizer) {
+        self.sizer = sizer;
+    }
+
     pub fn poll_next(&mut self, waker: &Waker) -> StreamOutput {
         assert!(self.range.start <= self.range.end);
         if self.range.start == self.range.end {
@@ -35,23 +358,38 @@ impl FileReadStream {
         }
 
         if let Some(file) = self.state.get_file() {
-            let buffer_size = buffer_size(self.range.end - self.range.start, MAX_BUFFER_SIZE);
+            // the last chunk has been taken by now
+            self.reservation = None;
+            let chunk_size = self.sizer.next_size(Instant::now());
+            let buffer_size = buffer_size(self.range.end - self.range.start, chunk_size);
+            let reservation = memory_budget::reserve(buffer_size, self.sizer.min());
+            let buffer_size = reservation.size();
+            self.reservation = Some(reservation);
             let buffer = BytesMut::from(vec![0u8; buffer_size]);
-            let task = match FileReadTask::create(file, buffer) {
+            let pool = self.pool.as_ref().map_or(&**DEFAULT_POOL, |x| &**x);
+            let task = match pool.spawn_read(file, buffer) {
                 Ok(x) => x,
                 Err(_) => return StreamOutput::Error(ErrorKind::WouldBlock.into()),
             };
             self.state.put_task(task);
         }
 
-        let task = self.state.get_task().unwrap();
-        match task.poll(waker) {
-            Poll::Ready(Ok((file, bytes))) => {
+        let mut task = self.state.get_task().unwrap();
+        match Pin::new(&mut task).poll(waker) {
+            // the file is shorter than its range, e.g. truncated since its size was read
+            Poll::Ready(Ok(Ok((_, ref bytes)))) if bytes.is_empty() => StreamOutput::Error(
+                IoError::new(ErrorKind::UnexpectedEof, "file ended before its range"),
+            ),
+            Poll::Ready(Ok(Ok((file, bytes)))) => {
                 self.range.start += bytes.len() as u64;
+                self.sizer.yielded(bytes.len(), Instant::now());
                 self.state.put_file(file);
                 StreamOutput::Item(bytes)
             }
-            Poll::Ready(Err((_, _, error))) => StreamOutput::Error(error),
+            Poll::Ready(Ok(Err(error))) => StreamOutput::Error(error),
+            Poll::Ready(Err(Canceled)) => {
+                StreamOutput::Error(IoError::new(ErrorKind::Other, "file read worker is gone"))
+            }
             Poll::Pending => {
                 self.state.put_task(task);
                 StreamOutput::Pending
@@ -61,13 +399,13 @@ impl FileReadStream {
 }
 
 enum StreamState {
-    Init(File),
-    Work(FileReadTask),
+    Init(Reader),
+    Work(oneshot::Receiver<ReadResult>),
     Temp,
 }
 
 impl StreamState {
-    fn get_file(&mut self) -> Option<File> {
+    fn get_file(&mut self) -> Option<Reader> {
         if let StreamState::Init(_) = self {
             if let StreamState::Init(file) = ::std::mem::replace(self, StreamState::Temp) {
                 Some(file)
@@ -79,11 +417,11 @@ impl StreamState {
         }
     }
 
-    fn put_file(&mut self, file: File) {
+    fn put_file(&mut self, file: Reader) {
         *self = StreamState::Init(file);
     }
 
-    fn get_task(&mut self) -> Option<FileReadTask> {
+    fn get_task(&mut self) -> Option<oneshot::Receiver<ReadResult>> {
         if let StreamState::Work(_) = self {
             if let StreamState::Work(task) = ::std::mem::replace(self, StreamState::Temp) {
                 Some(task)
@@ -95,7 +433,7 @@ impl StreamState {
         }
     }
 
-    fn put_task(&mut self, task: FileReadTask) {
+    fn put_task(&mut self, task: oneshot::Receiver<ReadResult>) {
         *self = StreamState::Work(task);
     }
 }
@@ -104,137 +442,505 @@ pub enum StreamOutput {
     Pending,
     Error(IoError),
     Item(Bytes),
-    Complete(File),
+    Complete(Reader),
 }
 
-#[derive(Clone)]
-struct FileReadTask {
-    state: Arc<Mutex<TaskState>>,
+pub(crate) type ReadResult = Result<(Reader, Bytes), IoError>;
+
+/// Work queued to the pool
+enum Job {
+    Read(ReadJob),
+    /// any other operation, sending its result itself, `fail` sends an error instead
+    Call {
+        call: Box<dyn Call<()>>,
+        fail: Box<dyn Call<IoError>>,
+    },
 }
 
-impl FileReadTask {
-    pub fn create(file: File, buffer: BytesMut) -> Result<Self, (File, BytesMut)> {
-        lazy_static! {
-            static ref SENDER: Sender<FileReadTask> = {
-                let (sender, receiver) = bounded(1024);
-                for _ in 0..8 {
-                    let receiver = receiver.clone();
-                    ::std::thread::spawn(|| worker(receiver));
-                }
-                sender
-            };
+impl Job {
+    /// How to answer the job without running it
+    fn into_fail(self) -> Fail {
+        match self {
+            Job::Read(job) => Fail::Read(job.sender),
+            Job::Call { fail, .. } => Fail::Call(fail),
         }
+    }
+}
 
-        let task = FileReadTask {
-            state: Arc::new(Mutex::new(TaskState::Init(file, buffer))),
-        };
-        match SENDER.try_send(task.clone()) {
-            Ok(_) => Ok(task),
-            Err(TrySendError::Full(_)) => match task.state.lock().unwrap().get_state() {
-                TaskState::Init(file, buffer) => Err((file, buffer)),
-                _ => unreachable!(),
-            },
-            Err(TrySendError::Disconnected(_)) => unreachable!(),
-        }
-    }
-
-    #[allow(clippy::type_complexity)]
-    pub fn poll(&self, waker: &Waker) -> Poll<Result<(File, Bytes), (File, BytesMut, IoError)>> {
-        let mut guard = self.state.lock().unwrap();
-        match guard.get_state() {
-            TaskState::Init(file, buffer) => {
-                guard.put_state(TaskState::Ready(file, buffer, waker.clone()));
-                Poll::Pending
-            }
-            TaskState::WaitWaker => {
-                guard.put_state(TaskState::SendWaker(waker.clone()));
-                Poll::Pending
+/// A boxed closure called once
+pub(crate) trait Call<A>: Send {
+    fn call(self: Box<Self>, arg: A);
+}
+
+impl<A, F: FnOnce(A) + Send> Call<A> for F {
+    fn call(self: Box<Self>, arg: A) {
+        (*self)(arg)
+    }
+}
+
+/// What a worker is doing, watched if operations have a timeout
+#[derive(Default)]
+struct WorkerState {
+    current: Mutex<Option<Operation>>,
+    /// replaced by another thread, exits after the current operation
+    abandoned: AtomicBool,
+}
+
+impl WorkerState {
+    fn begin(&self, fail: Fail) {
+        *self.current.lock().unwrap() = Some(Operation {
+            since: Instant::now(),
+            fail: Some(fail),
+        });
+    }
+
+    /// End the current operation, how it would have been failed unless the watchdog did
+    fn end(&self) -> Option<Fail> {
+        self.current.lock().unwrap().take().and_then(|x| x.fail)
+    }
+}
+
+/// An operation run by a worker
+struct Operation {
+    since: Instant,
+    /// taken to fail the operation once timed out
+    fail: Option<Fail>,
+}
+
+/// How to answer an operation which timed out or couldn't run
+enum Fail {
+    Read(oneshot::Sender<ReadResult>),
+    Call(Box<dyn Call<IoError>>),
+}
+
+impl Fail {
+    fn send(self, error: IoError) {
+        match self {
+            Fail::Read(sender) => {
+                let _ = sender.send(Err(error));
             }
-            TaskState::Done(result) => Poll::Ready(result),
-            TaskState::Temp
-            | TaskState::Working
-            | TaskState::SendWaker(_)
-            | TaskState::Ready(_, _, _) => Poll::Pending,
+            Fail::Call(fail) => fail.call(error),
+        }
+    }
+}
+
+/// A read queued to the pool, its result is sent back through a oneshot channel, which wakes
+/// the polling task without any lock shared between them
+struct ReadJob {
+    file: Reader,
+    buffer: BytesMut,
+    sender: oneshot::Sender<ReadResult>,
+}
+
+/// Runs operations of the pools on the blocking threads of a runtime instead of threads of their
+/// own, chosen at compile time by feature; queues, timeouts and stats of the pools apply alike
+pub(crate) trait Spawner: Sync {
+    /// Run `job` on a thread allowed to block, with `false` if there's none so that it fails
+    /// instead of blocking
+    fn spawn_blocking(&self, job: Box<dyn Call<bool>>);
+}
+
+#[cfg(all(feature = "tokio", feature = "async-std"))]
+compile_error!(
+    "features `tokio` and `async-std` are exclusive, choose the runtime of the application"
+);
+
+/// Runtime running operations of the pools, `None` if they run on threads of their own
+#[cfg(not(any(feature = "tokio", feature = "async-std")))]
+fn runtime() -> Option<&'static dyn Spawner> {
+    None
+}
+
+/// Runtime running operations of the pools, `None` if they run on threads of their own
+#[cfg(feature = "tokio")]
+fn runtime() -> Option<&'static dyn Spawner> {
+    Some(&TokioBlocking)
+}
+
+/// Runtime running operations of the pools, `None` if they run on threads of their own
+#[cfg(all(feature = "async-std", not(feature = "tokio")))]
+fn runtime() -> Option<&'static dyn Spawner> {
+    Some(&AsyncStdBlocking)
+}
+
+impl ReadPool {
+    /// Queue a read of `buffer.len()` bytes, give file and buffer back if the queue is full
+    pub(crate) fn spawn_read(
+        &self,
+        file: Reader,
+        buffer: BytesMut,
+    ) -> Result<oneshot::Receiver<ReadResult>, (Reader, BytesMut)> {
+        let (sender, receiver) = oneshot::channel();
+        match self.submit(Job::Read(ReadJob {
+            file,
+            buffer,
+            sender,
+        })) {
+            Ok(_) => Ok(receiver),
+            Err(Job::Read(job)) => Err((job.file, job.buffer)),
+            Err(_) => unreachable!(),
+        }
+    }
+}
+
+fn worker(receiver: &Receiver<Job>, state: &WorkerState, stats: &PoolStats) {
+    for job in receiver {
+        run_job(job, state, stats);
+        if state.abandoned.load(Ordering::SeqCst) {
+            return;
         }
     }
 }
 
-fn worker(receiver: Receiver<FileReadTask>) {
-    for task in receiver {
-        let mut guard = task.state.lock().unwrap();
-        let (mut file, mut buffer, waker) = match guard.get_state() {
-            TaskState::Init(file, buffer) => {
-                guard.put_state(TaskState::WaitWaker);
-                (file, buffer, None)
+/// Run one operation queued to a pool on a thread of the runtime, fail it with `WouldBlock` if
+/// the thread may not block
+fn run_one(
+    receiver: &Receiver<Job>,
+    workers: &Mutex<Vec<Arc<WorkerState>>>,
+    stats: &PoolStats,
+    can_block: bool,
+) {
+    // one is run per operation queued
+    let job = match receiver.try_recv() {
+        Ok(x) => x,
+        Err(_) => return,
+    };
+    if !can_block {
+        let error = IoError::new(ErrorKind::WouldBlock, "no thread may block");
+        job.into_fail().send(error);
+        return;
+    }
+    let state = Arc::new(WorkerState::default());
+    workers.lock().unwrap().push(state.clone());
+    run_job(job, &state, stats);
+    workers.lock().unwrap().retain(|x| !Arc::ptr_eq(x, &state));
+}
+
+fn run_job(job: Job, state: &WorkerState, stats: &PoolStats) {
+    match job {
+        Job::Read(job) => {
+            state.begin(Fail::Read(job.sender));
+            let read_result = read(job.file, job.buffer, stats);
+            // taken by the watchdog if timed out, the receiver is gone if the response body
+            // has been dropped
+            if let Some(Fail::Read(sender)) = state.end() {
+                let _ = sender.send(read_result);
             }
-            TaskState::Ready(file, buffer, waker) => {
-                guard.put_state(TaskState::Working);
-                (file, buffer, Some(waker))
+        }
+        Job::Call { call, fail } => {
+            state.begin(Fail::Call(fail));
+            if catch_unwind(AssertUnwindSafe(move || call.call(()))).is_err() {
+                stats.panics.fetch_add(1, Ordering::Relaxed);
+                error!("file operation panicked in {:?}", thread::current().name());
             }
-            TaskState::WaitWaker
-            | TaskState::SendWaker(_)
-            | TaskState::Working
-            | TaskState::Done(_)
-            | TaskState::Temp => unreachable!(),
-        };
-        drop(guard);
+            state.end();
+        }
+    }
+}
 
-        let read_result = match file.read(&mut buffer) {
-            Ok(size) => {
-                buffer.truncate(size);
-                Ok((file, buffer.freeze()))
-            }
-            Err(error) => Err((file, buffer, error)),
-        };
+/// Blocking threads (`tokio_threadpool::blocking`, as used by `tokio::fs`) of the tokio runtime
+/// an operation is queued from (feature `tokio`), threads of their own for operations queued
+/// outside of a runtime
+#[cfg(feature = "tokio")]
+struct TokioBlocking;
+
+#[cfg(feature = "tokio")]
+impl Spawner for TokioBlocking {
+    fn spawn_blocking(&self, job: Box<dyn Call<bool>>) {
+        use futures01::{future::poll_fn, Async};
+        use tokio_executor::{DefaultExecutor, Executor};
 
-        let mut guard = task.state.lock().unwrap();
-        match guard.get_state() {
-            TaskState::WaitWaker => guard.put_state(TaskState::Done(read_result)),
-            TaskState::SendWaker(waker) => {
-                guard.put_state(TaskState::Done(read_result));
-                waker.wake();
+        let mut executor = DefaultExecutor::current();
+        if executor.status().is_err() {
+            thread::Builder::new()
+                .name("tsf-blocking".to_string())
+                .spawn(move || job.call(true))
+                .expect("failed to spawn file operation thread");
+            return;
+        }
+        let job = Arc::new(Mutex::new(Some(job)));
+        let running = job.clone();
+        let blocking = poll_fn(move || -> Result<Async<()>, ()> {
+            let result = tokio_threadpool::blocking(|| {
+                let job = running.lock().unwrap().take();
+                if let Some(job) = job {
+                    job.call(true);
+                }
+            });
+            match result {
+                Ok(x) => Ok(x),
+                // not on a thread pool, e.g. of a current thread runtime, failed rather than
+                // blocking the runtime
+                Err(_) => {
+                    let job = running.lock().unwrap().take();
+                    if let Some(job) = job {
+                        job.call(false);
+                    }
+                    Ok(Async::Ready(()))
+                }
             }
-            TaskState::Working => {
-                guard.put_state(TaskState::Done(read_result));
-                waker.unwrap().wake();
+        });
+        if let Err(error) = executor.spawn(Box::new(blocking)) {
+            error!("failed to spawn file operation: {}", error);
+            let job = job.lock().unwrap().take();
+            if let Some(job) = job {
+                job.call(false);
             }
-            TaskState::Ready(_, _, _)
-            | TaskState::Done(_)
-            | TaskState::Init(_, _)
-            | TaskState::Temp => unreachable!(),
         }
-        drop(guard);
     }
 }
 
-#[derive(Debug)]
-enum TaskState {
-    Init(File, BytesMut),
-    Ready(File, BytesMut, Waker),
+/// Blocking threads of async-std (feature `async-std`), those its `fs::File` reads on, started
+/// on demand whether a runtime runs or not
+#[cfg(feature = "async-std")]
+struct AsyncStdBlocking;
 
-    WaitWaker,
-    SendWaker(Waker),
-
-    Working,
-    Done(Result<(File, Bytes), (File, BytesMut, std::io::Error)>),
+#[cfg(feature = "async-std")]
+impl Spawner for AsyncStdBlocking {
+    fn spawn_blocking(&self, job: Box<dyn Call<bool>>) {
+        // detached, the job answers through its own channel
+        drop(async_std::task::spawn_blocking(move || job.call(true)));
+    }
+}
 
-    Temp,
+fn read(mut file: Reader, mut buffer: BytesMut, stats: &PoolStats) -> ReadResult {
+    // a panicking reader (e.g. of a storage backend) fails its request only, the worker keeps
+    // serving others
+    catch_unwind(AssertUnwindSafe(move || match file.read(&mut buffer) {
+        Ok(size) => {
+            buffer.truncate(size);
+            Ok((file, buffer.freeze()))
+        }
+        Err(error) => Err(error),
+    }))
+    .unwrap_or_else(|_| {
+        stats.panics.fetch_add(1, Ordering::Relaxed);
+        error!("file read panicked in {:?}", thread::current().name());
+        Err(IoError::new(ErrorKind::Other, "file read panicked"))
+    })
 }
 
-impl TaskState {
-    fn get_state(&mut self) -> Self {
-        let result = ::std::mem::replace(self, TaskState::Temp);
-        if let TaskState::Temp = result {
-            unreachable!()
-        } else {
-            result
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::single_range::SingleRangeReader;
+    use futures::{executor::block_on, StreamExt};
+    use std::io::Cursor;
+
+    struct Panicking;
+
+    impl Read for Panicking {
+        fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> {
+            panic!("broken backend")
         }
     }
 
-    fn put_state(&mut self, state: Self) {
-        if let TaskState::Temp = std::mem::replace(self, state) {
-        } else {
-            unreachable!()
+    impl Seek for Panicking {
+        fn seek(&mut self, _: SeekFrom) -> std::io::Result<u64> {
+            Ok(0)
         }
     }
+
+    fn read(file: Reader, size: u64) -> Vec<std::io::Result<Bytes>> {
+        block_on(
+            SingleRangeReader::new(None, file, 0, size)
+                .unwrap()
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_panicking_read() {
+        let config = ReadPoolConfig {
+            thread_name: "tsf-read-panicking".to_string(),
+            ..ReadPoolConfig::default()
+        };
+        let pool = Arc::new(ReadPool::start(&config));
+        let read = |file: Reader, size| -> Vec<_> {
+            block_on(
+                SingleRangeReader::new(Some(pool.clone()), file, 0, size)
+                    .unwrap()
+                    .collect(),
+            )
+        };
+        // more panics than threads, none of them kills a worker
+        for _ in 0..config.threads * 2 {
+            let result = read(Box::new(Panicking), 10);
+            assert_eq!(1, result.len());
+            assert_eq!(ErrorKind::Other, result[0].as_ref().unwrap_err().kind());
+        }
+        let result = read(Box::new(Cursor::new(b"hello".to_vec())), 5);
+        assert_eq!(b"hello", &result[0].as_ref().unwrap()[..]);
+        assert_eq!(config.threads as u64 * 2, pool.health().panics);
+    }
+
+    #[test]
+    fn test_truncated_read() {
+        // a file shorter than its range ends with an error instead of empty chunks
+        let result = read(Box::new(Cursor::new(b"hello".to_vec())), 10);
+        assert_eq!(2, result.len());
+        assert_eq!(b"hello", &result[0].as_ref().unwrap()[..]);
+        let error = result[1].as_ref().unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+
+        assert!(read_pool_health().started);
+        assert!(configure_read_pool(ReadPoolConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_named_read_pool() {
+        let config = ReadPoolConfig {
+            threads: 1,
+            thread_name: "tsf-read-bulk".to_string(),
+            ..ReadPoolConfig::default()
+        };
+        add_read_pool("test-bulk", config.clone()).unwrap();
+        assert!(add_read_pool("test-bulk", config).is_err());
+        assert!(named_read_pool("test-other").is_none());
+
+        // panics are counted by the pool they happened in
+        let pool = named_read_pool("test-bulk").unwrap();
+        let file: Reader = Box::new(Panicking);
+        let result: Vec<_> = block_on(
+            SingleRangeReader::new(Some(pool), file, 0, 10)
+                .unwrap()
+                .collect(),
+        );
+        assert!(result[0].is_err());
+        let health = named_read_pool_health("test-bulk").unwrap();
+        assert_eq!(1, health.threads);
+        assert_eq!(1, health.panics);
+    }
+
+    #[test]
+    fn test_abandon_stuck() {
+        let timeout = Duration::from_millis(100);
+        let stuck = Arc::new(WorkerState::default());
+        let (sender, receiver) = oneshot::channel();
+        *stuck.current.lock().unwrap() = Some(Operation {
+            since: Instant::now() - timeout * 2,
+            fail: Some(Fail::Read(sender)),
+        });
+        let busy = Arc::new(WorkerState::default());
+        let (sender, _receiver) = oneshot::channel();
+        busy.begin(Fail::Read(sender));
+        let idle = Arc::new(WorkerState::default());
+
+        let workers = vec![stuck.clone(), busy.clone(), idle.clone()];
+        let stats = PoolStats::default();
+        assert_eq!(1, abandon_stuck(&workers, timeout, &stats));
+        assert_eq!(1, stats.timeouts.load(Ordering::Relaxed));
+        assert!(stuck.abandoned.load(Ordering::SeqCst));
+        assert!(!busy.abandoned.load(Ordering::SeqCst));
+        assert!(!idle.abandoned.load(Ordering::SeqCst));
+        let error = block_on(receiver).unwrap().err().unwrap();
+        assert_eq!(ErrorKind::TimedOut, error.kind());
+        // failed once only, whatever the worker does next
+        assert!(stuck.end().is_none());
+        assert_eq!(0, abandon_stuck(&[stuck], timeout, &stats));
+    }
+
+    #[test]
+    fn test_run_blocking_timeout() {
+        let config = ReadPoolConfig {
+            threads: 1,
+            thread_name: "tsf-read-slow".to_string(),
+            timeout: Some(Duration::from_millis(50)),
+            ..ReadPoolConfig::default()
+        };
+        add_read_pool("test-slow", config).unwrap();
+        let pool = named_read_pool("test-slow").unwrap();
+
+        let result = block_on(run_blocking(Some(&pool), || 42));
+        assert_eq!(42, result.unwrap());
+        let result = block_on(run_blocking(Some(&pool), || {
+            thread::sleep(Duration::from_millis(500))
+        }));
+        assert_eq!(ErrorKind::TimedOut, result.unwrap_err().kind());
+        // served by the replacement thread meanwhile
+        let result = block_on(run_blocking(Some(&pool), || 42));
+        assert_eq!(42, result.unwrap());
+        assert_eq!(1, named_read_pool_health("test-slow").unwrap().timeouts);
+    }
+
+    #[cfg(not(any(feature = "tokio", feature = "async-std")))]
+    #[test]
+    fn test_run_blocking_dispatched() {
+        let pool = ReadPool::start(&ReadPoolConfig {
+            threads: 1,
+            thread_name: "tsf-read-dispatched".to_string(),
+            ..ReadPoolConfig::default()
+        });
+        // off the caller's thread even without a timeout
+        let name = block_on(run_blocking(Some(&pool), || {
+            thread::current().name().map(str::to_string)
+        }));
+        assert!(name.unwrap().unwrap().starts_with("tsf-read-dispatched-"));
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn test_async_std_blocking() {
+        let pool = ReadPool::start(&ReadPoolConfig {
+            timeout: Some(Duration::from_millis(50)),
+            ..ReadPoolConfig::default()
+        });
+        assert_eq!(0, pool.health().threads);
+        let file: Reader = Box::new(Cursor::new(b"hello".to_vec()));
+        let reading = pool.spawn_read(file, BytesMut::from(vec![0; 5]));
+        let (_, bytes) = block_on(reading.ok().unwrap()).unwrap().unwrap();
+        assert_eq!(b"hello", &bytes[..]);
+
+        let name = || thread::current().name().map(str::to_string);
+        let name = block_on(run_blocking(Some(&pool), name)).unwrap();
+        assert!(!name.unwrap_or_default().starts_with("tsf-read"));
+        // timeouts apply to threads of the runtime too
+        let result = block_on(run_blocking(Some(&pool), || {
+            thread::sleep(Duration::from_millis(500))
+        }));
+        assert_eq!(ErrorKind::TimedOut, result.unwrap_err().kind());
+        assert_eq!(1, pool.health().timeouts);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_tokio_blocking() {
+        use futures01::{future::lazy, Future as _};
+        use tokio_executor::{Executor, SpawnError};
+
+        let pool = Arc::new(ReadPool::start(&ReadPoolConfig::default()));
+        assert_eq!(0, pool.health().threads);
+        let file = || -> Reader { Box::new(Cursor::new(b"hello".to_vec())) };
+        let runtime = tokio_threadpool::ThreadPool::new();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let reading = pool.clone();
+        runtime.spawn(lazy(move || {
+            let buffer = BytesMut::from(vec![0; 5]);
+            let _ = sender.send(reading.spawn_read(file(), buffer).ok().unwrap());
+            Ok(())
+        }));
+        let (_, bytes) = block_on(receiver.recv().unwrap()).unwrap().unwrap();
+        assert_eq!(b"hello", &bytes[..]);
+
+        // runs spawned futures on threads which may not block
+        struct Detached;
+
+        impl Executor for Detached {
+            fn spawn(
+                &mut self,
+                future: Box<dyn futures01::Future<Item = (), Error = ()> + Send>,
+            ) -> Result<(), SpawnError> {
+                thread::spawn(move || future.wait());
+                Ok(())
+            }
+        }
+
+        let mut enter = tokio_executor::enter().unwrap();
+        let result = tokio_executor::with_default(&mut Detached, &mut enter, |_| {
+            let buffer = BytesMut::from(vec![0; 5]);
+            block_on(pool.spawn_read(file(), buffer).ok().unwrap())
+        });
+        let error = result.unwrap().err().unwrap();
+        assert_eq!(ErrorKind::WouldBlock, error.kind());
+    }
 }
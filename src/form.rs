@@ -0,0 +1,185 @@
+//! Files of `multipart/form-data` bodies, ref: https://tools.ietf.org/html/rfc7578
+
+use std::ops::Range;
+
+/// File field of a form, other fields are skipped
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct FormFile {
+    /// file name as sent, may be a whole client path (`C:\Users\a.txt`)
+    pub filename: String,
+    /// position of content in body
+    pub content: Range<usize>,
+}
+
+/// Boundary given by `Content-Type` of a form, `None` for other types
+pub(crate) fn form_boundary(content_type: &str) -> Option<String> {
+    let media_type = content_type.split(';').next()?.trim();
+    if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    parameters(content_type)?
+        .into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("boundary"))
+        .map(|(_, value)| value)
+        .find(|x| !x.is_empty() && x.len() <= 70)
+}
+
+/// Split `body` into its file fields, `None` if it's malformed
+pub(crate) fn parse_form(body: &[u8], boundary: &str) -> Option<Vec<FormFile>> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let mut position = find(body, &delimiter, 0)? + delimiter.len();
+    let mut files = Vec::new();
+    loop {
+        if body[position..].starts_with(b"--") {
+            return Some(files);
+        }
+        // transport padding is allowed after a delimiter
+        while body.get(position) == Some(&b' ') || body.get(position) == Some(&b'\t') {
+            position += 1;
+        }
+        if !body[position..].starts_with(b"\r\n") {
+            return None;
+        }
+        let headers_start = position + 2;
+        let headers_end = find(body, b"\r\n\r\n", headers_start)?;
+        let headers = std::str::from_utf8(&body[headers_start..headers_end]).ok()?;
+        let content_start = headers_end + 4;
+        let mut next_delimiter = b"\r\n".to_vec();
+        next_delimiter.extend_from_slice(&delimiter);
+        let content_end = find(body, &next_delimiter, content_start)?;
+        if let Some(filename) = filename(headers) {
+            files.push(FormFile {
+                filename,
+                content: content_start..content_end,
+            });
+        }
+        position = content_end + next_delimiter.len();
+    }
+}
+
+/// `filename` of `Content-Disposition` among part headers, `None` for fields which are no file
+fn filename(headers: &str) -> Option<String> {
+    let value = headers.split("\r\n").find_map(|line| {
+        let mut parts = line.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some(name), Some(value))
+                if name.trim().eq_ignore_ascii_case("content-disposition") =>
+            {
+                Some(value)
+            }
+            _ => None,
+        }
+    })?;
+    parameters(value)?
+        .into_iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case("filename"))
+        .map(|(_, value)| value)
+}
+
+/// Parameters following the value of a header such as `form-data; name="a"`, `None` for an
+/// unterminated quoted string
+///
+/// Quoted strings may hold `;`, `\"` in them is a quote and other `\` are kept as browsers send
+/// them unescaped.
+fn parameters(header: &str) -> Option<Vec<(String, String)>> {
+    let mut params = Vec::new();
+    let mut rest = match header.find(';') {
+        Some(x) => &header[x..],
+        None => return Some(params),
+    };
+    while !rest.is_empty() {
+        // skip `;`
+        rest = &rest[1..];
+        let end = rest.find(|x| x == '=' || x == ';').unwrap_or(rest.len());
+        let name = rest[..end].trim();
+        rest = &rest[end..];
+        let mut value = String::new();
+        if rest.starts_with('=') {
+            rest = rest[1..].trim_start();
+            if rest.starts_with('"') {
+                rest = &rest[1..];
+                let mut chars = rest.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (i, '"') => break i + 1,
+                        (i, '\\') if rest[i + 1..].starts_with('"') => {
+                            chars.next();
+                            value.push('"');
+                        }
+                        (_, c) => value.push(c),
+                    }
+                };
+                rest = &rest[end..];
+                rest = &rest[rest.find(';').unwrap_or(rest.len())..];
+            } else {
+                let end = rest.find(';').unwrap_or(rest.len());
+                value.push_str(rest[..end].trim());
+                rest = &rest[end..];
+            }
+        }
+        if !name.is_empty() {
+            params.push((name.to_string(), value));
+        }
+    }
+    Some(params)
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|x| x == needle)
+        .map(|x| x + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_form_boundary() {
+        assert_eq!(
+            Some("abc".to_string()),
+            form_boundary("multipart/form-data; boundary=abc")
+        );
+        assert_eq!(
+            Some("a b".to_string()),
+            form_boundary("Multipart/Form-Data;charset=utf-8; Boundary=\"a b\"")
+        );
+        assert_eq!(None, form_boundary("multipart/mixed; boundary=abc"));
+        assert_eq!(None, form_boundary("multipart/form-data"));
+    }
+
+    #[test]
+    fn test_parse_form() {
+        let body = b"preamble\r\n--XX\r\n\
+            Content-Disposition: form-data; name=\"note\"\r\n\r\n\
+            hello\r\n--XX\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            line\r\n--X\r\n--XX  \r\n\
+            content-disposition: form-data; name=\"file\"; filename=\"C:\\\\b.bin\"\r\n\r\n\
+            \r\n--XX--\r\n";
+        let files = parse_form(body, "XX").unwrap();
+        assert_eq!(2, files.len());
+        assert_eq!("a.txt", files[0].filename);
+        assert_eq!(b"line\r\n--X", &body[files[0].content.clone()]);
+        assert_eq!("C:\\\\b.bin", files[1].filename);
+        assert!(files[1].content.clone().is_empty());
+
+        assert_eq!(
+            Some("say \"hi\"; bye.txt".to_string()),
+            filename(
+                "Content-Disposition: form-data; name=\"a\"; filename=\"say \\\"hi\\\"; bye.txt\""
+            )
+        );
+        assert_eq!(
+            None,
+            filename("Content-Disposition: form-data; filename=\"a; b")
+        );
+
+        assert_eq!(None, parse_form(b"--XX\r\nno headers end", "XX"));
+        assert_eq!(None, parse_form(b"--XX\r\n\r\nno closing delimiter", "XX"));
+        assert_eq!(None, parse_form(b"nothing", "XX"));
+    }
+}
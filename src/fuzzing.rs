@@ -0,0 +1,117 @@
+//! Internal range arithmetic exposed for fuzzing, enabled by feature `fuzzing`
+//!
+//! Not a stable API, targets live in the `fuzz` directory and run with `cargo fuzz`.
+
+use crate::{
+    multi_range::{MultipartPlan, PartHeader},
+    utils,
+    validators::entity_tags,
+};
+use std::ops::Range;
+
+pub use crate::range::ByteRange;
+
+/// See `utils::actual_range`
+pub fn actual_range(byte_range: ByteRange, file_size: u64) -> Option<Range<u64>> {
+    utils::actual_range(byte_range, file_size)
+}
+
+/// See `utils::merge_ranges`
+pub fn merge_ranges(ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    utils::merge_ranges(ranges)
+}
+
+/// Length of `multipart/byteranges` body announced in `Content-Length`, `None` on overflow
+pub fn multipart_length(
+    ranges: &[Range<u64>],
+    mime: &str,
+    boundary: &str,
+    total: u64,
+) -> Option<u64> {
+    MultipartPlan::new(ranges.to_vec(), mime, boundary, total).map(|x| x.content_length())
+}
+
+/// Panic unless range resolved from `byte_range` is non-empty and inside file
+pub fn check_actual_range(byte_range: ByteRange, file_size: u64) {
+    if let Some(range) = actual_range(byte_range, file_size) {
+        assert!(range.start < range.end, "empty range {:?}", range);
+        assert!(range.end <= file_size, "{:?} beyond {}", range, file_size);
+    }
+}
+
+/// Panic unless merged ranges are sorted, disjoint, non-adjacent and cover exactly the input
+pub fn check_merge_ranges(ranges: Vec<Range<u64>>) {
+    let merged = merge_ranges(ranges.clone());
+    for pair in merged.windows(2) {
+        assert!(pair[0].end < pair[1].start, "not disjoint: {:?}", merged);
+    }
+    for range in &merged {
+        assert!(range.start < range.end, "empty range in {:?}", merged);
+    }
+    for range in ranges.iter().filter(|x| x.start < x.end) {
+        assert!(
+            merged
+                .iter()
+                .any(|x| x.start <= range.start && range.end <= x.end),
+            "{:?} not covered by {:?}",
+            range,
+            merged
+        );
+    }
+    for range in &merged {
+        // every merged range starts and ends at some input bound
+        assert!(ranges.iter().any(|x| x.start == range.start));
+        assert!(ranges.iter().any(|x| x.end == range.end));
+    }
+}
+
+/// Panic unless `multipart_length` equals length of body actually written (bodies as zeros), or
+/// is `None` exactly if that overflows
+pub fn check_multipart_length(ranges: &[Range<u64>], mime: &str, boundary: &str, total: u64) {
+    let mut body = Vec::new();
+    for range in ranges {
+        PartHeader::new(range, mime, boundary, total).write(&mut body);
+    }
+    let terminator = format!("\r\n--{}--\r\n", boundary);
+    let written = ranges
+        .iter()
+        .try_fold(body.len() as u64, |sum, x| sum.checked_add(x.end - x.start))
+        .and_then(|x| x.checked_add(terminator.len() as u64));
+    assert_eq!(written, multipart_length(ranges, mime, boundary, total));
+}
+
+/// Panic unless tokenizing an entity tag list is consistent: tags are parts of the list, at most
+/// one tag per comma, and a tag without quotes survives being quoted and tokenized again
+pub fn check_entity_tags(list: &str) {
+    let tags: Vec<_> = entity_tags(list).collect();
+    assert!(tags.len() <= list.matches(',').count() + 1);
+    for tag in tags {
+        assert!(list.contains(tag.tag), "{:?} not in {:?}", tag, list);
+        if tag.tag.contains('"') || tag.tag.trim() != tag.tag || tag.tag.is_empty() {
+            continue;
+        }
+        let quoted = format!("{}\"{}\"", if tag.weak { "W/" } else { "" }, tag.tag);
+        assert_eq!(vec![tag], entity_tags(&quoted).collect::<Vec<_>>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checks() {
+        check_actual_range(ByteRange::FromToAll(3, 100), 10);
+        check_actual_range(ByteRange::Last(u64::max_value()), 10);
+        check_actual_range(ByteRange::FromToAll(0, u64::max_value()), u64::max_value());
+        check_merge_ranges(vec![5..9, 0..2, 2..3, 7..7, 8..12]);
+        check_entity_tags(" a,, W/\"b,c\" , w/d,\"");
+        check_multipart_length(
+            &[0..1, 9..1 << 40],
+            "text/plain",
+            "DCjanus",
+            u64::max_value(),
+        );
+        check_multipart_length(&[0..1, 2..u64::max_value()], "", "", u64::max_value());
+    }
+}
@@ -0,0 +1,134 @@
+//! Health check endpoint, e.g. for liveness and readiness probes of Kubernetes
+
+use crate::{
+    file_read::{pool_health, run_blocking, ReadPoolHealth},
+    StaticFiles,
+};
+use futures::future::FutureObj;
+use http::{header, StatusCode};
+use serde::Serialize;
+use std::fs;
+use tide::{configuration::Store, Request, Response, RouteMatch};
+
+/// Outcome of [`StaticFiles::health`](struct.StaticFiles.html#method.health), serialized as
+/// body of the [health endpoint](struct.StaticFiles.html#method.health_endpoint)
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    /// whether files can be served, i.e. the root is reachable
+    pub healthy: bool,
+    /// whether the root is a directory, checked by a `stat` within the read pool
+    /// [timeout](struct.ReadPoolConfig.html#structfield.timeout), backends are assumed reachable
+    pub root_reachable: bool,
+    pub read_pool: ReadPoolHealth,
+    /// entries of the content digest cache
+    pub cached_digests: usize,
+}
+
+/// Endpoint answering `200` with a [`HealthReport`](struct.HealthReport.html) as JSON if healthy,
+/// `503` otherwise, see [`StaticFiles::health_endpoint`](struct.StaticFiles.html#method.health_endpoint)
+#[derive(Clone)]
+pub struct HealthCheck {
+    files: StaticFiles,
+}
+
+impl StaticFiles {
+    /// Check that files can be served, cheap enough to be polled
+    pub fn health(&self) -> FutureObj<'static, HealthReport> {
+        let read_pool = self.read_pool.as_ref().map(|x| &**x);
+        let root_reachable = match self.backend {
+            Some(_) => None,
+            None => {
+                let root = self.root.clone();
+                Some(run_blocking(read_pool, move || {
+                    fs::metadata(&root).map(|x| x.is_dir()).unwrap_or(false)
+                }))
+            }
+        };
+        let read_pool = pool_health(read_pool);
+        let cached_digests = self.hash_cache.entries();
+        FutureObj::new(Box::new(
+            async move {
+                // backends are assumed reachable
+                let root_reachable = match root_reachable {
+                    Some(checking) => await!(checking).unwrap_or(false),
+                    None => true,
+                };
+                HealthReport {
+                    healthy: root_reachable,
+                    root_reachable,
+                    read_pool,
+                    cached_digests,
+                }
+            },
+        ))
+    }
+
+    /// Endpoint reporting [`health`](#method.health) of these files
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use tide_static_file::StaticFiles;
+    /// let mut app = tide::App::new(());
+    /// let files = StaticFiles::new("./public").unwrap();
+    /// app.at("/_health").get(files.health_endpoint());
+    /// app.at("/*").get(files);
+    /// ```
+    pub fn health_endpoint(&self) -> HealthCheck {
+        HealthCheck {
+            files: self.clone(),
+        }
+    }
+}
+
+impl HealthCheck {
+    async fn respond(files: StaticFiles) -> Response {
+        let report = await!(files.health());
+        let status = if report.healthy {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        http::Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(serde_json::to_string(&report).unwrap().into())
+            .unwrap()
+    }
+}
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for HealthCheck {
+    type Fut = FutureObj<'static, Response>;
+
+    fn call(&self, _: Data, _: Request, _: Option<RouteMatch<'_>>, _: &Store) -> Self::Fut {
+        FutureObj::new(Box::new(Self::respond(self.files.clone())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_health() {
+        let root = TempDir::new("health");
+        let files = StaticFiles::new(&root).unwrap();
+        let endpoint = files.health_endpoint();
+
+        let respond = || block_on(HealthCheck::respond(endpoint.files.clone()));
+        let response = respond();
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!("no-store", response.headers()[header::CACHE_CONTROL]);
+        let report: serde_json::Value =
+            serde_json::from_slice(&block_on(response.into_body().into_vec()).unwrap()).unwrap();
+        assert_eq!(true, report["root_reachable"]);
+        assert!(report["read_pool"]["queued"].is_number());
+
+        fs::remove_dir_all(&root).unwrap();
+        assert!(!block_on(files.health()).healthy);
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, respond().status());
+    }
+}
@@ -0,0 +1,53 @@
+//! Hooks given by applications, which may use application data (state of `tide::App`)
+//!
+//! Data is type-erased here, a hook returns `None` if the endpoint is mounted on an app whose
+//! data is not of the type the hook expects.
+
+use std::{
+    any::Any,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tide::Request;
+
+pub(crate) type Guard = dyn Fn(&dyn Any, &Request) -> Option<bool> + Send + Sync;
+pub(crate) type RootSelector = dyn Fn(&dyn Any, &Request) -> Option<PathBuf> + Send + Sync;
+pub(crate) type ResponseMapper =
+    dyn Fn(&dyn Any, &Path, &mut http::response::Parts) -> Option<()> + Send + Sync;
+
+pub(crate) fn guard<Data: 'static>(
+    guard: impl Fn(&Data, &Request) -> bool + Send + Sync + 'static,
+) -> Arc<Guard> {
+    Arc::new(move |data, req| data.downcast_ref().map(|data| guard(data, req)))
+}
+
+pub(crate) fn root_selector<Data: 'static>(
+    selector: impl Fn(&Data, &Request) -> PathBuf + Send + Sync + 'static,
+) -> Arc<RootSelector> {
+    Arc::new(move |data, req| data.downcast_ref().map(|data| selector(data, req)))
+}
+
+pub(crate) fn response_mapper<Data: 'static>(
+    mapper: impl Fn(&Data, &Path, &mut http::response::Parts) + Send + Sync + 'static,
+) -> Arc<ResponseMapper> {
+    Arc::new(move |data, path, parts| data.downcast_ref().map(|data| mapper(data, path, parts)))
+}
+
+pub(crate) const DATA_MISMATCH: &str =
+    "hook expects application data of another type than the app this endpoint is mounted on";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_mismatch() {
+        let guard = guard(|data: &u32, _| *data == 1);
+        let req = http::Request::builder()
+            .body(http_service::Body::empty())
+            .unwrap();
+        assert_eq!(Some(true), guard(&1u32, &req));
+        assert_eq!(Some(false), guard(&2u32, &req));
+        assert_eq!(None, guard(&(), &req));
+    }
+}
@@ -2,40 +2,173 @@
 
 //! Static file server implementation, work with [Tide](https://github.com/rustasync/tide)
 
+mod alias;
+mod archive;
+mod boundary;
+mod cache_busting;
+mod cache_control;
+mod canary;
+mod cas;
+mod checksums;
+mod chunk_size;
+mod clock;
+#[cfg(all(test, feature = "test-util"))]
+mod conformance;
+mod decorator;
+mod dir_sizes;
 mod error;
+mod error_pages;
+mod etag;
+mod extensions;
 mod file_read;
+#[cfg(feature = "upload")]
+mod form;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
+mod health;
+mod hooks;
+mod listing;
+mod localize;
+mod manifest;
+mod media;
+mod memory_budget;
+mod metrics;
 mod multi_range;
+mod options;
+#[cfg(feature = "otel")]
+mod otel;
+mod path;
+mod preflight;
+mod problem;
+mod progress;
+#[cfg(feature = "metrics")]
+mod prometheus;
+#[cfg(feature = "upload")]
+mod quota;
+pub mod range;
+mod range_parser;
+mod redirects;
+#[cfg(feature = "image")]
+mod resize;
 mod single_range;
+mod tar;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod trace;
+#[cfg(feature = "upload")]
+mod upload;
 mod utils;
+mod validators;
+mod variants;
+pub mod vfs;
+mod vhost;
+mod warm;
+mod watch;
 
-pub use crate::error::TSFResult;
+#[cfg(feature = "metrics")]
+pub use crate::prometheus::PrometheusMetrics;
+#[cfg(feature = "image")]
+pub use crate::resize::ImageResizer;
 use crate::{
-    multi_range::{MultiRangeReader, PartHeader},
-    single_range::SingleRangeReader,
+    archive::ArchiveFormat,
+    chunk_size::{ChunkSizer, MIN_CHUNK_SIZE},
+    decorator::ResponseDecorator,
+    error_pages::ErrorPages,
+    etag::HashCache,
+    extensions::{apply_overrides, mark_generated},
+    file_read::{pool_health, run_blocking, ReadPool},
+    hooks::{Guard, ResponseMapper, RootSelector},
+    listing::is_listable,
+    localize::localize,
+    manifest::{AssetManifest, IMMUTABLE_CACHE_CONTROL},
+    media::{media_kind, MediaKind, MEDIA_CORS_HEADERS, PLAYLIST_CACHE_CONTROL},
+    multi_range::MultipartPlan,
+    options::Settings,
+    path::{find_case_insensitive, join_canonical},
+    problem::{accepts_problem_json, problem_response},
+    range::{ByteRange, ContentRange},
+    range_parser::parse_ranges,
+    single_range::{read_inline, SingleRangeReader, INLINE_RANGE_SIZE},
     utils::{
-        actual_range, get_header, merge_ranges, metadata, resolve_path, ErrorResponse, BOUNDARY,
-        MULTI_RANGE_CONTENT_TYPE,
+        content_location, decide_ranges, get_header, is_integrity_query, metadata, range_header,
+        validators, ErrorResponse, FileInfo, RangeDecision, MAX_BUFFER_SIZE,
     },
+    validators::{entity_tags, EntityTag},
+    variants::Variant,
+    vfs::{Reader, Target, Vfs},
 };
+pub use crate::{
+    boundary::{BoundaryGenerator, FixedBoundary, RandomBoundary},
+    cache_control::CacheControl,
+    canary::{Canary, CanaryRule},
+    checksums::SHA256SUMS,
+    clock::{Clock, FixedClock, SystemClock},
+    error::TSFResult,
+    etag::EtagMode,
+    extensions::{Deadline, ForceDownload, Generated, MultipartBoundary, OverrideMime},
+    file_read::{
+        add_read_pool, configure_read_pool, named_read_pool_health, read_pool_health,
+        ReadPoolConfig, ReadPoolHealth,
+    },
+    health::{HealthCheck, HealthReport},
+    listing::LISTING_TEMPLATE,
+    localize::MessageCatalog,
+    memory_budget::{memory_in_flight, set_memory_budget},
+    metrics::{
+        ByteServingSnapshot, ByteServingStats, DeadlineStep, Metrics, Served, RANGE_SIZE_BUCKETS,
+    },
+    options::{Config, DotfilePolicy, Options, RangeUnitPolicy},
+    path::{is_contained, normalize_path, safe_join},
+    preflight::{PreflightIssue, PreflightReport},
+    progress::{PartEvent, PartObserver},
+    redirects::RedirectRule,
+    trace::TRACE_HEADER,
+    utils::{ContentDisposition, DispositionType, FileInfo},
+    variants::{VariantResolver, WidthVariants},
+    vhost::VirtualHosts,
+    warm::WarmReport,
+    watch::{ChangeEvent, ChangeEvents, ChangeKind},
+};
+use arc_swap::ArcSwap;
 use futures::{future::FutureObj, io::ErrorKind};
 use http::{
-    header::{self, HeaderValue},
-    StatusCode,
+    header::{self, HeaderName},
+    HeaderMap, HeaderValue, Method, StatusCode,
 };
 use http_service::Body;
 use httpdate::HttpDate;
-use log::error;
-use range_header::ByteRange;
+use log::{error, warn};
+use serde_json::json;
 use std::{
-    fs::File,
+    any::Any,
     ops::Range,
     path::{Path, PathBuf},
-    time::SystemTime,
+    sync::Arc,
+    time::{Duration, SystemTime},
 };
 use tide::{configuration::Store, IntoResponse, Request, Response, RouteMatch};
 
+#[derive(Clone)]
 pub struct StaticFiles {
     root: PathBuf,
+    settings: Arc<ArcSwap<Settings>>,
+    hash_cache: Arc<HashCache>,
+    metrics: Option<Arc<dyn Metrics>>,
+    root_selector: Option<Arc<RootSelector>>,
+    backend: Option<Arc<dyn Vfs>>,
+    clock: Arc<dyn Clock>,
+    boundary: Arc<dyn BoundaryGenerator>,
+    response_mapper: Option<Arc<ResponseMapper>>,
+    guard: Option<Arc<Guard>>,
+    part_observer: Option<Arc<dyn PartObserver>>,
+    variant_resolver: Option<Arc<dyn VariantResolver>>,
+    cache_busting_rule: Option<Arc<cache_busting::CacheBustingRule>>,
+    /// the default pool if `None`
+    read_pool: Option<Arc<ReadPool>>,
+    dir_sizes: Arc<dir_sizes::DirSizes>,
+    cas: Arc<cas::CasIndex>,
+    #[cfg(feature = "upload")]
+    quotas: Arc<quota::Quotas>,
 }
 
 impl StaticFiles {
@@ -48,146 +181,1532 @@ impl StaticFiles {
             root: root
                 .canonicalize()
                 .map_err(|_| error::NoSuchDirectory(root))?,
+            settings: Arc::new(ArcSwap::new(Arc::new(Settings::default()))),
+            hash_cache: Arc::new(HashCache::default()),
+            metrics: None,
+            root_selector: None,
+            backend: None,
+            clock: Arc::new(SystemClock),
+            boundary: Arc::new(RandomBoundary),
+            response_mapper: None,
+            guard: None,
+            part_observer: None,
+            variant_resolver: None,
+            cache_busting_rule: None,
+            read_pool: None,
+            dir_sizes: Arc::default(),
+            cas: Arc::default(),
+            #[cfg(feature = "upload")]
+            quotas: Arc::default(),
         })
     }
+
+    /// Serve entries of given backend instead of local files, see [`vfs`](vfs/index.html)
+    ///
+    /// [`root_selector`](struct.StaticFiles.html#method.root_selector) is ignored
+    /// in this case.
+    pub fn from_backend(backend: impl Vfs) -> Self {
+        Self {
+            root: PathBuf::new(),
+            settings: Arc::new(ArcSwap::new(Arc::new(Settings::default()))),
+            hash_cache: Arc::new(HashCache::default()),
+            metrics: None,
+            root_selector: None,
+            backend: Some(Arc::new(backend)),
+            clock: Arc::new(SystemClock),
+            boundary: Arc::new(RandomBoundary),
+            response_mapper: None,
+            guard: None,
+            part_observer: None,
+            variant_resolver: None,
+            cache_busting_rule: None,
+            read_pool: None,
+            dir_sizes: Arc::default(),
+            cas: Arc::default(),
+            #[cfg(feature = "upload")]
+            quotas: Arc::default(),
+        }
+    }
+
+    /// Create from a [`Config`](struct.Config.html), which is usually loaded from a config file
+    pub fn from_config(config: Config) -> TSFResult<Self> {
+        Self::new(config.root)?.options(config.options)
+    }
+
+    /// Replace all options, the asset manifest will be (re)loaded if specified
+    pub fn options(self, options: Options) -> TSFResult<Self> {
+        self.reload(options)?;
+        Ok(self)
+    }
+
+    /// Atomically replace all options of a running instance, the asset manifest will be
+    /// (re)loaded if specified
+    ///
+    /// All clones of this instance share the same options, so keep a clone before
+    /// registering the endpoint to reload it later. Requests in flight keep using
+    /// the options they started with.
+    pub fn reload(&self, options: Options) -> TSFResult<()> {
+        self.settings.store(Arc::new(Settings::load(options)?));
+        Ok(())
+    }
+
+    /// Return a copy of current options
+    pub fn current_options(&self) -> Options {
+        self.settings.load().options.clone()
+    }
+
+    /// Set strategy used to generate `ETag`, see [`EtagMode`](enum.EtagMode.html)
+    ///
+    /// Use [`EtagMode::Content`](enum.EtagMode.html#variant.Content) or
+    /// [`EtagMode::Build`](enum.EtagMode.html#variant.Build) when serving the same files from
+    /// multiple machines, so that all of them emit identical validators.
+    pub fn etag_mode(self, mode: EtagMode) -> Self {
+        self.update(|x| x.options.etag_mode = mode);
+        self
+    }
+
+    /// Load a JSON asset manifest (as generated by webpack or Vite), which maps logical names
+    /// (`app.js`) to fingerprinted files (`app.3f9ab2.js`), paths are relative to root
+    ///
+    /// Requests for logical names are served with the fingerprinted file,
+    /// requests for fingerprinted files are served with immutable cache headers.
+    pub fn manifest(self, path: impl AsRef<Path>) -> TSFResult<Self> {
+        let manifest = Arc::new(AssetManifest::load(path.as_ref())?);
+        self.update(|x| {
+            x.manifest = Some(manifest);
+            x.options.manifest = Some(path.as_ref().to_path_buf());
+        });
+        Ok(self)
+    }
+
+    /// Answer error responses with pages of given directory named after their status, e.g.
+    /// `404.html` or `416.txt`, served with the type of their extension, other statuses keep
+    /// built-in text
+    ///
+    /// Pages are read once into memory, set again to reload them. Clients asking for
+    /// [problem details](#method.problem_details) still get those.
+    pub fn error_pages(self, dir: impl AsRef<Path>) -> TSFResult<Self> {
+        let pages = Arc::new(ErrorPages::load(dir.as_ref())?);
+        self.update(|x| {
+            x.error_pages = Some(pages);
+            x.options.error_pages = Some(dir.as_ref().to_path_buf());
+        });
+        Ok(self)
+    }
+
+    /// Answer requests with query `?sri=1` by a JSON document like `{"integrity": "sha384-..."}`
+    /// instead of file content, default off
+    pub fn expose_integrity(self, enable: bool) -> Self {
+        self.update(|x| x.options.expose_integrity = enable);
+        self
+    }
+
+    /// Answer `4xx` and `5xx` responses of given status with `message` instead of the built-in
+    /// English text to requests preferring `language` (e.g. `de`, `pt-BR`) by `Accept-Language`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use http::StatusCode;
+    /// # use tide_static_file::StaticFiles;
+    /// let files = StaticFiles::new("./public")
+    ///     .unwrap()
+    ///     .error_message("de", StatusCode::NOT_FOUND, "Nicht gefunden")
+    ///     .error_message("fr", StatusCode::NOT_FOUND, "Introuvable");
+    /// ```
+    pub fn error_message(
+        self,
+        language: impl Into<String>,
+        status: StatusCode,
+        message: impl Into<String>,
+    ) -> Self {
+        let (language, message) = (language.into(), message.into());
+        self.update(|x| {
+            x.options
+                .error_messages
+                .entry(language)
+                .or_default()
+                .insert(status.as_u16(), message);
+        });
+        self
+    }
+
+    /// Answer requests carrying header [`TRACE_HEADER`](constant.TRACE_HEADER.html) by a JSON
+    /// explanation of how they are resolved and answered instead, default off
+    ///
+    /// It exposes file system layout, never turn it on in production.
+    pub fn debug_trace(self, enable: bool) -> Self {
+        self.update(|x| x.options.debug_trace = enable);
+        self
+    }
+
+    /// Render bodies of `4xx` and `5xx` responses as `application/problem+json` (RFC 7807) to
+    /// requests accepting `application/problem+json` or `application/json`, default off
+    pub fn problem_details(self, enable: bool) -> Self {
+        self.update(|x| x.options.problem_details = enable);
+        self
+    }
+
+    /// Answer requests for `<directory>/SHA256SUMS` by a generated checksum manifest of regular
+    /// files in that directory, in the format of `sha256sum`, default off
+    ///
+    /// Digests are computed on first request and cached until files change. An existing file
+    /// with the same name is served as is.
+    pub fn sha256sums(self, enable: bool) -> Self {
+        self.update(|x| x.options.sha256sums = enable);
+        self
+    }
+
+    /// Set whether files and directories whose name starts with `.` are served, default
+    /// [`DotfilePolicy::Allow`](enum.DotfilePolicy.html#variant.Allow)
+    pub fn dotfiles(self, policy: DotfilePolicy) -> Self {
+        self.update(|x| x.options.dotfiles = policy);
+        self
+    }
+
+    /// Answer `/robots.txt` by given content instead of any such file, e.g.
+    /// `"User-agent: *\nDisallow: /private/\n"`
+    pub fn robots_txt(self, content: impl Into<String>) -> Self {
+        let content = content.into();
+        self.update(|x| x.options.robots_txt = Some(content));
+        self
+    }
+
+    /// Serve `/.well-known/acme-challenge/<token>` from given directory instead of root, for
+    /// ACME HTTP-01 challenges (e.g. `certbot certonly --webroot`), regardless of
+    /// [`dotfiles`](#method.dotfiles) and storage backend
+    pub fn acme_challenge_dir(self, path: impl AsRef<Path>) -> TSFResult<Self> {
+        let canonical = path.as_ref().canonicalize()?;
+        self.update(|x| {
+            x.acme_challenge_dir = Some(canonical);
+            x.options.acme_challenge_dir = Some(path.as_ref().to_path_buf());
+        });
+        Ok(self)
+    }
+
+    /// Serve the file at path `to` for requests of path `from`, both relative to root, e.g.
+    /// `favicon.ico` from `assets/icons/fav.ico`
+    ///
+    /// Aliases are resolved before anything else (fingerprinted names of the
+    /// [manifest](#method.manifest) included), and are served like the file itself, not
+    /// redirected, with the `Content-Location` of the file.
+    pub fn alias(self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        let (from, to) = (from.into(), to.into());
+        self.update(|x| {
+            x.options.aliases.insert(from, to);
+        });
+        self
+    }
+
+    /// Redirect requests for path `from` (relative to root) to `to` with `status`, one of
+    /// `301`, `302`, `307` and `308`
+    ///
+    /// `from` matches exactly, or as a prefix if it ends with `*`, e.g. `docs/*`, whose match
+    /// replaces a trailing `*` of `to`. `to` is sent as `Location` as is (an absolute path or
+    /// URL), with bytes not allowed in a URI percent-encoded and the query of the request
+    /// appended unless it has its own. Redirects are checked in the order they were added,
+    /// before anything else is resolved.
+    pub fn redirect(
+        self,
+        from: impl Into<String>,
+        to: impl Into<String>,
+        status: StatusCode,
+    ) -> TSFResult<Self> {
+        let rule = RedirectRule {
+            from: from.into(),
+            to: to.into(),
+            status: status.as_u16(),
+        };
+        redirects::validate_redirects(std::slice::from_ref(&rule))?;
+        self.update(|x| x.options.redirects.push(rule));
+        Ok(self)
+    }
+
+    /// Answer requests for directories by a listing of their content, default off
+    ///
+    /// Listings are HTML, or JSON if asked by `Accept: application/json` or `?format=json`,
+    /// every entry comes with a rough kind (`image`, `archive`, `directory`, ...) and icon CSS
+    /// classes (`icon icon-image`). Hidden (dot) files are not listed. Only applies to the local
+    /// file system.
+    pub fn listing(self, enable: bool) -> Self {
+        self.update(|x| x.options.listing = enable);
+        self
+    }
+
+    /// With [`listing`](#method.listing), only list directories containing a file of given name
+    /// (e.g. `.listing`), others are not found, so that enabling listings doesn't expose every
+    /// directory
+    pub fn listing_marker(self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.update(|x| x.options.listing_marker = Some(name));
+        self
+    }
+
+    /// Render HTML [listings](#method.listing) with given template instead of
+    /// [`LISTING_TEMPLATE`](constant.LISTING_TEMPLATE.html), which documents its placeholders
+    pub fn listing_template(self, template: impl Into<String>) -> Self {
+        let template = template.into();
+        self.update(|x| x.options.listing_template = Some(template));
+        self
+    }
+
+    /// Show total size and number of files of directories in [listings](#method.listing),
+    /// default off
+    ///
+    /// Directories are walked in background threads (a few at once, giving up on trees of more
+    /// than 100000 entries) and summaries are cached for five minutes, so a listing never waits
+    /// for them: directories not walked yet show no size until a later request. See
+    /// [`listing_sizes_watch`](#method.listing_sizes_watch) to refresh them on changes.
+    pub fn listing_sizes(self, enable: bool) -> Self {
+        self.update(|x| x.options.listing_sizes = enable);
+        self
+    }
+
+    /// Forget cached [directory sizes](#method.listing_sizes) as soon as `events` report a
+    /// change below them, `events` should watch the root of these files
+    pub fn listing_sizes_watch(self, events: &ChangeEvents) -> Self {
+        dir_sizes::DirSizes::invalidate_on(&self.dir_sizes, self.root.clone(), events);
+        self
+    }
+
+    /// Also serve files by SHA-256 of their content below given prefix, e.g.
+    /// `cas/2cf24dba...` with prefix `cas`, with immutable caching, default off
+    ///
+    /// Files below root (but hidden ones) are hashed by a background thread once enabled, those
+    /// not hashed yet are not found. Hashes are checked before serving, and changed files are
+    /// hashed again when requested by their old hash; see
+    /// [`content_addressed_watch`](#method.content_addressed_watch) to find them by their new
+    /// content right away. Digests are shared with `ETag`s of
+    /// [`EtagMode::Content`](enum.EtagMode.html#variant.Content). Only applies to the local file
+    /// system. With a [root selector](#method.root_selector), only files below the selected root
+    /// are found; the [dotfiles policy](#method.dotfiles) applies to their path in it.
+    pub fn content_addressed(self, prefix: impl Into<String>) -> Self {
+        let prefix = prefix.into();
+        self.update(|x| x.options.content_addressed = Some(prefix));
+        if self.backend.is_none() {
+            cas::CasIndex::scan(&self.cas, self.root.clone(), self.hash_cache.clone());
+        }
+        self
+    }
+
+    /// Hash files again as `events` report changes, so that
+    /// [content addresses](#method.content_addressed) follow them right away, `events` should
+    /// watch the root of these files
+    pub fn content_addressed_watch(self, events: &ChangeEvents) -> Self {
+        let cache = self.hash_cache.clone();
+        cas::CasIndex::watch(&self.cas, self.root.clone(), cache, events);
+        self
+    }
+
+    /// Answer `?download=zip` or `?download=tar` on directories which may be
+    /// [listed](#method.listing) by an archive of their files, default off
+    ///
+    /// Archives are streamed while they are built, files stored uncompressed. ZIP archives have
+    /// ZIP64 fields where needed so that trees of more than 4 GiB or 65535 files make valid
+    /// archives. Tar archives are the same bytes as long as the files are, with an `ETag` and a
+    /// known length, so that interrupted downloads resume with `Range` (a single range). Hidden
+    /// (dot) files are left out, like from listings. A few archives are built at once, further
+    /// downloads are answered with `503 Service Unavailable` meanwhile.
+    pub fn archive_downloads(self, enable: bool) -> Self {
+        self.update(|x| x.options.archive_downloads = enable);
+        self
+    }
+
+    /// Leave entries matching given glob pattern out of [archives](#method.archive_downloads)
+    ///
+    /// Patterns without `/` match names at any depth (e.g. `.git`, `node_modules`, `*.log`),
+    /// others paths relative to the archived directory (e.g. `docs/**/*.pdf`); `*` and `?` don't
+    /// match `/`, `**` matches any number of directories. Excluded directories are not walked.
+    pub fn archive_exclude(self, pattern: impl Into<String>) -> Self {
+        let pattern = pattern.into();
+        self.update(|x| x.options.archive_excludes.push(pattern));
+        self
+    }
+
+    /// Reject [archives](#method.archive_downloads) larger than given size with
+    /// `413 Payload Too Large`, default unlimited
+    ///
+    /// The size is estimated from sizes of the files before anything is sent.
+    pub fn max_archive_size(self, size: u64) -> Self {
+        self.update(|x| x.options.max_archive_size = Some(size));
+        self
+    }
+
+    /// Find files ignoring case of url path when there is no exact match, default off
+    ///
+    /// Useful for content migrated from case-insensitive file systems. Ambiguous paths (e.g.
+    /// both `a.txt` and `A.TXT` exist) are not found. Only applies to the local file system.
+    pub fn case_insensitive(self, enable: bool) -> Self {
+        self.update(|x| x.options.case_insensitive = enable);
+        self
+    }
+
+    /// With [`case_insensitive`](#method.case_insensitive), answer `301 Moved Permanently` to
+    /// the on-disk casing instead of serving directly, so that every file has only one URL,
+    /// default off
+    pub fn canonical_case_redirect(self, enable: bool) -> Self {
+        self.update(|x| x.options.canonical_case_redirect = enable);
+        self
+    }
+
+    /// Set how to answer a `Range` header of a unit other than `bytes`, default
+    /// [`RangeUnitPolicy::Ignore`](enum.RangeUnitPolicy.html#variant.Ignore)
+    pub fn range_units(self, policy: RangeUnitPolicy) -> Self {
+        self.update(|x| x.options.range_units = policy);
+        self
+    }
+
+    /// Add a header to every response for a file, including `304`, `404` and `416`, unless the
+    /// response sets it already (e.g. `Cache-Control` of fingerprinted files)
+    pub fn header(self, name: HeaderName, value: HeaderValue) -> Self {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        self.update(|x| {
+            x.options.headers.insert(name.as_str().to_string(), value);
+        });
+        self
+    }
+
+    /// Send `X-Content-Type-Options: nosniff`, `X-Frame-Options: SAMEORIGIN` and
+    /// `Referrer-Policy: strict-origin-when-cross-origin` with every response for a file,
+    /// default off
+    ///
+    /// Headers given by [`header`](#method.header) take precedence.
+    pub fn security_headers(self, enable: bool) -> Self {
+        self.update(|x| x.options.security_headers = enable);
+        self
+    }
+
+    /// Send `Last-Modified` of files modified in the future (clock skew, extracted archives) as
+    /// current time, with a weak `ETag`, default off
+    ///
+    /// Such files can't be resumed by `If-Range` or matched by `If-Match` until their time has
+    /// come.
+    pub fn clamp_last_modified(self, enable: bool) -> Self {
+        self.update(|x| x.options.clamp_last_modified = enable);
+        self
+    }
+
+    /// Allow players on any origin to fetch HLS and DASH playlists and segments (`.m3u8`,
+    /// `.mpd`, `.ts`, `.m4s`) by CORS headers, default off
+    ///
+    /// Their MIME types are always set, and playlists are sent with `Cache-Control: no-cache`
+    /// as live streams keep rewriting them.
+    pub fn media_cors(self, enable: bool) -> Self {
+        self.update(|x| x.options.media_cors = enable);
+        self
+    }
+
+    /// Send `Surrogate-Control` (Fastly, Akamai, Varnish) with successful and `304` responses,
+    /// so edges cache for another lifetime than browsers, default none
+    ///
+    /// A value set by the response is kept.
+    pub fn surrogate_control(self, value: HeaderValue) -> Self {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        self.update(|x| x.options.surrogate_control = Some(value));
+        self
+    }
+
+    /// Send `CDN-Cache-Control` (Cloudflare, CloudFront) with successful and `304` responses,
+    /// see [`surrogate_control`](#method.surrogate_control)
+    pub fn cdn_cache_control(self, value: HeaderValue) -> Self {
+        let value = String::from_utf8_lossy(value.as_bytes()).into_owned();
+        self.update(|x| x.options.cdn_cache_control = Some(value));
+        self
+    }
+
+    /// Raise `max-age` and `s-maxage` of `Cache-Control`, `Surrogate-Control` and
+    /// `CDN-Cache-Control` below given lifetime, default none
+    ///
+    /// Applied to every response after any other header rule (asset manifest, configured
+    /// headers), clamping is logged and reported to
+    /// [`Metrics::cache_age_clamped`](trait.Metrics.html#method.cache_age_clamped).
+    pub fn min_max_age(self, age: Duration) -> Self {
+        self.update(|x| x.options.min_max_age = Some(age.as_secs()));
+        self
+    }
+
+    /// Lower `max-age` and `s-maxage` of `Cache-Control` above given lifetime, default none
+    ///
+    /// See [`min_max_age`](#method.min_max_age), this bound wins if the minimum exceeds it.
+    pub fn max_max_age(self, age: Duration) -> Self {
+        self.update(|x| x.options.max_max_age = Some(age.as_secs()));
+        self
+    }
+
+    /// Bounds of chunks read from files, default 16 KiB to 4 MiB
+    ///
+    /// Within them, chunks are sized by how fast the client consumes the body: small for slow
+    /// (mobile) clients to save memory per connection, large for fast (LAN) ones to save reads.
+    /// A maximum below the minimum is raised to it.
+    pub fn chunk_size_bounds(self, min: usize, max: usize) -> Self {
+        self.update(|x| {
+            x.options.min_chunk_size = Some(min);
+            x.options.max_chunk_size = Some(max);
+        });
+        self
+    }
+
+    /// Accept `PUT` and `DELETE` of files below given path (e.g. `inbox`, or `/` for
+    /// everything), and `POST` of `multipart/form-data` forms with files into it or directories
+    /// below, others get `405 Method Not Allowed`, default none
+    ///
+    /// Uploads are written to a temporary file and renamed once complete, missing directories
+    /// are created, directories are never replaced or removed. Authorize writes with a
+    /// [`guard`](#method.guard) checking the method. Backends are read-only.
+    #[cfg(feature = "upload")]
+    pub fn upload_path(self, path: impl Into<String>) -> Self {
+        let path = path.into();
+        self.update(|x| x.options.upload_paths.push(path));
+        self
+    }
+
+    /// Reject uploads larger than given size with `413 Payload Too Large`, default unlimited
+    /// for `PUT` and 64 MiB for forms, which are buffered in memory
+    #[cfg(feature = "upload")]
+    pub fn max_upload_size(self, size: u64) -> Self {
+        self.update(|x| x.options.max_upload_size = Some(size));
+        self
+    }
+
+    /// Reject uploads with `507 Insufficient Storage` once files below root would take more than
+    /// given size, default unlimited
+    ///
+    /// Usage is scanned on first upload and every few minutes in the background, and accounted on
+    /// writes of this endpoint in between.
+    #[cfg(feature = "upload")]
+    pub fn upload_quota(self, size: u64) -> Self {
+        self.update(|x| x.options.upload_quota = Some(size));
+        self
+    }
+
+    /// Same as [`upload_quota`](#method.upload_quota), for files below each
+    /// [`upload_path`](#method.upload_path) on its own
+    #[cfg(feature = "upload")]
+    pub fn upload_path_quota(self, size: u64) -> Self {
+        self.update(|x| x.options.upload_path_quota = Some(size));
+        self
+    }
+
+    /// Report served responses to given [`Metrics`](trait.Metrics.html),
+    /// e.g. [`ByteServingStats`](struct.ByteServingStats.html)
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Read files on the pool named `name` instead of the default one shared by all instances,
+    /// see [`add_read_pool`](fn.add_read_pool.html), which must have been called before
+    ///
+    /// Health reported to [`Metrics`](trait.Metrics.html) and by [`health`](#method.health)
+    /// is the one of this pool.
+    pub fn read_pool(mut self, name: &str) -> TSFResult<Self> {
+        match file_read::named_read_pool(name) {
+            Some(pool) => self.read_pool = Some(pool),
+            None => return Err(error::NoSuchReadPool(name.to_string()).into()),
+        }
+        Ok(self)
+    }
+
+    /// Report progress of parts of `multipart/byteranges` responses to given
+    /// [`PartObserver`](trait.PartObserver.html)
+    pub fn part_observer(mut self, observer: Arc<dyn PartObserver>) -> Self {
+        self.part_observer = Some(observer);
+        self
+    }
+
+    /// Serve pre-generated variants of files requested by query, e.g. thumbnails by
+    /// [`WidthVariants`](struct.WidthVariants.html)
+    ///
+    /// The original is served if the variant doesn't exist, with `Cache-Control: no-cache` so
+    /// that caches pick up the variant once generated. A found variant carries
+    /// `Content-Location`. Responses carry `Vary` naming the request header the resolver also
+    /// chooses by, if any (e.g. client hint `Sec-CH-Width`), so that shared caches keep variants
+    /// apart.
+    pub fn variant_resolver(mut self, resolver: Arc<dyn VariantResolver>) -> Self {
+        self.variant_resolver = Some(resolver);
+        self
+    }
+
+    /// Ignore query parameter `name` (e.g. `v` of `app.js?v=3`) when resolving files, as
+    /// templates append it to bust caches
+    ///
+    /// Such requests are cached like any other unless a
+    /// [`cache_busting_rule`](#method.cache_busting_rule) accepts their version. They carry the
+    /// same `Vary` as requests without the parameter (e.g. naming the header a
+    /// [variant resolver](#method.variant_resolver) chooses by), so that a representation cached
+    /// as immutable is never reused for other values of that header.
+    pub fn cache_busting_param(self, name: impl Into<String>) -> Self {
+        let name = name.into();
+        self.update(|x| x.options.cache_busting_params.push(name));
+        self
+    }
+
+    /// Serve requests carrying a [cache-busting parameter](#method.cache_busting_param) with
+    /// immutable cache headers if `rule` returns `true` given the logical path of the file and
+    /// the parameter's value, e.g. when the value is the file's current build number
+    ///
+    /// Versions `rule` rejects (stale templates) are cached like the file without one.
+    pub fn cache_busting_rule(
+        mut self,
+        rule: impl Fn(&str, &str) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.cache_busting_rule = Some(Arc::new(rule));
+        self
+    }
+
+    /// Use given source of current time instead of system time, e.g.
+    /// [`FixedClock`](struct.FixedClock.html) for reproducible responses
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Use given generator of multipart boundaries instead of random ones, e.g.
+    /// [`FixedBoundary`](struct.FixedBoundary.html) for reproducible responses
+    pub fn boundary_generator(mut self, generator: Arc<dyn BoundaryGenerator>) -> Self {
+        self.boundary = generator;
+        self
+    }
+
+    /// Pick the document root dynamically per request, e.g. `/data/tenants/{tenant-id}/public`
+    /// from a header or subdomain
+    ///
+    /// The selected root is canonicalized, and must be a directory inside the root given to
+    /// [`StaticFiles::new`](struct.StaticFiles.html#method.new), otherwise `404 Not Found`
+    /// is returned.
+    pub fn root_selector(
+        mut self,
+        selector: impl Fn(&Request) -> PathBuf + Send + Sync + 'static,
+    ) -> Self {
+        self.root_selector = Some(Arc::new(move |_, req| Some(selector(req))));
+        self
+    }
+
+    /// Same as [`root_selector`](#method.root_selector), with access to data of the app this
+    /// endpoint is mounted on, `404 Not Found` is returned if app data is not of type `Data`
+    pub fn root_selector_with_data<Data: 'static>(
+        mut self,
+        selector: impl Fn(&Data, &Request) -> PathBuf + Send + Sync + 'static,
+    ) -> Self {
+        self.root_selector = Some(hooks::root_selector(selector));
+        self
+    }
+
+    /// Only serve requests for which `guard` returns `true` given data of the app this endpoint
+    /// is mounted on, e.g. to check a session against a store, others get `403 Forbidden`
+    ///
+    /// `403 Forbidden` is also returned if app data is not of type `Data`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use tide_static_file::StaticFiles;
+    /// # use std::collections::HashSet;
+    /// let files = StaticFiles::new("./private")
+    ///     .unwrap()
+    ///     .guard(|tokens: &HashSet<String>, req| {
+    ///         req.headers()
+    ///             .get("x-token")
+    ///             .and_then(|x| x.to_str().ok())
+    ///             .map(|x| tokens.contains(x))
+    ///             .unwrap_or(false)
+    ///     });
+    /// ```
+    pub fn guard<Data: 'static>(
+        mut self,
+        guard: impl Fn(&Data, &Request) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.guard = Some(hooks::guard(guard));
+        self
+    }
+
+    /// Adjust every response for a file (including `304`, `206`, `412` and `416`) just before
+    /// it's sent, e.g. add headers depending on file path
+    ///
+    /// Called with the path of the file (relative to backend root for
+    /// [backends](vfs/index.html)) and status and headers of the response.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use tide_static_file::StaticFiles;
+    /// let files = StaticFiles::new("./public").unwrap().map_response(|path, parts| {
+    ///     if path.extension().map(|x| x == "wasm").unwrap_or(false) {
+    ///         parts.headers.insert("cross-origin-resource-policy", "same-origin".parse().unwrap());
+    ///     }
+    /// });
+    /// ```
+    pub fn map_response(
+        mut self,
+        mapper: impl Fn(&Path, &mut http::response::Parts) + Send + Sync + 'static,
+    ) -> Self {
+        self.response_mapper = Some(Arc::new(move |_, path, parts| {
+            mapper(path, parts);
+            Some(())
+        }));
+        self
+    }
+
+    /// Same as [`map_response`](#method.map_response), with access to data of the app this
+    /// endpoint is mounted on, responses are left untouched if app data is not of type `Data`
+    pub fn map_response_with_data<Data: 'static>(
+        mut self,
+        mapper: impl Fn(&Data, &Path, &mut http::response::Parts) + Send + Sync + 'static,
+    ) -> Self {
+        self.response_mapper = Some(hooks::response_mapper(mapper));
+        self
+    }
+
+    /// Return [Subresource Integrity](https://www.w3.org/TR/SRI/) string (`sha384-...`)
+    /// of the file which would be served for given url path, `None` if not found
+    ///
+    /// The digest is cached alongside content hashes until the file changes.
+    pub fn integrity(&self, url_path: &str) -> TSFResult<Option<String>> {
+        match self
+            .target_path(&self.settings.load(), &self.root, url_path, url_path)
+            .target
+        {
+            None => Ok(None),
+            Some(target) => self.hash_cache.integrity(&target).map(Some),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Settings)) {
+        let mut settings = Settings::clone(&self.settings.load());
+        f(&mut settings);
+        self.settings.store(Arc::new(settings));
+    }
+
+    /// Given url path, return the entry to be served and how it was selected
+    ///
+    /// `request_path` is the whole path of request URI, ending with `url_path`.
+    fn target_path(
+        &self,
+        settings: &Settings,
+        root: &Path,
+        request_path: &str,
+        url_path: &str,
+    ) -> Selection {
+        let aliased = alias::resolve_alias(&settings.options.aliases, url_path).unwrap_or(url_path);
+        let (served_path, immutable) = match &settings.manifest {
+            None => (aliased, false),
+            Some(manifest) => manifest.resolve(aliased),
+        };
+        let target = match &self.backend {
+            None => join_canonical(root, served_path).map(Target::Local),
+            Some(backend) => {
+                normalize_path(served_path).map(|x| Target::Backend(backend.clone(), x))
+            }
+        };
+        let content_location = if served_path != url_path {
+            content_location(request_path, url_path, served_path)
+        } else {
+            None
+        };
+        let mut selection = Selection {
+            target,
+            immutable,
+            content_location,
+            redirect: None,
+            variant_missing: false,
+        };
+        if selection.target.is_none() && self.backend.is_none() && settings.options.case_insensitive
+        {
+            let found = normalize_path(served_path)
+                .and_then(|x| find_case_insensitive(root, &x))
+                .filter(|(_, path)| path.is_file());
+            if let Some((canonical, path)) = found {
+                let location = content_location(request_path, url_path, &canonical);
+                if settings.options.canonical_case_redirect && location.is_some() {
+                    selection.redirect = location;
+                } else {
+                    selection.target = Some(Target::Local(path));
+                    selection.content_location = location;
+                }
+            }
+        }
+        selection
+    }
+
+    /// Selection of the file below `root` with content of SHA-256 `hash`, served as immutable
+    fn cas_selection(&self, settings: &Settings, root: &Path, hash: &str) -> Selection {
+        cas::CasIndex::scan(&self.cas, self.root.clone(), self.hash_cache.clone());
+        // the index is of the whole root, `root` may be a selected directory inside it
+        let within = root
+            .strip_prefix(&self.root)
+            .ok()
+            .and_then(Path::to_str)
+            .map(str::to_string);
+        let dotfiles = settings.options.dotfiles;
+        let target = within.and_then(|within| {
+            let accept = |logical_path: &str| {
+                let relative = match within.as_str() {
+                    "" => Some(logical_path),
+                    within => match logical_path.get(within.len()..) {
+                        Some(x) if logical_path.starts_with(within) && x.starts_with('/') => {
+                            Some(&x[1..])
+                        }
+                        _ => None,
+                    },
+                };
+                relative.map_or(false, |x| dotfiles.allows(x))
+            };
+            self.cas.lookup(&self.root, hash, &self.hash_cache, accept)
+        });
+        Selection {
+            target: target.map(Target::Local),
+            immutable: true,
+            ..Selection::default()
+        }
+    }
+
+    /// Look up the variant of entry at `url_path` requested by `query` and `hints`
+    fn find_variant(
+        &self,
+        root: &Path,
+        url_path: &str,
+        query: Option<&str>,
+        hints: &HeaderMap,
+    ) -> Variant {
+        let resolver = match &self.variant_resolver {
+            Some(resolver) => resolver,
+            None => return Variant::None,
+        };
+        let query = query.unwrap_or("");
+        let variant =
+            match normalize_path(url_path).and_then(|x| resolver.resolve(&x, query, hints)) {
+                Some(x) => x,
+                None => return Variant::None,
+            };
+        let exists = match &self.backend {
+            None => join_canonical(root, &variant)
+                .map(|x| x.is_file())
+                .unwrap_or(false),
+            Some(backend) => normalize_path(&variant)
+                .map(|x| backend.metadata(&x).is_ok())
+                .unwrap_or(false),
+        };
+        if exists {
+            Variant::Found(variant)
+        } else {
+            Variant::Missing
+        }
+    }
+
+    /// Headers of request the variant resolver chooses by besides the query
+    fn variant_hints(&self, req: &Request) -> HeaderMap {
+        let mut hints = HeaderMap::new();
+        if let Some(name) = self.variant_resolver.as_ref().and_then(|x| x.vary()) {
+            for value in req.headers().get_all(name) {
+                hints.append(name, value.clone());
+            }
+        }
+        hints
+    }
+
+    /// Document root the root selector chose for given request, `None` if it failed, see
+    /// [`check_root`](#method.check_root)
+    fn selected_root(&self, data: &dyn Any, req: &Request) -> Option<PathBuf> {
+        let selector = match &self.root_selector {
+            Some(x) if self.backend.is_none() => x,
+            _ => return Some(self.root.clone()),
+        };
+        let selected = selector(data, req);
+        if selected.is_none() {
+            error!("{}", hooks::DATA_MISMATCH);
+        }
+        selected
+    }
+
+    /// Canonical `selected` root, `None` unless it's a directory inside root
+    fn check_root(&self, selected: PathBuf) -> Option<PathBuf> {
+        if selected == self.root {
+            return Some(selected);
+        }
+        let root = match selected.canonicalize() {
+            Ok(x) => x,
+            Err(error) => {
+                warn!("failed to canonicalize root {:?}: {}", selected, error);
+                return None;
+            }
+        };
+        if !root.is_dir() || !root.starts_with(&self.root) {
+            warn!("selected root {:?} is not a directory inside root", root);
+            return None;
+        }
+        Some(root)
+    }
+
+    /// What `request` is answered with below the `selected` root, run on the read pool as every
+    /// step may touch the file system
+    fn resolve(
+        &self,
+        settings: &Settings,
+        selected: Option<PathBuf>,
+        request: &Resolving,
+    ) -> Resolution {
+        let root = selected.and_then(|x| self.check_root(x));
+        let url_path = request.url_path.as_ref().map(String::as_str);
+        let acme_challenge = match (&settings.acme_challenge_dir, url_path) {
+            (Some(dir), Some(url_path)) => acme_challenge_token(url_path).map(|token| Selection {
+                target: join_canonical(dir, &token)
+                    .filter(|x| x.is_file())
+                    .map(Target::Local),
+                ..Selection::default()
+            }),
+            _ => None,
+        };
+        let cas_hash = match (&settings.options.content_addressed, url_path) {
+            (Some(prefix), Some(url_path)) if self.backend.is_none() => {
+                cas::requested_hash(prefix, url_path)
+            }
+            _ => None,
+        };
+        let (selection, checksums_dir) = match (acme_challenge, cas_hash, &root, url_path) {
+            (Some(selection), ..) => (selection, None),
+            (None, Some(hash), Some(root), _) => (self.cas_selection(settings, root, &hash), None),
+            (None, None, Some(_), Some(url_path))
+                if !settings.options.dotfiles.allows(url_path) =>
+            {
+                (Selection::default(), None)
+            }
+            (None, None, Some(root), Some(url_path)) => {
+                let busting_params = &settings.options.cache_busting_params;
+                let query = request.query.as_ref().map(String::as_str);
+                let stripped = cache_busting::strip_params(query, busting_params);
+                let stripped = stripped.as_ref().map(|x| &**x);
+                let variant = self.find_variant(root, url_path, stripped, &request.hints);
+                let served_path = match &variant {
+                    Variant::Found(x) => x.as_str(),
+                    _ => url_path,
+                };
+                let request_path = &request.request_path;
+                let mut selection = self.target_path(settings, root, request_path, served_path);
+                match &variant {
+                    Variant::Found(x) => {
+                        selection.content_location = content_location(request_path, url_path, x)
+                    }
+                    Variant::Missing => selection.variant_missing = true,
+                    Variant::None => {}
+                }
+                let version = cache_busting::version(query, busting_params);
+                if let (Some(rule), Some(version)) = (&self.cache_busting_rule, version) {
+                    let current = selection.target.is_some()
+                        && !selection.variant_missing
+                        && normalize_path(url_path).map_or(false, |x| rule(&x, version));
+                    selection.immutable |= current;
+                }
+                let checksums_dir = match selection.target {
+                    None => self.checksums_dir(settings, root, url_path),
+                    Some(_) => None,
+                };
+                (selection, checksums_dir)
+            }
+            _ => (Selection::default(), None),
+        };
+        let listing_dir = match &selection.target {
+            Some(Target::Local(x)) if x.is_dir() => Some(x.clone()),
+            _ => None,
+        };
+        let listable = listing_dir
+            .as_ref()
+            .map_or(false, |x| is_listable(&settings.options, x));
+        let is_robots_txt = url_path
+            .and_then(normalize_path)
+            .map(|x| x == ROBOTS_TXT)
+            .unwrap_or(false);
+        let robots_txt = settings
+            .options
+            .robots_txt
+            .clone()
+            .filter(|_| is_robots_txt);
+        Resolution {
+            root,
+            selection,
+            checksums_dir,
+            robots_txt,
+            listing_dir,
+            listable,
+        }
+    }
+}
+
+/// What resolving a request depends on, owned to be sent to the read pool
+struct Resolving {
+    /// path captured by the route
+    url_path: Option<String>,
+    /// whole path of the request URI
+    request_path: String,
+    query: Option<String>,
+    /// headers named by [`VariantResolver::vary`]
+    hints: HeaderMap,
+}
+
+/// What a request is answered with, see [`StaticFiles::resolve`]
+pub(crate) struct Resolution {
+    /// document root, `None` if the selected one is unavailable
+    root: Option<PathBuf>,
+    selection: Selection,
+    /// directory whose checksum manifest is asked
+    checksums_dir: Option<PathBuf>,
+    /// configured content of `robots.txt` if asked
+    robots_txt: Option<String>,
+    /// directory found instead of a file
+    listing_dir: Option<PathBuf>,
+    /// whether `listing_dir` may be listed
+    listable: bool,
 }
 
-impl<Data> tide::Endpoint<Data, ()> for StaticFiles {
+impl Resolution {
+    /// How the request is answered, in order of precedence, shared by `call` and
+    /// [traces](trace/index.html)
+    fn answer(&mut self, settings: &Settings, req: &Request) -> Answer {
+        if let Some(dir) = self.checksums_dir.take() {
+            return Answer::Checksums(dir);
+        }
+        if let Some(content) = self.robots_txt.take() {
+            return Answer::RobotsTxt(content);
+        }
+        if let Some(dir) = self.listing_dir.take() {
+            let archive =
+                archive::archive_format(req).filter(|_| settings.options.archive_downloads);
+            return match archive {
+                _ if !self.listable => Answer::Unlisted(dir),
+                Some(format) => Answer::Archive(dir, format),
+                None => Answer::Listing(dir),
+            };
+        }
+        if settings.options.expose_integrity && is_integrity_query(req) {
+            return Answer::Integrity;
+        }
+        Answer::Entry
+    }
+}
+
+/// See [`Resolution::answer`]
+pub(crate) enum Answer {
+    /// generated checksum manifest of the directory
+    Checksums(PathBuf),
+    RobotsTxt(String),
+    /// generated listing of the directory
+    Listing(PathBuf),
+    /// `404 Not Found`, the directory may not be listed
+    Unlisted(PathBuf),
+    Archive(PathBuf, ArchiveFormat),
+    /// `Integrity` of the selected entry
+    Integrity,
+    /// the selected entry, see [`StaticFiles::decide`]
+    Entry,
+}
+
+/// How an opened entry is answered, see [`StaticFiles::decide`]
+pub(crate) enum Decision {
+    /// `304 Not Modified`
+    NotModified,
+    /// `412 Precondition Failed`
+    PreconditionFailed,
+    /// the whole entry with `200 OK`
+    Whole,
+    /// `400 Bad Request`, no valid `Range`
+    BadRange,
+    /// `416 Range Not Satisfiable`
+    Unsatisfiable,
+    /// sorted and disjoint ranges with `206 Partial Content`, at least one
+    Partial(Vec<Range<u64>>),
+}
+
+impl Decision {
+    pub(crate) fn status(&self) -> StatusCode {
+        match self {
+            Decision::NotModified => StatusCode::NOT_MODIFIED,
+            Decision::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            Decision::Whole => StatusCode::OK,
+            Decision::BadRange => StatusCode::BAD_REQUEST,
+            Decision::Unsatisfiable => StatusCode::RANGE_NOT_SATISFIABLE,
+            Decision::Partial(_) => StatusCode::PARTIAL_CONTENT,
+        }
+    }
+}
+
+/// Entry selected for a request
+#[derive(Default)]
+pub(crate) struct Selection {
+    target: Option<Target>,
+    /// fingerprinted file of asset manifest
+    immutable: bool,
+    /// `Content-Location` if a different entry than requested is served, e.g. the fingerprinted
+    /// file of a logical name
+    content_location: Option<String>,
+    /// path to redirect to instead of serving, e.g. in canonical case
+    redirect: Option<String>,
+    /// original served in place of a requested variant not generated yet
+    variant_missing: bool,
+}
+
+/// Metadata of an entry being read on the read pool, see [`StaticFiles::open`]
+type Opening = FutureObj<'static, Result<TSFResult<FileInfo>, std::io::Error>>;
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for StaticFiles {
     type Fut = FutureObj<'static, Response>;
 
-    fn call(&self, _: Data, req: Request, params: Option<RouteMatch<'_>>, _: &Store) -> Self::Fut {
-        let target_path = params
-            .and_then(|rm| rm.vec.first().map(|x| resolve_path(&self.root, x)))
-            .and_then(|x| x.canonicalize().ok());
-        FutureObj::new(Box::new(async move { Self::run(target_path, req) }))
+    fn call(
+        &self,
+        data: Data,
+        req: Request,
+        params: Option<RouteMatch<'_>>,
+        _: &Store,
+    ) -> Self::Fut {
+        if let Some(guard) = &self.guard {
+            let allowed = guard(&data, &req).unwrap_or_else(|| {
+                error!("{}", hooks::DATA_MISMATCH);
+                false
+            });
+            if !allowed {
+                let response = ErrorResponse::Forbidden.into_response();
+                return FutureObj::new(Box::new(async move { response }));
+            }
+        }
+        let settings = self.settings.load();
+        let url_path = params.and_then(|rm| rm.vec.first().cloned());
+        if let Some(url_path) = url_path {
+            let redirect =
+                redirects::find_redirect(&settings.options.redirects, url_path, req.uri().query());
+            if let Some((location, status)) = redirect {
+                // the query is kept by `find_redirect` already
+                let response = redirect_response(status, &location, None);
+                return FutureObj::new(Box::new(async move { response }));
+            }
+        }
+        // the hook runs here, what it selected is checked on the read pool
+        let selected_root = self.selected_root(&data, &req);
+        let pool = self.read_pool.as_ref().map(|x| &**x);
+        let (this, resolving_settings) = (self.clone(), settings.clone());
+        #[cfg(feature = "upload")]
+        {
+            if upload::is_write(req.method()) {
+                let checking =
+                    run_blocking(pool, move || selected_root.and_then(|x| this.check_root(x)));
+                let (this, url_path) = (self.clone(), url_path.map(str::to_string));
+                return FutureObj::new(Box::new(
+                    async move {
+                        let root = match await!(checking) {
+                            Ok(x) => x,
+                            Err(error) => return pool_error_response(&error),
+                        };
+                        let url_path = url_path.as_ref().map(String::as_str);
+                        await!(this.write_response(
+                            &settings,
+                            root.as_ref().map(|x| &**x),
+                            url_path,
+                            req
+                        ))
+                    },
+                ));
+            }
+        }
+        let request = Resolving {
+            url_path: url_path.map(str::to_string),
+            request_path: req.uri().path().to_string(),
+            query: req.uri().query().map(str::to_string),
+            hints: self.variant_hints(&req),
+        };
+        let resolving = run_blocking(pool, move || {
+            this.resolve(&resolving_settings, selected_root, &request)
+        });
+        // the path traced, see `trace_response`
+        let traced_path = Some(url_path.map(str::to_string))
+            .filter(|_| settings.options.debug_trace && req.headers().contains_key(TRACE_HEADER));
+        let listing_path = url_path.unwrap_or("").to_string();
+        let accept_language = get_header(&req, header::ACCEPT_LANGUAGE);
+        let problem_details = settings.options.problem_details
+            && get_header(&req, header::ACCEPT).map_or(false, |x| accepts_problem_json(&x));
+        let this = self.clone();
+        FutureObj::new(Box::new(
+            async move {
+                let mut resolution = match await!(resolving) {
+                    Ok(x) => x,
+                    Err(error) => return pool_error_response(&error),
+                };
+                if let Some(url_path) = traced_path {
+                    let tracing = this.generate(move |this| {
+                        let url_path = url_path.as_ref().map(String::as_str);
+                        this.trace_response(&settings, resolution, url_path, &req)
+                    });
+                    return await!(tracing);
+                }
+                let response = match resolution.answer(&settings, &req) {
+                    Answer::Checksums(dir) => {
+                        await!(this.generate(move |this| this.checksums_response(&dir, &req)))
+                    }
+                    Answer::RobotsTxt(content) => mark_generated(text_response(content)),
+                    Answer::Unlisted(_) => ErrorResponse::NotFound.into_response(),
+                    Answer::Archive(dir, format) => {
+                        await!(this.archive_response(&settings.options, &dir, format, &req))
+                    }
+                    Answer::Listing(dir) => {
+                        let generating = this
+                            .generate(move |this| this.listing_response(&dir, &listing_path, &req));
+                        await!(generating)
+                    }
+                    Answer::Integrity => {
+                        let target = resolution.selection.target;
+                        await!(this.generate(move |this| this.integrity_response(target)))
+                    }
+                    Answer::Entry => {
+                        let selection = resolution.selection;
+                        let path = selection.target.as_ref().map(|x| x.path().to_path_buf());
+                        let response = await!(this.run(&settings, selection, req));
+                        match (&this.response_mapper, path) {
+                            // entries of backends are only found missing when opened
+                            (Some(mapper), Some(path))
+                                if response.status() != StatusCode::NOT_FOUND =>
+                            {
+                                let (mut parts, body) = response.into_parts();
+                                if mapper(&data, &path, &mut parts).is_none() {
+                                    error!("{}", hooks::DATA_MISMATCH);
+                                }
+                                Response::from_parts(parts, body)
+                            }
+                            _ => response,
+                        }
+                    }
+                };
+                let response = localize(
+                    response,
+                    &settings.options.error_messages,
+                    accept_language.as_ref().map(String::as_str),
+                );
+                match &settings.error_pages {
+                    _ if problem_details => await!(problem_response(response)),
+                    Some(pages) => pages.apply(response),
+                    None => response,
+                }
+            },
+        ))
     }
 }
 
 impl StaticFiles {
-    fn run(target_path: Option<PathBuf>, req: Request) -> Response {
-        // TODO this function is too long
+    /// Response generated by `f` on the read pool, as generating it reads the file system
+    fn generate(
+        &self,
+        f: impl FnOnce(&StaticFiles) -> Response + Send + 'static,
+    ) -> FutureObj<'static, Response> {
+        let this = self.clone();
+        let generating = run_blocking(self.read_pool.as_ref().map(|x| &**x), move || {
+            mark_generated(f(&this))
+        });
+        FutureObj::new(Box::new(
+            async move { await!(generating).unwrap_or_else(|x| pool_error_response(&x)) },
+        ))
+    }
 
-        let target_path = match target_path {
+    fn integrity_response(&self, target: Option<Target>) -> Response {
+        let target = match target {
             None => return ErrorResponse::NotFound.into_response(),
             Some(x) => x,
         };
-        let (file, mime, file_size, last_modified, etag, content_disposition) =
-            match metadata(&target_path) {
-                Err(error) => {
-                    error!("unexpected error occurred: {:?}", error);
-                    return ErrorResponse::Unexpected.into_response();
+        let integrity = match self.hash_cache.integrity(&target) {
+            Err(error) => {
+                error!("unexpected error occurred: {:?}", error);
+                return ErrorResponse::Unexpected.into_response();
+            }
+            Ok(x) => x,
+        };
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .body(json!({ "integrity": integrity }).to_string().into())
+            .unwrap()
+    }
+
+    /// Respond to `req` with an entry given by the application, e.g. generated content or a
+    /// source no [backend](vfs/index.html) fits, handled like served files: conditional
+    /// requests, ranges, configured headers and metrics
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::{io::Cursor, time::SystemTime};
+    /// # use tide_static_file::{FileInfo, StaticFiles};
+    /// # let files = StaticFiles::new("./public").unwrap();
+    /// # let req = http::Request::new(http_service::Body::empty());
+    /// let content = b"generated".to_vec();
+    /// let info = FileInfo::new(
+    ///     Box::new(Cursor::new(content)),
+    ///     "report.txt",
+    ///     9,
+    ///     SystemTime::now(),
+    ///     "report-v1",
+    /// );
+    /// let response = files.respond_with(info, req);
+    /// ```
+    pub fn respond_with(&self, info: FileInfo, req: Request) -> Response {
+        let settings = self.settings.load();
+        let response = self.serve(&settings, info, &Selection::default(), false, req);
+        self.decorate(&settings, response)
+    }
+
+    /// Respond with given entry, every exit path is decorated with common headers
+    fn run(
+        &self,
+        settings: &Arc<Settings>,
+        mut selection: Selection,
+        req: Request,
+    ) -> FutureObj<'static, Response> {
+        #[cfg(feature = "otel")]
+        let span = otel::request_span(&req, selection.target.as_ref().map(Target::path));
+        let deadline = req.extensions().get::<Deadline>().cloned();
+        let opening = {
+            #[cfg(feature = "otel")]
+            let _entered = span.enter();
+            self.open(settings, &mut selection, &req, deadline)
+        };
+        let (this, settings) = (self.clone(), settings.clone());
+        FutureObj::new(Box::new(
+            async move {
+                let mut response = match opening {
+                    Err(response) => response,
+                    Ok((target, opening)) => {
+                        let opened = await!(opening);
+                        #[cfg(feature = "otel")]
+                        let _entered = span.enter();
+                        this.respond(&settings, selection, target, opened, req, deadline)
+                    }
+                };
+                // error responses of earlier steps are sent anyway
+                if response.status().is_success()
+                    && this.past_deadline(deadline, DeadlineStep::FirstByte)
+                {
+                    response = ErrorResponse::DeadlineExceeded.into_response();
                 }
-                Ok(x) => x,
-            };
-        let mime_text: &str = &mime.to_string();
+                let response = this.decorate(&settings, response);
+                #[cfg(feature = "otel")]
+                span.record("http.status_code", &u64::from(response.status().as_u16()));
+                response
+            },
+        ))
+    }
 
-        let mut common_response = http::Response::builder();
-        common_response
-            .header(header::ETAG, etag.clone())
-            .header(header::ACCEPT_RANGES, "bytes")
-            .header(
-                header::LAST_MODIFIED,
-                httpdate::fmt_http_date(last_modified),
-            )
-            .header(header::CONTENT_DISPOSITION, content_disposition.to_string());
+    fn decorate(&self, settings: &Settings, response: Response) -> Response {
+        let metrics = self.metrics.as_ref().map(|x| &**x as &dyn Metrics);
+        let response = ResponseDecorator::new(&settings.options, metrics).decorate(response);
+        if let Some(metrics) = metrics {
+            metrics.status(response.status());
+        }
+        response
+    }
 
-        let should_cache = Self::should_cache(
-            get_header(&req, http::header::IF_MODIFIED_SINCE),
-            get_header(&req, http::header::IF_NONE_MATCH),
-            last_modified,
-            &etag,
-        );
-        if should_cache {
-            return common_response
-                .status(StatusCode::NOT_MODIFIED)
-                .body(Body::empty())
-                .unwrap();
+    /// Whether `deadline` of a request passed after `step`, which is reported if so
+    fn past_deadline(&self, deadline: Option<Deadline>, step: DeadlineStep) -> bool {
+        if !deadline.map_or(false, Deadline::is_exceeded) {
+            return false;
         }
+        warn!("deadline exceeded after {:?}", step);
+        if let Some(metrics) = &self.metrics {
+            metrics.deadline_exceeded(step);
+        }
+        true
+    }
 
-        let should_range = Self::should_range(
-            get_header(&req, http::header::IF_RANGE),
-            &etag,
-            last_modified,
-        );
-        if !should_range {
-            return Self::whole_file_response(common_response, file, file_size, mime_text);
+    /// Start opening the entry of `selection` on the read pool, a response instead if there's
+    /// nothing to open (a redirect, no entry, the deadline passed)
+    fn open(
+        &self,
+        settings: &Settings,
+        selection: &mut Selection,
+        req: &Request,
+        deadline: Option<Deadline>,
+    ) -> Result<(Target, Opening), Response> {
+        if let Some(location) = &selection.redirect {
+            return Err(redirect_response(
+                StatusCode::MOVED_PERMANENTLY,
+                location,
+                req.uri().query(),
+            ));
+        }
+        if self.past_deadline(deadline, DeadlineStep::Resolution) {
+            return Err(ErrorResponse::DeadlineExceeded.into_response());
+        }
+        let target = match selection.target.take() {
+            None => return Err(ErrorResponse::NotFound.into_response()),
+            Some(x) => x,
+        };
+        let (opened, hash_cache) = (target.clone(), self.hash_cache.clone());
+        let etag_mode = settings.options.etag_mode.clone();
+        let opening = run_blocking(self.read_pool.as_ref().map(|x| &**x), move || {
+            metadata(&opened, &etag_mode, &hash_cache)
+        });
+        Ok((target, opening))
+    }
+
+    /// Respond with the entry `target` of `selection` once `opened`
+    fn respond(
+        &self,
+        settings: &Settings,
+        selection: Selection,
+        target: Target,
+        opened: Result<TSFResult<FileInfo>, std::io::Error>,
+        req: Request,
+        deadline: Option<Deadline>,
+    ) -> Response {
+        let info = match opened.map_err(failure::Error::from).and_then(|x| x) {
+            // entries of backends are not checked before opening
+            Err(ref error)
+                if error
+                    .downcast_ref::<std::io::Error>()
+                    .map(std::io::Error::kind)
+                    == Some(ErrorKind::NotFound) =>
+            {
+                return ErrorResponse::NotFound.into_response();
+            }
+            Err(ref error)
+                if error
+                    .downcast_ref::<std::io::Error>()
+                    .map(std::io::Error::kind)
+                    == Some(ErrorKind::TimedOut) =>
+            {
+                warn!("opening {:?} timed out", target.path());
+                return ErrorResponse::Timeout.into_response();
+            }
+            // the read pool's queue is full, or no thread of the runtime may block
+            Err(ref error)
+                if error
+                    .downcast_ref::<std::io::Error>()
+                    .map(std::io::Error::kind)
+                    == Some(ErrorKind::WouldBlock) =>
+            {
+                warn!("no thread to open {:?}", target.path());
+                return ErrorResponse::Busy.into_response();
+            }
+            Err(error) => {
+                error!("unexpected error occurred: {:?}", error);
+                return ErrorResponse::Unexpected.into_response();
+            }
+            Ok(x) => x,
+        };
+        if self.past_deadline(deadline, DeadlineStep::Metadata) {
+            return ErrorResponse::DeadlineExceeded.into_response();
         }
+        let local = match target {
+            Target::Local(_) => true,
+            Target::Backend(..) => false,
+        };
+        self.serve(settings, info, &selection, local, req)
+    }
 
-        let ranges: Option<Vec<ByteRange>> = req
-            .headers()
-            .get(http::header::RANGE)
-            .and_then(|x: &HeaderValue| x.to_str().ok())
-            .map(ByteRange::parse);
-        if ranges.is_none() {
-            return Self::whole_file_response(common_response, file, file_size, mime_text);
-        }
+    /// Respond with an opened entry, `local` if read from the local file system
+    fn serve(
+        &self,
+        settings: &Settings,
+        info: FileInfo,
+        selection: &Selection,
+        local: bool,
+        req: Request,
+    ) -> Response {
+        // TODO this function is too long
 
-        let ranges: Vec<ByteRange> = ranges.unwrap();
-        if ranges.is_empty() {
-            // no valid (format) 'Range' header value found
-            // for example: 'Range: lines=1-2' or 'Range: nothing'
-            return http::Response::builder()
-                .status(http::StatusCode::BAD_REQUEST)
-                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
-                .header(header::ACCEPT_RANGES, "bytes")
-                .body("failed to parse request header: Range".into())
-                .unwrap();
-        }
+        let now = self.clock.now();
+        let decision = Self::decide(settings, &req, &info, now);
+        let FileInfo {
+            file,
+            path,
+            mut mime,
+            size: file_size,
+            modified: last_modified,
+            etag,
+            disposition: mut content_disposition,
+            generated,
+            ..
+        } = info;
+        apply_overrides(&req, &mut mime, &mut content_disposition);
+        let mime_text: &str = &mime.to_string();
 
-        // "redirects and failures take precedence over the evaluation of
-        // preconditions in conditional requests."
-        // ref: https://tools.ietf.org/html/rfc7232#section-5
-        //
-        // It's too hard to check all things
-        // So we put precondition check here
-        let should_precondition_failed = Self::precondition_failed(
-            get_header(&req, http::header::IF_MATCH),
-            get_header(&req, http::header::IF_UNMODIFIED_SINCE),
+        let (last_modified, etag_value) = validators(
+            settings.options.clamp_last_modified,
             last_modified,
             &etag,
+            now,
         );
-        if should_precondition_failed {
-            return http::Response::builder()
-                .status(http::StatusCode::PRECONDITION_FAILED)
-                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
-                .header(header::ACCEPT_RANGES, "bytes")
-                .body("precondition failed".into())
-                .unwrap();
+        let mut common_response = http::Response::builder();
+        common_response
+            .header(header::DATE, httpdate::fmt_http_date(now))
+            .header(header::ETAG, etag_value.as_str())
+            .header(
+                header::LAST_MODIFIED,
+                httpdate::fmt_http_date(last_modified),
+            )
+            .header(header::CONTENT_DISPOSITION, content_disposition.to_string());
+        let media = media_kind(&path);
+        if selection.immutable {
+            common_response.header(header::CACHE_CONTROL, IMMUTABLE_CACHE_CONTROL);
+        } else if media == Some(MediaKind::Playlist) {
+            common_response.header(header::CACHE_CONTROL, PLAYLIST_CACHE_CONTROL);
+        } else if selection.variant_missing {
+            common_response.header(header::CACHE_CONTROL, "no-cache");
+        }
+        if media.is_some() && settings.options.media_cors {
+            for (name, value) in MEDIA_CORS_HEADERS {
+                common_response.header(*name, *value);
+            }
+        }
+        if let Some(location) = &selection.content_location {
+            common_response.header(header::CONTENT_LOCATION, location.as_str());
+        }
+        if let Some(vary) = self.variant_resolver.as_ref().and_then(|x| x.vary()) {
+            common_response.header(header::VARY, vary);
         }
 
-        let ranges: Vec<Range<u64>> = ranges
-            .into_iter()
-            .flat_map(|x| actual_range(x, file_size))
-            .collect();
-        let mut ranges = merge_ranges(ranges);
-        match ranges.len() {
-            0 => {
-                // no valid 'Range' header valid found
-                // for example: file size is 200, got 'Range: bytes=400-'
-                http::Response::builder()
-                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
-                    .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.to_string())
-                    .header(header::ACCEPT_RANGES, "bytes")
-                    .header(header::CONTENT_RANGE, format!("bytes */{}", file_size))
-                    .body("requested range not satisfiable".into())
-                    .unwrap()
+        let mut ranges = match decision {
+            Decision::PreconditionFailed => return precondition_failed_response(),
+            Decision::NotModified => {
+                self.record(Served::NotModified);
+                let response = common_response
+                    .status(StatusCode::NOT_MODIFIED)
+                    .body(Body::empty())
+                    .unwrap();
+                return if generated {
+                    mark_generated(response)
+                } else {
+                    response
+                };
             }
+            Decision::Whole if generated => {
+                let response =
+                    self.whole_file_response(common_response, file, file_size, mime_text);
+                return mark_generated(response);
+            }
+            Decision::Whole => {
+                return self.whole_file_response(common_response, file, file_size, mime_text);
+            }
+            Decision::BadRange => {
+                return http::Response::builder()
+                    .status(http::StatusCode::BAD_REQUEST)
+                    .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+                    .body("failed to parse request header: Range".into())
+                    .unwrap();
+            }
+            // for example: file size is 200, got 'Range: bytes=400-'
+            Decision::Unsatisfiable => return self.range_not_satisfiable_response(file_size),
+            Decision::Partial(x) => x,
+        };
+        match ranges.len() {
             1 => {
                 // only one valid 'Range' header found
                 let range = ranges.pop().unwrap();
 
-                if range.end == file_size && range.start == 0 {
-                    return Self::whole_file_response(common_response, file, file_size, mime_text);
-                }
-
-                let content_range_value = format!(
-                    "bytes {start}-{end}/{total}",
-                    start = range.start,
-                    end = range.end - 1,
-                    total = file_size
-                );
+                let content_range_value = ContentRange::new(range.clone(), file_size).to_string();
 
-                let reader = match SingleRangeReader::new(file, range.start, range.end) {
+                // tiny local ranges (video players, PDF viewers) skip the read pool, backends
+                // may be remote and are always streamed
+                let inline = local && range.end - range.start <= INLINE_RANGE_SIZE;
+                let body = if inline {
+                    read_inline(file, &range).map(Body::from)
+                } else {
+                    SingleRangeReader::new(self.read_pool.clone(), file, range.start, range.end)
+                        .map(|x| x.chunk_sizer(self.chunk_sizer(settings)).into_body())
+                };
+                let body = match body {
                     Ok(x) => x,
                     Err(error) => {
                         if error.kind() == ErrorKind::WouldBlock {
@@ -198,58 +1717,181 @@ impl StaticFiles {
                         return ErrorResponse::Unexpected.into_response();
                     }
                 };
+                self.record(Served::SingleRange {
+                    size: range.end - range.start,
+                });
+                self.record_ranges(std::slice::from_ref(&range));
 
                 common_response
                     .status(StatusCode::PARTIAL_CONTENT)
                     .header(header::CONTENT_TYPE, mime_text)
                     .header(header::CONTENT_RANGE, content_range_value)
                     .header(header::CONTENT_LENGTH, range.end - range.start)
-                    .body(reader.into_body())
+                    .body(body)
                     .unwrap()
             }
             _ => {
                 // multi valid 'Range' header found
-                let header_length: usize = ranges
-                    .iter()
-                    .map(|x| PartHeader::new(x, mime_text, file_size).size())
-                    .sum();
-                let body_length: u64 = ranges.iter().map(|x| x.end - x.start).sum();
-                let final_length = 8 + BOUNDARY.len(); /*"\r\n--".len() + BOUNDARY.len() + "--\r\n".len()*/
-                let content_length = header_length as u64 + body_length + final_length as u64;
+                let plan =
+                    MultipartPlan::new(ranges, mime_text, &self.boundary.boundary(), file_size);
+                let plan = match plan {
+                    Some(x) => x,
+                    // body longer than `u64::max_value()`, only possible for huge backends
+                    None => return self.range_not_satisfiable_response(file_size),
+                };
+                self.record(Served::MultiRange {
+                    parts: plan.ranges().len(),
+                    size: plan.body_length(),
+                });
+                self.record_ranges(plan.ranges());
 
-                let reader = MultiRangeReader::new(file, file_size, mime_text, ranges);
+                let content_type = plan.content_type();
+                let content_length = plan.content_length();
+                let boundary = MultipartBoundary(plan.boundary().to_string());
+                let mut reader = plan.into_reader(file);
+                if let Some(observer) = &self.part_observer {
+                    reader = reader.observe(observer.clone(), path);
+                }
+                if let Some(metrics) = &self.metrics {
+                    reader = reader.metrics(metrics.clone());
+                }
 
-                common_response
+                let mut response = common_response
                     .status(http::StatusCode::PARTIAL_CONTENT)
-                    .header(header::CONTENT_TYPE, MULTI_RANGE_CONTENT_TYPE)
+                    .header(header::CONTENT_TYPE, content_type)
                     .header(header::CONTENT_LENGTH, content_length)
                     .body(reader.into_body())
-                    .unwrap()
+                    .unwrap();
+                response.extensions_mut().insert(boundary);
+                response
             }
         }
     }
 }
 
 impl StaticFiles {
+    /// How to answer `req` with an opened entry, shared by [`serve`](#method.serve) and
+    /// [traces](trace/index.html)
+    pub(crate) fn decide(
+        settings: &Settings,
+        req: &Request,
+        info: &FileInfo,
+        now: SystemTime,
+    ) -> Decision {
+        let (last_modified, etag_value) = validators(
+            settings.options.clamp_last_modified,
+            info.modified,
+            &info.etag,
+            now,
+        );
+
+        // validators are evaluated before `Range`, a fresh cache gets 304 instead of 206
+        // ref: https://tools.ietf.org/html/rfc7232#section-6
+        let should_cache = Self::should_cache(
+            req.method(),
+            get_header(req, http::header::IF_MODIFIED_SINCE),
+            get_header(req, http::header::IF_NONE_MATCH),
+            last_modified,
+            &info.etag,
+        );
+        if should_cache {
+            if Self::not_modified_status(req.method()) == StatusCode::PRECONDITION_FAILED {
+                return Decision::PreconditionFailed;
+            }
+            return Decision::NotModified;
+        }
+
+        // byte offsets of generated content aren't stable, every representation type opts out
+        // of ranges here, and of `Accept-Ranges` when decorated
+        if info.generated {
+            return Decision::Whole;
+        }
+
+        // strong comparisons never match a clamped (weak) entity tag
+        let should_range = Self::should_range(
+            get_header(req, http::header::IF_RANGE),
+            &etag_value,
+            last_modified,
+            now,
+        );
+        if !should_range {
+            return Decision::Whole;
+        }
+
+        // fields of other units are left out if ignored
+        // ref: https://tools.ietf.org/html/rfc7233#section-3.1
+        let ranges: Vec<ByteRange> = match range_header(req, settings.options.range_units) {
+            None => return Decision::Whole,
+            Some(x) => parse_ranges(&x),
+        };
+        if ranges.is_empty() {
+            // no valid (format) 'Range' header value found
+            // for example: 'Range: bytes=nothing', or 'Range: lines=1-2' if rejected
+            return Decision::BadRange;
+        }
+
+        // "redirects and failures take precedence over the evaluation of
+        // preconditions in conditional requests."
+        // ref: https://tools.ietf.org/html/rfc7232#section-5
+        //
+        // It's too hard to check all things
+        // So we put precondition check here
+        let should_precondition_failed = Self::precondition_failed(
+            get_header(req, http::header::IF_MATCH),
+            get_header(req, http::header::IF_UNMODIFIED_SINCE),
+            last_modified,
+            &etag_value,
+        );
+        if should_precondition_failed {
+            return Decision::PreconditionFailed;
+        }
+
+        match decide_ranges(ranges, info.size) {
+            RangeDecision::Unsatisfiable => Decision::Unsatisfiable,
+            RangeDecision::Whole => Decision::Whole,
+            RangeDecision::Partial(x) => Decision::Partial(x),
+        }
+    }
+
+    fn range_not_satisfiable_response(&self, file_size: u64) -> Response {
+        self.record(Served::RangeNotSatisfiable);
+        http::Response::builder()
+            .status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.to_string())
+            .header(
+                header::CONTENT_RANGE,
+                ContentRange::unsatisfied(file_size).to_string(),
+            )
+            .body("requested range not satisfiable".into())
+            .unwrap()
+    }
+
     /// ref: https://tools.ietf.org/html/rfc7233#section-3.2
+    ///
+    /// `If-Range` requires a strong match, so that a resumed download never mixes bytes of
+    /// different versions of a file.
     pub(crate) fn should_range(
         if_range: Option<String>,
         etag: &str,
         last_modify: SystemTime,
+        now: SystemTime,
     ) -> bool {
-        if let Some(x) = if_range
-            .as_ref()
-            .and_then(|x| x.parse::<HttpDate>().ok())
-            .map(|x| x == HttpDate::from(last_modify))
-        {
-            return x;
-        }
-
-        if let Some(x) = if_range.map(|x| x.split(',').map(str::trim).any(|x| x == etag)) {
-            return x;
+        let if_range = match if_range {
+            None => return true,
+            Some(x) => x,
+        };
+        let if_range = if_range.trim();
+        if let Ok(date) = if_range.parse::<HttpDate>() {
+            // a date is only a strong validator if the file was not modified in the same second
+            // ref: https://tools.ietf.org/html/rfc7232#section-2.2.2
+            let strong = now
+                .duration_since(last_modify)
+                .map(|x| x.as_secs() >= 1)
+                .unwrap_or(false);
+            return strong && date == HttpDate::from(last_modify);
         }
-
-        true
+        // a single entity tag, weak ones never match
+        EntityTag::parse(if_range).strong_eq(etag)
     }
 
     /// HTTP 304 (Not Modified) or not
@@ -257,14 +1899,21 @@ impl StaticFiles {
     /// ref:
     /// + https://tools.ietf.org/html/rfc7232#section-3.2
     /// + https://tools.ietf.org/html/rfc7232#section-3.3
+    ///
+    /// The condition is evaluated for every method, but only `GET` and `HEAD` are answered with
+    /// 304, see [`not_modified_status`](#method.not_modified_status). `If-Modified-Since` is
+    /// ignored for other methods.
     pub(crate) fn should_cache(
+        method: &Method,
         if_modified_since: Option<String>,
         if_none_match: Option<String>,
         last_modified: SystemTime,
         etag: &str,
     ) -> bool {
         if let Some(etags) = if_none_match {
-            etags.split(',').map(str::trim).any(|x| x == etag)
+            entity_tags(&etags).any(|x| x.weak_eq(etag))
+        } else if !is_safe_read(method) {
+            false
         } else {
             if_modified_since
                 .and_then(|x| x.parse::<HttpDate>().ok())
@@ -273,17 +1922,33 @@ impl StaticFiles {
         }
     }
 
-    /// HTTP 412 (Precondition Failed) or not
+    /// Status answering a request whose `If-None-Match` or `If-Modified-Since` condition
+    /// evaluated to false, i.e. [`should_cache`](#method.should_cache)
+    ///
+    /// ref: https://tools.ietf.org/html/rfc7232#section-3.2
+    pub(crate) fn not_modified_status(method: &Method) -> StatusCode {
+        if is_safe_read(method) {
+            StatusCode::NOT_MODIFIED
+        } else {
+            StatusCode::PRECONDITION_FAILED
+        }
+    }
+
+    /// HTTP 412 (Precondition Failed) or not, given `If-Match` and `If-Unmodified-Since` of a
+    /// request and validators of the current representation
+    ///
+    /// Public for applications checking their own writes the way uploads are checked, see
+    /// [`write_precondition_failed`](#method.write_precondition_failed).
     ///
     /// ref: https://tools.ietf.org/html/rfc7232#section-4.2
-    pub(crate) fn precondition_failed(
+    pub fn precondition_failed(
         if_match: Option<String>,
         if_unmodified_since: Option<String>,
         last_modified: SystemTime,
         etag: &str,
     ) -> bool {
         if let Some(expect) = if_match {
-            expect.split(',').map(str::trim).all(|x| x != etag)
+            expect.trim() != "*" && entity_tags(&expect).all(|x| !x.strong_eq(etag))
         } else {
             if_unmodified_since
                 .and_then(|x| x.parse::<HttpDate>().ok())
@@ -292,12 +1957,67 @@ impl StaticFiles {
         }
     }
 
+    /// HTTP 412 (Precondition Failed) or not for a request changing a resource (`PUT`,
+    /// `DELETE`, ...), `current` are validators of the current representation if any
+    ///
+    /// Unlike reads, a matching `If-None-Match` fails instead of answering `304`, e.g.
+    /// `If-None-Match: *` only creates, and `If-Match` fails for a missing resource, e.g.
+    /// `If-Match: *` only replaces.
+    ///
+    /// ref: https://tools.ietf.org/html/rfc7232#section-6
+    pub fn write_precondition_failed(
+        if_match: Option<String>,
+        if_none_match: Option<String>,
+        if_unmodified_since: Option<String>,
+        current: Option<(SystemTime, &str)>,
+    ) -> bool {
+        let (last_modified, etag) = match current {
+            Some(x) => x,
+            // no modification date to compare `If-Unmodified-Since` with
+            None => return if_match.is_some(),
+        };
+        if Self::precondition_failed(if_match, if_unmodified_since, last_modified, etag) {
+            return true;
+        }
+        if_none_match
+            .map(|x| x.trim() == "*" || entity_tags(&x).any(|x| x.weak_eq(etag)))
+            .unwrap_or(false)
+    }
+
+    fn record(&self, served: Served) {
+        #[cfg(feature = "otel")]
+        otel::record_served(served);
+        if let Some(metrics) = &self.metrics {
+            metrics.response(served);
+            metrics.read_pool(pool_health(self.read_pool.as_ref().map(|x| &**x)));
+        }
+    }
+
+    fn chunk_sizer(&self, settings: &Settings) -> ChunkSizer {
+        let options = &settings.options;
+        ChunkSizer::new(
+            options.min_chunk_size.unwrap_or(MIN_CHUNK_SIZE),
+            options.max_chunk_size.unwrap_or(MAX_BUFFER_SIZE),
+        )
+    }
+
+    fn record_ranges(&self, ranges: &[Range<u64>]) {
+        #[cfg(feature = "otel")]
+        otel::record_ranges(ranges.len());
+        if let Some(metrics) = &self.metrics {
+            ranges.iter().for_each(|x| metrics.range(x.end - x.start));
+        }
+    }
+
     fn whole_file_response(
+        &self,
         mut common_response: http::response::Builder,
-        file: File,
+        file: Reader,
         file_size: u64,
         mime_text: &str,
     ) -> Response {
+        self.record(Served::Full { size: file_size });
+        // nothing to read, and readers require a non-empty range
         if file_size == 0 {
             return common_response
                 .status(StatusCode::OK)
@@ -307,8 +2027,8 @@ impl StaticFiles {
                 .unwrap();
         }
 
-        let reader = match SingleRangeReader::new(file, 0, file_size) {
-            Ok(x) => x,
+        let reader = match SingleRangeReader::new(self.read_pool.clone(), file, 0, file_size) {
+            Ok(x) => x.chunk_sizer(self.chunk_sizer(&self.settings.load())),
             Err(error) => {
                 if error.kind() == ErrorKind::WouldBlock {
                     error!("file read task queue is full");
@@ -328,13 +2048,101 @@ impl StaticFiles {
     }
 }
 
+const ROBOTS_TXT: &str = "robots.txt";
+const ACME_CHALLENGE: &str = ".well-known/acme-challenge/";
+
+/// Token of an ACME HTTP-01 challenge request, i.e. `/.well-known/acme-challenge/<token>`
+///
+/// ref: https://tools.ietf.org/html/rfc8555#section-8.3
+fn acme_challenge_token(url_path: &str) -> Option<String> {
+    let logical = normalize_path(url_path)?;
+    if !logical.starts_with(ACME_CHALLENGE) {
+        return None;
+    }
+    let token = &logical[ACME_CHALLENGE.len()..];
+    let valid = !token.is_empty()
+        && token
+            .bytes()
+            .all(|x| x.is_ascii_alphanumeric() || x == b'-' || x == b'_');
+    if valid {
+        Some(token.to_string())
+    } else {
+        None
+    }
+}
+
+fn text_response(content: String) -> Response {
+    http::Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, mime::TEXT_PLAIN_UTF_8.to_string())
+        .header(header::CONTENT_LENGTH, content.len() as u64)
+        .body(content.into())
+        .unwrap()
+}
+
+/// Response to a file operation the read pool failed to run, see [`run_blocking`]
+fn pool_error_response(error: &std::io::Error) -> Response {
+    match error.kind() {
+        ErrorKind::TimedOut => {
+            warn!("file operation timed out");
+            ErrorResponse::Timeout.into_response()
+        }
+        // the read pool's queue is full, or no thread of the runtime may block
+        ErrorKind::WouldBlock => ErrorResponse::Busy.into_response(),
+        _ => {
+            error!("unexpected error occurred: {:?}", error);
+            ErrorResponse::Unexpected.into_response()
+        }
+    }
+}
+
+fn redirect_response(status: StatusCode, location: &str, query: Option<&str>) -> Response {
+    let location = match query {
+        Some(query) => format!("{}?{}", location, query),
+        None => location.to_string(),
+    };
+    let reason = status.canonical_reason().unwrap_or("redirect");
+    http::Response::builder()
+        .status(status)
+        .header(header::LOCATION, location)
+        .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+        .body(reason.to_ascii_lowercase().into())
+        .unwrap()
+}
+
+fn precondition_failed_response() -> Response {
+    http::Response::builder()
+        .status(http::StatusCode::PRECONDITION_FAILED)
+        .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+        .body("precondition failed".into())
+        .unwrap()
+}
+
+/// `GET` or `HEAD`
+fn is_safe_read(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
 #[cfg(test)]
 mod tests {
-    use super::StaticFiles;
+    use super::{FileInfo, Generated, StaticFiles};
+    use crate::utils::TempDir;
+    use http::{
+        header::{self, HeaderName},
+        HeaderValue, Method, StatusCode,
+    };
+    use http_service::Body;
     use std::{
+        fs,
+        io::Cursor,
         ops::Add,
-        time::{Duration, UNIX_EPOCH},
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
+    use tide::Response;
+
+    fn body(response: Response) -> Vec<u8> {
+        futures::executor::block_on(response.into_body().into_vec()).unwrap()
+    }
 
     #[test]
     fn test_should_cache() {
@@ -350,6 +2158,7 @@ mod tests {
         assert_eq!(
             true,
             StaticFiles::should_cache(
+                &Method::GET,
                 Some(before_text.to_owned()),
                 None,
                 before.clone(),
@@ -359,6 +2168,7 @@ mod tests {
         assert_eq!(
             true,
             StaticFiles::should_cache(
+                &Method::GET,
                 Some(little_text.to_owned()),
                 None,
                 before.clone(),
@@ -367,15 +2177,28 @@ mod tests {
         );
         assert_eq!(
             false,
-            StaticFiles::should_cache(Some(after_text.to_owned()), None, before.clone(), "correct")
+            StaticFiles::should_cache(
+                &Method::GET,
+                Some(after_text.to_owned()),
+                None,
+                before.clone(),
+                "correct"
+            )
         );
         assert_eq!(
             false,
-            StaticFiles::should_cache(Some(before_text.to_owned()), None, after.clone(), "correct")
+            StaticFiles::should_cache(
+                &Method::GET,
+                Some(before_text.to_owned()),
+                None,
+                after.clone(),
+                "correct"
+            )
         );
         assert_eq!(
             false,
             StaticFiles::should_cache(
+                &Method::GET,
                 Some(after_text.to_owned()),
                 Some("wrong".to_owned()),
                 after.clone(),
@@ -385,6 +2208,7 @@ mod tests {
         assert_eq!(
             true,
             StaticFiles::should_cache(
+                &Method::GET,
                 Some(after_text.to_owned()),
                 Some("wrong, correct ".to_owned()),
                 after.clone(),
@@ -393,11 +2217,18 @@ mod tests {
         );
         assert_eq!(
             false,
-            StaticFiles::should_cache(None, Some("wrong".to_owned()), after.clone(), "correct")
+            StaticFiles::should_cache(
+                &Method::GET,
+                None,
+                Some("wrong".to_owned()),
+                after.clone(),
+                "correct"
+            )
         );
         assert_eq!(
             true,
             StaticFiles::should_cache(
+                &Method::GET,
                 Some(little_text.to_owned()),
                 Some("correct".to_owned()),
                 after.clone(),
@@ -406,6 +2237,119 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_conditional_method() {
+        let date = Some(httpdate::fmt_http_date(UNIX_EPOCH));
+        let etag = Some("correct".to_owned());
+        for method in &[Method::GET, Method::HEAD] {
+            assert!(StaticFiles::should_cache(
+                method,
+                date.clone(),
+                None,
+                UNIX_EPOCH,
+                "correct"
+            ));
+            assert_eq!(
+                StatusCode::NOT_MODIFIED,
+                StaticFiles::not_modified_status(method)
+            );
+        }
+        for method in &[Method::PUT, Method::DELETE, Method::POST] {
+            // If-Modified-Since is only meaningful for GET and HEAD
+            assert!(!StaticFiles::should_cache(
+                method,
+                date.clone(),
+                None,
+                UNIX_EPOCH,
+                "correct"
+            ));
+            assert!(StaticFiles::should_cache(
+                method,
+                None,
+                etag.clone(),
+                UNIX_EPOCH,
+                "correct"
+            ));
+            assert_eq!(
+                StatusCode::PRECONDITION_FAILED,
+                StaticFiles::not_modified_status(method)
+            );
+        }
+    }
+
+    #[test]
+    fn test_acme_challenge_token() {
+        use super::acme_challenge_token;
+        assert_eq!(
+            Some("Ab-_9".to_string()),
+            acme_challenge_token("/.well-known/acme-challenge/Ab-_9")
+        );
+        assert_eq!(
+            Some("x".to_string()),
+            acme_challenge_token("a/../.well-known/acme-challenge/./x")
+        );
+        assert_eq!(None, acme_challenge_token(".well-known/acme-challenge/"));
+        assert_eq!(None, acme_challenge_token(".well-known/acme-challenge/a/b"));
+        assert_eq!(
+            None,
+            acme_challenge_token(".well-known/acme-challenge/a.txt")
+        );
+        assert_eq!(None, acme_challenge_token(".well-known/other/x"));
+    }
+
+    #[test]
+    fn test_validator_lists() {
+        let list = |x: &str| Some(x.to_owned());
+        let time = UNIX_EPOCH;
+        assert!(StaticFiles::should_cache(
+            &Method::GET,
+            None,
+            list(" wrong ,,correct,"),
+            time,
+            "correct"
+        ));
+        assert!(StaticFiles::should_cache(
+            &Method::GET,
+            None,
+            list("w/\"correct\""),
+            time,
+            "correct"
+        ));
+        assert!(!StaticFiles::should_cache(
+            &Method::GET,
+            None,
+            list(" , "),
+            time,
+            "correct"
+        ));
+
+        assert!(!StaticFiles::precondition_failed(
+            list("wrong,\"correct\", "),
+            None,
+            time,
+            "correct"
+        ));
+        assert!(StaticFiles::precondition_failed(
+            list("w/correct"),
+            None,
+            time,
+            "correct"
+        ));
+
+        assert!(StaticFiles::should_range(
+            list(" \"correct\" "),
+            "correct",
+            time,
+            time
+        ));
+        assert!(!StaticFiles::should_range(
+            list("w/correct"),
+            "correct",
+            time,
+            time
+        ));
+    }
+
     #[test]
     fn test_precondition_failed() {
         let before = &UNIX_EPOCH;
@@ -491,8 +2435,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_write_precondition_failed() {
+        let time = UNIX_EPOCH;
+        let text = || Some(httpdate::fmt_http_date(time));
+        let tag = |x: &str| Some(x.to_string());
+        let failed = StaticFiles::write_precondition_failed;
+        let current = Some((time, "v1"));
+
+        assert!(!failed(None, None, None, None));
+        assert!(!failed(None, None, None, current));
+        assert!(!failed(tag("*"), None, None, current));
+        assert!(!failed(tag("v0, v1"), None, text(), current));
+        assert!(failed(tag("v0"), None, None, current));
+        assert!(failed(tag("*"), None, None, None));
+        assert!(!failed(None, None, text(), None));
+        assert!(failed(
+            None,
+            None,
+            text(),
+            Some((time + Duration::from_secs(1), "v2"))
+        ));
+
+        assert!(!failed(None, tag("*"), None, None));
+        assert!(failed(None, tag("*"), None, current));
+        assert!(failed(None, tag("W/v1"), None, current));
+        assert!(!failed(None, tag("v0"), None, current));
+    }
+
     #[test]
     fn test_should_range() {
+        let now = SystemTime::now();
         let before = &UNIX_EPOCH;
         let before_text = &httpdate::fmt_http_date(before.clone());
 
@@ -504,39 +2477,249 @@ mod tests {
 
         assert_eq!(
             true,
-            StaticFiles::should_range(Some(before_text.to_owned()), "correct", before.clone())
+            StaticFiles::should_range(Some(before_text.to_owned()), "correct", before.clone(), now)
         );
         assert_eq!(
             true,
-            StaticFiles::should_range(Some(little_text.to_owned()), "correct", before.clone())
+            StaticFiles::should_range(Some(little_text.to_owned()), "correct", before.clone(), now)
         );
         assert_eq!(
             false,
-            StaticFiles::should_range(Some(before_text.to_owned()), "correct", after.clone())
+            StaticFiles::should_range(Some(before_text.to_owned()), "correct", after.clone(), now)
         );
         assert_eq!(
             false,
-            StaticFiles::should_range(Some(after_text.to_owned()), "correct", before.clone())
+            StaticFiles::should_range(Some(after_text.to_owned()), "correct", before.clone(), now)
         );
         assert_eq!(
             true,
-            StaticFiles::should_range(Some("correct".to_owned()), "correct", before.clone()),
+            StaticFiles::should_range(Some("correct".to_owned()), "correct", before.clone(), now),
         );
         assert_eq!(
             false,
-            StaticFiles::should_range(Some("wrong".to_owned()), "correct", before.clone()),
+            StaticFiles::should_range(Some("wrong".to_owned()), "correct", before.clone(), now),
         );
         assert_eq!(
-            true,
+            false,
             StaticFiles::should_range(
                 Some("wrong, correct ".to_owned()),
                 "correct",
                 before.clone(),
+                now
+            ),
+        );
+        assert_eq!(
+            false,
+            StaticFiles::should_range(
+                Some("W/correct".to_owned()),
+                "W/correct",
+                before.clone(),
+                now
+            ),
+        );
+        assert_eq!(
+            false,
+            StaticFiles::should_range(
+                Some(httpdate::fmt_http_date(now)),
+                "correct",
+                now,
+                now + Duration::from_millis(500)
             ),
         );
         assert_eq!(
             true,
-            StaticFiles::should_range(None, "correct", before.clone())
+            StaticFiles::should_range(None, "correct", before.clone(), now)
         )
     }
+
+    #[test]
+    fn test_content_location() {
+        let dir = TempDir::new("location");
+        fs::write(dir.join("app.3f9ab2.js"), b"app").unwrap();
+        fs::write(dir.join("manifest.json"), br#"{"app.js": "app.3f9ab2.js"}"#).unwrap();
+
+        let files = StaticFiles::new(&dir)
+            .unwrap()
+            .manifest(dir.join("manifest.json"))
+            .unwrap();
+        let get = |request_path: &str, url_path: &str| {
+            let request = http::Request::builder().body(Body::empty()).unwrap();
+            let settings = files.settings.load();
+            let selection = files.target_path(&settings, &files.root, request_path, url_path);
+            futures::executor::block_on(files.run(&settings, selection, request))
+        };
+
+        let response = get("/static/app.js", "app.js");
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "/static/app.3f9ab2.js",
+            response.headers()[header::CONTENT_LOCATION]
+        );
+        let response = get("/static/app.3f9ab2.js", "app.3f9ab2.js");
+        assert!(!response.headers().contains_key(header::CONTENT_LOCATION));
+    }
+
+    #[test]
+    fn test_case_insensitive() {
+        let dir = TempDir::new("case");
+        fs::create_dir_all(dir.join("Docs")).unwrap();
+        fs::write(dir.join("Docs/Guide.txt"), b"guide").unwrap();
+
+        let get = |files: &StaticFiles| {
+            let request = http::Request::builder()
+                .uri("/docs/guide.txt?x=1")
+                .body(Body::empty())
+                .unwrap();
+            let settings = files.settings.load();
+            let root = files.root.clone();
+            let selection =
+                files.target_path(&settings, &root, "/docs/guide.txt", "docs/guide.txt");
+            futures::executor::block_on(files.run(&settings, selection, request))
+        };
+
+        let files = StaticFiles::new(&dir).unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, get(&files).status());
+
+        let files = files.case_insensitive(true);
+        let response = get(&files);
+        assert_eq!(StatusCode::OK, response.status());
+        assert_eq!(
+            "/Docs/Guide.txt",
+            response.headers()[header::CONTENT_LOCATION]
+        );
+        assert_eq!(b"guide".to_vec(), body(response));
+
+        let files = files.canonical_case_redirect(true);
+        let response = get(&files);
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, response.status());
+        assert_eq!("/Docs/Guide.txt?x=1", response.headers()[header::LOCATION]);
+    }
+
+    #[test]
+    fn test_respond_with() {
+        let files = StaticFiles::new(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let info = || {
+            let content = b"generated".to_vec();
+            FileInfo::new(Box::new(Cursor::new(content)), "a.txt", 9, UNIX_EPOCH, "v1")
+        };
+        let request = |name, value| {
+            http::Request::builder()
+                .header(name, value)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let response = files.respond_with(info(), request(header::RANGE, "bytes=3-5"));
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status());
+        assert_eq!("bytes 3-5/9", response.headers()[header::CONTENT_RANGE]);
+        assert_eq!("v1", response.headers()[header::ETAG]);
+        assert_eq!(b"era".to_vec(), body(response));
+
+        let response = files.respond_with(info(), request(header::IF_NONE_MATCH, "v1"));
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status());
+
+        // generated content is served whole, ranges aren't advertised
+        let response = files.respond_with(info().generated(), request(header::RANGE, "bytes=3-5"));
+        assert_eq!(StatusCode::OK, response.status());
+        assert!(!response.headers().contains_key(header::ACCEPT_RANGES));
+        assert!(response.extensions().get::<Generated>().is_some());
+        assert_eq!(b"generated".to_vec(), body(response));
+    }
+
+    #[test]
+    fn test_resume_after_replacement() {
+        let dir = TempDir::new("resume");
+        fs::write(dir.join("a.bin"), b"0123456789").unwrap();
+
+        let files = StaticFiles::new(&dir).unwrap();
+        let get = |headers: &[(header::HeaderName, &str)]| {
+            let mut request = http::Request::builder();
+            for (name, value) in headers {
+                request.header(name, *value);
+            }
+            let request = request.body(Body::empty()).unwrap();
+            let settings = files.settings.load();
+            let selection = files.target_path(&settings, &files.root, "/a.bin", "a.bin");
+            futures::executor::block_on(files.run(&settings, selection, request))
+        };
+
+        let first = get(&[(header::RANGE, "bytes=0-4")]);
+        assert_eq!(StatusCode::PARTIAL_CONTENT, first.status());
+        let old_etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_eq!(b"01234".to_vec(), body(first));
+
+        // replaced between chunk requests, resuming must restart with the new version
+        fs::write(dir.join("a.tmp"), b"abcdefghijk").unwrap();
+        fs::rename(dir.join("a.tmp"), dir.join("a.bin")).unwrap();
+        let second = get(&[
+            (header::RANGE, "bytes=5-"),
+            (header::IF_RANGE, old_etag.as_str()),
+        ]);
+        assert_eq!(StatusCode::OK, second.status());
+        let new_etag = second.headers()[header::ETAG].to_str().unwrap().to_string();
+        assert_ne!(old_etag, new_etag);
+        assert_eq!(b"abcdefghijk".to_vec(), body(second));
+
+        let third = get(&[
+            (header::RANGE, "bytes=5-"),
+            (header::IF_RANGE, new_etag.as_str()),
+        ]);
+        assert_eq!(StatusCode::PARTIAL_CONTENT, third.status());
+        assert_eq!(new_etag, third.headers()[header::ETAG]);
+        assert_eq!(b"fghijk".to_vec(), body(third));
+
+        let weak = format!("W/{}", new_etag);
+        let fourth = get(&[
+            (header::RANGE, "bytes=5-"),
+            (header::IF_RANGE, weak.as_str()),
+        ]);
+        assert_eq!(StatusCode::OK, fourth.status());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_special_file() {
+        let dir = TempDir::new("fifo");
+        let fifo = std::ffi::CString::new(dir.join("pipe").to_str().unwrap()).unwrap();
+        assert_eq!(0, unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) });
+
+        // opening a FIFO without writer would block the worker forever
+        let files = StaticFiles::new(&dir).unwrap();
+        let settings = files.settings.load();
+        let selection = files.target_path(&settings, &files.root, "/pipe", "pipe");
+        let response = futures::executor::block_on(files.run(
+            &settings,
+            selection,
+            http::Request::new(Body::empty()),
+        ));
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+    }
+
+    #[test]
+    fn test_cas_selection() {
+        let temp = TempDir::new("cas-root");
+        let dir = temp.canonicalize().unwrap();
+        fs::create_dir_all(dir.join("a/.hidden")).unwrap();
+        fs::create_dir_all(dir.join("b")).unwrap();
+        fs::write(dir.join("a/.hidden/hello.txt"), b"hello").unwrap();
+        fs::write(dir.join("b/hello.txt"), b"hello").unwrap();
+        let hello = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+        let files = StaticFiles::new(&dir)
+            .unwrap()
+            .dotfiles(super::DotfilePolicy::Deny);
+        for path in &["a/.hidden/hello.txt", "b/hello.txt"] {
+            files.cas.update(&files.root, path, &files.hash_cache);
+        }
+        let settings = files.settings.load();
+        let lookup = |root: &str| {
+            let root = files.root.join(root);
+            let selection = files.cas_selection(&settings, &root, hello);
+            selection.target.map(|x| x.path().to_path_buf())
+        };
+        assert_eq!(Some(dir.join("b/hello.txt")), lookup(""));
+        assert_eq!(Some(dir.join("b/hello.txt")), lookup("b"));
+        // the only copy below `a` is hidden
+        assert_eq!(None, lookup("a"));
+    }
 }
@@ -0,0 +1,532 @@
+use crate::{
+    dir_sizes::DirSizes,
+    error::TSFResult,
+    options::Options,
+    redirect_response,
+    utils::{encode_href, ErrorResponse},
+    vfs::Attributes,
+    StaticFiles,
+};
+use http::{header, StatusCode};
+use log::error;
+use serde::Serialize;
+use serde_json::json;
+use std::{fmt::Write, fs, path::Path};
+use tide::{IntoResponse, Request, Response};
+
+/// Built-in template of HTML listings, self-contained (inline CSS and JS, no external requests)
+/// and following `prefers-color-scheme`
+///
+/// `{{title}}` is replaced by the escaped request path, `{{breadcrumbs}}` by links to every
+/// directory from the mount point of the files down to the listed one, `{{rows}}` by a table
+/// row per entry (after a `..` row with class `parent` below the mount point):
+/// `<tr class="{kind}">` with cells of name (a link with icon classes), size in bytes (of
+/// directories only with sizes enabled, then titled with their number of files) and
+/// modification date. Start from it to give listings another look, see
+/// [`StaticFiles::listing_template`](struct.StaticFiles.html#method.listing_template).
+pub const LISTING_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<meta name="color-scheme" content="light dark">
+<title>Index of {{title}}</title>
+<style>
+:root { --fg: #222; --bg: #fff; --muted: #777; --link: #0645ad; --stripe: #f4f4f4; }
+@media (prefers-color-scheme: dark) {
+  :root { --fg: #ddd; --bg: #1b1b1b; --muted: #999; --link: #8ab4f8; --stripe: #262626; }
+}
+body { margin: 2em; font-family: system-ui, sans-serif; color: var(--fg); background: var(--bg); }
+a { color: var(--link); text-decoration: none; }
+nav { margin-bottom: 1em; color: var(--muted); }
+table { width: 100%; border-collapse: collapse; }
+th { text-align: left; color: var(--muted); cursor: pointer; user-select: none; }
+th, td { padding: .25em .75em; }
+td:nth-child(2) { text-align: right; }
+tbody tr:nth-child(even) { background: var(--stripe); }
+.icon::before { display: inline-block; width: 1.6em; content: "\1F4C4"; }
+.icon-directory::before { content: "\1F4C1"; }
+.icon-image::before { content: "\1F5BC"; }
+.icon-video::before { content: "\1F39E"; }
+.icon-audio::before { content: "\1F3B5"; }
+.icon-pdf::before { content: "\1F4D5"; }
+.icon-archive::before { content: "\1F4E6"; }
+.icon-code::before { content: "\1F4DC"; }
+.icon-text::before { content: "\1F4DD"; }
+</style>
+</head>
+<body>
+<h1>Index of {{title}}</h1>
+<nav>{{breadcrumbs}}</nav>
+<table>
+<thead><tr><th>Name</th><th>Size</th><th>Last modified</th></tr></thead>
+<tbody>
+{{rows}}</tbody>
+</table>
+<script>
+// sort by a column when its header is clicked, directories first, the parent stays on top
+document.querySelectorAll("th").forEach(function (th, column) {
+  th.addEventListener("click", function () {
+    var body = document.querySelector("tbody");
+    var rows = Array.prototype.slice.call(body.querySelectorAll("tr:not(.parent)"));
+    var order = th.dataset.order === "asc" ? -1 : 1;
+    th.dataset.order = order > 0 ? "asc" : "desc";
+    var key = function (row) {
+      var text = row.cells[column].textContent;
+      return [text.toLowerCase(), Number(text), Date.parse(text) || 0][column];
+    };
+    var isDir = function (row) { return row.classList.contains("directory") ? 0 : 1; };
+    rows.sort(function (a, b) {
+      var x = key(a), y = key(b);
+      return isDir(a) - isDir(b) || (x < y ? -order : x > y ? order : 0);
+    });
+    rows.forEach(function (row) { body.appendChild(row); });
+  });
+});
+</script>
+</body>
+</html>
+"#;
+
+/// A link to a directory above (or at) a listed one
+#[derive(Debug, Eq, PartialEq, Serialize)]
+pub(crate) struct Breadcrumb {
+    /// decoded name, `/` for the mount point at the root of the site
+    pub name: String,
+    /// absolute, percent encoded, with trailing slash
+    pub href: String,
+}
+
+/// An entry of a generated directory listing
+#[derive(Debug, Serialize)]
+pub(crate) struct Entry {
+    pub name: String,
+    /// relative to the listed directory, percent encoded
+    pub href: String,
+    pub is_dir: bool,
+    /// 0 for directories
+    pub size: u64,
+    /// size of files below a directory, with
+    /// [`listing_sizes`](struct.StaticFiles.html#method.listing_sizes) once known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub total_size: Option<u64>,
+    /// number of files below a directory, like `total_size`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file_count: Option<usize>,
+    /// HTTP date, `None` if unavailable
+    pub modified: Option<String>,
+    /// HTTP date, `None` if unavailable
+    pub created: Option<String>,
+    /// octal Unix permission bits, e.g. `0644`, `None` if unavailable
+    pub permissions: Option<String>,
+    /// Unix user id of owner, `None` if unavailable
+    pub owner: Option<u32>,
+    /// rough type derived from MIME, e.g. `image`, `archive`, `directory`
+    pub kind: &'static str,
+    /// CSS classes of the icon for `kind`, e.g. `icon icon-image`
+    pub icon: String,
+}
+
+impl StaticFiles {
+    /// Listing of directory `dir`, as JSON if asked by `Accept: application/json` or
+    /// `?format=json`, otherwise as HTML
+    ///
+    /// Requests without trailing slash are redirected first, so that relative links resolve
+    /// inside the directory.
+    pub(crate) fn listing_response(&self, dir: &Path, url_path: &str, req: &Request) -> Response {
+        let request_path = req.uri().path();
+        if !request_path.ends_with('/') {
+            // relative, a path like `//host` would make a protocol-relative `Location`
+            let last = request_path.rsplit('/').next().unwrap_or(request_path);
+            let location = format!("./{}/", last);
+            return redirect_response(StatusCode::MOVED_PERMANENTLY, &location, req.uri().query());
+        }
+        let settings = self.settings.load();
+        let mut entries = match read_entries(dir) {
+            Ok(x) => x,
+            Err(error) => {
+                error!("unexpected error occurred: {:?}", error);
+                return ErrorResponse::Unexpected.into_response();
+            }
+        };
+        if settings.options.listing_sizes {
+            for entry in entries.iter_mut().filter(|x| x.is_dir) {
+                if let Some(summary) = DirSizes::get(&self.dir_sizes, &dir.join(&entry.name)) {
+                    entry.total_size = Some(summary.size);
+                    entry.file_count = Some(summary.files);
+                }
+            }
+        }
+
+        let crumbs = breadcrumbs(request_path, url_path);
+        let is_root = crumbs.len() == 1;
+        let (content_type, body) = if wants_json(req) {
+            let parent = match crumbs.len() {
+                1 => None,
+                x => Some(&crumbs[x - 2].href),
+            };
+            let body = json!({
+                "path": request_path,
+                "breadcrumbs": crumbs,
+                "parent": parent,
+                "entries": entries,
+            });
+            (mime::APPLICATION_JSON, body.to_string())
+        } else {
+            let template = match &settings.options.listing_template {
+                Some(x) => x.as_str(),
+                None => LISTING_TEMPLATE,
+            };
+            (
+                mime::TEXT_HTML_UTF_8,
+                render_html(template, request_path, &crumbs, is_root, &entries),
+            )
+        };
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, content_type.to_string())
+            .header(header::CONTENT_LENGTH, body.len() as u64)
+            .body(body.into())
+            .unwrap()
+    }
+}
+
+/// Directories from the mount point down to the listed one, `url_path` being the part of
+/// `request_path` captured by the route
+///
+/// The mount point is `request_path` without `url_path`, e.g. `/files/` of `/files/a/b/` routed
+/// by `/files/*`, so that links stay below it whatever the route.
+pub(crate) fn breadcrumbs(request_path: &str, url_path: &str) -> Vec<Breadcrumb> {
+    let segments: Vec<_> = url_path
+        .split('/')
+        .filter(|x| !x.is_empty() && *x != ".")
+        .collect();
+    let captured = url_path.trim_start_matches('/');
+    let mount = match request_path.len().checked_sub(captured.len()) {
+        Some(x) if request_path.ends_with(captured) => &request_path[..x],
+        _ => "/",
+    };
+    let mut href = mount.to_string();
+    if !href.ends_with('/') {
+        href.push('/');
+    }
+    let decode = |x: &str| {
+        percent_encoding::percent_decode(x.as_bytes())
+            .decode_utf8_lossy()
+            .into_owned()
+    };
+    let name = match href.trim_end_matches('/').rsplit('/').next() {
+        Some(x) if !x.is_empty() => decode(x),
+        _ => "/".to_string(),
+    };
+    let mut crumbs = vec![Breadcrumb {
+        name,
+        href: href.clone(),
+    }];
+    for segment in segments {
+        href.push_str(segment);
+        href.push('/');
+        crumbs.push(Breadcrumb {
+            name: decode(segment),
+            href: href.clone(),
+        });
+    }
+    crumbs
+}
+
+/// Whether directory `dir` may be listed: listings are enabled, and the directory contains the
+/// marker file if one is required
+pub(crate) fn is_listable(options: &Options, dir: &Path) -> bool {
+    options.listing
+        && match &options.listing_marker {
+            None => true,
+            Some(marker) => dir.join(marker).is_file(),
+        }
+}
+
+fn wants_json(req: &Request) -> bool {
+    let query = req
+        .uri()
+        .query()
+        .map(|x| x.split('&').any(|x| x == "format=json"))
+        .unwrap_or(false);
+    let accept = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|x| x.to_str().ok())
+        .map(|x| x.contains("application/json"))
+        .unwrap_or(false);
+    query || accept
+}
+
+/// Entries of `dir`, directories first, then by name; hidden (dot) files and names which are
+/// not valid UTF-8 are skipped
+pub(crate) fn read_entries(dir: &Path) -> TSFResult<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let name = match item.file_name().into_string() {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') {
+            continue;
+        }
+        // follow symbolic links, like serving does
+        let metadata = match fs::metadata(item.path()) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let is_dir = metadata.is_dir();
+        let attributes = Attributes::from_fs(&metadata);
+        let kind = kind(&name, is_dir);
+        let href = if is_dir {
+            format!("{}/", encode_href(&name))
+        } else {
+            encode_href(&name)
+        };
+        entries.push(Entry {
+            href,
+            is_dir,
+            size: if is_dir { 0 } else { metadata.len() },
+            total_size: None,
+            file_count: None,
+            modified: metadata.modified().ok().map(httpdate::fmt_http_date),
+            created: attributes.created.map(httpdate::fmt_http_date),
+            permissions: attributes.permissions.map(|x| format!("{:04o}", x)),
+            owner: attributes.owner,
+            kind,
+            icon: format!("icon icon-{}", kind),
+            name,
+        });
+    }
+    entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+    Ok(entries)
+}
+
+/// Rough type of an entry, for icons
+fn kind(name: &str, is_dir: bool) -> &'static str {
+    if is_dir {
+        return "directory";
+    }
+    let mime = mime_guess::guess_mime_type(name);
+    match (mime.type_(), mime.subtype().as_str()) {
+        (mime::IMAGE, _) => "image",
+        (mime::VIDEO, _) => "video",
+        (mime::AUDIO, _) => "audio",
+        (mime::APPLICATION, "pdf") => "pdf",
+        (mime::APPLICATION, "zip")
+        | (mime::APPLICATION, "gzip")
+        | (mime::APPLICATION, "x-gzip")
+        | (mime::APPLICATION, "x-tar")
+        | (mime::APPLICATION, "x-bzip2")
+        | (mime::APPLICATION, "x-xz")
+        | (mime::APPLICATION, "x-7z-compressed")
+        | (mime::APPLICATION, "x-rar-compressed") => "archive",
+        (mime::APPLICATION, "javascript")
+        | (mime::APPLICATION, "json")
+        | (mime::APPLICATION, "xml")
+        | (mime::APPLICATION, "wasm") => "code",
+        (mime::TEXT, _) => "text",
+        _ => "file",
+    }
+}
+
+/// Fill `template` (see [`LISTING_TEMPLATE`]) with rows of `entries`
+fn render_html(
+    template: &str,
+    request_path: &str,
+    crumbs: &[Breadcrumb],
+    is_root: bool,
+    entries: &[Entry],
+) -> String {
+    let title =
+        escape_html(&percent_encoding::percent_decode(request_path.as_bytes()).decode_utf8_lossy());
+    let mut nav = Vec::new();
+    for (index, crumb) in crumbs.iter().enumerate() {
+        let name = escape_html(&crumb.name);
+        if index + 1 == crumbs.len() {
+            nav.push(format!("<span>{}</span>", name));
+        } else {
+            nav.push(format!(
+                "<a href=\"{}\">{}</a>",
+                escape_html(&crumb.href),
+                name
+            ));
+        }
+    }
+    let nav = nav.join(" / ");
+    let mut rows = String::new();
+    if !is_root {
+        rows.push_str(
+            "<tr class=\"directory parent\"><td><a class=\"icon icon-directory\" href=\"../\">\
+             ../</a></td><td></td><td></td></tr>\n",
+        );
+    }
+    for entry in entries {
+        write!(
+            rows,
+            "<tr class=\"{kind}\"><td><a class=\"{icon}\" href=\"{href}\">{name}{slash}</a></td>\
+             <td{count}>{size}</td><td>{modified}</td></tr>\n",
+            kind = entry.kind,
+            icon = entry.icon,
+            href = escape_html(&entry.href),
+            name = escape_html(&entry.name),
+            slash = if entry.is_dir { "/" } else { "" },
+            count = match entry.file_count {
+                Some(x) => format!(" title=\"{} files\"", x),
+                None => String::new(),
+            },
+            size = match entry.total_size {
+                Some(x) => x.to_string(),
+                None if entry.is_dir => String::new(),
+                None => entry.size.to_string(),
+            },
+            modified = entry.modified.as_ref().map(String::as_str).unwrap_or(""),
+        )
+        .unwrap();
+    }
+    // rows last, names in them may look like placeholders
+    template
+        .replace("{{title}}", &title)
+        .replace("{{breadcrumbs}}", &nav)
+        .replace("{{rows}}", &rows)
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_read_entries() {
+        let dir = TempDir::new("listing");
+        fs::create_dir_all(dir.join("sub dir")).unwrap();
+        fs::write(dir.join("b.png"), b"png").unwrap();
+        fs::write(dir.join("a#1.tar"), b"").unwrap();
+        fs::write(dir.join(".hidden"), b"").unwrap();
+
+        let entries = read_entries(&dir).unwrap();
+        let summary: Vec<_> = entries
+            .iter()
+            .map(|x| (x.name.as_str(), x.href.as_str(), x.kind, x.size))
+            .collect();
+        assert_eq!(
+            vec![
+                ("sub dir", "sub%20dir/", "directory", 0),
+                ("a#1.tar", "a%231.tar", "archive", 0),
+                ("b.png", "b.png", "image", 3),
+            ],
+            summary
+        );
+        assert_eq!("icon icon-image", entries[2].icon);
+        #[cfg(unix)]
+        assert!(entries
+            .iter()
+            .all(|x| x.permissions.is_some() && x.owner.is_some()));
+
+        let crumbs = breadcrumbs("/files/", "");
+        let html = render_html(LISTING_TEMPLATE, "/files/", &crumbs, false, &entries);
+        assert!(html.contains("<a class=\"icon icon-archive\" href=\"a%231.tar\">a#1.tar</a>"));
+        assert!(html.contains("href=\"../\""));
+        assert!(html.contains("<title>Index of /files/</title>"));
+        assert!(html.contains("prefers-color-scheme: dark"));
+        // no external assets
+        assert!(!html.contains("<link") && !html.contains(" src="));
+
+        let html = render_html("<ul>{{rows}}</ul>", "/", &crumbs, true, &entries[..1]);
+        assert!(html.starts_with("<ul><tr class=\"directory\">"));
+    }
+
+    #[test]
+    fn test_breadcrumbs() {
+        let summary = |request_path, url_path| {
+            breadcrumbs(request_path, url_path)
+                .into_iter()
+                .map(|x| (x.name, x.href))
+                .collect::<Vec<_>>()
+        };
+        let crumb = |name: &str, href: &str| (name.to_string(), href.to_string());
+        assert_eq!(vec![crumb("/", "/")], summary("/", ""));
+        assert_eq!(
+            vec![
+                crumb("files", "/files/"),
+                crumb("a b", "/files/a%20b/"),
+                crumb("c", "/files/a%20b/c/"),
+            ],
+            summary("/files/a%20b/c/", "a%20b/c/")
+        );
+        assert_eq!(
+            vec![crumb("/", "/"), crumb("docs", "/docs/")],
+            summary("/docs/", "docs/")
+        );
+        assert_eq!(vec![crumb("files", "/files/")], summary("/files/", ""));
+
+        let html = render_html(
+            "{{breadcrumbs}}",
+            "/files/a/",
+            &breadcrumbs("/files/a/", "a/"),
+            false,
+            &[],
+        );
+        assert_eq!("<a href=\"/files/\">files</a> / <span>a</span>", html);
+    }
+
+    #[test]
+    fn test_is_listable() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let mut options = Options::default();
+        assert!(!is_listable(&options, dir));
+        options.listing = true;
+        assert!(is_listable(&options, dir));
+        options.listing_marker = Some(".listing".to_string());
+        assert!(!is_listable(&options, dir));
+        options.listing_marker = Some("Cargo.toml".to_string());
+        assert!(is_listable(&options, dir));
+    }
+
+    #[test]
+    fn test_kind() {
+        assert_eq!("directory", kind("a.png", true));
+        assert_eq!("text", kind("a.txt", false));
+        assert_eq!("code", kind("a.js", false));
+        assert_eq!("pdf", kind("a.pdf", false));
+        assert_eq!("file", kind("a", false));
+    }
+
+    #[test]
+    fn test_redirect() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let files = StaticFiles::new(dir).unwrap();
+        let req = http::Request::builder()
+            .uri("//example.com?a=1")
+            .body(http_service::Body::empty())
+            .unwrap();
+        let response = files.listing_response(dir, "example.com", &req);
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, response.status());
+        assert_eq!("./example.com/?a=1", response.headers()[header::LOCATION]);
+    }
+
+    #[test]
+    fn test_template() {
+        assert!(!LISTING_TEMPLATE
+            .chars()
+            .any(|x| x.is_control() && x != '\n'));
+        assert!(LISTING_TEMPLATE.contains(r#"content: "\1F4C1";"#));
+    }
+}
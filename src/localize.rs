@@ -0,0 +1,137 @@
+//! Localized bodies of built-in error responses, negotiated by `Accept-Language`
+
+use http::{header, HeaderValue};
+use std::collections::BTreeMap;
+use tide::Response;
+
+/// Localized messages by language tag (e.g. `de`, `pt-BR`), then by status code
+pub type MessageCatalog = BTreeMap<String, BTreeMap<u16, String>>;
+
+/// Language of `catalog` preferred by `accept_language`, tags match case-insensitively, then by
+/// primary subtag, e.g. `de-CH` is served `de`
+pub(crate) fn negotiate_language<'a>(
+    accept_language: &str,
+    catalog: &'a MessageCatalog,
+) -> Option<&'a str> {
+    let mut ranges: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let tag = params.next()?.to_ascii_lowercase();
+            let quality = params
+                .filter_map(|x| {
+                    let x = x.replace(' ', "");
+                    if x.starts_with("q=") {
+                        x[2..].parse::<f32>().ok()
+                    } else {
+                        None
+                    }
+                })
+                .next()
+                .unwrap_or(1.0);
+            Some((tag, quality)).filter(|(tag, q)| !tag.is_empty() && tag != "*" && *q > 0.0)
+        })
+        .collect();
+    // stable, ties keep order of the header
+    ranges.sort_by(|x, y| y.1.partial_cmp(&x.1).unwrap());
+    let find = |tag: &str| {
+        catalog
+            .keys()
+            .find(|x| x.eq_ignore_ascii_case(tag))
+            .map(String::as_str)
+    };
+    ranges
+        .iter()
+        .find_map(|(tag, _)| find(tag).or_else(|| tag.split('-').next().and_then(find)))
+}
+
+/// Replace the plain text body of a `4xx` or `5xx` response by its message in the language
+/// preferred by `accept_language`, if the catalog has one
+pub(crate) fn localize(
+    response: Response,
+    catalog: &MessageCatalog,
+    accept_language: Option<&str>,
+) -> Response {
+    let status = response.status();
+    let plain = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(false, |x| x.as_bytes().starts_with(b"text/plain"));
+    if !(status.is_client_error() || status.is_server_error()) || !plain {
+        return response;
+    }
+    let found = accept_language
+        .and_then(|x| negotiate_language(x, catalog))
+        .and_then(|language| Some((language, catalog[language].get(&status.as_u16())?)));
+    let (language, message) = match found {
+        Some(x) => x,
+        None => return response,
+    };
+    let (mut parts, _) = response.into_parts();
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; charset=utf-8"),
+    );
+    if let Ok(language) = HeaderValue::from_str(language) {
+        parts.headers.insert(header::CONTENT_LANGUAGE, language);
+    }
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, message.clone().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ErrorResponse;
+    use futures::executor::block_on;
+    use tide::IntoResponse;
+
+    fn catalog() -> MessageCatalog {
+        let mut catalog = MessageCatalog::new();
+        let mut de = BTreeMap::new();
+        de.insert(404, "Nicht gefunden".to_string());
+        catalog.insert("de".to_string(), de);
+        let mut pt = BTreeMap::new();
+        pt.insert(404, "Não encontrado".to_string());
+        catalog.insert("pt-BR".to_string(), pt);
+        catalog
+    }
+
+    #[test]
+    fn test_negotiate_language() {
+        let catalog = catalog();
+        assert_eq!(Some("de"), negotiate_language("de", &catalog));
+        assert_eq!(Some("de"), negotiate_language("de-CH, en;q=0.8", &catalog));
+        assert_eq!(Some("pt-BR"), negotiate_language("pt-br", &catalog));
+        assert_eq!(
+            Some("pt-BR"),
+            negotiate_language("de;q=0.5, pt-BR;q=0.9", &catalog)
+        );
+        assert_eq!(None, negotiate_language("de;q=0, en", &catalog));
+        assert_eq!(None, negotiate_language("*", &catalog));
+        assert_eq!(None, negotiate_language("pt", &catalog));
+    }
+
+    #[test]
+    fn test_localize() {
+        let catalog = catalog();
+        let response = localize(
+            ErrorResponse::NotFound.into_response(),
+            &catalog,
+            Some("de-DE"),
+        );
+        assert_eq!("de", response.headers()[header::CONTENT_LANGUAGE]);
+        let body = block_on(response.into_body().into_vec()).unwrap();
+        assert_eq!("Nicht gefunden".as_bytes(), &body[..]);
+
+        // no message of this status
+        let response = localize(
+            ErrorResponse::Forbidden.into_response(),
+            &catalog,
+            Some("de"),
+        );
+        assert!(!response.headers().contains_key(header::CONTENT_LANGUAGE));
+        let response = localize(ErrorResponse::NotFound.into_response(), &catalog, None);
+        assert!(!response.headers().contains_key(header::CONTENT_LANGUAGE));
+    }
+}
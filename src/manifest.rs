@@ -0,0 +1,108 @@
+use crate::error::{InvalidManifest, TSFResult};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    path::Path,
+};
+
+/// `Cache-Control` value for fingerprinted files, their content never changes
+pub(crate) const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+/// Mapping from logical names (`app.js`) to fingerprinted files (`app.3f9ab2.js`)
+///
+/// Both flat manifests (webpack, `{"app.js": "app.3f9ab2.js"}`) and
+/// Vite manifests (`{"app.js": {"file": "app.3f9ab2.js", ...}}`) are accepted.
+/// All names are relative to the root directory.
+#[derive(Debug, Default)]
+pub(crate) struct AssetManifest {
+    logical: HashMap<String, String>,
+    fingerprinted: HashSet<String>,
+}
+
+impl AssetManifest {
+    pub fn load(path: &Path) -> TSFResult<Self> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> TSFResult<Self> {
+        let entries = match serde_json::from_str(text)? {
+            Value::Object(x) => x,
+            _ => return Err(InvalidManifest("top level is not an object".to_string()).into()),
+        };
+
+        let mut manifest = Self::default();
+        for (name, entry) in entries {
+            let file = match &entry {
+                Value::String(x) => x,
+                Value::Object(x) => match x.get("file") {
+                    Some(Value::String(x)) => x,
+                    _ => return Err(InvalidManifest(format!("no file for {}", name)).into()),
+                },
+                _ => return Err(InvalidManifest(format!("bad entry for {}", name)).into()),
+            };
+            let file = normalize(file).to_string();
+            manifest.fingerprinted.insert(file.clone());
+            manifest.logical.insert(normalize(&name).to_string(), file);
+        }
+        Ok(manifest)
+    }
+
+    /// Given url path, return the path to be served and whether it's a fingerprinted file
+    pub fn resolve<'a>(&'a self, url_path: &'a str) -> (&'a str, bool) {
+        let name = normalize(url_path);
+        if self.fingerprinted.contains(name) {
+            return (url_path, true);
+        }
+        match self.logical.get(name) {
+            Some(file) => (file, false),
+            None => (url_path, false),
+        }
+    }
+}
+
+fn normalize(name: &str) -> &str {
+    name.trim_start_matches('/')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() {
+        let manifest = AssetManifest::parse(
+            r#"{"app.js": "app.3f9ab2.js", "/css/app.css": "/css/app.1d2c3b.css"}"#,
+        )
+        .unwrap();
+        assert_eq!(("app.3f9ab2.js", false), manifest.resolve("app.js"));
+        assert_eq!(("app.3f9ab2.js", true), manifest.resolve("app.3f9ab2.js"));
+        assert_eq!(
+            ("css/app.1d2c3b.css", false),
+            manifest.resolve("/css/app.css")
+        );
+        assert_eq!(
+            ("/css/app.1d2c3b.css", true),
+            manifest.resolve("/css/app.1d2c3b.css")
+        );
+        assert_eq!(("other.js", false), manifest.resolve("other.js"));
+    }
+
+    #[test]
+    fn test_parse_vite() {
+        let manifest = AssetManifest::parse(
+            r#"{"main.js": {"file": "assets/main.4889e940.js", "isEntry": true}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            ("assets/main.4889e940.js", false),
+            manifest.resolve("main.js")
+        );
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert!(AssetManifest::parse("[]").is_err());
+        assert!(AssetManifest::parse(r#"{"app.js": 1}"#).is_err());
+        assert!(AssetManifest::parse(r#"{"app.js": {"src": "app.js"}}"#).is_err());
+    }
+}
@@ -0,0 +1,78 @@
+//! Pre-split HLS and DASH streams: playlists (`.m3u8`, `.mpd`) and media segments (`.ts`,
+//! `.m4s`)
+
+use mime::Mime;
+use std::path::Path;
+
+/// Part of a stream a file is
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum MediaKind {
+    /// rewritten while a live stream goes on, sent with `Cache-Control: no-cache`
+    Playlist,
+    /// never changes once written
+    Segment,
+}
+
+const MEDIA_TYPES: &[(&str, &str, MediaKind)] = &[
+    ("m3u8", "application/vnd.apple.mpegurl", MediaKind::Playlist),
+    ("mpd", "application/dash+xml", MediaKind::Playlist),
+    ("ts", "video/mp2t", MediaKind::Segment),
+    ("m4s", "video/iso.segment", MediaKind::Segment),
+];
+
+/// `Cache-Control` of playlists, players poll them for new segments
+pub(crate) const PLAYLIST_CACHE_CONTROL: &str = "no-cache";
+
+/// Headers sent for playlists and segments when `Options::media_cors` is on, so that players on
+/// other origins may fetch them, ranges included
+pub(crate) const MEDIA_CORS_HEADERS: &[(&str, &str)] = &[
+    ("access-control-allow-origin", "*"),
+    (
+        "access-control-expose-headers",
+        "Content-Length, Content-Range, Accept-Ranges",
+    ),
+];
+
+fn lookup(path: &Path) -> Option<&'static (&'static str, &'static str, MediaKind)> {
+    let extension = path.extension()?.to_str()?;
+    MEDIA_TYPES
+        .iter()
+        .find(|(x, _, _)| x.eq_ignore_ascii_case(extension))
+}
+
+pub(crate) fn media_kind(path: &Path) -> Option<MediaKind> {
+    lookup(path).map(|x| x.2)
+}
+
+/// MIME type of streams, overriding guesses by extension (e.g. `.ts` as TypeScript)
+pub(crate) fn media_mime(path: &Path) -> Option<Mime> {
+    lookup(path).map(|x| x.1.parse().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_media() {
+        for (extension, mime, _) in MEDIA_TYPES {
+            assert_eq!(
+                *mime,
+                mime.parse::<Mime>().unwrap().as_ref(),
+                "{}",
+                extension
+            );
+        }
+        let path = Path::new("live/index.M3U8");
+        assert_eq!(Some(MediaKind::Playlist), media_kind(path));
+        assert_eq!(
+            "application/vnd.apple.mpegurl",
+            media_mime(path).unwrap().as_ref()
+        );
+        let path = Path::new("live/segment-1.ts");
+        assert_eq!(Some(MediaKind::Segment), media_kind(path));
+        assert_eq!("video/mp2t", media_mime(path).unwrap().as_ref());
+        assert_eq!(None, media_kind(Path::new("index.html")));
+        assert_eq!(None, media_mime(Path::new("ts")));
+    }
+}
@@ -0,0 +1,100 @@
+//! Memory of read buffers in flight across all streamed bodies, bounded so that thousands of
+//! slow clients can't each pin a buffer of the largest chunk size
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static BUDGET: MemoryBudget = MemoryBudget::new();
+
+/// Bound memory of buffers of reads in flight, of all instances in a process, default none
+///
+/// A buffer counts from its read until the stream is polled for the next chunk. Once the budget
+/// is used up, chunks shrink down to their [minimum](struct.StaticFiles.html#method.chunk_size_bounds),
+/// which is always granted so that no stream stalls.
+pub fn set_memory_budget(bytes: Option<usize>) {
+    BUDGET
+        .limit
+        .store(bytes.unwrap_or(usize::max_value()), Ordering::SeqCst);
+}
+
+/// Bytes of read buffers currently in flight
+pub fn memory_in_flight() -> usize {
+    BUDGET.used.load(Ordering::SeqCst)
+}
+
+/// Reserve a buffer of up to `wanted` bytes from the process-wide budget
+pub(crate) fn reserve(wanted: usize, min: usize) -> Reservation {
+    BUDGET.reserve(wanted, min)
+}
+
+pub(crate) struct MemoryBudget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    const fn new() -> Self {
+        Self {
+            limit: AtomicUsize::new(usize::max_value()),
+            used: AtomicUsize::new(0),
+        }
+    }
+
+    /// Reserve `wanted` bytes, or what's left of the budget but at least `min` (or `wanted` if
+    /// smaller)
+    fn reserve(&'static self, wanted: usize, min: usize) -> Reservation {
+        let used = self.used.fetch_add(wanted, Ordering::SeqCst);
+        let limit = self.limit.load(Ordering::SeqCst);
+        let size = if used.saturating_add(wanted) <= limit {
+            wanted
+        } else {
+            let size = limit.saturating_sub(used).max(min).min(wanted);
+            self.used.fetch_sub(wanted - size, Ordering::SeqCst);
+            size
+        };
+        Reservation { budget: self, size }
+    }
+}
+
+/// Bytes taken from a budget, given back once dropped
+pub(crate) struct Reservation {
+    budget: &'static MemoryBudget,
+    size: usize,
+}
+
+impl Reservation {
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.budget.used.fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static TEST_BUDGET: MemoryBudget = MemoryBudget::new();
+
+    #[test]
+    fn test_memory_budget() {
+        TEST_BUDGET.limit.store(100, Ordering::SeqCst);
+        let first = TEST_BUDGET.reserve(60, 10);
+        assert_eq!(60, first.size());
+        // what's left
+        let second = TEST_BUDGET.reserve(60, 10);
+        assert_eq!(40, second.size());
+        // the minimum beyond the budget
+        let third = TEST_BUDGET.reserve(60, 10);
+        assert_eq!(10, third.size());
+        assert_eq!(5, TEST_BUDGET.reserve(5, 10).size());
+        assert_eq!(110, TEST_BUDGET.used.load(Ordering::SeqCst));
+
+        drop((first, second, third));
+        assert_eq!(0, TEST_BUDGET.used.load(Ordering::SeqCst));
+        assert_eq!(60, TEST_BUDGET.reserve(60, 10).size());
+    }
+}
@@ -0,0 +1,217 @@
+use crate::file_read::ReadPoolHealth;
+use http::StatusCode;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// What kind of response has been served
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Served {
+    /// `200 OK` with whole file
+    Full { size: u64 },
+    /// `206 Partial Content` with one range
+    SingleRange { size: u64 },
+    /// `206 Partial Content` with `multipart/byteranges` body, `size` excludes part headers
+    MultiRange { parts: usize, size: u64 },
+    /// `304 Not Modified`
+    NotModified,
+    /// `416 Range Not Satisfiable`
+    RangeNotSatisfiable,
+}
+
+/// Receive events about served responses, e.g. to feed a metrics system
+pub trait Metrics: Send + Sync {
+    /// Called once for every served response
+    fn response(&self, served: Served);
+
+    /// Called for every byte range served (after merging), with its size
+    fn range(&self, _size: u64) {}
+
+    /// Called once for every served response, with state of the file read pool
+    fn read_pool(&self, _health: ReadPoolHealth) {}
+
+    /// Called with the status of every response to a file request, whatever its outcome
+    fn status(&self, _status: StatusCode) {}
+
+    /// Called when a cache lifetime (`max-age` or `s-maxage`, in seconds) has been clamped to
+    /// configured bounds
+    fn cache_age_clamped(&self, _from: u64, _to: u64) {}
+
+    /// Called when a `multipart/byteranges` body ended at another length than announced in
+    /// `Content-Length`, a bug of this crate
+    fn multipart_length_mismatch(&self, _announced: u64, _written: u64) {}
+
+    /// Called when a response was dropped for a `503` as the request's
+    /// [`Deadline`](struct.Deadline.html) passed, with the step it passed after
+    fn deadline_exceeded(&self, _step: DeadlineStep) {}
+}
+
+/// Step of building a response after which a [`Deadline`](struct.Deadline.html) is checked
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum DeadlineStep {
+    /// selection of the entry to serve
+    Resolution,
+    /// opening the entry and reading its metadata
+    Metadata,
+    /// headers and body built, right before the first byte is sent
+    FirstByte,
+}
+
+/// Upper bounds (exclusive) of buckets in range size histogram, the last bucket is unbounded
+pub const RANGE_SIZE_BUCKETS: [u64; 8] = [
+    1 << 10,
+    16 << 10,
+    64 << 10,
+    256 << 10,
+    1 << 20,
+    4 << 20,
+    16 << 20,
+    64 << 20,
+];
+
+/// Built-in [`Metrics`](trait.Metrics.html) implementation, counts responses by kind
+/// and records a histogram of requested range sizes
+///
+/// Useful to tune chunk sizes and caches based on real client behavior.
+#[derive(Default)]
+pub struct ByteServingStats {
+    full: AtomicU64,
+    single_range: AtomicU64,
+    multi_range: AtomicU64,
+    not_modified: AtomicU64,
+    range_not_satisfiable: AtomicU64,
+    range_bytes: AtomicU64,
+    range_sizes: [AtomicU64; 9], // RANGE_SIZE_BUCKETS.len() + 1
+    length_mismatches: AtomicU64,
+}
+
+/// Point-in-time copy of [`ByteServingStats`](struct.ByteServingStats.html)
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ByteServingSnapshot {
+    pub full: u64,
+    pub single_range: u64,
+    pub multi_range: u64,
+    pub not_modified: u64,
+    pub range_not_satisfiable: u64,
+    /// total size of all served ranges
+    pub range_bytes: u64,
+    /// count of served ranges per bucket, see [`RANGE_SIZE_BUCKETS`](constant.RANGE_SIZE_BUCKETS.html)
+    pub range_sizes: Vec<u64>,
+    /// multipart bodies not matching their `Content-Length`, should stay zero
+    pub length_mismatches: u64,
+}
+
+impl ByteServingStats {
+    pub fn snapshot(&self) -> ByteServingSnapshot {
+        ByteServingSnapshot {
+            full: self.full.load(Ordering::Relaxed),
+            single_range: self.single_range.load(Ordering::Relaxed),
+            multi_range: self.multi_range.load(Ordering::Relaxed),
+            not_modified: self.not_modified.load(Ordering::Relaxed),
+            range_not_satisfiable: self.range_not_satisfiable.load(Ordering::Relaxed),
+            range_bytes: self.range_bytes.load(Ordering::Relaxed),
+            range_sizes: self
+                .range_sizes
+                .iter()
+                .map(|x| x.load(Ordering::Relaxed))
+                .collect(),
+            length_mismatches: self.length_mismatches.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Metrics for ByteServingStats {
+    fn response(&self, served: Served) {
+        let counter = match served {
+            Served::Full { .. } => &self.full,
+            Served::SingleRange { .. } => &self.single_range,
+            Served::MultiRange { .. } => &self.multi_range,
+            Served::NotModified => &self.not_modified,
+            Served::RangeNotSatisfiable => &self.range_not_satisfiable,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn range(&self, size: u64) {
+        self.range_bytes.fetch_add(size, Ordering::Relaxed);
+        self.range_sizes[bucket(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn multipart_length_mismatch(&self, _announced: u64, _written: u64) {
+        self.length_mismatches.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl ByteServingSnapshot {
+    /// Average size of served ranges, `None` if no range served
+    pub fn average_range_size(&self) -> Option<u64> {
+        match self.range_sizes.iter().sum::<u64>() {
+            0 => None,
+            count => Some(self.range_bytes / count),
+        }
+    }
+
+    /// Ratio of `416 Range Not Satisfiable` among all range requests, `None` if no range request
+    pub fn range_not_satisfiable_rate(&self) -> Option<f64> {
+        match self.single_range + self.multi_range + self.range_not_satisfiable {
+            0 => None,
+            total => Some(self.range_not_satisfiable as f64 / total as f64),
+        }
+    }
+}
+
+pub(crate) fn bucket(size: u64) -> usize {
+    RANGE_SIZE_BUCKETS
+        .iter()
+        .position(|limit| size < *limit)
+        .unwrap_or_else(|| RANGE_SIZE_BUCKETS.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_constraints() {
+        let stats = ByteServingStats::default();
+        assert_eq!(RANGE_SIZE_BUCKETS.len() + 1, stats.range_sizes.len());
+    }
+
+    #[test]
+    fn test_bucket() {
+        assert_eq!(0, bucket(0));
+        assert_eq!(0, bucket(1023));
+        assert_eq!(1, bucket(1024));
+        assert_eq!(RANGE_SIZE_BUCKETS.len(), bucket(64 << 20));
+        assert_eq!(RANGE_SIZE_BUCKETS.len(), bucket(u64::max_value()));
+    }
+
+    #[test]
+    fn test_byte_serving_stats() {
+        let stats = ByteServingStats::default();
+        assert_eq!(None, stats.snapshot().average_range_size());
+        assert_eq!(None, stats.snapshot().range_not_satisfiable_rate());
+
+        stats.response(Served::Full { size: 100 });
+        stats.response(Served::SingleRange { size: 100 });
+        stats.range(100);
+        stats.response(Served::MultiRange {
+            parts: 2,
+            size: 2148,
+        });
+        stats.range(100);
+        stats.range(2048);
+        stats.response(Served::RangeNotSatisfiable);
+        stats.multipart_length_mismatch(10, 11);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(1, snapshot.full);
+        assert_eq!(1, snapshot.single_range);
+        assert_eq!(1, snapshot.multi_range);
+        assert_eq!(1, snapshot.range_not_satisfiable);
+        assert_eq!(2248, snapshot.range_bytes);
+        assert_eq!(2, snapshot.range_sizes[0]);
+        assert_eq!(1, snapshot.range_sizes[1]);
+        assert_eq!(1, snapshot.length_mismatches);
+        assert_eq!(Some(749), snapshot.average_range_size());
+        assert_eq!(Some(1.0 / 3.0), snapshot.range_not_satisfiable_rate());
+    }
+}
@@ -1,34 +1,65 @@
-use crate::utils::{buffer_size, u64_width, BOUNDARY, MAX_BUFFER_SIZE};
+use crate::{
+    metrics::Metrics,
+    progress::{PartEvent, PartObserver},
+    utils::{buffer_size, u64_width, MAX_BUFFER_SIZE},
+    vfs::Reader,
+};
 use bytes::{buf::BufMut, Bytes};
 use futures::{task::Waker, Poll, Stream};
 use log::error;
 use std::{
+    cmp::min,
     collections::vec_deque::VecDeque,
-    fs::File,
-    io::{Cursor, Read, Seek, SeekFrom},
+    io::{Cursor, ErrorKind, Read, Seek, SeekFrom, Write},
     ops::Range,
+    path::PathBuf,
     pin::Pin,
+    sync::Arc,
 };
-const HEADER_SIZE_CONSTANT: usize = 56; // see the unit test for the actual meaning.
+const HEADER_SIZE_CONSTANT: usize = 49; // see the unit test for the actual meaning.
 
 pub(super) struct MultiRangeReader {
-    file: File,
+    file: Reader,
     file_size: u64,
     mime: String,
+    boundary: String,
     ranges: VecDeque<Range<u64>>,
     state: ToBeWritten,
+    /// rendered part header or terminator, copied into chunks as room allows
+    pending: Vec<u8>,
+    /// bytes of `pending` already copied
+    pending_position: usize,
+    /// offset of the part being written, before its range is consumed
+    part_start: u64,
+    chunk_size: usize,
+    observer: Option<(Arc<dyn PartObserver>, PathBuf)>,
+    /// `Content-Length` of the plan, checked against `written` once at the end
+    announced: Option<u64>,
+    written: u64,
+    metrics: Option<Arc<dyn Metrics>>,
 }
 
+/// What to produce once pending bytes are copied
 #[derive(Eq, PartialEq, Debug)]
 enum ToBeWritten {
+    /// part header of the first range
     Header,
+    /// content of the first range
     Body,
-    Final,
+    /// closing delimiter, after all parts
+    Terminator,
+    /// nothing, the stream ends
     None,
 }
 
 impl MultiRangeReader {
-    pub fn new(file: File, file_size: u64, mime: &str, ranges: Vec<Range<u64>>) -> Self {
+    pub fn new(
+        file: Reader,
+        file_size: u64,
+        mime: &str,
+        boundary: &str,
+        ranges: Vec<Range<u64>>,
+    ) -> Self {
         if ranges.len() < 2 {
             unreachable!()
         }
@@ -36,121 +67,252 @@ impl MultiRangeReader {
             file,
             file_size,
             mime: mime.to_string(),
+            boundary: boundary.to_string(),
             ranges: ranges.into(),
             state: ToBeWritten::Header,
+            pending: Vec::new(),
+            pending_position: 0,
+            part_start: 0,
+            chunk_size: MAX_BUFFER_SIZE,
+            observer: None,
+            announced: None,
+            written: 0,
+            metrics: None,
         }
     }
 
-    pub fn into_body(self) -> http_service::Body {
-        http_service::Body::from_stream(self)
+    /// Report a body length not matching `Content-Length` to `metrics`
+    pub fn metrics(mut self, metrics: Arc<dyn Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
     }
-}
 
-impl Stream for MultiRangeReader {
-    type Item = Result<Bytes, std::io::Error>;
+    /// Compare written length with announced one, at the end of body
+    fn check_length(&mut self) {
+        let announced = match self.announced.take() {
+            Some(x) => x,
+            None => return,
+        };
+        if announced == self.written {
+            return;
+        }
+        error!(
+            "multipart body of {} bytes announced as {}",
+            self.written, announced
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics.multipart_length_mismatch(announced, self.written);
+        }
+        debug_assert_eq!(announced, self.written, "multipart Content-Length mismatch");
+    }
 
-    fn poll_next(mut self: Pin<&mut Self>, _: &Waker) -> Poll<Option<Self::Item>> {
-        fn make_buffer(buffer: Cursor<Vec<u8>>) -> Result<Bytes, std::io::Error> {
-            let position = buffer.position();
-            if position == 0 {
-                error!("unexpected error occurred: stream item length is 0");
-                return Err(std::io::ErrorKind::Other.into());
-            }
+    /// Report progress of parts of file at `path` to `observer`
+    pub fn observe(mut self, observer: Arc<dyn PartObserver>, path: PathBuf) -> Self {
+        self.observer = Some((observer, path));
+        self
+    }
 
-            let mut inner = buffer.into_inner();
-            inner.truncate(position as usize);
-            Ok(inner.into())
+    fn notify(&self, event: impl FnOnce() -> PartEvent) {
+        if let Some((observer, path)) = &self.observer {
+            observer.part(path, event());
         }
+    }
 
-        let mut buffer = Cursor::new(vec![0u8; MAX_BUFFER_SIZE]); // XXX to be improved
+    pub fn into_body(self) -> http_service::Body {
+        http_service::Body::from_stream(self)
+    }
+
+    /// Fill `buffer` as much as possible, stop early only at the end of stream
+    fn fill(&mut self, buffer: &mut Cursor<Vec<u8>>) -> std::io::Result<()> {
         loop {
+            // part headers and terminator may span chunks
+            if self.pending_position < self.pending.len() {
+                let length = min(
+                    buffer.remaining_mut(),
+                    self.pending.len() - self.pending_position,
+                );
+                let copied = &self.pending[self.pending_position..self.pending_position + length];
+                buffer.write_all(copied)?;
+                self.pending_position += length;
+                if self.pending_position < self.pending.len() {
+                    return Ok(()); // buffer is full
+                }
+            }
+            if buffer.remaining_mut() == 0 {
+                return Ok(());
+            }
+
             match self.state {
                 ToBeWritten::Header => {
                     let first_range = self.ranges.front().unwrap();
-                    let part_header = PartHeader::new(first_range, &self.mime, self.file_size);
-                    if part_header.size() <= buffer.remaining_mut() {
-                        part_header.write(&mut buffer);
-                        self.state = ToBeWritten::Body;
-                        continue;
-                    } else {
-                        // no enough room
-                        return Poll::Ready(Some(make_buffer(buffer)));
-                    }
+                    self.pending.clear();
+                    self.pending_position = 0;
+                    PartHeader::new(first_range, &self.mime, &self.boundary, self.file_size)
+                        .write(&mut self.pending);
+                    let (offset, length) = (first_range.start, first_range.end - first_range.start);
+                    self.part_start = offset;
+                    self.notify(|| PartEvent::Started { offset, length });
+                    self.state = ToBeWritten::Body;
                 }
                 ToBeWritten::Body => {
-                    let mut first_range = self.ranges.pop_front().unwrap();
+                    let first_range = self.ranges.front_mut().unwrap();
                     let remain = first_range.end - first_range.start;
                     let slice_size = buffer_size(remain, buffer.remaining_mut());
                     let slice_start = buffer.position() as usize;
-                    let slice_end = slice_start + slice_size;
-                    let slice = &mut buffer.get_mut()[slice_start..slice_end];
+                    let slice = &mut buffer.get_mut()[slice_start..slice_start + slice_size];
 
-                    if let Err(error) = self.file.seek(SeekFrom::Start(first_range.start)) {
-                        error!("failed to seek: {:?}", error);
-                        return Poll::Ready(Some(Err(error)));
+                    self.file.seek(SeekFrom::Start(first_range.start))?;
+                    let chunk_size = self.file.read(slice)?;
+                    if chunk_size == 0 {
+                        // file was truncated after its size was read
+                        return Err(ErrorKind::UnexpectedEof.into());
                     }
-                    let chunk_size = match self.file.read(slice) {
-                        Ok(x) => x,
-                        Err(error) => {
-                            return Poll::Ready(Some(Err(error)));
-                        }
-                    };
-
                     first_range.start += chunk_size as u64;
                     buffer.set_position((slice_start + chunk_size) as u64);
 
                     debug_assert!(first_range.start <= first_range.end);
                     if first_range.start == first_range.end {
                         // this part has been completed
+                        let end = first_range.end;
+                        self.ranges.pop_front();
+                        let (offset, length) = (self.part_start, end - self.part_start);
+                        self.notify(|| PartEvent::Completed { offset, length });
                         self.state = match self.ranges.len() {
-                            0 => ToBeWritten::Final, // all parts has been completed
+                            0 => ToBeWritten::Terminator, // all parts has been completed
                             _ => ToBeWritten::Header,
                         };
-                        continue;
-                    } else {
-                        self.ranges.push_front(first_range);
-                        return Poll::Ready(Some(make_buffer(buffer)));
-                    }
-                }
-                ToBeWritten::Final => {
-                    if BOUNDARY.len() + 8 <= buffer.remaining_mut() {
-                        use std::io::Write;
-                        let write_result = write!(buffer, "\r\n--{}--\r\n", BOUNDARY);
-                        if let Err(error) = write_result {
-                            error!("failed to write final line: {}", error);
-                            return Poll::Ready(Some(Err(error)));
-                        }
-                        self.state = ToBeWritten::None;
-                    } else {
-                        // do nothing
                     }
-                    return Poll::Ready(Some(make_buffer(buffer)));
                 }
-
-                ToBeWritten::None => {
-                    if buffer.position() == 0 {
-                        return Poll::Ready(None);
-                    } else {
-                        return Poll::Ready(Some(make_buffer(buffer)));
-                    }
+                ToBeWritten::Terminator => {
+                    self.pending.clear();
+                    self.pending_position = 0;
+                    write!(self.pending, "\r\n--{}--\r\n", self.boundary)?;
+                    self.state = ToBeWritten::None;
                 }
+                ToBeWritten::None => return Ok(()),
             }
         }
     }
 }
 
+impl Stream for MultiRangeReader {
+    type Item = Result<Bytes, std::io::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _: &Waker) -> Poll<Option<Self::Item>> {
+        let mut buffer = Cursor::new(vec![0u8; self.chunk_size]); // XXX to be improved
+        if let Err(error) = self.fill(&mut buffer) {
+            error!("failed to read multipart body: {:?}", error);
+            return Poll::Ready(Some(Err(error)));
+        }
+        // buffer is only left empty at the end of stream, an empty chunk is never emitted
+        let position = buffer.position() as usize;
+        if position == 0 {
+            self.check_length();
+            return Poll::Ready(None);
+        }
+        self.written += position as u64;
+        let mut inner = buffer.into_inner();
+        inner.truncate(position);
+        Poll::Ready(Some(Ok(inner.into())))
+    }
+}
+
+/// Everything needed to serve a `multipart/byteranges` response, computed from the final set
+/// of ranges actually served (i.e. after clamping and merging), so that `Content-Length`
+/// always matches the body.
+#[derive(Debug)]
+pub(crate) struct MultipartPlan {
+    ranges: Vec<Range<u64>>,
+    mime: String,
+    boundary: String,
+    total: u64,
+    content_length: u64,
+    body_length: u64,
+}
+
+impl MultipartPlan {
+    /// `ranges` must be non-empty, sorted, disjoint and within `total`, as produced by
+    /// `actual_range` and `merge_ranges`
+    ///
+    /// `None` if the body is longer than `u64::max_value()`, e.g. for (almost) whole file of
+    /// that size, which can't be announced in `Content-Length`.
+    pub fn new(ranges: Vec<Range<u64>>, mime: &str, boundary: &str, total: u64) -> Option<Self> {
+        debug_assert!(ranges.iter().all(|x| x.start < x.end && x.end <= total));
+        debug_assert!(ranges.windows(2).all(|x| x[0].end <= x[1].start));
+
+        // single pass without allocation, the number of ranges is bounded by `MAX_RANGES`
+        let mut header_length = 0u64;
+        let mut body_length = 0u64;
+        for x in &ranges {
+            let header = PartHeader::new(x, mime, boundary, total).size() as u64;
+            header_length = header_length.checked_add(header)?;
+            body_length = body_length.checked_add(x.end - x.start)?;
+        }
+        let final_length = 8 + boundary.len(); /*"\r\n--".len() + boundary.len() + "--\r\n".len()*/
+        let content_length = header_length
+            .checked_add(body_length)?
+            .checked_add(final_length as u64)?;
+        Some(Self {
+            ranges,
+            mime: mime.to_string(),
+            boundary: boundary.to_string(),
+            total,
+            content_length,
+            body_length,
+        })
+    }
+
+    pub fn ranges(&self) -> &[Range<u64>] {
+        &self.ranges
+    }
+
+    /// Value of `Content-Length`, i.e. length of the whole multipart body
+    pub fn content_length(&self) -> u64 {
+        self.content_length
+    }
+
+    /// Bytes of file content served, excluding part headers
+    pub fn body_length(&self) -> u64 {
+        self.body_length
+    }
+
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// Value of `Content-Type`
+    pub fn content_type(&self) -> String {
+        format!("multipart/byteranges; boundary={}", self.boundary)
+    }
+
+    /// Reader of the body, checking it against `Content-Length` of this plan
+    pub fn into_reader(self, file: Reader) -> MultiRangeReader {
+        let mut reader =
+            MultiRangeReader::new(file, self.total, &self.mime, &self.boundary, self.ranges);
+        reader.announced = Some(self.content_length);
+        reader
+    }
+}
+
 #[derive(Debug)]
 pub(crate) struct PartHeader<'a> {
     range: &'a Range<u64>,
     mime_text: &'a str,
+    boundary: &'a str,
     total: u64,
 }
 
 impl<'a> PartHeader<'a> {
-    pub fn new(range: &'a Range<u64>, mime_text: &'a str, total: u64) -> PartHeader<'a> {
+    pub fn new(
+        range: &'a Range<u64>,
+        mime_text: &'a str,
+        boundary: &'a str,
+        total: u64,
+    ) -> PartHeader<'a> {
         Self {
             range,
             mime_text,
+            boundary,
             total,
         }
     }
@@ -159,6 +321,7 @@ impl<'a> PartHeader<'a> {
     /// The part header will be constructed in memory, so the return value type is `usize`.
     pub fn size(&self) -> usize {
         HEADER_SIZE_CONSTANT
+            + self.boundary.len()
             + self.mime_text.len()
             + u64_width(self.range.start)
             + u64_width(self.range.end - 1)
@@ -166,7 +329,7 @@ impl<'a> PartHeader<'a> {
     }
 
     /// Write part header into buffer
-    pub fn write(&self, buffer: &mut std::io::Write) {
+    pub fn write(&self, buffer: &mut Write) {
         let content_type = "content-type";
         let content_range = "content-range";
 
@@ -183,7 +346,7 @@ impl<'a> PartHeader<'a> {
                total = self.total,
                end = self.range.end - 1,
                start = self.range.start,
-               boundary = BOUNDARY,
+               boundary = self.boundary,
         ).expect("unexpected error occupied when constructing part header");
     }
 }
@@ -197,7 +360,7 @@ mod tests {
     fn test_part_header_size_constant() {
         // with feature `const_str_len`, this unit test will no longer be needed
         let expected = "\r\n".len() +
-            "--".len() + BOUNDARY.len() + "\r\n".len() +
+            "--".len() + /* boundary.len() + */ "\r\n".len() +
             header::CONTENT_TYPE.as_str().len() + ": ".len() + /* mime.len() + */"\r\n".len() +
             header::CONTENT_RANGE.as_str().len() + ": ".len() + "bytes ".len() + /* u64_width(range.start) + */ "-".len() + /* u64_width(range.end) + */"/".len() + /* u64_width(total) + */"\r\n".len() +
             "\r\n".len();
@@ -227,9 +390,227 @@ mod tests {
         ];
         for i in &test_case {
             let mut buffer = Cursor::new(vec![0u8; MAX_BUFFER_SIZE]);
-            let header = PartHeader::new(i.1, i.0, i.2);
+            let header = PartHeader::new(i.1, i.0, "DCjanus", i.2);
             header.write(&mut buffer);
             assert_eq!(header.size(), buffer.position() as usize);
         }
     }
+
+    fn chunks(
+        content: Vec<u8>,
+        size: u64,
+        ranges: Vec<Range<u64>>,
+        chunk_size: usize,
+    ) -> Vec<Result<Bytes, std::io::Error>> {
+        use futures::{executor::block_on, StreamExt};
+
+        let mut reader = MultiRangeReader::new(
+            Box::new(Cursor::new(content)),
+            size,
+            "text/plain",
+            "DCjanus",
+            ranges,
+        );
+        reader.chunk_size = chunk_size;
+        block_on(reader.collect())
+    }
+
+    #[test]
+    fn test_chunk_boundaries() {
+        let content: Vec<u8> = (0..=255).collect();
+        let ranges = vec![0..3, 10..12];
+        let mut expected = Vec::new();
+        for range in &ranges {
+            PartHeader::new(range, "text/plain", "DCjanus", 256).write(&mut expected);
+            expected.extend(&content[range.start as usize..range.end as usize]);
+        }
+        expected.extend(b"\r\n--DCjanus--\r\n");
+
+        // every chunk size up to whole body, so that each state ends exactly at a chunk boundary
+        for chunk_size in 1..=expected.len() + 1 {
+            let mut body = Vec::new();
+            for chunk in chunks(content.clone(), 256, ranges.clone(), chunk_size) {
+                let chunk = chunk.unwrap();
+                assert!(!chunk.is_empty(), "empty chunk of size {}", chunk_size);
+                assert!(chunk.len() <= chunk_size);
+                body.extend(chunk);
+            }
+            assert_eq!(expected, body, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn test_observer() {
+        use crate::progress::{PartEvent::*, PartObserver};
+        use std::{path::Path, sync::Mutex};
+
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<PartEvent>>);
+
+        impl PartObserver for Recorder {
+            fn part(&self, path: &Path, event: PartEvent) {
+                assert_eq!(Path::new("a.bin"), path);
+                self.0.lock().unwrap().push(event);
+            }
+        }
+
+        let recorder = Arc::new(Recorder::default());
+        let content: Vec<u8> = (0..=255).collect();
+        let reader = MultiRangeReader::new(
+            Box::new(Cursor::new(content)),
+            256,
+            "text/plain",
+            "DCjanus",
+            vec![0..3, 10..200],
+        )
+        .observe(recorder.clone(), PathBuf::from("a.bin"));
+        let chunks: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(reader));
+        assert!(chunks.iter().all(Result::is_ok));
+        assert_eq!(
+            vec![
+                Started {
+                    offset: 0,
+                    length: 3
+                },
+                Completed {
+                    offset: 0,
+                    length: 3
+                },
+                Started {
+                    offset: 10,
+                    length: 190
+                },
+                Completed {
+                    offset: 10,
+                    length: 190
+                },
+            ],
+            *recorder.0.lock().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_truncated_file() {
+        let content: Vec<u8> = (0..=255).collect();
+        let result = chunks(content, 300, vec![0..1, 250..300], MAX_BUFFER_SIZE);
+        assert_eq!(1, result.len());
+        let error = result.into_iter().next().unwrap().unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+    }
+
+    #[test]
+    fn test_multipart_plan() {
+        use crate::{
+            range::ByteRange,
+            utils::{actual_range, merge_ranges},
+        };
+
+        // clamped and merged: 0-3 and 2-5 become 0-5, 10-100 becomes 10-15
+        let ranges = vec![
+            ByteRange::FromToAll(0, 3),
+            ByteRange::FromToAll(10, 100),
+            ByteRange::FromToAll(2, 5),
+        ];
+        let ranges = ranges
+            .into_iter()
+            .flat_map(|x| actual_range(x, 16))
+            .collect();
+        let plan = MultipartPlan::new(merge_ranges(ranges), "text/plain", "DCjanus", 16).unwrap();
+        assert_eq!(&[0..6, 10..16], plan.ranges());
+        assert_eq!(12, plan.body_length());
+        assert_eq!(
+            "multipart/byteranges; boundary=DCjanus",
+            plan.content_type()
+        );
+
+        let mut body = Vec::new();
+        for range in plan.ranges() {
+            PartHeader::new(range, "text/plain", "DCjanus", 16).write(&mut body);
+            body.extend((range.start..range.end).map(|_| b'x'));
+        }
+        body.extend(b"\r\n--DCjanus--\r\n");
+        assert_eq!(body.len() as u64, plan.content_length());
+    }
+
+    #[test]
+    fn test_length_mismatch() {
+        let stats = Arc::new(crate::ByteServingStats::default());
+        let mut reader = MultiRangeReader::new(
+            Box::new(Cursor::new(vec![0u8; 16])),
+            16,
+            "text/plain",
+            "DCjanus",
+            vec![0..1, 2..3],
+        )
+        .metrics(stats.clone());
+        reader.announced = Some(1);
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            futures::executor::block_on(futures::StreamExt::collect::<Vec<_>>(reader))
+        }));
+        // reported before the debug assertion fails
+        assert_eq!(cfg!(debug_assertions), result.is_err());
+        assert_eq!(1, stats.snapshot().length_mismatches);
+    }
+
+    #[test]
+    fn test_multipart_plan_overflow() {
+        let max = u64::max_value();
+        let plan = MultipartPlan::new(vec![0..1, 2..max], "text/plain", "DCjanus", max);
+        assert!(plan.is_none());
+        let plan = MultipartPlan::new(vec![0..1, 2..1 << 62], "text/plain", "DCjanus", max);
+        assert!(plan.unwrap().content_length() > 1 << 62);
+    }
+
+    /// Whole body of a plan over `CONTENT`, checked against `Content-Length`
+    fn golden_body(ranges: Vec<Range<u64>>) -> String {
+        const CONTENT: &[u8] = b"0123456789abcdef";
+        let plan = MultipartPlan::new(ranges, "text/plain", "DCjanus", 16).unwrap();
+        let content_length = plan.content_length();
+        let reader = plan.into_reader(Box::new(Cursor::new(CONTENT)));
+        let chunks: Vec<_> = futures::executor::block_on(futures::StreamExt::collect(reader));
+        let body: Vec<u8> = chunks.into_iter().flat_map(Result::unwrap).collect();
+        assert_eq!(content_length, body.len() as u64);
+        String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn test_golden_two_parts() {
+        let expected = concat!(
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 0-1/16\r\n",
+            "\r\n",
+            "01",
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 5-7/16\r\n",
+            "\r\n",
+            "567",
+            "\r\n--DCjanus--\r\n",
+        );
+        assert_eq!(expected, golden_body(vec![0..2, 5..8]));
+    }
+
+    #[test]
+    fn test_golden_three_parts() {
+        let expected = concat!(
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 0-0/16\r\n",
+            "\r\n",
+            "0",
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 4-5/16\r\n",
+            "\r\n",
+            "45",
+            "\r\n--DCjanus\r\n",
+            "content-type: text/plain\r\n",
+            "content-range: bytes 10-15/16\r\n",
+            "\r\n",
+            "abcdef",
+            "\r\n--DCjanus--\r\n",
+        );
+        assert_eq!(expected, golden_body(vec![0..1, 4..6, 10..16]));
+    }
 }
@@ -0,0 +1,325 @@
+use crate::{
+    decorator::{validate_cache_ages, validate_headers},
+    error::TSFResult,
+    error_pages::ErrorPages,
+    etag::EtagMode,
+    localize::MessageCatalog,
+    manifest::AssetManifest,
+    path::normalize_path,
+    redirects::{validate_redirects, RedirectRule},
+};
+use serde::Deserialize;
+use std::{collections::BTreeMap, path::PathBuf, sync::Arc};
+
+/// Whole configuration of a [`StaticFiles`](struct.StaticFiles.html), can be deserialized from
+/// an application config file (TOML, JSON, ...)
+///
+/// # Example
+///
+/// ```toml
+/// root = "./public"
+/// etag_mode = "content"
+/// manifest = "./public/manifest.json"
+/// expose_integrity = true
+/// ```
+#[derive(Debug, Clone, Deserialize)]
+pub struct Config {
+    pub root: PathBuf,
+    #[serde(flatten)]
+    pub options: Options,
+}
+
+/// Options of a [`StaticFiles`](struct.StaticFiles.html), every field is optional when
+/// deserializing
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// see [`StaticFiles::etag_mode`](struct.StaticFiles.html#method.etag_mode)
+    pub etag_mode: EtagMode,
+    /// see [`StaticFiles::manifest`](struct.StaticFiles.html#method.manifest)
+    pub manifest: Option<PathBuf>,
+    /// see [`StaticFiles::expose_integrity`](struct.StaticFiles.html#method.expose_integrity)
+    pub expose_integrity: bool,
+    /// see [`StaticFiles::error_pages`](struct.StaticFiles.html#method.error_pages)
+    pub error_pages: Option<PathBuf>,
+    /// messages by language, then by status code, see
+    /// [`StaticFiles::error_message`](struct.StaticFiles.html#method.error_message)
+    pub error_messages: MessageCatalog,
+    /// served paths by requested path, see
+    /// [`StaticFiles::alias`](struct.StaticFiles.html#method.alias)
+    pub aliases: BTreeMap<String, String>,
+    /// see [`StaticFiles::redirect`](struct.StaticFiles.html#method.redirect)
+    pub redirects: Vec<RedirectRule>,
+    /// see [`StaticFiles::cache_busting_param`](struct.StaticFiles.html#method.cache_busting_param)
+    pub cache_busting_params: Vec<String>,
+    /// see [`StaticFiles::problem_details`](struct.StaticFiles.html#method.problem_details)
+    pub problem_details: bool,
+    /// see [`StaticFiles::sha256sums`](struct.StaticFiles.html#method.sha256sums)
+    pub sha256sums: bool,
+    /// see [`StaticFiles::dotfiles`](struct.StaticFiles.html#method.dotfiles)
+    pub dotfiles: DotfilePolicy,
+    /// see [`StaticFiles::robots_txt`](struct.StaticFiles.html#method.robots_txt)
+    pub robots_txt: Option<String>,
+    /// see [`StaticFiles::acme_challenge_dir`](struct.StaticFiles.html#method.acme_challenge_dir)
+    pub acme_challenge_dir: Option<PathBuf>,
+    /// see [`StaticFiles::listing`](struct.StaticFiles.html#method.listing)
+    pub listing: bool,
+    /// see [`StaticFiles::listing_marker`](struct.StaticFiles.html#method.listing_marker)
+    pub listing_marker: Option<String>,
+    /// see [`StaticFiles::listing_template`](struct.StaticFiles.html#method.listing_template)
+    pub listing_template: Option<String>,
+    /// see [`StaticFiles::listing_sizes`](struct.StaticFiles.html#method.listing_sizes)
+    pub listing_sizes: bool,
+    /// see [`StaticFiles::archive_downloads`](struct.StaticFiles.html#method.archive_downloads)
+    pub archive_downloads: bool,
+    /// see [`StaticFiles::archive_exclude`](struct.StaticFiles.html#method.archive_exclude)
+    pub archive_excludes: Vec<String>,
+    /// see [`StaticFiles::max_archive_size`](struct.StaticFiles.html#method.max_archive_size), in
+    /// bytes
+    pub max_archive_size: Option<u64>,
+    /// prefix of content addresses, see
+    /// [`StaticFiles::content_addressed`](struct.StaticFiles.html#method.content_addressed)
+    pub content_addressed: Option<String>,
+    /// see [`StaticFiles::case_insensitive`](struct.StaticFiles.html#method.case_insensitive)
+    pub case_insensitive: bool,
+    /// see [`StaticFiles::canonical_case_redirect`](struct.StaticFiles.html#method.canonical_case_redirect)
+    pub canonical_case_redirect: bool,
+    /// see [`StaticFiles::range_units`](struct.StaticFiles.html#method.range_units)
+    pub range_units: RangeUnitPolicy,
+    /// see [`StaticFiles::header`](struct.StaticFiles.html#method.header)
+    pub headers: BTreeMap<String, String>,
+    /// see [`StaticFiles::security_headers`](struct.StaticFiles.html#method.security_headers)
+    pub security_headers: bool,
+    /// see [`StaticFiles::clamp_last_modified`](struct.StaticFiles.html#method.clamp_last_modified)
+    pub clamp_last_modified: bool,
+    /// see [`StaticFiles::media_cors`](struct.StaticFiles.html#method.media_cors)
+    pub media_cors: bool,
+    /// see [`StaticFiles::surrogate_control`](struct.StaticFiles.html#method.surrogate_control)
+    pub surrogate_control: Option<String>,
+    /// see [`StaticFiles::cdn_cache_control`](struct.StaticFiles.html#method.cdn_cache_control)
+    pub cdn_cache_control: Option<String>,
+    /// see [`StaticFiles::min_max_age`](struct.StaticFiles.html#method.min_max_age), in seconds
+    pub min_max_age: Option<u64>,
+    /// see [`StaticFiles::max_max_age`](struct.StaticFiles.html#method.max_max_age), in seconds
+    pub max_max_age: Option<u64>,
+    /// see [`StaticFiles::chunk_size_bounds`](struct.StaticFiles.html#method.chunk_size_bounds)
+    pub min_chunk_size: Option<usize>,
+    /// see [`StaticFiles::chunk_size_bounds`](struct.StaticFiles.html#method.chunk_size_bounds)
+    pub max_chunk_size: Option<usize>,
+    /// see [`StaticFiles::upload_path`](struct.StaticFiles.html#method.upload_path)
+    #[cfg(feature = "upload")]
+    pub upload_paths: Vec<String>,
+    /// see [`StaticFiles::max_upload_size`](struct.StaticFiles.html#method.max_upload_size), in
+    /// bytes
+    #[cfg(feature = "upload")]
+    pub max_upload_size: Option<u64>,
+    /// see [`StaticFiles::upload_quota`](struct.StaticFiles.html#method.upload_quota), in bytes
+    #[cfg(feature = "upload")]
+    pub upload_quota: Option<u64>,
+    /// see [`StaticFiles::upload_path_quota`](struct.StaticFiles.html#method.upload_path_quota),
+    /// in bytes
+    #[cfg(feature = "upload")]
+    pub upload_path_quota: Option<u64>,
+    /// Answer requests carrying header [`TRACE_HEADER`](constant.TRACE_HEADER.html) by a JSON
+    /// explanation of how the request would be resolved, instead of file content
+    ///
+    /// It exposes file system layout, never turn it on in production.
+    pub debug_trace: bool,
+}
+
+/// How to answer a `Range` header of a unit other than `bytes`, e.g. `Range: lines=1-2`
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RangeUnitPolicy {
+    /// Ignore the header and serve whole file with `200 OK`, as recommended by RFC 7233 (default)
+    Ignore,
+    /// Answer `400 Bad Request`
+    Reject,
+}
+
+impl Default for RangeUnitPolicy {
+    fn default() -> Self {
+        RangeUnitPolicy::Ignore
+    }
+}
+
+/// Whether files and directories whose name starts with `.` are served
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DotfilePolicy {
+    /// Serve them like any other file (default)
+    Allow,
+    /// Answer `404 Not Found`
+    Deny,
+    /// Answer `404 Not Found`, except anything under `/.well-known/` (ACME HTTP-01 challenges,
+    /// `security.txt`, ...)
+    DenyExceptWellKnown,
+}
+
+impl Default for DotfilePolicy {
+    fn default() -> Self {
+        DotfilePolicy::Allow
+    }
+}
+
+impl DotfilePolicy {
+    /// Whether url path (as captured from request) may be served
+    pub(crate) fn allows(self, url_path: &str) -> bool {
+        let logical = match normalize_path(url_path) {
+            Some(x) => x,
+            None => return true, // not found anyway
+        };
+        let mut segments = logical.split('/');
+        match self {
+            DotfilePolicy::Allow => true,
+            DotfilePolicy::Deny => segments.all(|x| !x.starts_with('.')),
+            DotfilePolicy::DenyExceptWellKnown => match segments.next() {
+                Some(".well-known") => true,
+                Some(x) if x.starts_with('.') => false,
+                _ => segments.all(|x| !x.starts_with('.')),
+            },
+        }
+    }
+}
+
+/// Options together with states loaded from them, swapped atomically as a whole
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Settings {
+    pub options: Options,
+    pub manifest: Option<Arc<AssetManifest>>,
+    /// canonicalized `options.acme_challenge_dir`
+    pub acme_challenge_dir: Option<PathBuf>,
+    /// loaded from `options.error_pages`
+    pub error_pages: Option<Arc<ErrorPages>>,
+}
+
+impl Settings {
+    pub fn load(options: Options) -> TSFResult<Self> {
+        validate_headers(&options)?;
+        validate_cache_ages(&options)?;
+        validate_redirects(&options.redirects)?;
+        let manifest = match &options.manifest {
+            None => None,
+            Some(path) => Some(Arc::new(AssetManifest::load(path)?)),
+        };
+        let acme_challenge_dir = match &options.acme_challenge_dir {
+            None => None,
+            Some(path) => Some(path.canonicalize()?),
+        };
+        let error_pages = match &options.error_pages {
+            None => None,
+            Some(path) => Some(Arc::new(ErrorPages::load(path)?)),
+        };
+        Ok(Self {
+            options,
+            manifest,
+            acme_challenge_dir,
+            error_pages,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deserialize() {
+        let config: Config = serde_json::from_str(r#"{"root": "./public"}"#).unwrap();
+        assert_eq!(PathBuf::from("./public"), config.root);
+        assert_eq!(EtagMode::Metadata, config.options.etag_mode);
+        assert_eq!(None, config.options.manifest);
+        assert_eq!(false, config.options.expose_integrity);
+        assert_eq!(None, config.options.error_pages);
+        assert!(config.options.error_messages.is_empty());
+        assert!(config.options.aliases.is_empty());
+        assert!(config.options.redirects.is_empty());
+        assert!(config.options.cache_busting_params.is_empty());
+        assert_eq!(false, config.options.problem_details);
+        assert_eq!(false, config.options.debug_trace);
+        assert_eq!(false, config.options.sha256sums);
+        assert_eq!(RangeUnitPolicy::Ignore, config.options.range_units);
+        assert_eq!(DotfilePolicy::Allow, config.options.dotfiles);
+        assert_eq!(None, config.options.robots_txt);
+        assert_eq!(None, config.options.acme_challenge_dir);
+        assert_eq!(false, config.options.listing);
+        assert_eq!(None, config.options.listing_marker);
+        assert_eq!(None, config.options.listing_template);
+        assert_eq!(false, config.options.listing_sizes);
+        assert_eq!(false, config.options.archive_downloads);
+        assert!(config.options.archive_excludes.is_empty());
+        assert_eq!(None, config.options.max_archive_size);
+        assert_eq!(None, config.options.content_addressed);
+        assert_eq!(false, config.options.case_insensitive);
+        assert_eq!(false, config.options.canonical_case_redirect);
+        assert!(config.options.headers.is_empty());
+        assert_eq!(false, config.options.security_headers);
+        assert_eq!(false, config.options.clamp_last_modified);
+        assert_eq!(false, config.options.media_cors);
+        assert_eq!(None, config.options.surrogate_control);
+        assert_eq!(None, config.options.cdn_cache_control);
+        assert_eq!(None, config.options.min_max_age);
+        assert_eq!(None, config.options.max_max_age);
+        assert_eq!(None, config.options.min_chunk_size);
+        assert_eq!(None, config.options.max_chunk_size);
+        #[cfg(feature = "upload")]
+        {
+            assert!(config.options.upload_paths.is_empty());
+            assert_eq!(None, config.options.max_upload_size);
+            assert_eq!(None, config.options.upload_quota);
+            assert_eq!(None, config.options.upload_path_quota);
+        }
+
+        let config: Config = serde_json::from_str(
+            r#"{
+                "root": "./public",
+                "etag_mode": {"build": "v1"},
+                "manifest": "./public/manifest.json",
+                "expose_integrity": true
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(EtagMode::Build("v1".to_string()), config.options.etag_mode);
+        assert_eq!(
+            Some(PathBuf::from("./public/manifest.json")),
+            config.options.manifest
+        );
+        assert_eq!(true, config.options.expose_integrity);
+
+        let options: Options =
+            serde_json::from_str(r#"{"etag_mode": "content", "range_units": "reject"}"#).unwrap();
+        assert_eq!(EtagMode::Content, options.etag_mode);
+        assert_eq!(RangeUnitPolicy::Reject, options.range_units);
+
+        let options: Options =
+            serde_json::from_str(r#"{"error_messages": {"de": {"404": "Nicht gefunden"}}}"#)
+                .unwrap();
+        assert_eq!("Nicht gefunden", options.error_messages["de"][&404]);
+
+        let options: Options = serde_json::from_str(r#"{"headers": {"bad name": "x"}}"#).unwrap();
+        assert!(Settings::load(options).is_err());
+    }
+
+    #[test]
+    fn test_dotfile_policy() {
+        let paths = [
+            "a/b.txt",
+            ".env",
+            "a/.git/config",
+            ".well-known/acme-challenge/x",
+        ];
+        let allowed =
+            |policy: DotfilePolicy| paths.iter().map(|x| policy.allows(x)).collect::<Vec<_>>();
+        assert_eq!(vec![true, true, true, true], allowed(DotfilePolicy::Allow));
+        assert_eq!(
+            vec![true, false, false, false],
+            allowed(DotfilePolicy::Deny)
+        );
+        assert_eq!(
+            vec![true, false, false, true],
+            allowed(DotfilePolicy::DenyExceptWellKnown)
+        );
+        assert!(!DotfilePolicy::Deny.allows("/a/%2Egit/config"));
+        assert!(!DotfilePolicy::DenyExceptWellKnown.allows("a/.well-known/x"));
+    }
+}
@@ -0,0 +1,62 @@
+//! `tracing` spans of served files, enabled by feature `otel`
+//!
+//! Field names follow OpenTelemetry semantic conventions where one exists, so that with the
+//! `tracing-opentelemetry` layer installed by the application, spans show up in its distributed
+//! traces as children of the current request span. A span covers resolving, opening and
+//! answering a file request, not streaming the body.
+
+use crate::{metrics::Served, options::RangeUnitPolicy, utils::range_header};
+use std::path::Path;
+use tide::Request;
+use tracing::{field, info_span, Span};
+
+/// Span of a file request, `path` of the entry to be served if any
+pub(crate) fn request_span(req: &Request, path: Option<&Path>) -> Span {
+    let span = info_span!(
+        "static_file",
+        http.method = %req.method(),
+        http.target = %req.uri(),
+        http.status_code = field::Empty,
+        http.range = field::Empty,
+        file.path = field::Empty,
+        file.size = field::Empty,
+        tsf.served = field::Empty,
+        tsf.ranges = field::Empty,
+        tsf.cache = field::Empty,
+    );
+    if let Some(path) = path {
+        span.record("file.path", &field::display(path.display()));
+    }
+    // every field as sent
+    if let Some(range) = range_header(req, RangeUnitPolicy::Reject) {
+        span.record("http.range", &range.as_str());
+    }
+    span
+}
+
+/// Record outcome of the current span, `hit` of `tsf.cache` means the client's copy was still
+/// valid (`304`)
+pub(crate) fn record_served(served: Served) {
+    let span = Span::current();
+    let (kind, size) = match served {
+        Served::Full { size } => ("full", Some(size)),
+        Served::SingleRange { size } => ("single_range", Some(size)),
+        Served::MultiRange { size, .. } => ("multi_range", Some(size)),
+        Served::NotModified => ("not_modified", None),
+        Served::RangeNotSatisfiable => ("range_not_satisfiable", None),
+    };
+    span.record("tsf.served", &kind);
+    if let Some(size) = size {
+        span.record("file.size", &size);
+    }
+    let cache = match served {
+        Served::NotModified => "hit",
+        _ => "miss",
+    };
+    span.record("tsf.cache", &cache);
+}
+
+/// Record number of ranges served by the current span
+pub(crate) fn record_ranges(count: usize) {
+    Span::current().record("tsf.ranges", &(count as u64));
+}
@@ -0,0 +1,399 @@
+use percent_encoding::percent_decode;
+use std::{
+    borrow::Cow,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// What [`resolve_path`](fn.resolve_path.html) does with a segment of url path
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) enum SegmentAction {
+    /// `.` or empty
+    Skip,
+    /// `..`, remove last pushed segment if any
+    Pop,
+    Push,
+    /// not valid UTF-8 after percent decoding (e.g. overlong encoding `%c0%ae`),
+    /// the whole path is rejected
+    Undecodable,
+    /// contains path separator, NUL or percent escape after decoding
+    /// (e.g. `%2f`, `%252e`), the whole path is rejected
+    Forbidden,
+}
+
+impl SegmentAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SegmentAction::Skip => "skip",
+            SegmentAction::Pop => "pop",
+            SegmentAction::Push => "push",
+            SegmentAction::Undecodable => "undecodable",
+            SegmentAction::Forbidden => "forbidden",
+        }
+    }
+}
+
+/// Split url path into segments, return raw segment, decoded segment and what to do with it
+///
+/// Every segment is percent decoded exactly once, then validated again,
+/// so a double encoded `%252e%252e` never becomes `..` in any later decoding.
+pub(crate) fn segments(
+    url_path: &str,
+) -> impl Iterator<Item = (&str, Option<Cow<'_, str>>, SegmentAction)> {
+    url_path.split(|c| c == '/' || c == '\\').map(|raw| {
+        match percent_decode(raw.as_bytes()).decode_utf8() {
+            Err(_) => (raw, None, SegmentAction::Undecodable),
+            Ok(decoded) => {
+                let action = match decoded.as_ref() {
+                    "" | "." => SegmentAction::Skip,
+                    ".." => SegmentAction::Pop,
+                    x if is_forbidden_segment(x) => SegmentAction::Forbidden,
+                    _ => SegmentAction::Push,
+                };
+                (raw, Some(decoded), action)
+            }
+        }
+    })
+}
+
+fn is_forbidden_segment(decoded: &str) -> bool {
+    let bytes = decoded.as_bytes();
+    let still_encoded = bytes
+        .windows(3)
+        .any(|x| x[0] == b'%' && x[1].is_ascii_hexdigit() && x[2].is_ascii_hexdigit());
+    still_encoded
+        || decoded.contains(|c| c == '/' || c == '\\' || c == '\0')
+        || (cfg!(windows) && decoded.contains(':')) // drive or stream, e.g. `C:`
+}
+
+/// Normalize url path (percent encoded, as captured from request) into a logical path, which
+/// is relative to the root of any backend (file system, embedded, archive, remote, ...)
+///
+/// The result contains decoded segments joined by `/`, without any empty, `.` or `..` segment,
+/// and is verified by [`is_contained`](fn.is_contained.html). `..` never goes beyond root.
+/// Return `None` if url path contains any undecodable or forbidden segment.
+///
+/// # Example
+///
+/// ```
+/// # use tide_static_file::normalize_path;
+/// assert_eq!(Some("b/c".to_string()), normalize_path("/a/../b/./%63"));
+/// assert_eq!(Some("etc".to_string()), normalize_path("../../etc"));
+/// assert_eq!(None, normalize_path("%252e%252e/etc"));
+/// ```
+pub fn normalize_path(url_path: &str) -> Option<String> {
+    let mut normalized: Vec<Cow<'_, str>> = Vec::new();
+    for (_, decoded, action) in segments(url_path) {
+        match (action, decoded) {
+            (SegmentAction::Skip, _) => {}
+            (SegmentAction::Pop, _) => {
+                normalized.pop();
+            }
+            (SegmentAction::Push, Some(x)) => normalized.push(x),
+            (SegmentAction::Push, None)
+            | (SegmentAction::Undecodable, _)
+            | (SegmentAction::Forbidden, _) => return None,
+        }
+    }
+    Some(normalized.join("/")).filter(|x| is_contained(x))
+}
+
+/// Verify that a logical path (segments joined by `/`) could never escape the root
+/// it's joined onto, by any backend
+///
+/// Backends which don't have a file system to canonicalize paths against (embedded,
+/// archive, remote) must rely on this check instead.
+pub fn is_contained(logical_path: &str) -> bool {
+    logical_path.is_empty()
+        || logical_path
+            .split('/')
+            .all(|x| !x.is_empty() && x != "." && x != ".." && !is_forbidden_segment(x))
+}
+
+/// Given root path and url_path, return absolute path, `None` if url_path contains
+/// any undecodable or forbidden segment
+///
+/// The main purpose of this function is to prevent [directory traversal attack](https://en.wikipedia.org/wiki/Directory_traversal_attack),
+/// it works on url path only, without touching the file system.
+pub(crate) fn resolve_path(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let normalized = normalize_path(url_path)?;
+    Some(
+        normalized
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .fold(root.to_path_buf(), |path, x| path.join(x)),
+    )
+}
+
+/// Join `url_path` (as captured from request, percent encoded) onto `root`, the same way
+/// [`StaticFiles`](struct.StaticFiles.html) does, return `None` if the result doesn't exist
+/// or is outside of `root`
+///
+/// Both `..` segments and symbolic links pointing outside are prevented from escaping `root`,
+/// so other parts of an application (upload handlers, thumbnailers, ...) could share exactly
+/// the same sandboxing logic.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::safe_join;
+/// # use std::path::Path;
+/// let path = safe_join(Path::new("./public"), "/images/../%E4%BD%A0%E5%A5%BD.png");
+/// ```
+pub fn safe_join(root: &Path, url_path: &str) -> Option<PathBuf> {
+    join_canonical(&root.canonicalize().ok()?, url_path)
+}
+
+/// Same as [`safe_join`](fn.safe_join.html), but `root` must be canonicalized already
+pub(crate) fn join_canonical(root: &Path, url_path: &str) -> Option<PathBuf> {
+    resolve_path(root, url_path)?
+        .canonicalize()
+        .ok()
+        .filter(|x| x.starts_with(root)) // e.g. symbolic link to outside
+}
+
+/// Find entry at logical path under (canonicalized) `root` ignoring case, return the logical
+/// path in on-disk casing and the canonicalized path
+///
+/// Segments are matched exactly first, then by case-insensitive comparison against directory
+/// content, `None` if a segment matches several entries, e.g. both `a.txt` and `A.TXT`.
+pub(crate) fn find_case_insensitive(root: &Path, logical_path: &str) -> Option<(String, PathBuf)> {
+    let mut path = root.to_path_buf();
+    let mut canonical = Vec::new();
+    for segment in logical_path.split('/').filter(|x| !x.is_empty()) {
+        if path.join(segment).exists() {
+            canonical.push(segment.to_string());
+            path.push(segment);
+            continue;
+        }
+        let lowercase = segment.to_lowercase();
+        let mut found = fs::read_dir(&path)
+            .ok()?
+            .filter_map(|x| x.ok()?.file_name().into_string().ok())
+            .filter(|x| x.to_lowercase() == lowercase);
+        let name = found.next()?;
+        if found.next().is_some() {
+            return None;
+        }
+        path.push(&name);
+        canonical.push(name);
+    }
+    let path = path.canonicalize().ok().filter(|x| x.starts_with(root))?;
+    Some((canonical.join("/"), path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+    use std::path::Component;
+
+    #[test]
+    fn test_resolve_path() {
+        let base_dir = &PathBuf::from("/virtual");
+        let resolve = |x: &str| resolve_path(base_dir, x);
+        assert_eq!(resolve("foo"), Some(PathBuf::from("/virtual/foo")));
+        assert_eq!(resolve("/foo"), Some(PathBuf::from("/virtual/foo")));
+        assert_eq!(resolve("////foo"), Some(PathBuf::from("/virtual/foo")));
+        assert_eq!(resolve("../foo"), Some(PathBuf::from("/virtual/foo")));
+        assert_eq!(resolve("foo/.."), Some(PathBuf::from("/virtual")));
+        assert_eq!(
+            resolve("foo/../other"),
+            Some(PathBuf::from("/virtual/other"))
+        );
+        assert_eq!(
+            resolve("%E4%BD%A0%E5%A5%BD"),
+            Some(PathBuf::from("/virtual/你好"))
+        );
+        assert_eq!(resolve("100%25"), Some(PathBuf::from("/virtual/100%")));
+    }
+
+    #[test]
+    fn test_resolve_path_traversal_payloads() {
+        // mostly from https://owasp.org/www-community/attacks/Path_Traversal
+        let base_dir = &PathBuf::from("/virtual");
+        let payloads = [
+            "../../../etc/passwd",
+            "..\\..\\..\\etc\\passwd",
+            "%2e%2e/%2e%2e/etc/passwd",
+            "%2e%2e%2f%2e%2e%2fetc%2fpasswd",
+            "..%2f..%2fetc%2fpasswd",
+            "%2e%2e%5c%2e%2e%5cetc%5cpasswd",
+            "..%5c..%5cetc%5cpasswd",
+            "%252e%252e%252f%252e%252e%252fetc%252fpasswd",
+            "%252e%252e/%252e%252e/etc/passwd",
+            "..%252f..%252fetc%252fpasswd",
+            "..%c0%af..%c0%afetc%c0%afpasswd",
+            "%c0%ae%c0%ae/%c0%ae%c0%ae/etc/passwd",
+            "..%c1%9c..%c1%9cetc%c1%9cpasswd",
+            "%e0%80%ae%e0%80%ae/etc/passwd",
+            "..%00/etc/passwd",
+            "etc/passwd%00.png",
+            "....//....//etc/passwd",
+            "..;/..;/etc/passwd",
+        ];
+        for payload in payloads.iter() {
+            if let Some(path) = resolve_path(base_dir, payload) {
+                assert!(path.starts_with(base_dir), "{} => {:?}", payload, path);
+                assert!(
+                    path.components()
+                        .all(|x| x != std::path::Component::ParentDir),
+                    "{} => {:?}",
+                    payload,
+                    path
+                );
+            }
+        }
+
+        assert_eq!(None, resolve_path(base_dir, "%252e%252e/etc/passwd"));
+        assert_eq!(None, resolve_path(base_dir, "..%2f..%2fetc/passwd"));
+        assert_eq!(None, resolve_path(base_dir, "%c0%ae%c0%ae/etc/passwd"));
+        assert_eq!(None, resolve_path(base_dir, "etc/passwd%00.png"));
+    }
+
+    #[test]
+    fn test_safe_join() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let canonical_root = root.canonicalize().unwrap();
+        assert_eq!(
+            Some(canonical_root.join("Cargo.toml")),
+            safe_join(root, "src/../Cargo.toml")
+        );
+        assert_eq!(
+            Some(canonical_root.join("src").join("lib.rs")),
+            safe_join(root, "/../src/./lib.rs")
+        );
+        assert_eq!(None, safe_join(root, "../../../../../../etc/passwd"));
+        assert_eq!(None, safe_join(root, "no-such-file"));
+    }
+
+    #[test]
+    fn test_find_case_insensitive() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .canonicalize()
+            .unwrap();
+        assert_eq!(
+            Some(("src/lib.rs".to_string(), root.join("src/lib.rs"))),
+            find_case_insensitive(&root, "SRC/Lib.RS")
+        );
+        assert_eq!(
+            Some(("Cargo.toml".to_string(), root.join("Cargo.toml"))),
+            find_case_insensitive(&root, "cargo.TOML")
+        );
+        assert_eq!(None, find_case_insensitive(&root, "src/no-such-file"));
+    }
+
+    #[test]
+    fn test_segments() {
+        let actions = segments("/a/./%2e%2e/%ff/b/%2e%2e%2f/%252e")
+            .map(|(raw, decoded, action)| (raw, decoded.map(Cow::into_owned), action))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                ("", Some("".to_string()), SegmentAction::Skip),
+                ("a", Some("a".to_string()), SegmentAction::Push),
+                (".", Some(".".to_string()), SegmentAction::Skip),
+                ("%2e%2e", Some("..".to_string()), SegmentAction::Pop),
+                ("%ff", None, SegmentAction::Undecodable),
+                ("b", Some("b".to_string()), SegmentAction::Push),
+                (
+                    "%2e%2e%2f",
+                    Some("../".to_string()),
+                    SegmentAction::Forbidden
+                ),
+                ("%252e", Some("%2e".to_string()), SegmentAction::Forbidden),
+            ],
+            actions
+        );
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(Some("".to_string()), normalize_path(""));
+        assert_eq!(Some("".to_string()), normalize_path("/../.."));
+        assert_eq!(Some("a/b".to_string()), normalize_path("//a//b//"));
+        assert_eq!(Some("a/b".to_string()), normalize_path("a\\b"));
+        assert_eq!(Some("b".to_string()), normalize_path("a/%2e%2e/b"));
+        assert_eq!(None, normalize_path("a/%2e%2e%2fb"));
+    }
+
+    #[test]
+    fn test_is_contained() {
+        assert!(is_contained(""));
+        assert!(is_contained("a/b.txt"));
+        assert!(is_contained("a/.../b"));
+        assert!(!is_contained("/a"));
+        assert!(!is_contained("a/"));
+        assert!(!is_contained("a//b"));
+        assert!(!is_contained("a/./b"));
+        assert!(!is_contained("a/../b"));
+        assert!(!is_contained(".."));
+        assert!(!is_contained("a\\..\\b"));
+        assert!(!is_contained("a/%2e%2e"));
+    }
+
+    /// Feed the normalizer with random combinations of tricky segments
+    #[test]
+    fn test_normalize_path_fuzz() {
+        const TOKENS: [&str; 18] = [
+            "",
+            ".",
+            "..",
+            "...",
+            "a",
+            "b c",
+            "%2e",
+            "%2e%2e",
+            "%2f",
+            "%5c",
+            "\\",
+            "%00",
+            "%252e",
+            "%c0%ae",
+            "%ff",
+            "%E4%BD%A0",
+            "100%",
+            "~",
+        ];
+
+        // xorshift, deterministic so that failures are reproducible
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        let root = Path::new("/virtual");
+        for _ in 0..10000 {
+            let length = (next() % 8) as usize;
+            let url_path = (0..length)
+                .map(|_| TOKENS[(next() % TOKENS.len() as u64) as usize])
+                .collect::<Vec<_>>()
+                .join("/");
+
+            let normalized = match normalize_path(&url_path) {
+                None => continue,
+                Some(x) => x,
+            };
+            assert!(is_contained(&normalized), "{:?}", url_path);
+
+            let resolved = resolve_path(root, &url_path).unwrap();
+            assert!(resolved.starts_with(root), "{:?}", url_path);
+            assert!(
+                resolved.components().all(|x| x != Component::ParentDir),
+                "{:?}",
+                url_path
+            );
+
+            // normalizing an encoded normalized path changes nothing
+            let encoded = normalized
+                .split('/')
+                .map(|x| utf8_percent_encode(x, PATH_SEGMENT_ENCODE_SET).to_string())
+                .collect::<Vec<_>>()
+                .join("/");
+            assert_eq!(Some(&normalized), normalize_path(&encoded).as_ref());
+        }
+    }
+}
@@ -0,0 +1,163 @@
+//! Checks of a document root before serving it, so that deployments fail fast instead of
+//! answering `500` in production
+
+use crate::{
+    error::{PreflightFailed, TSFResult},
+    StaticFiles,
+};
+use serde::Serialize;
+use std::{
+    fs::{self, File},
+    path::{Path, PathBuf},
+};
+
+/// Problem of an entry found by [`StaticFiles::preflight`](struct.StaticFiles.html#method.preflight)
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PreflightIssue {
+    /// file can't be opened or directory can't be listed, with the error
+    Unreadable(String),
+    /// symbolic link to nothing
+    DanglingSymlink,
+    /// name isn't valid UTF-8, it can't be requested by URL
+    NonUtf8Name,
+    /// directory below maximum depth, not checked
+    TooDeep,
+}
+
+/// Outcome of [`StaticFiles::preflight`](struct.StaticFiles.html#method.preflight),
+/// serializable for deployment pipelines
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize)]
+pub struct PreflightReport {
+    /// files checked
+    pub files: usize,
+    /// directories checked
+    pub directories: usize,
+    pub issues: Vec<(PathBuf, PreflightIssue)>,
+}
+
+impl PreflightReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// `Err` if any issue was found, e.g. to abort startup
+    pub fn check(&self) -> TSFResult<()> {
+        if self.is_ok() {
+            Ok(())
+        } else {
+            Err(PreflightFailed(self.issues.len()).into())
+        }
+    }
+
+    fn walk(&mut self, dir: &Path, depth: usize) {
+        self.directories += 1;
+        let entries = match fs::read_dir(dir) {
+            Ok(x) => x,
+            Err(error) => return self.issue(dir, PreflightIssue::Unreadable(error.to_string())),
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(x) => x,
+                Err(error) => {
+                    self.issue(dir, PreflightIssue::Unreadable(error.to_string()));
+                    continue;
+                }
+            };
+            let path = entry.path();
+            if entry.file_name().to_str().is_none() {
+                self.issue(&path, PreflightIssue::NonUtf8Name);
+            }
+            // follows symbolic links, as serving does
+            let meta = match fs::metadata(&path) {
+                Ok(x) => x,
+                Err(_) if fs::symlink_metadata(&path).is_ok() => {
+                    self.issue(&path, PreflightIssue::DanglingSymlink);
+                    continue;
+                }
+                Err(error) => {
+                    self.issue(&path, PreflightIssue::Unreadable(error.to_string()));
+                    continue;
+                }
+            };
+            if meta.is_dir() {
+                if depth == 0 {
+                    self.issue(&path, PreflightIssue::TooDeep);
+                } else {
+                    self.walk(&path, depth - 1);
+                }
+            } else if meta.is_file() {
+                self.files += 1;
+                if let Err(error) = File::open(&path) {
+                    self.issue(&path, PreflightIssue::Unreadable(error.to_string()));
+                }
+            }
+        }
+    }
+
+    fn issue(&mut self, path: &Path, issue: PreflightIssue) {
+        self.issues.push((path.to_path_buf(), issue));
+    }
+}
+
+impl StaticFiles {
+    /// Walk the root at most `max_depth` directories deep, checking that every file can be
+    /// opened, no symbolic link dangles and every name is UTF-8
+    ///
+    /// Symbolic links are followed, a link to a directory above would be walked until
+    /// `max_depth`. Backends aren't checked.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use tide_static_file::StaticFiles;
+    /// let files = StaticFiles::new("./public").unwrap();
+    /// let report = files.preflight(16);
+    /// report.check().expect("document root not ready");
+    /// ```
+    pub fn preflight(&self, max_depth: usize) -> PreflightReport {
+        let mut report = PreflightReport::default();
+        if self.backend.is_none() {
+            report.walk(&self.root, max_depth);
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_preflight() {
+        let root = TempDir::new("preflight");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("index.html"), b"").unwrap();
+        fs::write(root.join("a/b/c.txt"), b"").unwrap();
+        let files = StaticFiles::new(&root).unwrap();
+
+        let report = files.preflight(2);
+        assert!(report.is_ok());
+        assert!(report.check().is_ok());
+        assert_eq!((2, 3), (report.files, report.directories));
+
+        let report = files.preflight(1);
+        assert_eq!(
+            vec![(
+                root.canonicalize().unwrap().join("a/b"),
+                PreflightIssue::TooDeep
+            )],
+            report.issues
+        );
+        assert!(report.check().is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(root.join("missing"), root.join("link")).unwrap();
+            let report = files.preflight(2);
+            assert_eq!(1, report.issues.len());
+            assert_eq!(PreflightIssue::DanglingSymlink, report.issues[0].1);
+        }
+    }
+}
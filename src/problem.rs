@@ -0,0 +1,94 @@
+//! Error bodies as `application/problem+json`, ref: https://tools.ietf.org/html/rfc7807
+
+use http::{header, HeaderValue};
+use serde_json::json;
+use tide::Response;
+
+/// Media type of problem details
+pub(crate) const PROBLEM_JSON: &str = "application/problem+json";
+
+/// Whether `Accept` prefers JSON, i.e. lists `application/problem+json` or `application/json`
+/// with a non-zero quality
+pub(crate) fn accepts_problem_json(accept: &str) -> bool {
+    accept.split(',').any(|range| {
+        let mut params = range.split(';').map(str::trim);
+        let media = params.next().unwrap_or("").to_ascii_lowercase();
+        let rejected = params.any(|x| {
+            let x = x.replace(' ', "");
+            x == "q=0" || x.starts_with("q=0.") && x[4..].bytes().all(|x| x == b'0')
+        });
+        (media == PROBLEM_JSON || media == "application/json") && !rejected
+    })
+}
+
+/// Turn a `4xx` or `5xx` response with a built-in plain text body into problem details, its text
+/// becomes `detail`
+///
+/// Other responses are given back as they are, e.g. bodies of custom error pages.
+pub(crate) async fn problem_response(response: Response) -> Response {
+    let status = response.status();
+    let plain = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map_or(false, |x| x.as_bytes().starts_with(b"text/plain"));
+    if !(status.is_client_error() || status.is_server_error()) || !plain {
+        return response;
+    }
+    let (mut parts, body) = response.into_parts();
+    let detail = match await!(body.into_vec()) {
+        Ok(x) => String::from_utf8_lossy(&x).into_owned(),
+        Err(_) => String::new(),
+    };
+    let problem = json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or(""),
+        "status": status.as_u16(),
+        "detail": detail,
+    })
+    .to_string();
+    parts
+        .headers
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, problem.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ErrorResponse;
+    use futures::executor::block_on;
+    use http::StatusCode;
+    use tide::IntoResponse;
+
+    #[test]
+    fn test_accepts_problem_json() {
+        assert!(accepts_problem_json("application/problem+json"));
+        assert!(accepts_problem_json("text/html;q=0.9, application/json"));
+        assert!(accepts_problem_json("Application/JSON; q=0.5"));
+        assert!(!accepts_problem_json("application/json;q=0"));
+        assert!(!accepts_problem_json("application/json; q=0.00"));
+        assert!(!accepts_problem_json("text/html, */*"));
+    }
+
+    #[test]
+    fn test_problem_response() {
+        let response = block_on(problem_response(ErrorResponse::NotFound.into_response()));
+        assert_eq!(StatusCode::NOT_FOUND, response.status());
+        assert_eq!(PROBLEM_JSON, response.headers()[header::CONTENT_TYPE]);
+        let body = block_on(response.into_body().into_vec()).unwrap();
+        let problem: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            json!({"type": "about:blank", "title": "Not Found", "status": 404, "detail": "not found"}),
+            problem
+        );
+
+        let response = http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/plain")
+            .body("fine".into())
+            .unwrap();
+        let response = block_on(problem_response(response));
+        assert_eq!("text/plain", response.headers()[header::CONTENT_TYPE]);
+    }
+}
@@ -0,0 +1,23 @@
+use std::path::Path;
+
+/// Progress of one part of a `multipart/byteranges` response
+///
+/// Offsets and lengths are in bytes of file content, excluding part headers.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PartEvent {
+    /// Content of the part starts being read
+    Started { offset: u64, length: u64 },
+    /// Content of the part has been read completely
+    Completed { offset: u64, length: u64 },
+}
+
+/// Receive progress of multipart responses, e.g. to show which segments of a file clients are
+/// fetching
+///
+/// Events are reported while the body is produced, which may be ahead of what the client has
+/// received. A part never completes if the client goes away or reading fails.
+pub trait PartObserver: Send + Sync {
+    /// Called with the path of the file (relative to backend root for
+    /// [backends](vfs/index.html)) and the event
+    fn part(&self, path: &Path, event: PartEvent);
+}
@@ -0,0 +1,239 @@
+//! [`Metrics`](trait.Metrics.html) exposed in the Prometheus text format, enabled by feature
+//! `metrics`
+
+use crate::{
+    file_read::read_pool_health,
+    metrics::{bucket, Metrics, Served, RANGE_SIZE_BUCKETS},
+};
+use futures::future::FutureObj;
+use http::{header, StatusCode};
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tide::{configuration::Store, Request, Response, RouteMatch};
+
+/// `Content-Type` of the text exposition format
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Counters of served responses, rendered by its endpoint for Prometheus to scrape
+///
+/// Clones share counters, so that one is given to [`StaticFiles::metrics`] and another routed.
+/// State of the read pool is read when rendering.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use tide_static_file::{PrometheusMetrics, StaticFiles};
+/// let metrics = PrometheusMetrics::default();
+/// let mut app = tide::App::new(());
+/// app.at("/metrics").get(metrics.clone());
+/// app.at("/*").get(StaticFiles::new("./public").unwrap().metrics(Arc::new(metrics)));
+/// ```
+///
+/// [`StaticFiles::metrics`]: struct.StaticFiles.html#method.metrics
+#[derive(Clone, Default)]
+pub struct PrometheusMetrics {
+    counters: Arc<Counters>,
+}
+
+#[derive(Default)]
+struct Counters {
+    statuses: Mutex<BTreeMap<u16, u64>>,
+    served_bytes: AtomicU64,
+    not_modified: AtomicU64,
+    range_bytes: AtomicU64,
+    range_sizes: [AtomicU64; 9], // RANGE_SIZE_BUCKETS.len() + 1
+}
+
+impl Metrics for PrometheusMetrics {
+    fn response(&self, served: Served) {
+        let counters = &self.counters;
+        match served {
+            Served::Full { size }
+            | Served::SingleRange { size }
+            | Served::MultiRange { size, .. } => {
+                counters.served_bytes.fetch_add(size, Ordering::Relaxed);
+            }
+            Served::NotModified => {
+                counters.not_modified.fetch_add(1, Ordering::Relaxed);
+            }
+            Served::RangeNotSatisfiable => {}
+        }
+    }
+
+    fn range(&self, size: u64) {
+        self.counters.range_bytes.fetch_add(size, Ordering::Relaxed);
+        self.counters.range_sizes[bucket(size)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn status(&self, status: StatusCode) {
+        let mut statuses = self.counters.statuses.lock().unwrap();
+        *statuses.entry(status.as_u16()).or_insert(0) += 1;
+    }
+}
+
+impl PrometheusMetrics {
+    /// Render counters in the text exposition format
+    pub fn render(&self) -> String {
+        let counters = &self.counters;
+        let load = |x: &AtomicU64| x.load(Ordering::Relaxed);
+        let mut text = String::new();
+
+        let statuses: Vec<_> = counters
+            .statuses
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(status, count)| (format!("{{status=\"{}\"}}", status), *count))
+            .collect();
+        family(
+            &mut text,
+            "tsf_responses_total",
+            "counter",
+            "Responses by status code.",
+            &statuses,
+        );
+        let served_bytes = [(String::new(), load(&counters.served_bytes))];
+        family(
+            &mut text,
+            "tsf_served_bytes_total",
+            "counter",
+            "Bytes of file content served.",
+            &served_bytes,
+        );
+        let not_modified = [(String::new(), load(&counters.not_modified))];
+        family(
+            &mut text,
+            "tsf_not_modified_total",
+            "counter",
+            "Conditional requests answered with 304, i.e. hits of client caches.",
+            &not_modified,
+        );
+
+        // buckets of the text format are cumulative
+        let mut cumulative = 0;
+        let mut ranges = Vec::new();
+        for (index, count) in counters.range_sizes.iter().map(load).enumerate() {
+            cumulative += count;
+            let le = match RANGE_SIZE_BUCKETS.get(index) {
+                Some(limit) => limit.to_string(),
+                None => "+Inf".to_string(),
+            };
+            ranges.push((format!("_bucket{{le=\"{}\"}}", le), cumulative));
+        }
+        ranges.push(("_sum".to_string(), load(&counters.range_bytes)));
+        ranges.push(("_count".to_string(), cumulative));
+        family(
+            &mut text,
+            "tsf_range_size_bytes",
+            "histogram",
+            "Sizes of served byte ranges.",
+            &ranges,
+        );
+
+        let pool = read_pool_health();
+        let gauges = [
+            (
+                "tsf_read_pool_threads",
+                "gauge",
+                "Threads of the read pool.",
+                pool.threads as u64,
+            ),
+            (
+                "tsf_read_pool_queued",
+                "gauge",
+                "Reads waiting for a thread of the read pool.",
+                pool.queued as u64,
+            ),
+            (
+                "tsf_read_pool_panics_total",
+                "counter",
+                "Reads which panicked.",
+                pool.panics,
+            ),
+            (
+                "tsf_read_pool_timeouts_total",
+                "counter",
+                "File operations which timed out.",
+                pool.timeouts,
+            ),
+        ];
+        for (name, kind, help, value) in gauges.iter() {
+            family(&mut text, name, kind, help, &[(String::new(), *value)]);
+        }
+        text
+    }
+
+    fn respond(&self) -> Response {
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(self.render().into())
+            .unwrap()
+    }
+}
+
+/// Append metric family `name` with samples given as name suffix and labels, and value
+fn family(text: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    writeln!(text, "# HELP {} {}", name, help).unwrap();
+    writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+    for (suffix, value) in samples {
+        writeln!(text, "{}{} {}", name, suffix, value).unwrap();
+    }
+}
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for PrometheusMetrics {
+    type Fut = FutureObj<'static, Response>;
+
+    fn call(&self, _: Data, _: Request, _: Option<RouteMatch<'_>>, _: &Store) -> Self::Fut {
+        let response = self.respond();
+        FutureObj::new(Box::new(async move { response }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let metrics = PrometheusMetrics::default();
+        metrics.status(StatusCode::OK);
+        metrics.status(StatusCode::OK);
+        metrics.status(StatusCode::NOT_FOUND);
+        metrics.response(Served::Full { size: 100 });
+        metrics.response(Served::SingleRange { size: 10 });
+        metrics.range(10);
+        metrics.range(2048);
+        metrics.response(Served::NotModified);
+
+        let text = metrics.render();
+        let lines: Vec<_> = text.lines().collect();
+        for line in &[
+            "# TYPE tsf_responses_total counter",
+            "tsf_responses_total{status=\"200\"} 2",
+            "tsf_responses_total{status=\"404\"} 1",
+            "tsf_served_bytes_total 110",
+            "tsf_not_modified_total 1",
+            "# TYPE tsf_range_size_bytes histogram",
+            "tsf_range_size_bytes_bucket{le=\"1024\"} 1",
+            "tsf_range_size_bytes_bucket{le=\"16384\"} 2",
+            "tsf_range_size_bytes_bucket{le=\"+Inf\"} 2",
+            "tsf_range_size_bytes_sum 2058",
+            "tsf_range_size_bytes_count 2",
+            "# TYPE tsf_read_pool_queued gauge",
+        ] {
+            assert!(lines.contains(line), "{} missing in\n{}", line, text);
+        }
+
+        let response = metrics.clone().respond();
+        assert_eq!(CONTENT_TYPE, response.headers()[header::CONTENT_TYPE]);
+    }
+}
@@ -0,0 +1,168 @@
+//! Size quotas of uploads, enabled by feature `upload`
+//!
+//! Usage of a directory is scanned once (like `du`), then kept up to date by the writes of this
+//! endpoint, and scanned again after [`RESCAN_INTERVAL`] to catch up with other writers. Scans
+//! run on threads of their own, uploads wait for the first one and are checked against the last
+//! usage known during later ones.
+
+use futures::{channel::oneshot, future::FutureObj};
+use log::error;
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Age after which usage of a directory is scanned again
+pub(crate) const RESCAN_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Bytes used below directories with a quota
+#[derive(Debug, Default)]
+pub(crate) struct Quotas {
+    usage: Arc<Mutex<HashMap<PathBuf, Usage>>>,
+}
+
+#[derive(Debug)]
+struct Usage {
+    bytes: u64,
+    scanned: Instant,
+    /// a scan is running, started once this got stale
+    scanning: bool,
+}
+
+impl Quotas {
+    /// Resolves once usage of all `quotas` has been scanned at least once, starts scans of those
+    /// not scanned within [`RESCAN_INTERVAL`] without waiting for them
+    ///
+    /// Concurrent first uploads below a directory may scan it more than once.
+    pub fn scanned(&self, quotas: &[(PathBuf, u64)]) -> FutureObj<'static, ()> {
+        let mut first_scans = Vec::new();
+        for (dir, _) in quotas {
+            // `Some(true)` if stale, unlocked before scanning
+            let stale = self.usage.lock().unwrap().get_mut(dir).map(|usage| {
+                let stale = !usage.scanning && usage.scanned.elapsed() >= RESCAN_INTERVAL;
+                usage.scanning |= stale;
+                stale
+            });
+            match stale {
+                None => first_scans.push(self.spawn_scan(dir)),
+                // nothing waits for it
+                Some(true) => drop(self.spawn_scan(dir)),
+                Some(false) => (),
+            }
+        }
+        FutureObj::new(Box::new(
+            async move {
+                for scan in first_scans {
+                    // usage is unknown if the scan thread failed, see `usage`
+                    let _ = await!(scan);
+                }
+            },
+        ))
+    }
+
+    /// Bytes files below `dirs` may grow by together without exceeding their quota, `None` if
+    /// there's no quota
+    ///
+    /// Usage is the last known one, see [`scanned`](#method.scanned). Concurrent uploads are
+    /// checked against the same headroom, they may overshoot a quota by their size.
+    pub fn headroom(&self, quotas: &[(PathBuf, u64)]) -> Option<u64> {
+        quotas
+            .iter()
+            .map(|(dir, quota)| quota.saturating_sub(self.usage(dir)))
+            .min()
+    }
+
+    /// Account a file below scanned directories growing from `old` to `new` bytes
+    pub fn record(&self, path: &Path, old: u64, new: u64) {
+        let mut usages = self.usage.lock().unwrap();
+        for (dir, usage) in usages.iter_mut() {
+            if path.starts_with(dir) {
+                usage.bytes = usage.bytes.saturating_sub(old).saturating_add(new);
+            }
+        }
+    }
+
+    /// Last known usage of `dir`, 0 if it was never scanned
+    fn usage(&self, dir: &Path) -> u64 {
+        self.usage.lock().unwrap().get(dir).map_or(0, |x| x.bytes)
+    }
+
+    /// Scan `dir` on a thread of its own, resolving once its usage is updated
+    fn spawn_scan(&self, dir: &Path) -> oneshot::Receiver<()> {
+        let (sender, receiver) = oneshot::channel();
+        let usages = self.usage.clone();
+        let path = dir.to_path_buf();
+        let spawned = thread::Builder::new()
+            .name("tsf-quota-scan".to_string())
+            .spawn(move || {
+                // scanned unlocked, writes meanwhile are lost until the next scan
+                let usage = Usage {
+                    bytes: scan(&path),
+                    scanned: Instant::now(),
+                    scanning: false,
+                };
+                usages.lock().unwrap().insert(path, usage);
+                let _ = sender.send(());
+            });
+        if let Err(error) = spawned {
+            error!("failed to scan usage of {:?}: {}", dir, error);
+            // retried by the next upload
+            if let Some(usage) = self.usage.lock().unwrap().get_mut(dir) {
+                usage.scanning = false;
+            }
+        }
+        receiver
+    }
+}
+
+/// Size of files below `dir`, symbolic links are not followed
+fn scan(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| match entry.file_type() {
+            Ok(x) if x.is_dir() => scan(&entry.path()),
+            Ok(x) if x.is_file() => entry.metadata().map(|x| x.len()).unwrap_or(0),
+            _ => 0,
+        })
+        .fold(0, u64::saturating_add)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_quotas() {
+        let root = TempDir::new("quota");
+        fs::create_dir_all(root.join("inbox/a")).unwrap();
+        fs::write(root.join("top.bin"), vec![0; 10]).unwrap();
+        fs::write(root.join("inbox/a/b.bin"), vec![0; 20]).unwrap();
+        let quotas = Quotas::default();
+        assert_eq!(None, quotas.headroom(&[]));
+        let limits = vec![(root.to_path_buf(), 100), (root.join("inbox"), 50)];
+        block_on(quotas.scanned(&limits));
+        assert_eq!(Some(30), quotas.headroom(&limits));
+        // fresh, not scanned again
+        fs::write(root.join("other.bin"), vec![0; 10]).unwrap();
+        block_on(quotas.scanned(&limits));
+        assert_eq!(Some(30), quotas.headroom(&limits));
+        fs::remove_file(root.join("other.bin")).unwrap();
+
+        quotas.record(&root.join("inbox/a/b.bin"), 20, 40);
+        assert_eq!(Some(10), quotas.headroom(&limits));
+        quotas.record(&root.join("top.bin"), 10, 0);
+        assert_eq!(Some(10), quotas.headroom(&limits));
+        quotas.record(&root.join("inbox/a/b.bin"), 40, 0);
+        assert_eq!(Some(50), quotas.headroom(&limits));
+    }
+}
@@ -0,0 +1,123 @@
+//! Range arithmetic of this crate, for applications building partial responses of their own,
+//! e.g. of blobs stored in a database
+//!
+//! # Example
+//!
+//! ```
+//! # use tide_static_file::range::{parse_range_header, ContentRange, RangeDecision};
+//! match parse_range_header("bytes=0-9,5-14", 100) {
+//!     Some(RangeDecision::Partial(ranges)) => {
+//!         assert_eq!(vec![0..15], ranges);
+//!         assert_eq!("bytes 0-14/100", ContentRange::new(0..15, 100).to_string());
+//!     }
+//!     _ => unreachable!(),
+//! }
+//! ```
+
+use crate::{
+    range_parser::parse_ranges,
+    utils::{self, decide_ranges},
+};
+use std::{
+    fmt::{self, Display},
+    ops::Range,
+};
+
+pub use crate::utils::RangeDecision;
+
+/// A byte range spec of a `Range` header, ref: https://tools.ietf.org/html/rfc7233#section-2.1
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ByteRange {
+    /// `first-`, from `first` to end
+    FromTo(u64),
+    /// `first-last`, both inclusive
+    FromToAll(u64, u64),
+    /// `-length`, last `length` bytes
+    Last(u64),
+}
+
+/// Convert a range of header to a range of content of `size` bytes, `None` if it selects nothing
+pub fn actual_range(byte_range: ByteRange, size: u64) -> Option<Range<u64>> {
+    utils::actual_range(byte_range, size)
+}
+
+/// Sort ranges and merge overlapping or adjacent ones, empty ranges are dropped
+pub fn merge_ranges(ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
+    utils::merge_ranges(ranges)
+}
+
+/// Parse a `Range` header value and resolve it against content of `size` bytes, the way
+/// [`StaticFiles`](../struct.StaticFiles.html) does
+///
+/// `None` if the value is not a valid `bytes` range set, which should be ignored (`200 OK`) or
+/// rejected (`400 Bad Request`).
+pub fn parse_range_header(value: &str, size: u64) -> Option<RangeDecision> {
+    let ranges = parse_ranges(value);
+    if ranges.is_empty() {
+        return None;
+    }
+    Some(decide_ranges(ranges, size))
+}
+
+/// Value of `Content-Range`, ref: https://tools.ietf.org/html/rfc7233#section-4.2
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ContentRange {
+    /// `None` for `416 Range Not Satisfiable`
+    pub range: Option<Range<u64>>,
+    pub total: u64,
+}
+
+impl ContentRange {
+    /// Range of a `206 Partial Content` response, must be non-empty and within `total`
+    pub fn new(range: Range<u64>, total: u64) -> Self {
+        debug_assert!(range.start < range.end && range.end <= total);
+        Self {
+            range: Some(range),
+            total,
+        }
+    }
+
+    /// Value of a `416 Range Not Satisfiable` response
+    pub fn unsatisfied(total: u64) -> Self {
+        Self { range: None, total }
+    }
+}
+
+impl Display for ContentRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.range {
+            Some(range) => write!(f, "bytes {}-{}/{}", range.start, range.end - 1, self.total),
+            None => write!(f, "bytes */{}", self.total),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_range_header() {
+        assert_eq!(
+            Some(RangeDecision::Partial(vec![0..2, 8..10])),
+            parse_range_header("bytes=0-1, -2", 10)
+        );
+        assert_eq!(
+            Some(RangeDecision::Whole),
+            parse_range_header("bytes=0-", 10)
+        );
+        assert_eq!(
+            Some(RangeDecision::Unsatisfiable),
+            parse_range_header("bytes=10-", 10)
+        );
+        assert_eq!(None, parse_range_header("lines=0-1", 10));
+        assert_eq!(None, parse_range_header("bytes=nothing", 10));
+    }
+
+    #[test]
+    fn test_content_range() {
+        assert_eq!("bytes 0-0/1", ContentRange::new(0..1, 1).to_string());
+        assert_eq!("bytes 5-9/10", ContentRange::new(5..10, 10).to_string());
+        assert_eq!("bytes */10", ContentRange::unsatisfied(10).to_string());
+    }
+}
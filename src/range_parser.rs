@@ -0,0 +1,157 @@
+//! Parsers of `Range` header values into byte range specs
+//!
+//! ref: https://tools.ietf.org/html/rfc7233#section-2.1
+
+use crate::range::ByteRange;
+
+/// Parse a `Range` header value, an empty result if it's no valid `bytes` range set
+pub(crate) trait RangeParser {
+    fn parse(&self, value: &str) -> Vec<ByteRange>;
+}
+
+/// Strict RFC 7233 grammar, except that whitespace around list elements and the unit is
+/// tolerated
+///
+/// + the unit is case-insensitive, any other unit than `bytes` is invalid
+/// + positions are digits only (no sign), values beyond `u64` saturate, i.e. select until end of
+///   file or nothing
+/// + a single invalid spec (e.g. `5-3`) invalidates the whole set
+/// + empty list elements are skipped, ref: https://tools.ietf.org/html/rfc7230#section-7
+pub(crate) struct StrictParser;
+
+/// Parser used for requests
+pub(crate) const PARSER: StrictParser = StrictParser;
+
+/// Parse with [`PARSER`]
+pub(crate) fn parse_ranges(value: &str) -> Vec<ByteRange> {
+    PARSER.parse(value)
+}
+
+impl RangeParser for StrictParser {
+    fn parse(&self, value: &str) -> Vec<ByteRange> {
+        let index = match value.find('=') {
+            Some(x) => x,
+            None => return Vec::new(),
+        };
+        if !value[..index].trim().eq_ignore_ascii_case("bytes") {
+            return Vec::new();
+        }
+        let specs = value[index + 1..]
+            .split(',')
+            .map(str::trim)
+            .filter(|x| !x.is_empty());
+        let mut ranges = Vec::new();
+        for spec in specs {
+            match parse_spec(spec) {
+                Some(x) => ranges.push(x),
+                None => return Vec::new(),
+            }
+        }
+        ranges
+    }
+}
+
+fn parse_spec(spec: &str) -> Option<ByteRange> {
+    let index = spec.find('-')?;
+    let (first, last) = (&spec[..index], &spec[index + 1..]);
+    match (first.is_empty(), last.is_empty()) {
+        (true, true) => None,
+        (true, false) => Some(ByteRange::Last(parse_position(last)?)),
+        (false, true) => Some(ByteRange::FromTo(parse_position(first)?)),
+        (false, false) => {
+            let (first, last) = (parse_position(first)?, parse_position(last)?);
+            if first > last {
+                return None;
+            }
+            Some(ByteRange::FromToAll(first, last))
+        }
+    }
+}
+
+/// `1*DIGIT`, saturating at `u64::max_value()`
+fn parse_position(digits: &str) -> Option<u64> {
+    if digits.is_empty() || !digits.bytes().all(|x| x.is_ascii_digit()) {
+        return None;
+    }
+    Some(digits.bytes().fold(0u64, |position, x| {
+        position
+            .saturating_mul(10)
+            .saturating_add(u64::from(x - b'0'))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ByteRange::*;
+
+    /// Parser of crate `range_header`, used before
+    struct LegacyParser;
+
+    impl RangeParser for LegacyParser {
+        fn parse(&self, value: &str) -> Vec<ByteRange> {
+            range_header::ByteRange::parse(value)
+                .into_iter()
+                .map(|x| match x {
+                    range_header::ByteRange::FromTo(x) => FromTo(x),
+                    range_header::ByteRange::FromToAll(x, y) => FromToAll(x, y),
+                    range_header::ByteRange::Last(x) => Last(x),
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(vec![FromToAll(0, 1)], PARSER.parse("bytes=0-1"));
+        assert_eq!(
+            vec![FromTo(5), Last(3), FromToAll(1, 1)],
+            PARSER.parse(" Bytes = 5- , -3,,1-1 ,")
+        );
+        let max = u64::max_value();
+        assert_eq!(
+            vec![FromToAll(0, max), Last(max), FromTo(max)],
+            PARSER
+                .parse("bytes=0-99999999999999999999,-99999999999999999999,18446744073709551616-")
+        );
+
+        for invalid in &[
+            "",
+            "bytes",
+            "bytes=",
+            "bytes=-",
+            "bytes=5-3",
+            "bytes=+1-2",
+            "bytes=1 -2",
+            "bytes=0-1,x",
+            "bytes=0x10-",
+            "lines=1-2",
+            "bytes 0-1",
+        ] {
+            assert!(PARSER.parse(invalid).is_empty(), "{:?}", invalid);
+        }
+    }
+
+    /// Well-formed values parse the same as before
+    #[test]
+    fn test_differential() {
+        let values = [
+            "bytes=0-1",
+            "bytes=0-",
+            "bytes=-5",
+            "bytes=0-1,4-5",
+            "bytes=0-3,2-5",
+            "bytes=16-20,-0,2-3",
+            "bytes=500-999,-100",
+            "bytes=0-0,-1",
+        ];
+        for value in &values {
+            assert_eq!(
+                LegacyParser.parse(value),
+                PARSER.parse(value),
+                "{:?}",
+                value
+            );
+        }
+    }
+}
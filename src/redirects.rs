@@ -0,0 +1,177 @@
+//! Redirect table configured in code, matched before anything is resolved, see
+//! [`StaticFiles::redirect`](struct.StaticFiles.html#method.redirect)
+
+use crate::{
+    error::{InvalidRedirect, TSFResult},
+    path::normalize_path,
+};
+use http::StatusCode;
+use serde::Deserialize;
+
+/// A redirect of requests for `from` to `to`
+///
+/// `from` is a path relative to root, matched exactly, or as a prefix if it ends with `*`.
+/// `to` is sent as `Location` as is (an absolute path or URL), a trailing `*` of it is replaced
+/// by what the `*` of `from` matched.
+#[derive(Debug, Clone, Eq, PartialEq, Deserialize)]
+pub struct RedirectRule {
+    pub from: String,
+    pub to: String,
+    /// one of 301, 302, 307 and 308, default 301
+    #[serde(default = "default_status")]
+    pub status: u16,
+}
+
+fn default_status() -> u16 {
+    StatusCode::MOVED_PERMANENTLY.as_u16()
+}
+
+/// Check statuses of `rules`
+pub(crate) fn validate_redirects(rules: &[RedirectRule]) -> TSFResult<()> {
+    for rule in rules {
+        match rule.status {
+            301 | 302 | 307 | 308 => {}
+            status => return Err(InvalidRedirect(format!("{} for {}", status, rule.from)).into()),
+        }
+    }
+    Ok(())
+}
+
+/// `Location` and status of the first of `rules` matching `url_path`, with `query` of the
+/// request carried over unless the target has its own
+pub(crate) fn find_redirect(
+    rules: &[RedirectRule],
+    url_path: &str,
+    query: Option<&str>,
+) -> Option<(String, StatusCode)> {
+    let logical = normalize_path(url_path)?;
+    rules.iter().find_map(|rule| {
+        let rest = matched_rest(rule.from.trim_start_matches('/'), &logical)?;
+        let location = match (rule.to.ends_with('*'), rest) {
+            (true, Some(rest)) => format!(
+                "{}{}",
+                encode(&rule.to[..rule.to.len() - 1], false),
+                encode(rest, true)
+            ),
+            _ => encode(&rule.to, false),
+        };
+        let location = match query {
+            Some(query) if !location.contains('?') => match location.find('#') {
+                Some(index) => format!("{}?{}{}", &location[..index], query, &location[index..]),
+                None => format!("{}?{}", location, query),
+            },
+            _ => location,
+        };
+        Some((location, StatusCode::from_u16(rule.status).ok()?))
+    })
+}
+
+/// Whether `from` matches `logical`, with the part a trailing `*` matched
+fn matched_rest<'a>(from: &str, logical: &'a str) -> Option<Option<&'a str>> {
+    if !from.ends_with('*') {
+        return Some(None).filter(|_| from.trim_end_matches('/') == logical);
+    }
+    let stem = &from[..from.len() - 1];
+    if logical.starts_with(stem) {
+        Some(Some(&logical[stem.len()..]))
+    } else if stem.ends_with('/') && logical == &stem[..stem.len() - 1] {
+        // `docs/*` matches `docs` too
+        Some(Some(""))
+    } else {
+        None
+    }
+}
+
+/// Percent-encode bytes not allowed in a URI; configured targets keep their `%` escapes, `?`
+/// and `#`, paths from requests (`decoded`) have them encoded as well
+fn encode(value: &str, decoded: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for &byte in value.as_bytes() {
+        let escape = byte <= b' '
+            || byte >= 0x7f
+            || b"\"<>\\^`{|}".contains(&byte)
+            || (decoded && b"%?#".contains(&byte));
+        if escape {
+            encoded.push_str(&format!("%{:02X}", byte));
+        } else {
+            encoded.push(char::from(byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(from: &str, to: &str, status: u16) -> RedirectRule {
+        RedirectRule {
+            from: from.to_string(),
+            to: to.to_string(),
+            status,
+        }
+    }
+
+    #[test]
+    fn test_find_redirect() {
+        let rules = vec![
+            rule("/old.html", "/new.html", 301),
+            rule("docs/*", "https://docs.example.com/*", 308),
+            rule("tmp/*", "/elsewhere/", 302),
+            rule("menu", "/Speisekarte über uns", 307),
+        ];
+        assert_eq!(
+            Some(("/new.html".to_string(), StatusCode::MOVED_PERMANENTLY)),
+            find_redirect(&rules, "old.html", None)
+        );
+        assert_eq!(
+            Some(("/new.html?a=1".to_string(), StatusCode::MOVED_PERMANENTLY)),
+            find_redirect(&rules, "/./old.html", Some("a=1"))
+        );
+        assert_eq!(
+            Some((
+                "https://docs.example.com/a%20b/%C3%BC%3F%25.html".to_string(),
+                StatusCode::PERMANENT_REDIRECT
+            )),
+            find_redirect(&rules, "docs/a%20b/%C3%BC%3f%25.html", None)
+        );
+        assert_eq!(
+            Some((
+                "https://docs.example.com/".to_string(),
+                StatusCode::PERMANENT_REDIRECT
+            )),
+            find_redirect(&rules, "docs", None)
+        );
+        assert_eq!(
+            Some(("/elsewhere/".to_string(), StatusCode::FOUND)),
+            find_redirect(&rules, "tmp/x/y", None)
+        );
+        assert_eq!(
+            Some((
+                "/Speisekarte%20%C3%BCber%20uns".to_string(),
+                StatusCode::TEMPORARY_REDIRECT
+            )),
+            find_redirect(&rules, "menu", None)
+        );
+        assert_eq!(None, find_redirect(&rules, "old.htm", None));
+        assert_eq!(None, find_redirect(&rules, "docsx", None));
+        assert_eq!(None, find_redirect(&rules, "%252e%252e/old.html", None));
+
+        let rules = vec![rule("a", "/b?x=1#top", 301), rule("c", "/d#top", 301)];
+        assert_eq!(
+            "/b?x=1#top",
+            find_redirect(&rules, "a", Some("y=2")).unwrap().0
+        );
+        assert_eq!(
+            "/d?y=2#top",
+            find_redirect(&rules, "c", Some("y=2")).unwrap().0
+        );
+    }
+
+    #[test]
+    fn test_validate_redirects() {
+        assert!(validate_redirects(&[rule("a", "/b", 308), rule("c", "/d", 302)]).is_ok());
+        assert!(validate_redirects(&[rule("a", "/b", 200)]).is_err());
+        assert!(validate_redirects(&[rule("a", "/b", 303)]).is_err());
+    }
+}
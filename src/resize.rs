@@ -0,0 +1,171 @@
+//! Resized variants of images generated on first request, enabled by feature `image`
+
+use crate::{
+    error::TSFResult,
+    path::{join_canonical, normalize_path, resolve_path},
+    variants::{width_variant, VariantResolver, WidthVariants, WIDTH_HINT},
+};
+use http::HeaderMap;
+use image::{FilterType, GenericImageView, ImageFormat};
+use log::warn;
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+/// Generate width variants of images (`photos/cat.jpg?w=320`) into a cache directory under root
+/// on first request, and serve them from there
+///
+/// Only widths given are generated, so that clients can't make the server resize to arbitrary
+/// sizes, the width is chosen like by [`WidthVariants`](struct.WidthVariants.html). Images are
+/// never scaled up, a variant at least as wide as the original is a copy.
+/// Variants older than their original are generated again.
+/// Supported formats are JPEG, PNG, GIF and BMP, chosen by extension.
+///
+/// Resizing runs on the request, give this to
+/// [`StaticFiles::variant_resolver`](struct.StaticFiles.html#method.variant_resolver) of
+/// the same root only for trusted or rate limited traffic.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::sync::Arc;
+/// # use tide_static_file::{ImageResizer, StaticFiles};
+/// let resizer = ImageResizer::new("./public", ".variants", vec![320, 640]).unwrap();
+/// let files = StaticFiles::new("./public")
+///     .unwrap()
+///     .variant_resolver(Arc::new(resizer));
+/// ```
+pub struct ImageResizer {
+    root: PathBuf,
+    /// logical path of cache directory
+    cache_dir: String,
+    widths: WidthVariants,
+}
+
+impl ImageResizer {
+    /// `cache_dir` is relative to `root`, and created if missing
+    pub fn new(
+        root: impl AsRef<Path>,
+        cache_dir: &str,
+        widths: impl IntoIterator<Item = u32>,
+    ) -> TSFResult<Self> {
+        let root = root.as_ref().canonicalize()?;
+        let cache_dir = match normalize_path(cache_dir).filter(|x| !x.is_empty()) {
+            Some(x) => x,
+            None => return Err(failure::err_msg("cache directory must be inside root")),
+        };
+        fs::create_dir_all(root.join(&cache_dir))?;
+        Ok(Self {
+            root,
+            cache_dir,
+            widths: WidthVariants::new(widths),
+        })
+    }
+
+    fn generate(
+        &self,
+        original: &Path,
+        variant: &Path,
+        width: u32,
+        format: ImageFormat,
+    ) -> TSFResult<()> {
+        let image = image::open(original)?;
+        if let Some(parent) = variant.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // written aside and renamed, so that concurrent requests never serve a partial file
+        let name = variant.file_name().unwrap().to_string_lossy();
+        let temporary = variant.with_file_name(format!(".{}.tmp", name));
+        if width >= image.width() {
+            fs::copy(original, &temporary)?;
+        } else {
+            let resized = image.resize(width, u32::max_value(), FilterType::Lanczos3);
+            let mut file = BufWriter::new(File::create(&temporary)?);
+            resized.write_to(&mut file, format)?;
+        }
+        fs::rename(&temporary, variant)?;
+        Ok(())
+    }
+}
+
+impl VariantResolver for ImageResizer {
+    fn resolve(&self, path: &str, query: &str, hints: &HeaderMap) -> Option<String> {
+        let width = self.widths.requested(query, hints)?;
+        if path.starts_with(&format!("{}/", self.cache_dir)) {
+            return None; // already a variant
+        }
+        let format = image_format(path)?;
+        let variant = format!("{}/{}", self.cache_dir, width_variant(path, width));
+        let target = resolve_path(&self.root, &variant)?;
+        let original = join_canonical(&self.root, path).filter(|x| x.is_file())?;
+        if is_stale(&original, &target) {
+            if let Err(error) = self.generate(&original, &target, width, format) {
+                warn!(
+                    "failed to resize {:?} to width {}: {}",
+                    original, width, error
+                );
+                return None;
+            }
+        }
+        Some(variant)
+    }
+
+    fn vary(&self) -> Option<&'static str> {
+        Some(WIDTH_HINT)
+    }
+}
+
+/// Whether variant is missing or older than original
+fn is_stale(original: &Path, variant: &Path) -> bool {
+    let modified = |x: &Path| x.metadata().and_then(|x| x.modified()).ok();
+    match (modified(original), modified(variant)) {
+        (Some(original), Some(variant)) => variant < original,
+        _ => true,
+    }
+}
+
+fn image_format(path: &str) -> Option<ImageFormat> {
+    let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "jpg" | "jpeg" => Some(ImageFormat::JPEG),
+        "png" => Some(ImageFormat::PNG),
+        "gif" => Some(ImageFormat::GIF),
+        "bmp" => Some(ImageFormat::BMP),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_resize() {
+        let root = TempDir::new("resize");
+        fs::create_dir_all(root.join("photos")).unwrap();
+        image::RgbImage::new(64, 32)
+            .save(root.join("photos/cat.png"))
+            .unwrap();
+        let resizer = ImageResizer::new(&root, ".variants", vec![16, 128]).unwrap();
+        let none = HeaderMap::new();
+
+        let variant = resizer.resolve("photos/cat.png", "w=16", &none).unwrap();
+        assert_eq!(".variants/photos/cat.w16.png", variant);
+        let resized = image::open(root.join(&variant)).unwrap();
+        assert_eq!((16, 8), resized.dimensions());
+
+        let variant = resizer.resolve("photos/cat.png", "w=128", &none).unwrap();
+        assert_eq!(
+            (64, 32),
+            image::open(root.join(&variant)).unwrap().dimensions()
+        );
+
+        assert_eq!(None, resizer.resolve("photos/cat.png", "w=32", &none));
+        assert_eq!(None, resizer.resolve("photos/dog.png", "w=16", &none));
+        assert_eq!(None, resizer.resolve("photos/cat.txt", "w=16", &none));
+        assert_eq!(None, resizer.resolve(&variant, "w=16", &none));
+    }
+}
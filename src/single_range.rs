@@ -1,20 +1,60 @@
-use crate::file_read::{FileReadStream, StreamOutput};
+use crate::{
+    chunk_size::ChunkSizer,
+    file_read::{FileReadStream, ReadPool, StreamOutput},
+    vfs::Reader,
+};
 use bytes::Bytes;
 use futures::{task::Waker, Poll, Stream};
-use std::{fs::File, ops::Range, pin::Pin};
+use std::{
+    io::{Read, Seek, SeekFrom},
+    ops::Range,
+    pin::Pin,
+    sync::Arc,
+};
+
+/// Single ranges up to this size of local files are read at once into an inline body
+pub(crate) const INLINE_RANGE_SIZE: u64 = 64 * 1024;
+
+/// Read `range` of file directly, without the read pool, for ranges up to
+/// [`INLINE_RANGE_SIZE`]
+pub(crate) fn read_inline(mut file: Reader, range: &Range<u64>) -> std::io::Result<Vec<u8>> {
+    debug_assert!(range.end - range.start <= INLINE_RANGE_SIZE);
+    let mut buffer = vec![0u8; (range.end - range.start) as usize];
+    file.seek(SeekFrom::Start(range.start))?;
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
 
 pub(super) struct SingleRangeReader {
     reader: FileReadStream,
+    /// failed or complete, the stream ends
+    done: bool,
 }
 
 impl SingleRangeReader {
-    pub fn new(file: File, start: u64, end: u64) -> Result<Self, std::io::Error> {
+    /// Read on `pool`, the default one if `None`; panic if range is empty, empty bodies are
+    /// served without reader
+    pub fn new(
+        pool: Option<Arc<ReadPool>>,
+        file: Reader,
+        start: u64,
+        end: u64,
+    ) -> Result<Self, std::io::Error> {
         assert!(start < end);
-        let reader = match FileReadStream::new(file, Range { start, end }) {
+        let reader = match FileReadStream::new(pool, file, Range { start, end }) {
             Ok(x) => x,
             Err((_, error)) => return Err(error),
         };
-        Ok(Self { reader })
+        Ok(Self {
+            reader,
+            done: false,
+        })
+    }
+
+    /// Read chunks sized by `sizer` instead of default bounds
+    pub fn chunk_sizer(mut self, sizer: ChunkSizer) -> Self {
+        self.reader.chunk_sizer(sizer);
+        self
     }
 
     pub fn into_body(self) -> http_service::Body {
@@ -26,11 +66,35 @@ impl Stream for SingleRangeReader {
     type Item = Result<Bytes, std::io::Error>;
 
     fn poll_next(mut self: Pin<&mut Self>, waker: &Waker) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
         match self.reader.poll_next(waker) {
             StreamOutput::Pending => Poll::Pending,
-            StreamOutput::Error(error) => Poll::Ready(Some(Err(error))),
+            StreamOutput::Error(error) => {
+                self.done = true;
+                Poll::Ready(Some(Err(error)))
+            }
             StreamOutput::Item(data) => Poll::Ready(Some(Ok(data))),
-            StreamOutput::Complete(_) => Poll::Ready(None),
+            StreamOutput::Complete(_) => {
+                self.done = true;
+                Poll::Ready(None)
+            }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, ErrorKind};
+
+    #[test]
+    fn test_read_inline() {
+        let file = || -> Reader { Box::new(Cursor::new(b"0123456789".to_vec())) };
+        assert_eq!(b"345", &read_inline(file(), &(3..6)).unwrap()[..]);
+        // file was truncated after its size was read
+        let error = read_inline(file(), &(8..12)).unwrap_err();
+        assert_eq!(ErrorKind::UnexpectedEof, error.kind());
+    }
+}
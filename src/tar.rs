@@ -0,0 +1,309 @@
+//! Deterministic tar archives of directories, for downloads which can be resumed
+//!
+//! The same files always make the same bytes: entries are sorted by name and their metadata is
+//! fixed (mode `0644`, owned by `0:0`, no user or group names) but for the modification time.
+//! So the archive length is known up front, and a range of it is rebuilt on request by skipping
+//! what comes before, seeking into files instead of reading them. Names not fitting a ustar
+//! header and sizes beyond 8 GiB are recorded in PAX extended headers, ref:
+//! https://pubs.opengroup.org/onlinepubs/9699919799/utilities/pax.html
+
+use crate::archive::ArchiveEntry;
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, ErrorKind, Read, Seek, SeekFrom, Write},
+    ops::Range,
+    time::UNIX_EPOCH,
+};
+
+const BLOCK: u64 = 512;
+
+/// Largest value of an 11 digit octal field
+const MAX_OCTAL: u64 = 0o777_7777_7777;
+
+/// Entries of an archive with their header blocks, built before anything is sent
+pub(crate) struct TarPlan {
+    parts: Vec<(Vec<u8>, ArchiveEntry)>,
+    length: u64,
+}
+
+impl TarPlan {
+    pub fn new(entries: Vec<ArchiveEntry>) -> Self {
+        let parts: Vec<_> = entries.into_iter().map(|x| (header(&x), x)).collect();
+        let length = parts
+            .iter()
+            .map(|(header, entry)| header.len() as u64 + entry.size + padding(entry.size))
+            .sum::<u64>()
+            + 2 * BLOCK;
+        Self { parts, length }
+    }
+
+    /// Length of the whole archive
+    pub fn length(&self) -> u64 {
+        self.length
+    }
+
+    /// Strong `ETag` of the archive, from names, sizes and modification times of its files
+    pub fn etag(&self) -> String {
+        let mut hasher = Sha256::new();
+        for (_, entry) in &self.parts {
+            let modified = entry
+                .modified
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            hasher.input(format!("{}\0{}\0{:?}\n", entry.name, entry.size, modified).as_bytes());
+        }
+        format!("tar-{:.32x}", hasher.result())
+    }
+
+    /// Write bytes `range` of the archive, files which changed since the plan was made fail
+    pub fn write(&self, range: Range<u64>, mut writer: impl Write) -> io::Result<()> {
+        // part of `length` bytes at `position` of the archive which is inside `range`
+        let window = |position: u64, length: u64| {
+            let start = range.start.max(position).min(position + length);
+            let end = range.end.min(position + length).max(start);
+            start - position..end - position
+        };
+        let zeros = [0; BLOCK as usize * 2];
+        let mut position = 0;
+        for (header, entry) in &self.parts {
+            if position >= range.end {
+                return Ok(());
+            }
+            let part = window(position, header.len() as u64);
+            writer.write_all(&header[part.start as usize..part.end as usize])?;
+            position += header.len() as u64;
+            let part = window(position, entry.size);
+            if part.start < part.end {
+                copy_part(entry, part, &mut writer)?;
+            }
+            position += entry.size;
+            let part = window(position, padding(entry.size));
+            writer.write_all(&zeros[..(part.end - part.start) as usize])?;
+            position += padding(entry.size);
+        }
+        let part = window(position, 2 * BLOCK);
+        writer.write_all(&zeros[..(part.end - part.start) as usize])
+    }
+}
+
+/// Copy bytes `part` of the file of `entry`, unless it changed
+fn copy_part(entry: &ArchiveEntry, part: Range<u64>, writer: &mut impl Write) -> io::Result<()> {
+    let mut file = File::open(&entry.path)?;
+    let metadata = file.metadata()?;
+    let changed = || io::Error::new(ErrorKind::Other, format!("{} changed", entry.name));
+    if metadata.len() != entry.size || metadata.modified().ok() != Some(entry.modified) {
+        return Err(changed());
+    }
+    file.seek(SeekFrom::Start(part.start))?;
+    let length = part.end - part.start;
+    if io::copy(&mut file.take(length), writer)? != length {
+        return Err(changed());
+    }
+    Ok(())
+}
+
+/// Zeros following content of `size` bytes up to the next block
+fn padding(size: u64) -> u64 {
+    (BLOCK - size % BLOCK) % BLOCK
+}
+
+/// Header blocks of `entry`, after a PAX extended header if it doesn't fit a ustar header
+fn header(entry: &ArchiveEntry) -> Vec<u8> {
+    let mut records = String::new();
+    let (prefix, name) = match split_name(&entry.name) {
+        Some(x) => x,
+        None => {
+            records.push_str(&pax_record("path", &entry.name));
+            ("", &entry.name[..])
+        }
+    };
+    let size = if entry.size > MAX_OCTAL {
+        records.push_str(&pax_record("size", &entry.size.to_string()));
+        0
+    } else {
+        entry.size
+    };
+    let modified = entry
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs().min(MAX_OCTAL))
+        .unwrap_or(0);
+
+    let mut blocks = Vec::new();
+    if !records.is_empty() {
+        let length = records.len() as u64;
+        blocks.extend_from_slice(&block(b"PaxHeader", b"", length, 0, b'x'));
+        blocks.extend_from_slice(records.as_bytes());
+        blocks.resize(blocks.len() + padding(length) as usize, 0);
+    }
+    blocks.extend_from_slice(&block(
+        name.as_bytes(),
+        prefix.as_bytes(),
+        size,
+        modified,
+        b'0',
+    ));
+    blocks
+}
+
+/// `name` split into the prefix and name fields of a ustar header, at a `/`
+fn split_name(name: &str) -> Option<(&str, &str)> {
+    if name.len() <= 100 {
+        return Some(("", name));
+    }
+    name.match_indices('/')
+        .map(|(index, _)| (&name[..index], &name[index + 1..]))
+        .find(|(prefix, name)| prefix.len() <= 155 && name.len() <= 100 && !name.is_empty())
+}
+
+/// A PAX record, `"{length} {key}={value}\n"` with `length` counting itself
+fn pax_record(key: &str, value: &str) -> String {
+    let rest = key.len() + value.len() + 3;
+    let mut length = rest + 1;
+    while length != rest + length.to_string().len() {
+        length = rest + length.to_string().len();
+    }
+    format!("{} {}={}\n", length, key, value)
+}
+
+/// A ustar header block, names longer than their fields are cut
+fn block(name: &[u8], prefix: &[u8], size: u64, modified: u64, kind: u8) -> [u8; 512] {
+    let mut block = [0; 512];
+    let mut put = |at: usize, length: usize, value: &[u8]| {
+        let length = value.len().min(length);
+        block[at..at + length].copy_from_slice(&value[..length]);
+    };
+    put(0, 100, name);
+    put(100, 8, b"0000644\0");
+    put(108, 8, b"0000000\0");
+    put(116, 8, b"0000000\0");
+    put(124, 12, format!("{:011o}\0", size).as_bytes());
+    put(136, 12, format!("{:011o}\0", modified).as_bytes());
+    // checksum is computed with its own field as spaces
+    put(148, 8, b"        ");
+    put(156, 1, &[kind]);
+    put(257, 8, b"ustar\x0000");
+    put(345, 155, prefix);
+    let checksum: u32 = block.iter().map(|&x| u32::from(x)).sum();
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::{
+        fs,
+        time::{Duration, SystemTime},
+    };
+
+    fn entry(root: &std::path::Path, name: &str, content: &[u8]) -> ArchiveEntry {
+        let path = root.join(name.replace('/', "_"));
+        fs::write(&path, content).unwrap();
+        ArchiveEntry {
+            name: name.to_string(),
+            size: content.len() as u64,
+            modified: fs::metadata(&path).unwrap().modified().unwrap(),
+            path,
+        }
+    }
+
+    #[test]
+    fn test_pax_record() {
+        assert_eq!("11 path=a\n", pax_record("path", "a"));
+        // 99 bytes without the length, whose two digits make it 101, which needs three
+        let value = "v".repeat(92);
+        assert_eq!(102, pax_record("path", &value).len());
+        assert!(pax_record("path", &value).starts_with("102 "));
+    }
+
+    #[test]
+    fn test_split_name() {
+        assert_eq!(Some(("", "a/b.txt")), split_name("a/b.txt"));
+        let long = format!("{}/{}", "d".repeat(120), "f".repeat(90));
+        assert_eq!(Some((&long[..120], &long[121..])), split_name(&long));
+        assert_eq!(None, split_name(&"f".repeat(101)));
+    }
+
+    #[test]
+    fn test_header() {
+        let entry = ArchiveEntry {
+            name: "a.txt".to_string(),
+            path: "a.txt".into(),
+            size: 5,
+            modified: UNIX_EPOCH + Duration::from_secs(1_554_122_096),
+        };
+        let blocks = header(&entry);
+        assert_eq!(512, blocks.len());
+        assert_eq!(b"a.txt\0", &blocks[..6]);
+        assert_eq!(b"00000000005\0", &blocks[124..136]);
+        assert_eq!(b"13450402560\0", &blocks[136..148]);
+        let checksum = blocks
+            .iter()
+            .enumerate()
+            .map(|(index, &x)| {
+                if index >= 148 && index < 156 {
+                    32
+                } else {
+                    u32::from(x)
+                }
+            })
+            .sum::<u32>();
+        assert_eq!(format!("{:06o}\0 ", checksum).as_bytes(), &blocks[148..156]);
+
+        let huge = ArchiveEntry {
+            name: "n".repeat(150),
+            size: MAX_OCTAL + 1,
+            ..entry
+        };
+        let blocks = header(&huge);
+        assert_eq!(3 * 512, blocks.len());
+        assert_eq!(b'x', blocks[156]);
+        let records = format!(
+            "{}{}",
+            pax_record("path", &huge.name),
+            pax_record("size", "8589934592")
+        );
+        assert_eq!(records.as_bytes(), &blocks[512..512 + records.len()]);
+        assert_eq!(b"00000000000\0", &blocks[1024 + 124..1024 + 136]);
+    }
+
+    #[test]
+    fn test_plan() {
+        let root = TempDir::new("tar");
+        let entries = vec![
+            entry(&root, "a.txt", b"hello"),
+            entry(&root, &format!("{}/b.bin", "d".repeat(150)), &[7; 600]),
+        ];
+        let plan = TarPlan::new(entries.clone());
+        assert_eq!(512 + 512 + 512 + 1024 + 1024, plan.length());
+        assert_eq!(plan.etag(), TarPlan::new(entries.clone()).etag());
+
+        let mut whole = Vec::new();
+        plan.write(0..plan.length(), &mut whole).unwrap();
+        assert_eq!(plan.length(), whole.len() as u64);
+        assert_eq!(b"hello", &whole[512..517]);
+        assert_eq!(&[7; 600][..], &whole[1536..2136]);
+        assert!(whole[2136..].iter().all(|&x| x == 0));
+
+        // any range is the same bytes as in the whole archive
+        for range in vec![0..1, 510..520, 515..1600, 2000..plan.length(), 0..0] {
+            let mut part = Vec::new();
+            plan.write(range.clone(), &mut part).unwrap();
+            assert_eq!(&whole[range.start as usize..range.end as usize], &part[..]);
+        }
+
+        // a changed file fails the archive, even if its size stayed the same
+        fs::write(&entries[0].path, b"HELLO").unwrap();
+        let modified = SystemTime::now() + Duration::from_secs(10);
+        let plan = TarPlan::new(vec![ArchiveEntry {
+            modified,
+            ..entries[0].clone()
+        }]);
+        assert!(plan.write(0..plan.length(), &mut Vec::new()).is_err());
+        // a range before it still works
+        plan.write(0..512, &mut Vec::new()).unwrap();
+    }
+}
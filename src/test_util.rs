@@ -0,0 +1,159 @@
+//! In-memory client for testing endpoints without binding sockets, enabled by feature
+//! `test-util`
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use tide_static_file::{StaticFiles, test_util::client};
+//! let mut client = client(StaticFiles::new("./").unwrap());
+//! let response = client.get("/Cargo.toml").header("range", "bytes=0-9").send();
+//! assert_eq!(206, response.status.as_u16());
+//! assert_eq!(10, response.body.len());
+//! ```
+
+use crate::MultipartBoundary;
+use futures::{executor::block_on, TryFutureExt};
+use http::{header::HeaderName, Extensions, HeaderMap, HttpTryFrom, Method, StatusCode};
+use http_service::{Body, HttpService};
+
+/// Mount `endpoint` on every path of an in-memory app, and return a client of it
+pub fn client(endpoint: impl tide::Endpoint<(), ()> + Clone) -> TestClient<impl HttpService> {
+    let mut app = tide::App::new(());
+    app.at("/*").get(endpoint.clone()).head(endpoint);
+    TestClient::new(app.into_http_service())
+}
+
+/// Client sending requests to an in-memory `HttpService`
+pub struct TestClient<S: HttpService> {
+    service: S,
+    connection: S::Connection,
+}
+
+impl<S: HttpService> TestClient<S> {
+    pub fn new(service: S) -> Self {
+        let connection = block_on(service.connect().into_future())
+            .ok()
+            .expect("failed to connect");
+        Self {
+            service,
+            connection,
+        }
+    }
+
+    pub fn get(&mut self, uri: &str) -> TestRequest<'_, S> {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn head(&mut self, uri: &str) -> TestRequest<'_, S> {
+        self.request(Method::HEAD, uri)
+    }
+
+    pub fn request(&mut self, method: Method, uri: &str) -> TestRequest<'_, S> {
+        let mut request = http::Request::builder();
+        request.method(method).uri(uri);
+        TestRequest {
+            client: self,
+            request,
+        }
+    }
+}
+
+/// Request under construction, see [`TestClient`](struct.TestClient.html)
+pub struct TestRequest<'a, S: HttpService> {
+    client: &'a mut TestClient<S>,
+    request: http::request::Builder,
+}
+
+impl<'a, S: HttpService> TestRequest<'a, S> {
+    pub fn header<K>(mut self, name: K, value: &str) -> Self
+    where
+        HeaderName: HttpTryFrom<K>,
+    {
+        self.request.header(name, value);
+        self
+    }
+
+    /// Send request and wait for the whole response, panic on invalid request
+    pub fn send(mut self) -> TestResponse {
+        let request = self.request.body(Body::empty()).expect("invalid request");
+        let client = self.client;
+        let response = block_on(
+            client
+                .service
+                .respond(&mut client.connection, request)
+                .into_future(),
+        )
+        .ok()
+        .expect("failed to respond");
+
+        let (parts, body) = response.into_parts();
+        TestResponse {
+            status: parts.status,
+            headers: parts.headers,
+            extensions: parts.extensions,
+            body: block_on(body.into_vec()).expect("failed to read body"),
+        }
+    }
+}
+
+/// Response with whole body received
+#[derive(Debug)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub extensions: Extensions,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    /// Return value of given header, `None` if absent or not visible ASCII
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|x| x.to_str().ok())
+    }
+
+    /// Boundary of a `multipart/byteranges` response
+    pub fn boundary(&self) -> Option<&str> {
+        self.extensions
+            .get::<MultipartBoundary>()
+            .map(|x| x.0.as_str())
+    }
+
+    /// Body as text, invalid UTF-8 replaced
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::StaticFiles;
+
+    #[test]
+    fn test_client() {
+        let mut client = client(StaticFiles::new(env!("CARGO_MANIFEST_DIR")).unwrap());
+        let whole = client.get("/LICENSE").send();
+        assert_eq!(StatusCode::OK, whole.status);
+        let etag = whole.header("etag").unwrap().to_string();
+        let size = whole.body.len();
+
+        let response = client.get("/LICENSE").header("range", "bytes=0-9").send();
+        assert_eq!(StatusCode::PARTIAL_CONTENT, response.status);
+        assert_eq!(&whole.body[..10], &response.body[..]);
+        assert_eq!(
+            Some(format!("bytes 0-9/{}", size).as_str()),
+            response.header("content-range")
+        );
+
+        let response = client.get("/LICENSE").header("if-none-match", &etag).send();
+        assert_eq!(StatusCode::NOT_MODIFIED, response.status);
+        assert!(response.body.is_empty());
+
+        let range = format!("bytes={}-", size);
+        let response = client.get("/LICENSE").header("range", &range).send();
+        assert_eq!(StatusCode::RANGE_NOT_SATISFIABLE, response.status);
+
+        let response = client.get("/no-such-file").send();
+        assert_eq!(StatusCode::NOT_FOUND, response.status);
+    }
+}
@@ -0,0 +1,206 @@
+use crate::{
+    options::Settings,
+    path::{normalize_path, segments},
+    utils::{metadata, range_header, validators},
+    Answer, Decision, Resolution, Selection, StaticFiles,
+};
+use http::{header, StatusCode};
+use serde_json::{json, Value};
+use std::{io::ErrorKind, path::Path};
+use tide::{Request, Response};
+
+/// Request header which asks for a resolution trace instead of file content,
+/// only respected when [`Options::debug_trace`](struct.Options.html#structfield.debug_trace) is on
+pub const TRACE_HEADER: &str = "x-static-files-trace";
+
+/// Names of index files reported for directories, which are listed rather than answered by one
+const INDEX_CANDIDATES: &[&str] = &["index.html", "index.htm"];
+
+impl StaticFiles {
+    /// Explain how given request is answered, as a JSON document, from the same resolution and
+    /// decisions it is answered by
+    pub(crate) fn trace_response(
+        &self,
+        settings: &Settings,
+        resolution: Resolution,
+        url_path: Option<&str>,
+        req: &Request,
+    ) -> Response {
+        let trace = self.trace(settings, resolution, url_path, req);
+        http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(serde_json::to_string_pretty(&trace).unwrap().into())
+            .unwrap()
+    }
+
+    fn trace(
+        &self,
+        settings: &Settings,
+        mut resolution: Resolution,
+        url_path: Option<&str>,
+        req: &Request,
+    ) -> Value {
+        let url_path = match url_path {
+            Some(x) => x,
+            None => return json!({ "error": "no path captured by route" }),
+        };
+        let segments: Vec<Value> = segments(url_path)
+            .map(|(raw, decoded, action)| {
+                json!({ "raw": raw, "decoded": decoded, "action": action.as_str() })
+            })
+            .collect();
+        let answer = resolution.answer(settings, req);
+        let Resolution {
+            root, selection, ..
+        } = resolution;
+        let mut trace = json!({
+            "url_path": url_path,
+            "segments": segments,
+            "normalized": normalize_path(url_path),
+            "root": root,
+            "selection": {
+                "target": selection.target.as_ref().map(|x| x.path()),
+                "immutable": selection.immutable,
+                "content_location": &selection.content_location,
+                "redirect": &selection.redirect,
+                "variant_missing": selection.variant_missing,
+            },
+        });
+        let decision = match answer {
+            Answer::Checksums(_) => "generated checksum manifest",
+            Answer::RobotsTxt(_) => "generated robots.txt",
+            Answer::Listing(dir) => {
+                trace["index_candidates"] = index_candidates(&dir);
+                "generated listing"
+            }
+            Answer::Unlisted(dir) => {
+                trace["index_candidates"] = index_candidates(&dir);
+                "404 Not Found, directory not listable"
+            }
+            Answer::Archive(..) => "generated archive",
+            Answer::Integrity if selection.target.is_none() => "404 Not Found",
+            Answer::Integrity => "generated integrity",
+            Answer::Entry => {
+                self.trace_entry(settings, selection, req, &mut trace);
+                return trace;
+            }
+        };
+        trace["decision"] = json!(decision);
+        trace
+    }
+
+    /// Trace of answering with the selected entry as `serve` does
+    fn trace_entry(
+        &self,
+        settings: &Settings,
+        selection: Selection,
+        req: &Request,
+        trace: &mut Value,
+    ) {
+        if let Some(location) = &selection.redirect {
+            trace["decision"] = json!(format!("301 Moved Permanently to {}", location));
+            return;
+        }
+        let target = match selection.target {
+            Some(x) => x,
+            None => {
+                trace["decision"] = json!("404 Not Found");
+                return;
+            }
+        };
+        let info = match metadata(&target, &settings.options.etag_mode, &self.hash_cache) {
+            Ok(x) => x,
+            Err(error) => {
+                let kind = error
+                    .downcast_ref::<std::io::Error>()
+                    .map(std::io::Error::kind);
+                trace["decision"] = match kind {
+                    Some(ErrorKind::NotFound) => json!("404 Not Found"),
+                    _ => json!(format!("unexpected error: {}", error)),
+                };
+                return;
+            }
+        };
+        let now = self.clock.now();
+        let (last_modified, etag_value) = validators(
+            settings.options.clamp_last_modified,
+            info.modified,
+            &info.etag,
+            now,
+        );
+        trace["representation"] = json!({
+            "path": target.path(),
+            "mime": info.mime.to_string(),
+            "size": info.size,
+            "last_modified": httpdate::fmt_http_date(last_modified),
+            "etag": etag_value,
+            "generated": info.generated,
+            "created": info.attributes.created.map(httpdate::fmt_http_date),
+            "permissions": info.attributes.permissions.map(|x| format!("{:04o}", x)),
+            "owner": &info.attributes.owner,
+        });
+        let decision = Self::decide(settings, req, &info, now);
+        trace["range"] =
+            json!({ "header": range_header(req, settings.options.range_units), "selected": [] });
+        if let Decision::Partial(ranges) = &decision {
+            trace["range"]["selected"] =
+                ranges.iter().map(|x| json!([x.start, x.end - 1])).collect();
+        }
+        trace["decision"] = json!(decision.status().to_string());
+    }
+}
+
+fn index_candidates(dir: &Path) -> Value {
+    INDEX_CANDIDATES
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            json!({ "path": path, "exists": path.is_file() })
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "test-util"))]
+mod tests {
+    use crate::{test_util::client, utils::TempDir, DotfilePolicy, StaticFiles, TRACE_HEADER};
+    use serde_json::{json, Value};
+    use std::fs;
+
+    #[test]
+    fn test_trace() {
+        let temp = TempDir::new("trace");
+        fs::create_dir_all(temp.join("docs")).unwrap();
+        fs::write(temp.join("docs/index.html"), b"index").unwrap();
+        fs::write(temp.join(".secret"), b"secret").unwrap();
+        fs::write(temp.join("hello.txt"), b"hello").unwrap();
+        let files = StaticFiles::new(&temp)
+            .unwrap()
+            .dotfiles(DotfilePolicy::Deny)
+            .debug_trace(true);
+        let mut client = client(files);
+        let mut trace = |path: &str| -> Value {
+            let response = client
+                .get(path)
+                .header(TRACE_HEADER, "1")
+                .header("range", "bytes=0-1")
+                .send();
+            assert_eq!(200, response.status.as_u16());
+            serde_json::from_slice(&response.body).unwrap()
+        };
+
+        let hello = trace("/hello.txt");
+        assert_eq!(json!("206 Partial Content"), hello["decision"]);
+        assert_eq!(json!([[0, 1]]), hello["range"]["selected"]);
+        assert_eq!(json!(5), hello["representation"]["size"]);
+        // denied like when served, nothing about the file is told
+        let secret = trace("/.secret");
+        assert_eq!(json!("404 Not Found"), secret["decision"]);
+        assert_eq!(Value::Null, secret["representation"]);
+        assert_eq!(Value::Null, secret["selection"]["target"]);
+        let docs = trace("/docs");
+        assert_eq!(json!(true), docs["index_candidates"][0]["exists"]);
+        assert_eq!(json!(false), docs["index_candidates"][1]["exists"]);
+    }
+}
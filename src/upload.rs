@@ -0,0 +1,675 @@
+//! `PUT` and `DELETE` of files below configured paths, and `POST` of forms with files into
+//! them, enabled by feature `upload`
+//!
+//! Writes are authorized by the [guard](../struct.StaticFiles.html#method.guard) like reads, the
+//! guard of a read-write endpoint should check the method. Mount the endpoint for writing
+//! methods as well, e.g. `app.at("/files/*").get(files.clone()).put(files.clone())`.
+//!
+//! `PUT` and `DELETE` honor `If-Match`, `If-None-Match` and `If-Unmodified-Since` against the
+//! validators reads of the file carry, for optimistic concurrency.
+//!
+//! Uploads exceeding a [quota](../struct.StaticFiles.html#method.upload_quota) are rejected by
+//! `507 Insufficient Storage`.
+//!
+//! Files are checked, written and removed on the read pool, never on the executor.
+
+use crate::{
+    file_read::{run_blocking, ReadPool},
+    form::{form_boundary, parse_form, FormFile},
+    options::{Options, Settings},
+    path::{normalize_path, resolve_path},
+    pool_error_response, precondition_failed_response,
+    quota::Quotas,
+    utils::{get_header, metadata, ErrorResponse},
+    vfs::Target,
+    StaticFiles,
+};
+use futures::{future::FutureObj, StreamExt};
+use http::{header, Method, StatusCode};
+use log::error;
+use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
+use serde_json::json;
+use std::{
+    fs::{self, File},
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tide::{IntoResponse, Request, Response};
+
+/// `PUT`, `DELETE` or `POST`
+pub(crate) fn is_write(method: &Method) -> bool {
+    method == Method::PUT || method == Method::DELETE || method == Method::POST
+}
+
+/// Logical path of the entry of `Options::upload_paths` `url_path` is below, or is if
+/// `inclusive` (directories forms are posted to)
+pub(crate) fn upload_path(options: &Options, url_path: &str, inclusive: bool) -> Option<String> {
+    let path = normalize_path(url_path)?;
+    options
+        .upload_paths
+        .iter()
+        .filter_map(|x| normalize_path(x))
+        .find(|prefix| {
+            (inclusive && path == *prefix)
+                || prefix.is_empty() && !path.is_empty()
+                || path.starts_with(&format!("{}/", prefix))
+        })
+}
+
+/// Name of a posted file without any client directory, `None` if nothing is left
+fn sanitize_filename(filename: &str) -> Option<&str> {
+    let name = filename.rsplit(|x| x == '/' || x == '\\').next()?.trim();
+    match name {
+        "" | "." | ".." => None,
+        _ => Some(name),
+    }
+}
+
+/// File system path of `url_path` below canonical `root`, `None` if its nearest existing
+/// ancestor is outside root, e.g. through a symbolic link
+fn target(root: &Path, url_path: &str) -> Option<PathBuf> {
+    let path = resolve_path(root, url_path)?;
+    let existing = path.ancestors().skip(1).find(|x| x.exists())?;
+    if existing.canonicalize().ok()?.starts_with(root) {
+        Some(path)
+    } else {
+        None
+    }
+}
+
+/// Limits of a write request, checked while its body is read
+struct Limits {
+    /// size of body
+    max_size: Option<u64>,
+    quotas: Arc<Quotas>,
+    /// directories written files are below with their quota
+    dirs: Vec<(PathBuf, u64)>,
+}
+
+impl Limits {
+    /// Bytes files written may take in total, given the size of files they replace
+    fn allowed(&self, replaced: u64) -> u64 {
+        self.quotas
+            .headroom(&self.dirs)
+            .map_or(u64::max_value(), |x| x.saturating_add(replaced))
+    }
+}
+
+/// Size of file at `path`, 0 if missing
+fn file_size(path: &Path) -> u64 {
+    fs::metadata(path).map(|x| x.len()).unwrap_or(0)
+}
+
+/// Total size of files directly in `dir`, the most a form posted into it can replace
+fn files_size(dir: &Path) -> u64 {
+    fs::read_dir(dir).map_or(0, |entries| {
+        entries
+            .filter_map(Result::ok)
+            .filter(|x| x.file_type().map(|x| x.is_file()).unwrap_or(false))
+            .map(|x| file_size(&x.path()))
+            .sum()
+    })
+}
+
+/// Run file system operation `f` of a write on `pool`, the response to send instead if the pool
+/// fails to run it
+fn blocking<T, F>(pool: &Option<Arc<ReadPool>>, f: F) -> FutureObj<'static, Result<T, Response>>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    let running = run_blocking(pool.as_ref().map(|x| &**x), f);
+    FutureObj::new(Box::new(
+        async move { await!(running).map_err(|x| pool_error_response(&x)) },
+    ))
+}
+
+/// Remove `temporary` file of a failed upload on `pool`
+fn discard(pool: &Option<Arc<ReadPool>>, temporary: PathBuf) -> FutureObj<'static, ()> {
+    let removing = blocking(pool, move || {
+        let _ = fs::remove_file(&temporary);
+    });
+    FutureObj::new(Box::new(
+        async move {
+            let _ = await!(removing);
+        },
+    ))
+}
+
+/// Size of form bodies buffered in memory if `Options::max_upload_size` is not set
+const MAX_FORM_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Bytes a form body may take beyond files it carries, for boundaries and headers of its parts
+const FORM_OVERHEAD: u64 = 64 * 1024;
+
+impl StaticFiles {
+    /// Answer a write request, `405 Method Not Allowed` outside upload paths
+    pub(crate) fn write_response(
+        &self,
+        settings: &Arc<Settings>,
+        root: Option<&Path>,
+        url_path: Option<&str>,
+        req: Request,
+    ) -> FutureObj<'static, Response> {
+        let ready = |response: Response| FutureObj::new(Box::new(async move { response }));
+        let is_post = req.method() == Method::POST;
+        // backends are read-only
+        let prefix = url_path
+            .filter(|_| self.backend.is_none())
+            .and_then(|x| upload_path(&settings.options, x, is_post));
+        let (root, url_path, prefix) = match (root, url_path, prefix) {
+            (Some(root), Some(path), Some(prefix)) => (root, path, prefix),
+            _ => return ready(method_not_allowed_response()),
+        };
+        if !settings.options.dotfiles.allows(url_path) {
+            return ready(ErrorResponse::Forbidden.into_response());
+        }
+        let global = settings
+            .options
+            .upload_quota
+            .map(|x| (root.to_path_buf(), x));
+        // prefix is normalized already, it can't escape root
+        let local = settings
+            .options
+            .upload_path_quota
+            .map(|x| (root.join(&prefix), x));
+        let limits = Limits {
+            max_size: settings.options.max_upload_size,
+            quotas: self.quotas.clone(),
+            dirs: global.into_iter().chain(local).collect(),
+        };
+        let (root, pool) = (root.to_path_buf(), self.read_pool.clone());
+        if is_post {
+            let dir = url_path.trim_end_matches('/').to_string();
+            let options = settings.options.clone();
+            return FutureObj::new(Box::new(post(root, dir, options, limits, pool, req)));
+        }
+        let (this, settings, url_path) = (self.clone(), settings.clone(), url_path.to_string());
+        let checking = blocking(&pool, move || {
+            let path = match target(&root, &url_path) {
+                Some(x) => x,
+                None => return Err(ErrorResponse::Forbidden.into_response()),
+            };
+            // checked before the body is read, a concurrent write may still slip in between
+            if this.write_precondition(&settings, &path, &req) {
+                return Err(precondition_failed_response());
+            }
+            if req.method() == Method::DELETE {
+                return Err(delete(&path, &this.quotas));
+            }
+            Ok((path, req))
+        });
+        FutureObj::new(Box::new(
+            async move {
+                match await!(checking) {
+                    Ok(Ok((path, req))) => await!(put(path, limits, pool, req)),
+                    Ok(Err(response)) | Err(response) => response,
+                }
+            },
+        ))
+    }
+
+    /// Whether conditions of `req` fail against the file at `path`, validators are those reads
+    /// of it carry
+    fn write_precondition(&self, settings: &Settings, path: &Path, req: &Request) -> bool {
+        let info = if path.is_file() {
+            let target = Target::Local(path.to_path_buf());
+            metadata(&target, &settings.options.etag_mode, &self.hash_cache).ok()
+        } else {
+            None
+        };
+        Self::write_precondition_failed(
+            get_header(req, header::IF_MATCH),
+            get_header(req, header::IF_NONE_MATCH),
+            get_header(req, header::IF_UNMODIFIED_SINCE),
+            info.as_ref().map(|x| (x.modified, x.etag.as_str())),
+        )
+    }
+}
+
+/// Store body of `req` at `path`, `201 Created` for a new file and `204 No Content` for a
+/// replaced one
+///
+/// The body is written aside and renamed once complete, so that readers never see a partial
+/// file and a failed upload leaves the previous one.
+async fn put(path: PathBuf, limits: Limits, pool: Option<Arc<ReadPool>>, req: Request) -> Response {
+    let inspected = path.clone();
+    let inspecting = blocking(&pool, move || {
+        let (is_dir, exists) = (inspected.is_dir(), inspected.exists());
+        (is_dir, exists, file_size(&inspected))
+    });
+    let (is_dir, exists, replaced) = match await!(inspecting) {
+        Ok(x) => x,
+        Err(response) => return response,
+    };
+    if is_dir {
+        return status_response(StatusCode::CONFLICT, "is a directory");
+    }
+    let created = !exists;
+    let max_size = limits.max_size.unwrap_or_else(u64::max_value);
+    await!(limits.quotas.scanned(&limits.dirs));
+    let allowed = limits.allowed(replaced);
+    // answered before reading, clients sending `Expect: 100-continue` don't send the body
+    let announced = get_header(&req, header::CONTENT_LENGTH).and_then(|x| x.parse::<u64>().ok());
+    if let Some(response) = announced.and_then(|x| check_size(x, max_size, allowed)) {
+        return response;
+    }
+
+    let temporary = temporary_path(&path);
+    let (parent, created_file) = (path.parent().map(Path::to_path_buf), temporary.clone());
+    let creating = blocking(&pool, move || {
+        parent
+            .map_or(Ok(()), fs::create_dir_all)
+            .and_then(|_| File::create(&created_file))
+    });
+    let mut file = match await!(creating) {
+        Ok(Ok(x)) => x,
+        Ok(Err(error)) => {
+            error!("failed to create {:?}: {}", temporary, error);
+            return ErrorResponse::Unexpected.into_response();
+        }
+        Err(response) => return response,
+    };
+
+    let mut body = req.into_body();
+    let mut written = 0u64;
+    while let Some(chunk) = await!(body.next()) {
+        let chunk = match chunk {
+            Ok(x) => x,
+            Err(_) => {
+                await!(discard(&pool, temporary));
+                return status_response(StatusCode::BAD_REQUEST, "failed to read body");
+            }
+        };
+        written = written.saturating_add(chunk.len() as u64);
+        if let Some(response) = check_size(written, max_size, allowed) {
+            await!(discard(&pool, temporary));
+            return response;
+        }
+        let writing = blocking(&pool, move || {
+            let result = file.write_all(&chunk);
+            (file, result)
+        });
+        file = match await!(writing) {
+            Ok((file, Ok(()))) => file,
+            Ok((_, Err(error))) => {
+                error!("failed to write {:?}: {}", temporary, error);
+                await!(discard(&pool, temporary));
+                return ErrorResponse::Unexpected.into_response();
+            }
+            Err(response) => {
+                await!(discard(&pool, temporary));
+                return response;
+            }
+        };
+    }
+    let (stored, renamed) = (path.clone(), temporary.clone());
+    let storing = blocking(&pool, move || {
+        file.sync_all().and_then(|_| fs::rename(&renamed, &stored))
+    });
+    match await!(storing) {
+        Ok(Ok(())) => {}
+        Ok(Err(error)) => {
+            error!("failed to store {:?}: {}", path, error);
+            await!(discard(&pool, temporary));
+            return ErrorResponse::Unexpected.into_response();
+        }
+        Err(response) => {
+            await!(discard(&pool, temporary));
+            return response;
+        }
+    }
+    limits.quotas.record(&path, replaced, written);
+
+    if created {
+        status_response(StatusCode::CREATED, "created")
+    } else {
+        status_response(StatusCode::NO_CONTENT, "")
+    }
+}
+
+/// Store files of a `multipart/form-data` body into directory at url path `dir`, answered by
+/// `201 Created` with their logical paths as JSON
+///
+/// Only the last segment of file names is kept, it goes through the same path checks as
+/// request paths. The body is buffered, `Options::max_upload_size` or `MAX_FORM_SIZE` limits it
+/// as a whole and quotas are checked while it's read, against files it may replace.
+async fn post(
+    root: PathBuf,
+    dir: String,
+    options: Options,
+    limits: Limits,
+    pool: Option<Arc<ReadPool>>,
+    req: Request,
+) -> Response {
+    let boundary = match get_header(&req, header::CONTENT_TYPE).and_then(|x| form_boundary(&x)) {
+        Some(x) => x,
+        None => {
+            return status_response(
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "expect multipart/form-data",
+            )
+        }
+    };
+    let max_size = limits.max_size.unwrap_or(MAX_FORM_SIZE);
+    // files land directly in `dir`, they can't replace more than it holds
+    let listed = resolve_path(&root, &dir);
+    let replaceable = match await!(blocking(&pool, move || listed.map_or(0, |x| files_size(&x)))) {
+        Ok(x) => x,
+        Err(response) => return response,
+    };
+    await!(limits.quotas.scanned(&limits.dirs));
+    let allowed = limits.allowed(replaceable).saturating_add(FORM_OVERHEAD);
+    let announced = get_header(&req, header::CONTENT_LENGTH).and_then(|x| x.parse::<u64>().ok());
+    if let Some(response) = announced.and_then(|x| check_size(x, max_size, allowed)) {
+        return response;
+    }
+    let mut body = req.into_body();
+    let mut content = Vec::new();
+    while let Some(chunk) = await!(body.next()) {
+        let chunk = match chunk {
+            Ok(x) => x,
+            Err(_) => return status_response(StatusCode::BAD_REQUEST, "failed to read body"),
+        };
+        let size = content.len() as u64 + chunk.len() as u64;
+        if let Some(response) = check_size(size, max_size, allowed) {
+            return response;
+        }
+        content.extend_from_slice(&chunk);
+    }
+    let files = match parse_form(&content, &boundary) {
+        Some(ref x) if x.is_empty() => {
+            return status_response(StatusCode::BAD_REQUEST, "no file in form")
+        }
+        Some(x) => x,
+        None => return status_response(StatusCode::BAD_REQUEST, "malformed form"),
+    };
+    let storing = blocking(&pool, move || {
+        store_form(&root, &dir, &options, &limits, &content, &files)
+    });
+    await!(storing).unwrap_or_else(|x| x)
+}
+
+/// Store `files` of a form with `content` into directory at url path `dir`, see [`post`]
+fn store_form(
+    root: &Path,
+    dir: &str,
+    options: &Options,
+    limits: &Limits,
+    content: &[u8],
+    files: &[FormFile],
+) -> Response {
+    // every name is checked before anything is written
+    let mut targets = Vec::new();
+    for file in files {
+        // encoded, so that it's decoded exactly once like request paths
+        let url_path = match sanitize_filename(&file.filename) {
+            Some(x) => format!(
+                "{}/{}",
+                dir,
+                utf8_percent_encode(x, PATH_SEGMENT_ENCODE_SET)
+            ),
+            None => return status_response(StatusCode::BAD_REQUEST, "invalid file name"),
+        };
+        let logical_path = match normalize_path(&url_path) {
+            Some(_) if !options.dotfiles.allows(&url_path) => {
+                return ErrorResponse::Forbidden.into_response()
+            }
+            Some(x) => x,
+            None => return status_response(StatusCode::BAD_REQUEST, "invalid file name"),
+        };
+        let path = match target(root, &url_path) {
+            Some(ref x) if x.is_dir() => {
+                return status_response(StatusCode::CONFLICT, "is a directory")
+            }
+            Some(x) => x,
+            None => return ErrorResponse::Forbidden.into_response(),
+        };
+        targets.push((logical_path, path));
+    }
+    let replaced: u64 = targets.iter().map(|(_, x)| file_size(x)).sum();
+    let written: u64 = files.iter().map(|x| x.content.len() as u64).sum();
+    if written > limits.allowed(replaced) {
+        return insufficient_storage_response();
+    }
+    for (file, (_, path)) in files.iter().zip(&targets) {
+        let replaced = file_size(path);
+        if let Err(error) = store(path, &content[file.content.clone()]) {
+            error!("failed to store {:?}: {}", path, error);
+            return ErrorResponse::Unexpected.into_response();
+        }
+        limits
+            .quotas
+            .record(path, replaced, file.content.len() as u64);
+    }
+
+    let stored: Vec<_> = targets.into_iter().map(|(x, _)| x).collect();
+    http::Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::CONTENT_TYPE, mime::APPLICATION_JSON.to_string())
+        .body(json!({ "files": stored }).to_string().into())
+        .unwrap()
+}
+
+/// Path next to `path` an upload is written to before it's renamed, unique in this process
+fn temporary_path(path: &Path) -> PathBuf {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    path.with_file_name(format!(".{}.{}.upload", name, id))
+}
+
+/// Write `content` at `path` atomically
+fn store(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    let temporary = temporary_path(path);
+    let result = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| File::create(&temporary))
+        .and_then(|mut file| file.write_all(content).and_then(|_| file.sync_all()))
+        .and_then(|_| fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// `413 Payload Too Large` or `507 Insufficient Storage` if `size` bytes are too many
+fn check_size(size: u64, max_size: u64, allowed: u64) -> Option<Response> {
+    if size > max_size {
+        Some(status_response(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "payload too large",
+        ))
+    } else if size > allowed {
+        Some(insufficient_storage_response())
+    } else {
+        None
+    }
+}
+
+fn insufficient_storage_response() -> Response {
+    status_response(StatusCode::INSUFFICIENT_STORAGE, "quota exceeded")
+}
+
+/// Remove file at `path`, directories are never removed
+fn delete(path: &Path, quotas: &Quotas) -> Response {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(x) => x,
+        Err(ref error) if error.kind() == ErrorKind::NotFound => {
+            return ErrorResponse::NotFound.into_response();
+        }
+        Err(error) => {
+            error!("unexpected error occurred: {:?}", error);
+            return ErrorResponse::Unexpected.into_response();
+        }
+    };
+    if metadata.is_dir() {
+        return status_response(StatusCode::CONFLICT, "is a directory");
+    }
+    match fs::remove_file(path) {
+        Ok(()) => {
+            quotas.record(path, metadata.len(), 0);
+            status_response(StatusCode::NO_CONTENT, "")
+        }
+        Err(error) => {
+            error!("failed to remove {:?}: {}", path, error);
+            ErrorResponse::Unexpected.into_response()
+        }
+    }
+}
+
+fn method_not_allowed_response() -> Response {
+    let mut response = status_response(StatusCode::METHOD_NOT_ALLOWED, "method not allowed");
+    response
+        .headers_mut()
+        .insert(header::ALLOW, header::HeaderValue::from_static("GET, HEAD"));
+    response
+}
+
+fn status_response(status: StatusCode, text: &'static str) -> Response {
+    http::Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+        .body(text.into())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use futures::executor::block_on;
+    use http_service::Body;
+
+    #[test]
+    fn test_upload_path() {
+        let mut options = Options::default();
+        assert_eq!(None, upload_path(&options, "inbox/a.txt", false));
+        options.upload_paths = vec!["/inbox/".to_string()];
+        assert_eq!(
+            Some("inbox".to_string()),
+            upload_path(&options, "inbox/a/b.txt", false)
+        );
+        assert_eq!(None, upload_path(&options, "inbox", false));
+        assert_eq!(
+            Some("inbox".to_string()),
+            upload_path(&options, "inbox/", true)
+        );
+        assert_eq!(None, upload_path(&options, "inbox2/a.txt", false));
+        assert_eq!(None, upload_path(&options, "inbox/../a.txt", false));
+
+        assert_eq!(Some("a.txt"), sanitize_filename("C:\\Users\\me\\a.txt"));
+        assert_eq!(Some("a.txt"), sanitize_filename("../../a.txt"));
+        assert_eq!(None, sanitize_filename("dir/.."));
+        assert_eq!(None, sanitize_filename(""));
+    }
+
+    #[test]
+    fn test_put_and_delete() {
+        let temp = TempDir::new("upload");
+        let root = temp.canonicalize().unwrap();
+        let quotas = Arc::new(Quotas::default());
+        let upload = |name: &str, content: &'static [u8], max_size| {
+            let path = target(&root, name).unwrap();
+            let req = http::Request::builder()
+                .method(Method::PUT)
+                .body(Body::from(content.to_vec()))
+                .unwrap();
+            let limits = Limits {
+                max_size,
+                quotas: quotas.clone(),
+                dirs: vec![(root.join("inbox"), 10)],
+            };
+            block_on(put(path, limits, None, req)).status()
+        };
+
+        assert_eq!(StatusCode::CREATED, upload("inbox/a.txt", b"first", None));
+        assert_eq!(
+            StatusCode::NO_CONTENT,
+            upload("inbox/a.txt", b"second", Some(6))
+        );
+        assert_eq!(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            upload("inbox/a.txt", b"too large", Some(6))
+        );
+        assert_eq!(
+            b"second".to_vec(),
+            fs::read(root.join("inbox/a.txt")).unwrap()
+        );
+        assert_eq!(1, fs::read_dir(root.join("inbox")).unwrap().count());
+
+        // 6 of 10 bytes of quota are taken
+        assert_eq!(
+            StatusCode::INSUFFICIENT_STORAGE,
+            upload("inbox/b.txt", b"fifth", None)
+        );
+        assert_eq!(StatusCode::CREATED, upload("inbox/b.txt", b"four", None));
+        assert_eq!(
+            StatusCode::NO_CONTENT,
+            upload("inbox/a.txt", b"sixths", None)
+        );
+
+        let delete = |path: &Path| delete(path, &quotas);
+        assert_eq!(StatusCode::CONFLICT, delete(&root.join("inbox")).status());
+        let path = root.join("inbox/a.txt");
+        assert_eq!(StatusCode::NO_CONTENT, delete(&path).status());
+        assert_eq!(StatusCode::NOT_FOUND, delete(&path).status());
+        assert_eq!(Some(6), quotas.headroom(&[(root.join("inbox"), 10)]));
+    }
+
+    #[test]
+    fn test_post() {
+        let temp = TempDir::new("post");
+        let root = temp.canonicalize().unwrap();
+        let mut options = Options::default();
+        options.upload_paths = vec!["inbox".to_string()];
+        let upload = |filename: &str| {
+            let body = format!(
+                "--XX\r\n\
+                 Content-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\n\r\n\
+                 content\r\n--XX--\r\n",
+                filename
+            );
+            let req = http::Request::builder()
+                .method(Method::POST)
+                .header(header::CONTENT_TYPE, "multipart/form-data; boundary=XX")
+                .body(Body::from(body.into_bytes()))
+                .unwrap();
+            let limits = Limits {
+                max_size: None,
+                quotas: Arc::default(),
+                dirs: vec![(root.clone(), 10)],
+            };
+            let response = block_on(post(
+                root.clone(),
+                "inbox".to_string(),
+                options.clone(),
+                limits,
+                None,
+                req,
+            ));
+            let status = response.status();
+            (status, block_on(response.into_body().into_vec()).unwrap())
+        };
+
+        let (status, body) = upload("..\\100%25.txt");
+        assert_eq!(StatusCode::CREATED, status);
+        assert_eq!(br#"{"files":["inbox/100%25.txt"]}"#.to_vec(), body);
+        assert_eq!(
+            b"content".to_vec(),
+            fs::read(root.join("inbox/100%25.txt")).unwrap()
+        );
+        assert_eq!(StatusCode::BAD_REQUEST, upload("..").0);
+        // replacing the 7 bytes file fits, another one doesn't
+        assert_eq!(StatusCode::CREATED, upload("100%25.txt").0);
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, upload("b.txt").0);
+        // rejected while read, before the form is parsed
+        let padding = "x".repeat(FORM_OVERHEAD as usize + 100);
+        assert_eq!(StatusCode::INSUFFICIENT_STORAGE, upload(&padding).0);
+    }
+}
@@ -1,15 +1,20 @@
-use crate::error::TSFResult;
+use crate::{
+    error::TSFResult,
+    etag::{etag, EtagMode, HashCache},
+    media::{media_kind, media_mime},
+    options::RangeUnitPolicy,
+    range::ByteRange,
+    vfs::{Attributes, Reader, Target},
+};
 use http::{
     header::{self, AsHeaderName},
     StatusCode,
 };
 use mime::Mime;
-use percent_encoding::{percent_decode, utf8_percent_encode};
-use range_header::ByteRange;
+use percent_encoding::{utf8_percent_encode, PATH_SEGMENT_ENCODE_SET};
 use std::{
     cmp::{max, min},
     fmt::Display,
-    fs::File,
     ops::Range,
     path::{Path, PathBuf},
     time::SystemTime,
@@ -17,17 +22,27 @@ use std::{
 use tide::{IntoResponse, Response};
 
 pub(crate) const MAX_BUFFER_SIZE: usize = 1024 * 1024 * 4;
-pub(crate) const BOUNDARY: &str = "DCjanus"; // :-P
-pub(crate) const MULTI_RANGE_CONTENT_TYPE: &str = "multipart/byteranges; boundary=DCjanus";
 
 pub(crate) enum ErrorResponse {
+    Forbidden,
     NotFound,
     Unexpected,
+    /// file system took too long, see [`ReadPoolConfig::timeout`](struct.ReadPoolConfig.html#structfield.timeout)
+    Timeout,
+    /// [`Deadline`](struct.Deadline.html) of request exceeded
+    DeadlineExceeded,
+    /// no thread is free to serve the request, e.g. of an archive
+    Busy,
 }
 
 impl IntoResponse for ErrorResponse {
     fn into_response(self) -> Response {
         match self {
+            ErrorResponse::Forbidden => http::Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+                .body("forbidden".into())
+                .unwrap(),
             ErrorResponse::NotFound => http::Response::builder()
                 .status(StatusCode::NOT_FOUND)
                 .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
@@ -38,6 +53,21 @@ impl IntoResponse for ErrorResponse {
                 .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
                 .body("unexpected error occurred".into())
                 .unwrap(),
+            ErrorResponse::Timeout => http::Response::builder()
+                .status(StatusCode::GATEWAY_TIMEOUT)
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+                .body("file system timed out".into())
+                .unwrap(),
+            ErrorResponse::DeadlineExceeded => http::Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+                .body("deadline exceeded".into())
+                .unwrap(),
+            ErrorResponse::Busy => http::Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::CONTENT_TYPE, mime::TEXT_PLAIN.to_string())
+                .body("server busy".into())
+                .unwrap(),
         }
     }
 }
@@ -48,57 +78,163 @@ pub(crate) fn get_header(req: &tide::Request, name: impl AsHeaderName) -> Option
         .and_then(|x| x.to_str().ok().map(std::string::ToString::to_string))
 }
 
-/// Given root path and url_path, return absolute path
-/// The main purpose of this function is to prevent [directory traversal attack](https://en.wikipedia.org/wiki/Directory_traversal_attack)
-pub(crate) fn resolve_path(root: &Path, url_path: &str) -> PathBuf {
-    let mut p = PathBuf::new();
-    for i in url_path.split(|c| c == '/' || c == '\\') {
-        if let Ok(i) = percent_decode(i.as_bytes()).decode_utf8() {
-            match i.as_ref() {
-                "." => {
-                    continue;
-                }
-                ".." => {
-                    p.pop();
-                }
-                x => {
-                    p.push(x);
-                }
+/// Value of `Range` header, several header fields are combined into one range set
+///
+/// e.g. `Range: bytes=0-1` and `Range: bytes=4-5` become `bytes=0-1,4-5`. Fields of another
+/// unit are left out if `units` are ignored, otherwise appended as is, so that the combined
+/// value doesn't parse.
+pub(crate) fn range_header(req: &tide::Request, units: RangeUnitPolicy) -> Option<String> {
+    let mut values = req
+        .headers()
+        .get_all(header::RANGE)
+        .iter()
+        .filter_map(|x| x.to_str().ok())
+        .filter(|x| units != RangeUnitPolicy::Ignore || is_bytes_unit(x));
+    let mut combined = values.next()?.to_string();
+    let unit = combined.find('=').map(|x| combined[..x].trim().to_string());
+    for value in values {
+        combined.push(',');
+        match (value.find('='), &unit) {
+            (Some(index), Some(unit)) if value[..index].trim().eq_ignore_ascii_case(unit) => {
+                combined.push_str(&value[index + 1..])
             }
+            _ => combined.push_str(value),
+        }
+    }
+    Some(combined)
+}
+
+/// Value of `Content-Location` when `served` is served for a request of `url_path`, as
+/// absolute path, `None` if the request path doesn't end with `url_path`
+///
+/// e.g. request path `/static/app.js`, url path `app.js`, served `app.3f9ab2.js`:
+/// `/static/app.3f9ab2.js`
+pub(crate) fn content_location(request_path: &str, url_path: &str, served: &str) -> Option<String> {
+    if !request_path.ends_with(url_path) {
+        return None;
+    }
+    let prefix = request_path[..request_path.len() - url_path.len()].trim_end_matches('/');
+    Some(format!(
+        "{}/{}",
+        prefix,
+        encode_href(served.trim_start_matches('/'))
+    ))
+}
+
+/// Percent encode a logical path (decoded segments joined by `/`) for use in generated links and
+/// `Location`-like headers, every segment is encoded so that `#`, `?`, `%` and non-ASCII
+/// characters in names survive
+pub(crate) fn encode_href(logical_path: &str) -> String {
+    logical_path
+        .split('/')
+        .map(|x| utf8_percent_encode(x, PATH_SEGMENT_ENCODE_SET).to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Whether request asks for SRI integrity string instead of file content, i.e. `?sri=1`
+pub(crate) fn is_integrity_query(req: &tide::Request) -> bool {
+    req.uri()
+        .query()
+        .map(|query| query.split('&').any(|x| x == "sri=1"))
+        .unwrap_or(false)
+}
+
+/// An opened entry and everything needed to serve it, see
+/// [`StaticFiles::respond_with`](struct.StaticFiles.html#method.respond_with)
+pub struct FileInfo {
+    pub file: Reader,
+    /// path used to guess MIME and file name, reported to observers
+    pub path: PathBuf,
+    pub mime: Mime,
+    pub size: u64,
+    pub modified: SystemTime,
+    pub etag: String,
+    pub disposition: ContentDisposition,
+    pub attributes: Attributes,
+    /// content generated on request instead of stored, see [`Generated`](struct.Generated.html)
+    pub generated: bool,
+}
+
+impl FileInfo {
+    /// Describe an entry of given content, MIME type and `Content-Disposition` are derived from
+    /// `path`
+    pub fn new(
+        file: Reader,
+        path: impl Into<PathBuf>,
+        size: u64,
+        modified: SystemTime,
+        etag: impl Into<String>,
+    ) -> Self {
+        let path = path.into();
+        let mime = mime_guess::guess_mime_type(&path);
+        Self {
+            file,
+            disposition: ContentDisposition::new(&mime, &path),
+            path,
+            mime,
+            size,
+            modified,
+            etag: etag.into(),
+            attributes: Attributes::default(),
+            generated: false,
         }
     }
-    root.join(p)
+
+    /// Mark content as generated on request (compressed, transformed, rendered), so that it's
+    /// always served whole
+    pub fn generated(mut self) -> Self {
+        self.generated = true;
+        self
+    }
 }
 
-/// Given file path, return file and some information about this file
+/// Given entry, return reader and some information about this entry
 pub(crate) fn metadata(
-    path: &Path,
-) -> TSFResult<(File, Mime, u64, SystemTime, String, ContentDisposition)> {
-    let mime = mime_guess::guess_mime_type(&path);
-    let file = File::open(path)?;
-    let meta = file.metadata()?;
-    let size = meta.len();
-    let last_modify = meta.modified()?;
-
-    let etag = format!(
-        "{:x}-{:x}",
-        last_modify
-            .duration_since(::std::time::UNIX_EPOCH)?
-            .as_secs(),
-        size
-    );
-
-    let disposition = ContentDisposition {
-        ty: match mime.type_() {
-            mime::IMAGE | mime::TEXT | mime::VIDEO => DispositionType::Inline,
-            _ => DispositionType::Attachment,
-        },
-        filename: path
-            .file_name()
-            .and_then(|x| x.to_os_string().into_string().ok()),
-    };
+    target: &Target,
+    etag_mode: &EtagMode,
+    hash_cache: &HashCache,
+) -> TSFResult<FileInfo> {
+    let path = target.path();
+    let mime = media_mime(path).unwrap_or_else(|| mime_guess::guess_mime_type(path));
+    let (file, meta, attributes) = target.open()?;
+    let size = meta.size;
+    let last_modify = meta.modified;
+
+    let etag = etag(etag_mode, hash_cache, target, &meta)?;
+    let disposition = ContentDisposition::new(&mime, path);
+
+    Ok(FileInfo {
+        file,
+        path: path.to_path_buf(),
+        mime,
+        size,
+        modified: last_modify,
+        etag,
+        disposition,
+        attributes,
+        generated: false,
+    })
+}
 
-    Ok((file, mime, size, last_modify, etag, disposition))
+/// `Last-Modified` and `ETag` as sent for an entry
+///
+/// A modification time in the future (clock skew, extracted archives) is no valid
+/// `Last-Modified`, with `clamp` on it's replaced by `now` and the entity tag made weak, as the
+/// content may change again before its time.
+///
+/// ref: https://tools.ietf.org/html/rfc7232#section-2.2.1
+pub(crate) fn validators(
+    clamp: bool,
+    modified: SystemTime,
+    etag: &str,
+    now: SystemTime,
+) -> (SystemTime, String) {
+    if clamp && modified > now {
+        (now, format!("W/{}", etag))
+    } else {
+        (modified, etag.to_string())
+    }
 }
 
 pub enum DispositionType {
@@ -116,9 +252,26 @@ impl Display for DispositionType {
 }
 
 // TODO unit test
-pub(crate) struct ContentDisposition {
-    ty: DispositionType,
-    filename: Option<String>,
+pub struct ContentDisposition {
+    pub ty: DispositionType,
+    pub filename: Option<String>,
+}
+
+impl ContentDisposition {
+    /// Images, text, videos and stream playlists inline, anything else as attachment, named
+    /// after `path`
+    pub fn new(mime: &Mime, path: &Path) -> Self {
+        Self {
+            ty: match mime.type_() {
+                mime::IMAGE | mime::TEXT | mime::VIDEO => DispositionType::Inline,
+                _ if media_kind(path).is_some() => DispositionType::Inline,
+                _ => DispositionType::Attachment,
+            },
+            filename: path
+                .file_name()
+                .and_then(|x| x.to_os_string().into_string().ok()),
+        }
+    }
 }
 
 impl Display for ContentDisposition {
@@ -135,6 +288,16 @@ impl Display for ContentDisposition {
     }
 }
 
+/// Whether `Range` header value is of unit `bytes`, the only unit supported
+///
+/// Units are case-insensitive, ref: https://tools.ietf.org/html/rfc7233#section-2
+pub(crate) fn is_bytes_unit(range: &str) -> bool {
+    match range.find('=') {
+        Some(index) => range[..index].trim().eq_ignore_ascii_case("bytes"),
+        None => false,
+    }
+}
+
 /// Convert range in header to range in file
 ///
 /// # Example
@@ -159,7 +322,8 @@ pub(crate) fn actual_range(byte_range: ByteRange, file_size: u64) -> Option<Rang
             if start <= end && start < file_size {
                 Some(Range {
                     start,
-                    end: min(file_size, end + 1),
+                    // `end` may be `u64::max_value()`
+                    end: min(file_size, end.saturating_add(1)),
                 })
             } else {
                 None
@@ -216,6 +380,62 @@ pub(super) fn u64_width(x: u64) -> usize {
     NUMBERS.iter().position(|limit| *limit > x).unwrap_or(19) + 1
 }
 
+/// Decision on a syntactically valid `Range` header, ref: https://tools.ietf.org/html/rfc7233#section-4.4
+#[derive(Debug, Eq, PartialEq)]
+pub enum RangeDecision {
+    /// no range selects anything, `416 Range Not Satisfiable`
+    Unsatisfiable,
+    /// ranges select the whole file, or satisfiable but select nothing (suffix range of an
+    /// empty file), serve whole file with `200 OK`
+    Whole,
+    /// sorted and disjoint ranges to serve with `206 Partial Content`, at least one
+    Partial(Vec<Range<u64>>),
+}
+
+/// Most ranges of a `Range` header served as such, whole file is served for more
+///
+/// Bounds the work of resolving ranges and planning multipart responses per request.
+/// ref: https://tools.ietf.org/html/rfc7233#section-6.1
+pub(crate) const MAX_RANGES: usize = 100;
+
+/// Resolve, clamp and merge ranges of header against file size
+///
+/// + more than [`MAX_RANGES`] ranges are ignored, i.e. the whole file is served
+/// + `bytes=-0` selects nothing, so it's unsatisfiable on its own
+/// + a range set is unsatisfiable only if no range in it is satisfiable
+/// + a suffix range of non-zero length is satisfiable even for an empty file, which has no
+///   byte to send, so the (empty) whole file is served
+/// + ranges selecting the whole file are served as such, except a single suffix range longer
+///   than the file, which is answered `206` with `Content-Range: bytes 0-(size-1)/size` as some
+///   CDN validators require
+pub(crate) fn decide_ranges(ranges: Vec<ByteRange>, file_size: u64) -> RangeDecision {
+    if ranges.len() > MAX_RANGES {
+        return RangeDecision::Whole;
+    }
+    let empty_suffix = file_size == 0
+        && ranges.iter().any(|x| match x {
+            ByteRange::Last(length) => *length > 0,
+            _ => false,
+        });
+    let single_suffix = match ranges.as_slice() {
+        [ByteRange::Last(_)] => true,
+        _ => false,
+    };
+    let ranges = ranges
+        .into_iter()
+        .flat_map(|x| actual_range(x, file_size))
+        .collect();
+    let ranges = merge_ranges(ranges);
+    match ranges.as_slice() {
+        [] if empty_suffix => RangeDecision::Whole,
+        [] => RangeDecision::Unsatisfiable,
+        [range] if range.start == 0 && range.end == file_size && !single_suffix => {
+            RangeDecision::Whole
+        }
+        _ => RangeDecision::Partial(ranges),
+    }
+}
+
 pub(crate) fn merge_ranges(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
     // XXX less memory allocation?
     ranges.sort_by_cached_key(|x| x.start);
@@ -232,10 +452,64 @@ pub(crate) fn merge_ranges(mut ranges: Vec<Range<u64>>) -> Vec<Range<u64>> {
     result
 }
 
+/// Empty directory of tests below the system's temporary one, named after `name` and this
+/// process, removed with its content on drop
+#[cfg(test)]
+pub(crate) struct TempDir(PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("tide-static-file-{}-{}", name, std::process::id()));
+        // left by an aborted run
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::mem::size_of;
+    use std::{mem::size_of, time::Duration};
+
+    #[test]
+    fn test_validators() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(100);
+        let past = now - Duration::from_secs(1);
+        let future = now + Duration::from_secs(1);
+        assert_eq!((past, "a".to_string()), validators(true, past, "a", now));
+        assert_eq!((now, "a".to_string()), validators(true, now, "a", now));
+        assert_eq!((now, "W/a".to_string()), validators(true, future, "a", now));
+        assert_eq!(
+            (future, "a".to_string()),
+            validators(false, future, "a", now)
+        );
+    }
 
     #[test]
     fn test_merge_range() {
@@ -264,33 +538,6 @@ mod tests {
     fn test_constraints() {
         assert!(size_of::<usize>() <= size_of::<u64>());
         assert!(size_of::<usize>() >= size_of::<u32>());
-        assert_eq!(
-            MULTI_RANGE_CONTENT_TYPE,
-            format!("multipart/byteranges; boundary={}", BOUNDARY)
-        );
-    }
-
-    #[test]
-    fn test_resolve_path() {
-        let base_dir = &PathBuf::from("/virtual");
-        assert_eq!(resolve_path(base_dir, "foo"), PathBuf::from("/virtual/foo"));
-        assert_eq!(
-            resolve_path(base_dir, "/foo"),
-            PathBuf::from("/virtual/foo")
-        );
-        assert_eq!(
-            resolve_path(base_dir, "////foo"),
-            PathBuf::from("/virtual/foo")
-        );
-        assert_eq!(
-            resolve_path(base_dir, "../foo"),
-            PathBuf::from("/virtual/foo")
-        );
-        assert_eq!(resolve_path(base_dir, "foo/.."), PathBuf::from("/virtual"));
-        assert_eq!(
-            resolve_path(base_dir, "foo/../other"),
-            PathBuf::from("/virtual/other")
-        );
     }
 
     #[test]
@@ -326,6 +573,120 @@ mod tests {
             }),
             actual_range(ByteRange::Last(100), 200)
         );
+
+        let max = u64::max_value();
+        assert_eq!(Some(0..10), actual_range(ByteRange::FromToAll(0, max), 10));
+        assert_eq!(
+            Some(0..max),
+            actual_range(ByteRange::FromToAll(0, max), max)
+        );
+        assert_eq!(None, actual_range(ByteRange::FromToAll(max, max), max));
+        assert_eq!(Some(0..max), actual_range(ByteRange::Last(max), max));
+    }
+
+    #[test]
+    fn test_decide_ranges() {
+        use ByteRange::*;
+        use RangeDecision::*;
+
+        assert_eq!(Unsatisfiable, decide_ranges(vec![Last(0)], 10));
+        assert_eq!(
+            Partial(vec![5..10]),
+            decide_ranges(vec![Last(0), FromTo(5)], 10)
+        );
+        assert_eq!(
+            Unsatisfiable,
+            decide_ranges(vec![FromTo(10), FromToAll(20, 30)], 10)
+        );
+        assert_eq!(Whole, decide_ranges(vec![FromToAll(0, 4), FromTo(5)], 10));
+        assert_eq!(Partial(vec![0..10]), decide_ranges(vec![Last(20)], 10));
+        assert_eq!(Partial(vec![0..10]), decide_ranges(vec![Last(10)], 10));
+        assert_eq!(Whole, decide_ranges(vec![Last(20), FromTo(0)], 10));
+        assert_eq!(
+            Partial(vec![0..1, 9..10]),
+            decide_ranges(vec![FromToAll(0, 0), Last(1)], 10)
+        );
+
+        // empty file
+        assert_eq!(Unsatisfiable, decide_ranges(vec![FromToAll(0, 0)], 0));
+        assert_eq!(Unsatisfiable, decide_ranges(vec![FromTo(0), Last(0)], 0));
+        assert_eq!(Whole, decide_ranges(vec![Last(5)], 0));
+
+        let many: Vec<_> = (0..=MAX_RANGES as u64)
+            .map(|x| FromToAll(x * 2, x * 2))
+            .collect();
+        assert_eq!(Whole, decide_ranges(many, 1000));
+        let many = (0..MAX_RANGES as u64)
+            .map(|x| FromToAll(x * 2, x * 2))
+            .collect();
+        match decide_ranges(many, 1000) {
+            Partial(x) => assert_eq!(MAX_RANGES, x.len()),
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+
+    #[test]
+    fn test_encode_href() {
+        assert_eq!("a/b.txt", encode_href("a/b.txt"));
+        assert_eq!("read%20me.txt", encode_href("read me.txt"));
+        assert_eq!("a%23b/c%3Fd", encode_href("a#b/c?d"));
+        assert_eq!("100%25.txt", encode_href("100%.txt"));
+        assert_eq!("%F0%9F%A6%80.png", encode_href("🦀.png"));
+        assert_eq!("%E4%BD%A0%E5%A5%BD", encode_href("你好"));
+    }
+
+    #[test]
+    fn test_content_location() {
+        assert_eq!(
+            Some("/static/app.3f9ab2.js".to_string()),
+            content_location("/static/app.js", "app.js", "app.3f9ab2.js")
+        );
+        assert_eq!(
+            Some("/css/app%20v2.1d2c3b.css".to_string()),
+            content_location("/css/app.css", "css/app.css", "/css/app v2.1d2c3b.css")
+        );
+        assert_eq!(
+            None,
+            content_location("/static/app%2Ejs", "app.js", "app.3f9ab2.js")
+        );
+    }
+
+    #[test]
+    fn test_range_header() {
+        fn with_units(values: &[&str], units: RangeUnitPolicy) -> Option<String> {
+            let mut request = http::Request::builder();
+            for value in values {
+                request.header(header::RANGE, *value);
+            }
+            range_header(&request.body(http_service::Body::empty()).unwrap(), units)
+        }
+        let combined = |values: &[&str]| with_units(values, RangeUnitPolicy::Reject);
+
+        assert_eq!(None, combined(&[]));
+        assert_eq!(Some("bytes=0-1".to_string()), combined(&["bytes=0-1"]));
+        assert_eq!(
+            Some("bytes=0-1,4-5, -2".to_string()),
+            combined(&["bytes=0-1", "Bytes=4-5, -2"])
+        );
+        assert_eq!(
+            Some("bytes=0-1,lines=1-2".to_string()),
+            combined(&["bytes=0-1", "lines=1-2"])
+        );
+        assert_eq!(
+            Some("bytes=0-1".to_string()),
+            with_units(&["lines=1-2", "bytes=0-1"], RangeUnitPolicy::Ignore)
+        );
+        assert_eq!(None, with_units(&["lines=1-2"], RangeUnitPolicy::Ignore));
+    }
+
+    #[test]
+    fn test_is_bytes_unit() {
+        assert!(is_bytes_unit("bytes=0-1"));
+        assert!(is_bytes_unit(" Bytes = 0-1"));
+        assert!(is_bytes_unit("bytes=garbage"));
+        assert!(!is_bytes_unit("lines=1-2"));
+        assert!(!is_bytes_unit("bytes"));
+        assert!(!is_bytes_unit(""));
     }
 
     #[test]
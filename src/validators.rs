@@ -0,0 +1,115 @@
+//! Tokenizer of entity tag lists in conditional request headers (`If-Match`, `If-None-Match`,
+//! `If-Range`)
+//!
+//! Lenient by design: whitespace around tags, empty list elements (e.g. trailing commas) and a
+//! lower case weakness prefix `w/` are tolerated, and quotes are optional since this crate sends
+//! unquoted tags.
+
+/// An entity tag of a request header
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub(crate) struct EntityTag<'a> {
+    pub weak: bool,
+    /// tag without quotes
+    pub tag: &'a str,
+}
+
+impl<'a> EntityTag<'a> {
+    pub fn parse(value: &'a str) -> Self {
+        let value = value.trim();
+        let (weak, value) = if value.starts_with("W/") || value.starts_with("w/") {
+            (true, value[2..].trim_start())
+        } else {
+            (false, value)
+        };
+        let tag = if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            &value[1..value.len() - 1]
+        } else {
+            value
+        };
+        Self { weak, tag }
+    }
+
+    /// ref: https://tools.ietf.org/html/rfc7232#section-2.3.2
+    pub fn strong_eq(&self, etag: &str) -> bool {
+        !self.weak && self.tag == etag
+    }
+
+    /// ref: https://tools.ietf.org/html/rfc7232#section-2.3.2
+    pub fn weak_eq(&self, etag: &str) -> bool {
+        self.tag == etag
+    }
+}
+
+/// Iterate tags of a comma separated list, commas inside quotes don't separate
+pub(crate) fn entity_tags(list: &str) -> EntityTags<'_> {
+    EntityTags { rest: list }
+}
+
+pub(crate) struct EntityTags<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for EntityTags<'a> {
+    type Item = EntityTag<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.rest.is_empty() {
+                return None;
+            }
+            let mut quoted = false;
+            let end = self
+                .rest
+                .char_indices()
+                .find(|&(_, c)| {
+                    if c == '"' {
+                        quoted = !quoted;
+                    }
+                    c == ',' && !quoted
+                })
+                .map(|(index, _)| index);
+            let item = match end {
+                Some(end) => {
+                    let item = &self.rest[..end];
+                    self.rest = &self.rest[end + 1..];
+                    item
+                }
+                None => std::mem::replace(&mut self.rest, ""),
+            };
+            if !item.trim().is_empty() {
+                return Some(EntityTag::parse(item));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(list: &str) -> Vec<(bool, &str)> {
+        entity_tags(list).map(|x| (x.weak, x.tag)).collect()
+    }
+
+    #[test]
+    fn test_entity_tags() {
+        assert_eq!(vec![(false, "a")], tags("a"));
+        assert_eq!(vec![(false, "a"), (true, "b")], tags(" a ,W/b"));
+        assert_eq!(vec![(false, "a"), (true, "b")], tags(",\"a\",, w/ \"b\" ,"));
+        assert_eq!(vec![(false, "a,b"), (false, "c")], tags("\"a,b\", c"));
+        assert_eq!(vec![(false, "\"")], tags("\""));
+        assert!(tags(" , ,").is_empty());
+        assert!(tags("").is_empty());
+    }
+
+    #[test]
+    fn test_comparison() {
+        let weak = EntityTag::parse("W/\"a\"");
+        assert!(weak.weak_eq("a"));
+        assert!(!weak.strong_eq("a"));
+        let strong = EntityTag::parse("a");
+        assert!(strong.weak_eq("a"));
+        assert!(strong.strong_eq("a"));
+        assert!(!strong.strong_eq("b"));
+    }
+}
@@ -0,0 +1,143 @@
+//! Pre-generated variants of files selected by query or client hint, e.g. thumbnails of images
+//!
+//! Nothing is generated here, a variant is served if it exists, the original otherwise.
+
+use http::HeaderMap;
+use std::collections::BTreeSet;
+
+/// Client hint of the layout width of an image in device pixels, sent by browsers to sites
+/// opting in with `Accept-CH: Sec-CH-Width`
+pub(crate) const WIDTH_HINT: &str = "sec-ch-width";
+
+/// Map a request for a variant of a file to the logical path of that variant
+pub trait VariantResolver: Send + Sync {
+    /// `path` is the logical path of the requested file (e.g. `photos/cat.jpg`), `query` the
+    /// raw query string (empty if none) and `hints` the request headers named by
+    /// [`vary`](#method.vary), return `None` if no variant is requested
+    fn resolve(&self, path: &str, query: &str, hints: &HeaderMap) -> Option<String>;
+
+    /// Request header the choice depends on besides the query, for `Vary`, `None` by default
+    fn vary(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+/// Width variants of images: `photos/cat.jpg?w=320` is `photos/cat.w320.jpg`
+///
+/// Without `w`, the smallest width covering client hint `Sec-CH-Width` is chosen, so responses
+/// carry `Vary: sec-ch-width`. Only given widths are looked up, so that arbitrary queries don't
+/// probe the file system.
+#[derive(Debug, Clone)]
+pub struct WidthVariants {
+    widths: BTreeSet<u32>,
+}
+
+impl WidthVariants {
+    pub fn new(widths: impl IntoIterator<Item = u32>) -> Self {
+        Self {
+            widths: widths.into_iter().collect(),
+        }
+    }
+
+    /// Allowed width requested by `w` of query
+    pub(crate) fn width(&self, query: &str) -> Option<u32> {
+        query
+            .split('&')
+            .filter_map(|x| {
+                let mut parts = x.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some("w"), Some(width)) => Some(width),
+                    _ => None,
+                }
+            })
+            .next()?
+            .parse()
+            .ok()
+            .filter(|x| self.widths.contains(x))
+    }
+
+    /// Allowed width requested by `w` of query, or else the smallest covering the width hint
+    pub(crate) fn requested(&self, query: &str, hints: &HeaderMap) -> Option<u32> {
+        self.width(query).or_else(|| {
+            let hint: u32 = hints.get(WIDTH_HINT)?.to_str().ok()?.trim().parse().ok()?;
+            self.widths.range(hint..).next().cloned()
+        })
+    }
+}
+
+/// Logical path of variant of given width, `photos/cat.jpg` as `photos/cat.w320.jpg`
+pub(crate) fn width_variant(path: &str, width: u32) -> String {
+    let (directory, name) = match path.rfind('/') {
+        Some(index) => path.split_at(index + 1),
+        None => ("", path),
+    };
+    let (stem, extension) = match name.rfind('.') {
+        Some(index) if index > 0 => name.split_at(index),
+        _ => (name, ""),
+    };
+    format!("{}{}.w{}{}", directory, stem, width, extension)
+}
+
+impl VariantResolver for WidthVariants {
+    fn resolve(&self, path: &str, query: &str, hints: &HeaderMap) -> Option<String> {
+        self.requested(query, hints).map(|x| width_variant(path, x))
+    }
+
+    fn vary(&self) -> Option<&'static str> {
+        Some(WIDTH_HINT)
+    }
+}
+
+/// Outcome of looking up the variant requested by a query
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum Variant {
+    /// no variant requested
+    None,
+    /// logical path of the existing variant
+    Found(String),
+    /// requested variant doesn't exist (yet), the original is served
+    Missing,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_width_variants() {
+        let variants = WidthVariants::new(vec![320, 640]);
+        let none = HeaderMap::new();
+        assert_eq!(
+            Some("photos/cat.w320.jpg".to_string()),
+            variants.resolve("photos/cat.jpg", "w=320", &none)
+        );
+        assert_eq!(
+            Some("cat.w640".to_string()),
+            variants.resolve("cat", "v=2&w=640", &none)
+        );
+        assert_eq!(
+            Some(".hidden.w320".to_string()),
+            variants.resolve(".hidden", "w=320", &none)
+        );
+        assert_eq!(None, variants.resolve("cat.jpg", "w=100", &none));
+        assert_eq!(None, variants.resolve("cat.jpg", "w=abc", &none));
+        assert_eq!(None, variants.resolve("cat.jpg", "width=320", &none));
+        assert_eq!(None, variants.resolve("cat.jpg", "", &none));
+
+        let mut hints = HeaderMap::new();
+        hints.insert(WIDTH_HINT, "300".parse().unwrap());
+        assert_eq!(
+            Some("cat.w320.jpg".to_string()),
+            variants.resolve("cat.jpg", "", &hints)
+        );
+        assert_eq!(
+            Some("cat.w640.jpg".to_string()),
+            variants.resolve("cat.jpg", "w=640", &hints)
+        );
+        hints.insert(WIDTH_HINT, "320".parse().unwrap());
+        assert_eq!(Some(320), variants.requested("", &hints));
+        hints.insert(WIDTH_HINT, "1000".parse().unwrap());
+        assert_eq!(None, variants.resolve("cat.jpg", "", &hints));
+        assert_eq!(Some("sec-ch-width"), variants.vary());
+    }
+}
@@ -0,0 +1,346 @@
+use crate::error::TSFResult;
+use log::warn;
+use std::{
+    collections::HashMap,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, ErrorKind, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+};
+
+const INDEX: &str = "index.log";
+
+/// Directory keeping local copies of remote entries, within a size budget
+///
+/// Least recently used entries are removed when the budget is exceeded. Usage is recorded in
+/// an append-only index inside the directory, which is replayed and checked against the
+/// directory content when opened, so it survives crashes: entries which are missing or
+/// truncated are forgotten, and files unknown to the index (e.g. partial downloads) are removed.
+pub struct CacheDir {
+    dir: PathBuf,
+    max_size: u64,
+    state: Mutex<State>,
+}
+
+struct State {
+    entries: HashMap<String, Entry>,
+    /// incremented on every use, for LRU ordering
+    clock: u64,
+    total_size: u64,
+    index: BufWriter<File>,
+    /// lines in index, compacted when far more than entries
+    index_lines: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    size: u64,
+    last_used: u64,
+}
+
+impl CacheDir {
+    /// Open (or create) cache directory, keep total size of entries under `max_size` bytes
+    pub fn open(dir: impl AsRef<Path>, max_size: u64) -> TSFResult<Self> {
+        fs::create_dir_all(dir.as_ref())?;
+        let dir = dir.as_ref().canonicalize()?;
+
+        let mut entries = HashMap::new();
+        let mut clock = 0;
+        if let Ok(file) = File::open(dir.join(INDEX)) {
+            for line in BufReader::new(file).lines() {
+                // a torn last line is harmless, entries are checked against the directory below
+                let line = match line {
+                    Ok(x) => x,
+                    Err(_) => break,
+                };
+                clock += 1;
+                replay(&mut entries, &line, clock);
+            }
+        }
+
+        // trust the directory over the index
+        entries.retain(|name, entry| {
+            fs::metadata(dir.join(name))
+                .map(|x| x.is_file() && x.len() == entry.size)
+                .unwrap_or(false)
+        });
+        for item in fs::read_dir(&dir)? {
+            let item = item?;
+            let name = item.file_name();
+            let known = name
+                .to_str()
+                .map(|x| x == INDEX || entries.contains_key(x))
+                .unwrap_or(false);
+            if !known && item.file_type()?.is_file() {
+                fs::remove_file(item.path())?;
+            }
+        }
+
+        let total_size = entries.values().map(|x| x.size).sum();
+        let index = write_index(&dir, &entries)?;
+        let cache = Self {
+            dir,
+            max_size,
+            state: Mutex::new(State {
+                index_lines: entries.len(),
+                entries,
+                clock,
+                total_size,
+                index,
+            }),
+        };
+        cache.evict(&mut cache.state.lock().unwrap(), None)?;
+        Ok(cache)
+    }
+
+    /// Total size of cached entries
+    pub fn size(&self) -> u64 {
+        self.state.lock().unwrap().total_size
+    }
+
+    /// Open cached entry with given name, and mark it as recently used
+    pub fn get(&self, name: &str) -> Option<File> {
+        let mut state = self.state.lock().unwrap();
+        state.entries.get(name)?;
+        let file = match File::open(self.dir.join(name)) {
+            Ok(x) => x,
+            Err(_) => {
+                let _ = state.remove(&self.dir, name);
+                return None;
+            }
+        };
+        state.clock += 1;
+        let clock = state.clock;
+        state.entries.get_mut(name).unwrap().last_used = clock;
+        if let Err(error) = state
+            .append(&format!("use {}", name))
+            .and_then(|_| self.compact(&mut state))
+        {
+            warn!("failed to update cache index: {}", error);
+        }
+        Some(file)
+    }
+
+    /// Add an entry with content written by `write`, then return it opened for reading
+    ///
+    /// Content is written to a temporary file and moved in place when complete, so that
+    /// readers never see partial content. `name` must be a plain file name without whitespace,
+    /// e.g. a hex digest.
+    pub fn insert(
+        &self,
+        name: &str,
+        write: impl FnOnce(&mut File) -> io::Result<()>,
+    ) -> io::Result<File> {
+        if name.is_empty()
+            || name == INDEX
+            || name.starts_with('.')
+            || name.contains(|x: char| x.is_whitespace() || x == '/' || x == '\\')
+        {
+            return Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid entry name",
+            ));
+        }
+
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let temp_path = self.dir.join(format!(
+            ".{}-{}-{}.part",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let result = File::create(&temp_path).and_then(|mut file| {
+            write(&mut file)?;
+            file.sync_all()
+        });
+        if let Err(error) = result {
+            let _ = fs::remove_file(&temp_path);
+            return Err(error);
+        }
+
+        let path = self.dir.join(name);
+        let mut state = self.state.lock().unwrap();
+        fs::rename(&temp_path, &path)?;
+        let file = File::open(&path)?;
+        let size = file.metadata()?.len();
+        if let Some(old) = state.entries.remove(name) {
+            state.total_size -= old.size;
+        }
+        state.clock += 1;
+        let entry = Entry {
+            size,
+            last_used: state.clock,
+        };
+        state.entries.insert(name.to_string(), entry);
+        state.total_size += size;
+        state.append(&format!("put {} {}", name, size))?;
+        self.evict(&mut state, Some(name))?;
+        Ok(file)
+    }
+
+    /// Remove least recently used entries until within budget, `keep` is removed last
+    fn evict(&self, state: &mut State, keep: Option<&str>) -> io::Result<()> {
+        while state.total_size > self.max_size {
+            let victim = state
+                .entries
+                .iter()
+                .min_by_key(|(name, entry)| (Some(name.as_str()) == keep, entry.last_used))
+                .map(|(name, _)| name.clone());
+            match victim {
+                Some(name) => state.remove(&self.dir, &name)?,
+                None => break,
+            }
+        }
+        self.compact(state)
+    }
+
+    /// Rewrite index if it's mostly history
+    fn compact(&self, state: &mut State) -> io::Result<()> {
+        if state.index_lines > 1024 && state.index_lines > state.entries.len() * 4 {
+            state.index = write_index(&self.dir, &state.entries)?;
+            state.index_lines = state.entries.len();
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    fn append(&mut self, line: &str) -> io::Result<()> {
+        self.index_lines += 1;
+        writeln!(self.index, "{}", line)?;
+        self.index.flush()
+    }
+
+    fn remove(&mut self, dir: &Path, name: &str) -> io::Result<()> {
+        if let Some(entry) = self.entries.remove(name) {
+            self.total_size -= entry.size;
+        }
+        // removed from index first, a leftover file is cleaned up on next open
+        self.append(&format!("del {}", name))?;
+        if let Err(error) = fs::remove_file(dir.join(name)) {
+            if error.kind() != ErrorKind::NotFound {
+                warn!("failed to remove cache entry {}: {}", name, error);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn replay(entries: &mut HashMap<String, Entry>, line: &str, clock: u64) {
+    let mut parts = line.split(' ');
+    match (parts.next(), parts.next(), parts.next()) {
+        (Some("put"), Some(name), Some(size)) => {
+            if let Ok(size) = size.parse() {
+                let entry = Entry {
+                    size,
+                    last_used: clock,
+                };
+                entries.insert(name.to_string(), entry);
+            }
+        }
+        (Some("use"), Some(name), None) => {
+            if let Some(entry) = entries.get_mut(name) {
+                entry.last_used = clock;
+            }
+        }
+        (Some("del"), Some(name), None) => {
+            entries.remove(name);
+        }
+        _ => {}
+    }
+}
+
+/// Atomically replace index by a compact one, in LRU order, and return it opened for appending
+fn write_index(dir: &Path, entries: &HashMap<String, Entry>) -> io::Result<BufWriter<File>> {
+    let mut sorted: Vec<_> = entries.iter().collect();
+    sorted.sort_by_key(|(_, entry)| entry.last_used);
+
+    let temp_path = dir.join(format!(".{}.part", INDEX));
+    let mut temp = BufWriter::new(File::create(&temp_path)?);
+    for (name, entry) in sorted {
+        writeln!(temp, "put {} {}", name, entry.size)?;
+    }
+    temp.into_inner().map_err(|x| x.into_error())?.sync_all()?;
+    fs::rename(&temp_path, dir.join(INDEX))?;
+
+    let index = OpenOptions::new().append(true).open(dir.join(INDEX))?;
+    Ok(BufWriter::new(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::io::Read;
+
+    fn insert(cache: &CacheDir, name: &str, size: usize) {
+        cache
+            .insert(name, |file| file.write_all(&vec![b'x'; size]))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_lru_eviction() {
+        let dir = TempDir::new("lru");
+        let cache = CacheDir::open(&dir, 100).unwrap();
+        insert(&cache, "a", 40);
+        insert(&cache, "b", 40);
+        assert!(cache.get("a").is_some());
+        insert(&cache, "c", 40);
+
+        // "b" is the least recently used
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("c").is_some());
+        assert!(!dir.join("b").exists());
+        assert_eq!(80, cache.size());
+
+        let mut content = String::new();
+        cache
+            .get("c")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(40, content.len());
+
+        assert!(cache.insert("../x", |_| Ok(())).is_err());
+        assert!(cache.insert(INDEX, |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_recovery() {
+        let dir = TempDir::new("recovery");
+        {
+            let cache = CacheDir::open(&dir, 1000).unwrap();
+            insert(&cache, "a", 10);
+            insert(&cache, "b", 20);
+            insert(&cache, "c", 30);
+            assert!(cache.get("a").is_some());
+        }
+
+        // simulate a crash: torn index line, partial download, truncated entry
+        let mut index = OpenOptions::new()
+            .append(true)
+            .open(dir.join(INDEX))
+            .unwrap();
+        index.write_all(b"put d 4").unwrap();
+        fs::write(dir.join(".e-1-1.part"), b"partial").unwrap();
+        fs::write(dir.join("c"), b"short").unwrap();
+
+        let cache = CacheDir::open(&dir, 1000).unwrap();
+        assert_eq!(30, cache.size());
+        assert!(cache.get("c").is_none());
+        assert!(cache.get("d").is_none());
+        assert!(!dir.join(".e-1-1.part").exists());
+        assert!(!dir.join("c").exists());
+
+        // LRU order survives reopening, "b" is older than "a"
+        let cache = CacheDir::open(&dir, 25).unwrap();
+        assert_eq!(10, cache.size());
+        assert!(cache.get("a").is_some());
+        assert!(cache.get("b").is_none());
+    }
+}
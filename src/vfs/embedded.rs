@@ -0,0 +1,108 @@
+use crate::{
+    path::normalize_path,
+    vfs::{Metadata, Reader, Vfs},
+};
+use std::{
+    collections::HashMap,
+    io::{self, Cursor, ErrorKind},
+    time::SystemTime,
+};
+
+/// Serve files compiled into the binary, e.g. by `include_bytes!`
+///
+/// Embedded files have no modification time, their `ETag` is synthesized from content hash,
+/// and `Last-Modified` is the time this value was created unless given by
+/// [`modified`](#method.modified).
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::{StaticFiles, vfs::Embedded};
+/// let embedded = Embedded::new()
+///     .file("index.html", b"<h1>Hello</h1>")
+///     .file("css/site.css", b"h1 { color: red }");
+/// let files = StaticFiles::from_backend(embedded);
+/// ```
+#[derive(Clone)]
+pub struct Embedded {
+    files: HashMap<String, (&'static [u8], Metadata)>,
+    modified: SystemTime,
+}
+
+impl Embedded {
+    pub fn new() -> Self {
+        Self {
+            files: HashMap::new(),
+            modified: SystemTime::now(),
+        }
+    }
+
+    /// Add a file at given path (relative to root), panic if path escapes the root
+    pub fn file(mut self, path: &str, content: &'static [u8]) -> Self {
+        let path = normalize_path(path).expect("invalid path of embedded file");
+        let metadata = Metadata::from_content(content, self.modified);
+        self.files.insert(path, (content, metadata));
+        self
+    }
+
+    /// Set `Last-Modified` of all files, e.g. build time
+    pub fn modified(mut self, modified: SystemTime) -> Self {
+        self.modified = modified;
+        for (_, metadata) in self.files.values_mut() {
+            metadata.modified = modified;
+        }
+        self
+    }
+}
+
+impl Default for Embedded {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Vfs for Embedded {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        match self.files.get(path) {
+            Some((_, metadata)) => Ok(metadata.clone()),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)> {
+        match self.files.get(path) {
+            Some((content, metadata)) => Ok((Box::new(Cursor::new(*content)), metadata.clone())),
+            None => Err(ErrorKind::NotFound.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Read, time::UNIX_EPOCH};
+
+    #[test]
+    fn test_embedded() {
+        let embedded = Embedded::new()
+            .file("/a/./b.txt", b"abc")
+            .modified(UNIX_EPOCH);
+
+        let metadata = embedded.metadata("a/b.txt").unwrap();
+        assert_eq!(3, metadata.size);
+        assert_eq!(UNIX_EPOCH, metadata.modified);
+        assert_eq!(Metadata::from_content(b"abc", UNIX_EPOCH), metadata);
+
+        let mut content = String::new();
+        embedded
+            .open("a/b.txt")
+            .unwrap()
+            .0
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!("abc", content);
+
+        let error = embedded.metadata("a").unwrap_err();
+        assert_eq!(ErrorKind::NotFound, error.kind());
+    }
+}
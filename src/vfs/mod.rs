@@ -0,0 +1,282 @@
+//! Backends serving entries from somewhere other than the local file system
+
+mod cache_dir;
+mod embedded;
+#[cfg(any(feature = "s3", feature = "upstream"))]
+mod remote;
+#[cfg(unix)]
+mod root_handle;
+#[cfg(feature = "s3")]
+mod s3;
+#[cfg(feature = "sqlar")]
+mod sqlar;
+mod synthetic;
+#[cfg(feature = "upstream")]
+mod upstream;
+
+#[cfg(unix)]
+pub use self::root_handle::RootHandle;
+#[cfg(feature = "s3")]
+pub use self::s3::S3Backend;
+#[cfg(feature = "sqlar")]
+pub use self::sqlar::SqlarBackend;
+#[cfg(feature = "upstream")]
+pub use self::upstream::UpstreamBackend;
+pub use self::{cache_dir::CacheDir, embedded::Embedded, synthetic::Synthetic};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io::{self, Read, Seek},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A source of entries to be served, e.g. an object storage bucket
+///
+/// Paths given to backends are logical paths produced by
+/// [`normalize_path`](../fn.normalize_path.html): decoded segments joined by `/`, never escaping
+/// the root. Methods are called from blocking context (the file read pool), so they
+/// may block.
+pub trait Vfs: Send + Sync + 'static {
+    /// Return metadata of entry at given logical path, `io::ErrorKind::NotFound` if no such entry
+    fn metadata(&self, path: &str) -> io::Result<Metadata>;
+
+    /// Open entry at given logical path for reading, with metadata of what was opened (e.g.
+    /// taken from the file descriptor), so that it's resolved once and can't change in between
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)>;
+}
+
+/// Metadata of an entry
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Metadata {
+    pub size: u64,
+    /// backends without modification time should synthesize a stable one
+    pub modified: SystemTime,
+    /// `ETag` given by backend (e.g. object storage), used instead of generated one
+    pub etag: Option<String>,
+}
+
+/// Optional attributes of an entry beyond validators, platform permitting, e.g. for listings
+///
+/// Only the local file system provides them.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Attributes {
+    /// creation (birth) time, unavailable on some file systems
+    pub created: Option<SystemTime>,
+    /// Unix permission bits, e.g. `0o644`
+    pub permissions: Option<u32>,
+    /// Unix user id of owner
+    pub owner: Option<u32>,
+}
+
+impl Attributes {
+    pub(crate) fn from_fs(meta: &std::fs::Metadata) -> Self {
+        #[cfg(unix)]
+        let (permissions, owner) = {
+            use std::os::unix::fs::MetadataExt;
+            (Some(meta.mode() & 0o7777), Some(meta.uid()))
+        };
+        #[cfg(not(unix))]
+        let (permissions, owner) = (None, None);
+        Self {
+            created: meta.created().ok(),
+            permissions,
+            owner,
+        }
+    }
+}
+
+impl Metadata {
+    /// Synthesize validators of an entry without modification time, e.g. compiled into the
+    /// binary: `ETag` from content hash, `Last-Modified` from given time (e.g. build time)
+    pub fn from_content(content: &[u8], modified: SystemTime) -> Self {
+        Self {
+            size: content.len() as u64,
+            modified,
+            etag: Some(format!("{:x}", Sha256::digest(content))),
+        }
+    }
+
+    /// Synthesize validators of an archive entry: `ETag` from its checksum (CRC-32 of zip, header
+    /// checksum of tar) and size, `Last-Modified` from its own modification time if recorded,
+    /// otherwise from the archive
+    pub fn from_archive_entry(
+        size: u64,
+        checksum: u32,
+        modified: Option<SystemTime>,
+        archive_modified: SystemTime,
+    ) -> Self {
+        Self {
+            size,
+            // HTTP dates have second precision only
+            modified: truncate_to_secs(modified.unwrap_or(archive_modified)),
+            etag: Some(format!("{:08x}-{:x}", checksum, size)),
+        }
+    }
+}
+
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(x) => UNIX_EPOCH + Duration::from_secs(x.as_secs()),
+        Err(_) => UNIX_EPOCH,
+    }
+}
+
+/// Readable and seekable content of an entry
+pub trait VfsRead: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> VfsRead for T {}
+
+pub type Reader = Box<dyn VfsRead>;
+
+/// Entry to be served, either a local file or an entry of a backend
+#[derive(Clone)]
+pub(crate) enum Target {
+    Local(PathBuf),
+    Backend(Arc<dyn Vfs>, String),
+}
+
+impl Target {
+    /// Path used to guess MIME and file name
+    pub fn path(&self) -> &Path {
+        match self {
+            Target::Local(path) => path,
+            Target::Backend(_, path) => Path::new(path),
+        }
+    }
+
+    /// Key identifying this entry in caches shared by several sources
+    pub fn cache_key(&self) -> (usize, PathBuf) {
+        match self {
+            Target::Local(path) => (0, path.clone()),
+            Target::Backend(backend, path) => (
+                &**backend as *const dyn Vfs as *const () as usize,
+                PathBuf::from(path),
+            ),
+        }
+    }
+
+    pub fn metadata(&self) -> io::Result<Metadata> {
+        match self {
+            Target::Local(path) => {
+                let meta = std::fs::metadata(path)?;
+                if !meta.is_file() {
+                    return Err(io::ErrorKind::NotFound.into());
+                }
+                local_metadata(&meta)
+            }
+            Target::Backend(backend, path) => backend.metadata(path),
+        }
+    }
+
+    /// Open entry, return reader, metadata and attributes of the opened entry
+    pub fn open(&self) -> io::Result<(Reader, Metadata, Attributes)> {
+        match self {
+            Target::Local(path) => {
+                let file = open_local(path)?;
+                let meta = file.metadata()?;
+                let metadata = local_metadata(&meta)?;
+                Ok((Box::new(file), metadata, Attributes::from_fs(&meta)))
+            }
+            Target::Backend(backend, path) => {
+                let (reader, metadata) = backend.open(path)?;
+                Ok((reader, metadata, Attributes::default()))
+            }
+        }
+    }
+}
+
+/// Open regular file at canonicalized `path`
+///
+/// The path was checked to be below the root when canonicalized, it may have been swapped for a
+/// symbolic link since. On Unix it's walked from `/` without following any, as a canonicalized
+/// path has none, so that what's opened is still below the root. Ancestors need search
+/// permission only on Linux, see [`root_handle::open_walked`].
+#[cfg(unix)]
+fn open_local(path: &Path) -> io::Result<File> {
+    use std::path::Component;
+    let segments: Vec<_> = path
+        .components()
+        .filter_map(|x| match x {
+            Component::Normal(x) => Some(x),
+            _ => None,
+        })
+        .collect();
+    self::root_handle::open_beneath(&self::root_handle::open_walked(Path::new("/"))?, &segments)
+}
+
+#[cfg(not(unix))]
+fn open_local(path: &Path) -> io::Result<File> {
+    let file = File::open(path)?;
+    if !file.metadata()?.is_file() {
+        return Err(io::ErrorKind::NotFound.into());
+    }
+    Ok(file)
+}
+
+fn local_metadata(meta: &std::fs::Metadata) -> io::Result<Metadata> {
+    Ok(Metadata {
+        size: meta.len(),
+        modified: meta.modified()?,
+        etag: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+
+    #[test]
+    fn test_synthesized_metadata() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_500);
+        let metadata = Metadata::from_content(b"abc", time);
+        assert_eq!(3, metadata.size);
+        assert_eq!(
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            metadata.etag.as_ref().map(String::as_str)
+        );
+
+        let metadata = Metadata::from_archive_entry(42, 0x3524_41c2, None, time);
+        assert_eq!(UNIX_EPOCH + Duration::from_secs(1), metadata.modified);
+        assert_eq!(
+            Some("352441c2-2a"),
+            metadata.etag.as_ref().map(String::as_str)
+        );
+        let metadata = Metadata::from_archive_entry(42, 0x3524_41c2, Some(UNIX_EPOCH), time);
+        assert_eq!(UNIX_EPOCH, metadata.modified);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_open_local() {
+        let temp = TempDir::new("local");
+        let root = temp.canonicalize().unwrap();
+        std::fs::create_dir_all(root.join("a")).unwrap();
+        std::fs::write(root.join("a/b.txt"), b"abc").unwrap();
+        let path = root.join("a/b.txt");
+        assert_eq!(3, open_local(&path).unwrap().metadata().unwrap().len());
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            open_local(&root.join("a")).unwrap_err().kind()
+        );
+
+        // swapped for a symbolic link after canonicalization
+        std::fs::rename(root.join("a"), root.join("c")).unwrap();
+        std::os::unix::fs::symlink(root.join("c"), root.join("a")).unwrap();
+        assert_eq!(
+            io::ErrorKind::NotFound,
+            open_local(&path).unwrap_err().kind()
+        );
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_attributes() {
+        let target = Target::Local(PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml"));
+        let (_, _, attributes) = target.open().unwrap();
+        assert!(attributes.permissions.unwrap() & 0o400 != 0);
+        assert!(attributes.owner.is_some());
+    }
+}
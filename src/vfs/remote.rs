@@ -0,0 +1,295 @@
+//! Helpers shared by backends talking HTTP to a remote service
+
+use crate::vfs::Metadata;
+use httpdate::HttpDate;
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Response, StatusCode,
+};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Upper bound of entries remembered by a `Revalidator`
+const MAX_REMEMBERED: usize = 65536;
+
+/// Read metadata from response of a `HEAD` request
+pub(crate) fn response_metadata(response: &Response) -> io::Result<Metadata> {
+    match response.status() {
+        StatusCode::OK => {}
+        StatusCode::NOT_FOUND | StatusCode::GONE => return Err(ErrorKind::NotFound.into()),
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+            return Err(ErrorKind::PermissionDenied.into());
+        }
+        status => return Err(to_io_error(format!("unexpected status: {}", status))),
+    }
+
+    let get = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .map(str::to_string)
+    };
+    let size = get(header::CONTENT_LENGTH)
+        .and_then(|x| x.parse().ok())
+        .ok_or_else(|| to_io_error("no valid Content-Length"))?;
+    let modified = get(header::LAST_MODIFIED)
+        .and_then(|x| x.parse::<HttpDate>().ok())
+        .map(SystemTime::from)
+        .unwrap_or(UNIX_EPOCH);
+    Ok(Metadata {
+        size,
+        modified,
+        etag: get(header::ETAG),
+    })
+}
+
+/// Remembers metadata of remote entries, and revalidates them by conditional requests
+/// once they are older than `max_age`
+///
+/// Upstream `304 Not Modified` keeps the remembered metadata, so that local copies named
+/// after it stay valid and nothing is transferred again.
+pub(crate) struct Revalidator {
+    max_age: Duration,
+    entries: Mutex<HashMap<String, (Metadata, Instant)>>,
+}
+
+impl Revalidator {
+    pub fn new(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Return metadata of entry at `path`, `head` sends a `HEAD` request with given
+    /// extra headers
+    pub fn metadata(
+        &self,
+        path: &str,
+        head: impl FnOnce(HeaderMap) -> io::Result<Response>,
+    ) -> io::Result<Metadata> {
+        let stale = match self.entries.lock().unwrap().get(path) {
+            Some((metadata, checked)) if checked.elapsed() < self.max_age => {
+                return Ok(metadata.clone());
+            }
+            Some((metadata, _)) => Some(metadata.clone()),
+            None => None,
+        };
+
+        let response = head(stale.as_ref().map(conditional_headers).unwrap_or_default())?;
+        let metadata = match stale {
+            Some(stale) if response.status() == StatusCode::NOT_MODIFIED => stale,
+            _ => match response_metadata(&response) {
+                Ok(x) => x,
+                Err(error) => {
+                    self.entries.lock().unwrap().remove(path);
+                    return Err(error);
+                }
+            },
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_REMEMBERED && !entries.contains_key(path) {
+            entries.clear();
+        }
+        entries.insert(path.to_string(), (metadata.clone(), Instant::now()));
+        Ok(metadata)
+    }
+}
+
+/// Validators of a remembered version as conditional request headers
+pub(crate) fn conditional_headers(metadata: &Metadata) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(etag) = metadata
+        .etag
+        .as_ref()
+        .and_then(|x| HeaderValue::from_str(x).ok())
+    {
+        headers.insert(header::IF_NONE_MATCH, etag);
+    }
+    if metadata.modified != UNIX_EPOCH {
+        let date = httpdate::fmt_http_date(metadata.modified);
+        headers.insert(header::IF_MODIFIED_SINCE, date.parse().unwrap());
+    }
+    headers
+}
+
+/// `Range` request headers, together with `If-Match` if `etag` is given
+pub(crate) fn range_headers(range: &str, etag: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Ok(range) = HeaderValue::from_str(range) {
+        headers.insert(header::RANGE, range);
+    }
+    if let Some(etag) = etag.and_then(|x| HeaderValue::from_str(x).ok()) {
+        headers.insert(header::IF_MATCH, etag);
+    }
+    headers
+}
+
+/// Remote entry opened for reading, every read is a ranged `GET` sent by `get` with given
+/// extra headers
+pub(crate) struct RemoteObject<F> {
+    get: F,
+    size: u64,
+    /// ensure all reads see the same version of entry
+    etag: Option<String>,
+    position: u64,
+}
+
+impl<F> RemoteObject<F>
+where
+    F: Fn(HeaderMap) -> io::Result<Response> + Send,
+{
+    pub fn new(get: F, metadata: Metadata) -> Self {
+        Self {
+            get,
+            size: metadata.size,
+            etag: metadata.etag,
+            position: 0,
+        }
+    }
+}
+
+impl<F> Read for RemoteObject<F>
+where
+    F: Fn(HeaderMap) -> io::Result<Response> + Send,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position >= self.size {
+            return Ok(0);
+        }
+        let end = min(self.size, self.position + buf.len() as u64);
+        let range = format!("bytes={}-{}", self.position, end - 1);
+        let etag = self.etag.as_ref().map(String::as_str);
+        let mut response = (self.get)(range_headers(&range, etag))?;
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {}
+            StatusCode::OK if self.position == 0 => {}
+            StatusCode::PRECONDITION_FAILED => {
+                return Err(to_io_error("entry changed while reading"));
+            }
+            status => return Err(to_io_error(format!("unexpected status: {}", status))),
+        }
+
+        let expected = (end - self.position) as usize;
+        let mut filled = 0;
+        while filled < expected {
+            match response.read(&mut buf[filled..expected])? {
+                // the entry is known to be longer, `Ok(0)` would read it again forever
+                0 => return Err(ErrorKind::UnexpectedEof.into()),
+                size => filled += size,
+            }
+        }
+        self.position += filled as u64;
+        Ok(filled)
+    }
+}
+
+impl<F> Seek for RemoteObject<F> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => offset(self.size, x),
+            SeekFrom::Current(x) => offset(self.position, x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn offset(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+pub(crate) fn to_io_error<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(ErrorKind::Other, error)
+}
+
+/// Percent encode everything except unreserved characters
+pub(crate) fn uri_encode(segment: &str) -> String {
+    let mut encoded = String::with_capacity(segment.len());
+    for byte in segment.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Percent encode every segment of a logical path
+pub(crate) fn encode_path(path: &str) -> String {
+    path.split('/')
+        .map(uri_encode)
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!("a-b_c.d~e", uri_encode("a-b_c.d~e"));
+        assert_eq!("a%20b%2B%E4%BD%A0", uri_encode("a b+你"));
+        assert_eq!("a/b%20c/d", encode_path("a/b c/d"));
+    }
+
+    #[test]
+    fn test_conditional_headers() {
+        let metadata = Metadata {
+            size: 1,
+            modified: UNIX_EPOCH + Duration::from_secs(784_111_777),
+            etag: Some("\"abc\"".to_string()),
+        };
+        let headers = conditional_headers(&metadata);
+        assert_eq!("\"abc\"", headers[header::IF_NONE_MATCH]);
+        assert_eq!(
+            "Sun, 06 Nov 1994 08:49:37 GMT",
+            headers[header::IF_MODIFIED_SINCE]
+        );
+
+        let metadata = Metadata {
+            size: 1,
+            modified: UNIX_EPOCH,
+            etag: None,
+        };
+        assert!(conditional_headers(&metadata).is_empty());
+
+        let headers = range_headers("bytes=0-9", None);
+        assert_eq!("bytes=0-9", headers[header::RANGE]);
+        assert!(!headers.contains_key(header::IF_MATCH));
+    }
+
+    #[test]
+    fn test_offset() {
+        assert_eq!(Some(15), offset(10, 5));
+        assert_eq!(Some(5), offset(10, -5));
+        assert_eq!(None, offset(10, -11));
+        assert_eq!(None, offset(u64::max_value(), 1));
+    }
+}
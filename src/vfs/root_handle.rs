@@ -0,0 +1,177 @@
+use crate::vfs::{local_metadata, Metadata, Reader, Vfs};
+use std::{
+    ffi::{CString, OsStr},
+    fs::{File, OpenOptions},
+    io::{self, ErrorKind},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::OpenOptionsExt,
+        io::{AsRawFd, FromRawFd, RawFd},
+    },
+    path::Path,
+};
+
+/// Serve files below a directory opened once, resolving every request relative to its handle
+/// (`openat`) instead of by path, Unix only
+///
+/// The root is opened at construction, so the process may `chroot` or drop privileges after
+/// that and keep serving. Each segment is opened without following symbolic links, so nothing
+/// swapped in between resolving and opening a path can escape the root; symbolic links below
+/// the root are not served.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::{StaticFiles, vfs::RootHandle};
+/// let files = StaticFiles::from_backend(RootHandle::open("./public").unwrap());
+/// // chroot or drop privileges here
+/// ```
+pub struct RootHandle {
+    root: File,
+}
+
+impl RootHandle {
+    /// Open directory `root`
+    pub fn open(root: impl AsRef<Path>) -> io::Result<Self> {
+        let root = File::open(root)?;
+        if !root.metadata()?.is_dir() {
+            return Err(io::Error::new(ErrorKind::Other, "root is not a directory"));
+        }
+        Ok(Self { root })
+    }
+
+    /// Open regular file at logical `path`, segment by segment
+    fn open_file(&self, path: &str) -> io::Result<File> {
+        let segments: Vec<_> = path
+            .split('/')
+            .filter(|x| !x.is_empty())
+            .map(OsStr::new)
+            .collect();
+        open_beneath(&self.root, &segments)
+    }
+}
+
+/// Flags of directories only walked through: `O_PATH` on Linux needs search permission only, not
+/// read permission (e.g. ancestors of mode `0711`)
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const WALK_FLAGS: libc::c_int = libc::O_PATH | libc::O_DIRECTORY;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const WALK_FLAGS: libc::c_int = libc::O_DIRECTORY;
+
+/// Open directory `path` to walk below it with [`open_beneath`]
+pub(crate) fn open_walked(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(WALK_FLAGS)
+        .open(path)
+}
+
+/// Open regular file at `segments` below directory `dir`, segment by segment, `NotFound` if any
+/// of them is a symbolic link or the file isn't regular (directory, FIFO, socket, device)
+pub(crate) fn open_beneath(dir: &File, segments: &[&OsStr]) -> io::Result<File> {
+    let (name, dirs) = match segments.split_last() {
+        Some(x) => x,
+        None => return Err(ErrorKind::NotFound.into()),
+    };
+    let mut parent: Option<File> = None;
+    for segment in dirs {
+        let fd = parent.as_ref().unwrap_or(dir).as_raw_fd();
+        parent = Some(open_at(fd, segment, WALK_FLAGS)?);
+    }
+    let fd = parent.as_ref().unwrap_or(dir).as_raw_fd();
+    // a FIFO would block opening until a writer shows up, no effect on regular files
+    let file = open_at(fd, name, libc::O_NONBLOCK)?;
+    if !file.metadata()?.is_file() {
+        return Err(ErrorKind::NotFound.into());
+    }
+    Ok(file)
+}
+
+/// Open `name` in directory `dir` for reading, never following a symbolic link (a link is
+/// `ENOTDIR` with `O_PATH | O_DIRECTORY`, `ELOOP` otherwise)
+fn open_at(dir: RawFd, name: &OsStr, flags: libc::c_int) -> io::Result<File> {
+    if name == "." || name == ".." {
+        return Err(ErrorKind::NotFound.into());
+    }
+    let name = CString::new(name.as_bytes()).map_err(|_| io::Error::from(ErrorKind::NotFound))?;
+    let flags = flags | libc::O_RDONLY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    let fd = unsafe { libc::openat(dir, name.as_ptr(), flags) };
+    if fd < 0 {
+        let error = io::Error::last_os_error();
+        return match error.raw_os_error() {
+            // a symbolic link, or a file where a directory is expected
+            Some(libc::ELOOP) | Some(libc::ENOTDIR) => Err(ErrorKind::NotFound.into()),
+            _ => Err(error),
+        };
+    }
+    Ok(unsafe { File::from_raw_fd(fd) })
+}
+
+impl Vfs for RootHandle {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        local_metadata(&self.open_file(path)?.metadata()?)
+    }
+
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)> {
+        let file = self.open_file(path)?;
+        let metadata = local_metadata(&file.metadata()?)?;
+        Ok((Box::new(file), metadata))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use std::{fs, io::Read};
+
+    #[test]
+    fn test_root_handle() {
+        let root = TempDir::new("handle");
+        fs::create_dir_all(root.join("a")).unwrap();
+        fs::write(root.join("a/b.txt"), b"abc").unwrap();
+        std::os::unix::fs::symlink(root.join("a/b.txt"), root.join("link.txt")).unwrap();
+        let handle = RootHandle::open(&root).unwrap();
+
+        assert_eq!(3, handle.metadata("a/b.txt").unwrap().size);
+        let mut content = String::new();
+        handle
+            .open("a/b.txt")
+            .unwrap()
+            .0
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!("abc", content);
+        for path in &[
+            "a",
+            "",
+            "missing.txt",
+            "link.txt",
+            "a/b.txt/c",
+            "../a/b.txt",
+        ] {
+            let error = handle.metadata(path).unwrap_err();
+            assert_eq!(ErrorKind::NotFound, error.kind(), "{}", path);
+        }
+
+        // still served once the path is gone
+        let moved = TempDir::new("handle-moved");
+        fs::rename(&root, &moved).unwrap();
+        assert_eq!(3, handle.metadata("a/b.txt").unwrap().size);
+    }
+
+    #[test]
+    fn test_unreadable_ancestors() {
+        use std::os::unix::fs::PermissionsExt;
+        let root = TempDir::new("handle-unreadable");
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::write(root.join("a/b/c.txt"), b"abc").unwrap();
+        // searchable but not readable
+        fs::set_permissions(root.join("a"), fs::Permissions::from_mode(0o311)).unwrap();
+        let dir = open_walked(&root).unwrap();
+        let segments: Vec<_> = ["a", "b", "c.txt"].iter().map(OsStr::new).collect();
+        let file = open_beneath(&dir, &segments);
+        fs::set_permissions(root.join("a"), fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(3, file.unwrap().metadata().unwrap().len());
+    }
+}
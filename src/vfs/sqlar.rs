@@ -0,0 +1,280 @@
+use crate::{
+    error::TSFResult,
+    vfs::{Metadata, Reader, Vfs},
+};
+use flate2::read::ZlibDecoder;
+use rusqlite::{DatabaseName, OpenFlags};
+use std::{
+    io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// File type bits of `mode`
+const S_IFMT: i64 = 0o170_000;
+const S_IFREG: i64 = 0o100_000;
+
+/// Serve files of a [SQLite Archive](https://sqlite.org/sqlar.html), e.g. created by
+/// `sqlite3 site.sqlar -Ac public/*`, read-only
+///
+/// Stored (uncompressed) files are read by incremental blob I/O, so ranges only read what
+/// they need. Compressed files are inflated into memory when opened. Directories and symbolic
+/// links are not served.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::{StaticFiles, vfs::SqlarBackend};
+/// let backend = SqlarBackend::open("./site.sqlar").unwrap();
+/// let files = StaticFiles::from_backend(backend);
+/// ```
+#[derive(Clone)]
+pub struct SqlarBackend {
+    path: PathBuf,
+    /// for lookups, readers open their own
+    connection: Arc<Mutex<rusqlite::Connection>>,
+}
+
+/// Row of a file in the archive
+struct Entry {
+    rowid: i64,
+    mtime: i64,
+    /// size of the file
+    size: u64,
+    /// size of the stored blob, smaller than `size` if compressed
+    stored: u64,
+}
+
+impl Entry {
+    fn metadata(&self) -> Metadata {
+        Metadata {
+            size: self.size,
+            modified: UNIX_EPOCH + Duration::from_secs(self.mtime.max(0) as u64),
+            etag: None,
+        }
+    }
+}
+
+impl SqlarBackend {
+    /// Open archive at `path`, fails if it has no `sqlar` table
+    pub fn open(path: impl AsRef<Path>) -> TSFResult<Self> {
+        let path = path.as_ref().to_path_buf();
+        let connection = connect(&path)?;
+        connection.prepare("SELECT name, mode, mtime, sz, data FROM sqlar LIMIT 0")?;
+        Ok(Self {
+            path,
+            connection: Arc::new(Mutex::new(connection)),
+        })
+    }
+
+    fn entry(&self, path: &str) -> io::Result<Entry> {
+        let connection = self.connection.lock().unwrap();
+        let row = connection.query_row(
+            "SELECT rowid, mode, mtime, sz, length(data) FROM sqlar WHERE name = ?",
+            &[path],
+            |row| -> rusqlite::Result<_> {
+                let mode: i64 = row.get_checked(1)?;
+                let size: i64 = row.get_checked(3)?;
+                let stored: Option<i64> = row.get_checked(4)?;
+                Ok((row.get_checked(0)?, mode, row.get_checked(2)?, size, stored))
+            },
+        );
+        let (rowid, mode, mtime, size, stored) = match row.and_then(|x| x) {
+            Ok(x) => x,
+            Err(rusqlite::Error::QueryReturnedNoRows) => return Err(ErrorKind::NotFound.into()),
+            Err(error) => return Err(to_io_error(error)),
+        };
+        // some writers leave the type bits of regular files out
+        if (mode & S_IFMT != S_IFREG && mode & S_IFMT != 0) || size < 0 {
+            return Err(ErrorKind::NotFound.into());
+        }
+        Ok(Entry {
+            rowid,
+            mtime,
+            size: size as u64,
+            stored: stored.unwrap_or(0) as u64,
+        })
+    }
+}
+
+impl Vfs for SqlarBackend {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        Ok(self.entry(path)?.metadata())
+    }
+
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)> {
+        let entry = self.entry(path)?;
+        let metadata = entry.metadata();
+        let mut reader = BlobReader {
+            connection: connect(&self.path).map_err(to_io_error)?,
+            rowid: entry.rowid,
+            position: 0,
+            size: entry.stored,
+        };
+        if entry.stored == entry.size {
+            return Ok((Box::new(reader), metadata));
+        }
+        // compressed by zlib, as the blob is smaller than the file
+        let mut content = Vec::with_capacity(entry.size as usize);
+        ZlibDecoder::new(&mut reader)
+            .take(entry.size + 1)
+            .read_to_end(&mut content)?;
+        if content.len() as u64 != entry.size {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} inflated to {} bytes", path, content.len()),
+            ));
+        }
+        Ok((Box::new(Cursor::new(content)), metadata))
+    }
+}
+
+fn connect(path: &Path) -> rusqlite::Result<rusqlite::Connection> {
+    let flags = OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+    let connection = rusqlite::Connection::open_with_flags(path, flags)?;
+    connection.execute_batch("PRAGMA query_only = 1")?;
+    Ok(connection)
+}
+
+fn to_io_error(error: rusqlite::Error) -> io::Error {
+    io::Error::new(ErrorKind::Other, error.to_string())
+}
+
+/// Blob of a row, every read opens it at the current position, so that the reader owns its
+/// connection only and is sent across threads
+struct BlobReader {
+    connection: rusqlite::Connection,
+    rowid: i64,
+    position: u64,
+    size: u64,
+}
+
+impl Read for BlobReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position >= self.size || buf.is_empty() {
+            return Ok(0);
+        }
+        let mut blob = self
+            .connection
+            .blob_open(DatabaseName::Main, "sqlar", "data", self.rowid, true)
+            .map_err(to_io_error)?;
+        blob.seek(SeekFrom::Start(self.position))?;
+        let size = blob.read(buf)?;
+        self.position += size as u64;
+        Ok(size)
+    }
+}
+
+impl Seek for BlobReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => offset(self.size, x),
+            SeekFrom::Current(x) => offset(self.position, x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "seek before start of blob",
+            )),
+        }
+    }
+}
+
+fn offset(base: u64, offset: i64) -> Option<u64> {
+    if offset >= 0 {
+        base.checked_add(offset as u64)
+    } else {
+        base.checked_sub(offset.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::TempDir;
+    use flate2::{write::ZlibEncoder, Compression};
+    use rusqlite::types::ToSql;
+    use std::io::Write;
+
+    #[test]
+    fn test_sqlar() {
+        let dir = TempDir::new("sqlar");
+        let path = dir.join("files.sqlar");
+        let content: Vec<u8> = (0..100_000u32).map(|x| (x % 251) as u8).collect();
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&content).unwrap();
+        let compressed = encoder.finish().unwrap();
+        {
+            let connection = rusqlite::Connection::open(&path).unwrap();
+            connection
+                .execute_batch(
+                    "CREATE TABLE sqlar(name TEXT PRIMARY KEY, mode INT, mtime INT, sz INT, \
+                     data BLOB)",
+                )
+                .unwrap();
+            let mut insert = connection
+                .prepare("INSERT INTO sqlar VALUES (?, ?, ?, ?, ?)")
+                .unwrap();
+            let rows: Vec<(&str, i64, i64, Option<&[u8]>)> = vec![
+                (
+                    "a/stored.bin",
+                    0o100_644,
+                    content.len() as i64,
+                    Some(&content[..]),
+                ),
+                (
+                    "a/packed.bin",
+                    0o100_644,
+                    content.len() as i64,
+                    Some(&compressed[..]),
+                ),
+                ("a/empty.txt", 0o100_644, 0, None),
+                ("a", 0o040_755, 0, None),
+            ];
+            for (name, mode, size, data) in rows {
+                let params: [&dyn ToSql; 5] = [&name, &mode, &1_554_122_096i64, &size, &data];
+                insert.execute(&params).unwrap();
+            }
+        }
+
+        let backend = SqlarBackend::open(&path).unwrap();
+        let metadata = backend.metadata("a/packed.bin").unwrap();
+        assert_eq!(content.len() as u64, metadata.size);
+        assert_eq!(
+            UNIX_EPOCH + Duration::from_secs(1_554_122_096),
+            metadata.modified
+        );
+        for name in &["a/stored.bin", "a/packed.bin"] {
+            let (mut reader, metadata) = backend.open(name).unwrap();
+            assert_eq!(content.len() as u64, metadata.size);
+            reader.seek(SeekFrom::Start(99_990)).unwrap();
+            let mut tail = Vec::new();
+            reader.read_to_end(&mut tail).unwrap();
+            assert_eq!(&content[99_990..], &tail[..], "{}", name);
+            reader.seek(SeekFrom::Current(-20)).unwrap();
+            let mut part = [0; 5];
+            reader.read_exact(&mut part).unwrap();
+            assert_eq!(&content[99_980..99_985], &part[..], "{}", name);
+        }
+        let mut empty = Vec::new();
+        backend
+            .open("a/empty.txt")
+            .unwrap()
+            .0
+            .read_to_end(&mut empty)
+            .unwrap();
+        assert!(empty.is_empty());
+        for name in &["a", "missing"] {
+            assert_eq!(
+                ErrorKind::NotFound,
+                backend.metadata(name).unwrap_err().kind()
+            );
+        }
+    }
+}
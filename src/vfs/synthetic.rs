@@ -0,0 +1,154 @@
+use crate::{
+    path::normalize_path,
+    vfs::{Metadata, Reader, Vfs},
+};
+use std::{
+    cmp::min,
+    collections::HashMap,
+    io::{self, ErrorKind, Read, Seek, SeekFrom},
+    thread,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Serve generated content of configurable size and latency, for benchmarks and tests
+///
+/// Byte at offset `n` of every file is `n % 251`, so content is cheap to generate and
+/// misplaced ranges are detectable. Latency is applied once per `open` and once per `read`,
+/// simulating a slow disk or network.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tide_static_file::{StaticFiles, vfs::Synthetic};
+/// let synthetic = Synthetic::new()
+///     .file("big.bin", 1 << 30)
+///     .latency(Duration::from_millis(1));
+/// let files = StaticFiles::from_backend(synthetic);
+/// ```
+#[derive(Clone, Default)]
+pub struct Synthetic {
+    files: HashMap<String, u64>,
+    latency: Duration,
+}
+
+impl Synthetic {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a file of given size at given path (relative to root), panic if path escapes the root
+    pub fn file(mut self, path: &str, size: u64) -> Self {
+        let path = normalize_path(path).expect("invalid path of synthetic file");
+        self.files.insert(path, size);
+        self
+    }
+
+    /// Set latency of opening and of every read
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    fn size(&self, path: &str) -> io::Result<u64> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| ErrorKind::NotFound.into())
+    }
+}
+
+impl Vfs for Synthetic {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        let size = self.size(path)?;
+        Ok(Metadata {
+            size,
+            modified: UNIX_EPOCH,
+            etag: Some(format!("synthetic-{:x}", size)),
+        })
+    }
+
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)> {
+        let metadata = self.metadata(path)?;
+        sleep(self.latency);
+        let reader = SyntheticReader {
+            size: metadata.size,
+            position: 0,
+            latency: self.latency,
+        };
+        Ok((Box::new(reader), metadata))
+    }
+}
+
+fn sleep(latency: Duration) {
+    if latency != Duration::from_secs(0) {
+        thread::sleep(latency);
+    }
+}
+
+struct SyntheticReader {
+    size: u64,
+    position: u64,
+    latency: Duration,
+}
+
+impl Read for SyntheticReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        sleep(self.latency);
+        let remain = self.size.saturating_sub(self.position);
+        let length = min(remain, buf.len() as u64) as usize;
+        for (i, byte) in buf[..length].iter_mut().enumerate() {
+            *byte = ((self.position + i as u64) % 251) as u8;
+        }
+        self.position += length as u64;
+        Ok(length)
+    }
+}
+
+impl Seek for SyntheticReader {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(x) => Some(x),
+            SeekFrom::End(x) => offset(self.size, x),
+            SeekFrom::Current(x) => offset(self.position, x),
+        };
+        match position {
+            Some(x) => {
+                self.position = x;
+                Ok(x)
+            }
+            None => Err(io::Error::new(
+                ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+fn offset(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_synthetic() {
+        let synthetic = Synthetic::new().file("a/b.bin", 300);
+        assert_eq!(300, synthetic.metadata("a/b.bin").unwrap().size);
+        assert!(synthetic.metadata("a").is_err());
+
+        let (mut reader, metadata) = synthetic.open("a/b.bin").unwrap();
+        assert_eq!(300, metadata.size);
+        reader.seek(SeekFrom::Start(249)).unwrap();
+        let mut content = Vec::new();
+        reader.read_to_end(&mut content).unwrap();
+        assert_eq!(51, content.len());
+        assert_eq!(&[249, 250, 0, 1], &content[..4]);
+    }
+}
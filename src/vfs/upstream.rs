@@ -0,0 +1,212 @@
+use crate::{
+    error::TSFResult,
+    vfs::{
+        remote::{encode_path, to_io_error, RemoteObject, Revalidator},
+        CacheDir, Metadata, Reader, Vfs,
+    },
+};
+use reqwest::{
+    header::{self, HeaderMap, HeaderValue},
+    Client, Method, Response, StatusCode, Url,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    fs::File,
+    io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+
+/// Serve files proxied from an upstream HTTP server, i.e. a simple pull-through static cache
+///
+/// Request paths are appended to the base URL. Validators (`ETag`, `Last-Modified`) of upstream
+/// are used as is, so conditional and range requests are answered as for local files.
+/// Without cache directory, ranges are passed through to upstream, otherwise a whole file is
+/// downloaded on first access and later requests are served from disk until upstream
+/// changes it.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tide_static_file::{StaticFiles, vfs::UpstreamBackend};
+/// let backend = UpstreamBackend::new("https://releases.example.com/downloads/")
+///     .unwrap()
+///     .cache_dir("/var/cache/static", 10 * 1024 * 1024 * 1024)
+///     .unwrap()
+///     .max_age(Duration::from_secs(60));
+/// let files = StaticFiles::from_backend(backend);
+/// ```
+#[derive(Clone)]
+pub struct UpstreamBackend {
+    client: Client,
+    base: Url,
+    cache: Option<Arc<CacheDir>>,
+    revalidator: Arc<Revalidator>,
+}
+
+impl UpstreamBackend {
+    /// `base` is the URL which request paths are relative to, a trailing `/` is implied
+    pub fn new(base: &str) -> TSFResult<Self> {
+        let mut base = Url::parse(base)?;
+        if base.cannot_be_a_base() {
+            return Err(failure::err_msg("upstream URL cannot be a base"));
+        }
+        if !base.path().ends_with('/') {
+            let path = format!("{}/", base.path());
+            base.set_path(&path);
+        }
+        Ok(Self {
+            client: Client::new(),
+            base,
+            cache: None,
+            revalidator: Arc::new(Revalidator::new(Duration::from_secs(0))),
+        })
+    }
+
+    /// Keep downloaded files in given directory, which is created if not exists, evict least
+    /// recently used ones when their total size exceeds `max_size` bytes
+    ///
+    /// See [`CacheDir`](struct.CacheDir.html), several backends can share one directory through
+    /// [`cache`](#method.cache).
+    pub fn cache_dir(self, dir: impl AsRef<Path>, max_size: u64) -> TSFResult<Self> {
+        Ok(self.cache(Arc::new(CacheDir::open(dir, max_size)?)))
+    }
+
+    /// Keep downloaded files in given cache directory
+    pub fn cache(mut self, cache: Arc<CacheDir>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Revalidate remembered metadata of files only when older than `max_age`, default zero
+    ///
+    /// Revalidation is a conditional request with remembered validators, an upstream
+    /// `304 Not Modified` keeps the cached copy.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.revalidator = Arc::new(Revalidator::new(max_age));
+        self
+    }
+
+    fn send(&self, method: Method, path: &str, headers: HeaderMap) -> io::Result<Response> {
+        let url = self.base.join(&encode_path(path)).map_err(to_io_error)?;
+        self.client
+            .request(method, url)
+            .headers(headers)
+            .send()
+            .map_err(to_io_error)
+    }
+
+    /// Return cached copy of given version of entry, download it if absent
+    fn cached(&self, cache: &CacheDir, path: &str, metadata: &Metadata) -> io::Result<File> {
+        let name = cache_name(path, metadata);
+        if let Some(file) = cache.get(&name) {
+            if file.metadata()?.len() == metadata.size {
+                return Ok(file);
+            }
+        }
+        cache.insert(&name, |file| self.download(path, metadata, file))
+    }
+
+    fn download(&self, path: &str, metadata: &Metadata, to: &mut File) -> io::Result<()> {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = metadata
+            .etag
+            .as_ref()
+            .and_then(|x| HeaderValue::from_str(x).ok())
+        {
+            headers.insert(header::IF_MATCH, etag);
+        }
+        let mut response = self.send(Method::GET, path, headers)?;
+        match response.status() {
+            StatusCode::OK => {}
+            StatusCode::PRECONDITION_FAILED => return Err(to_io_error("entry changed upstream")),
+            status => return Err(to_io_error(format!("unexpected status: {}", status))),
+        }
+        let size = io::copy(&mut response, to)?;
+        if size != metadata.size {
+            return Err(to_io_error("entry changed upstream"));
+        }
+        Ok(())
+    }
+}
+
+impl Vfs for UpstreamBackend {
+    fn metadata(&self, path: &str) -> io::Result<Metadata> {
+        self.revalidator
+            .metadata(path, |headers| self.send(Method::HEAD, path, headers))
+    }
+
+    fn open(&self, path: &str) -> io::Result<(Reader, Metadata)> {
+        let metadata = self.metadata(path)?;
+        if let Some(cache) = &self.cache {
+            let file = self.cached(cache, path, &metadata)?;
+            return Ok((Box::new(file), metadata));
+        }
+        let (backend, path) = (self.clone(), path.to_string());
+        let get = move |headers| backend.send(Method::GET, &path, headers);
+        Ok((Box::new(RemoteObject::new(get, metadata.clone())), metadata))
+    }
+}
+
+/// File name of a cached version of entry, changes whenever upstream changes the entry
+fn cache_name(path: &str, metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs())
+        .unwrap_or(0);
+    let version = format!(
+        "{}\n{}\n{}\n{}",
+        path,
+        metadata.size,
+        modified,
+        metadata.etag.as_ref().map(String::as_str).unwrap_or("")
+    );
+    format!("{:x}", Sha256::digest(version.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    #[test]
+    fn test_new() {
+        let backend = UpstreamBackend::new("https://example.com/downloads").unwrap();
+        assert_eq!("https://example.com/downloads/", backend.base.as_str());
+        assert_eq!(
+            "https://example.com/downloads/a%20b/c.txt",
+            backend
+                .base
+                .join(&encode_path("a b/c.txt"))
+                .unwrap()
+                .as_str()
+        );
+        assert!(UpstreamBackend::new("data:text/plain,hello").is_err());
+    }
+
+    #[test]
+    fn test_cache_name() {
+        let metadata = Metadata {
+            size: 42,
+            modified: UNIX_EPOCH + Duration::from_secs(1_000_000),
+            etag: Some("\"abc\"".to_string()),
+        };
+        let name = cache_name("a/b.txt", &metadata);
+        assert_eq!(64, name.len());
+        assert_ne!(name, cache_name("a/c.txt", &metadata));
+
+        let changed = Metadata {
+            modified: SystemTime::now(),
+            ..metadata.clone()
+        };
+        assert_ne!(name, cache_name("a/b.txt", &changed));
+        let changed = Metadata {
+            etag: None,
+            ..metadata
+        };
+        assert_ne!(name, cache_name("a/b.txt", &changed));
+    }
+}
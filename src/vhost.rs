@@ -0,0 +1,118 @@
+use crate::{etag::HashCache, utils::ErrorResponse, StaticFiles};
+use futures::future::FutureObj;
+use http::header;
+use std::{collections::HashMap, sync::Arc};
+use tide::{configuration::Store, IntoResponse, Request, Response, RouteMatch};
+
+/// Serve several static sites from one endpoint, selected by `Host` header
+///
+/// All sites share the same file read pool and content hash cache.
+///
+/// # Example
+///
+/// ```no_run
+/// # use tide_static_file::{StaticFiles, VirtualHosts};
+/// let hosts = VirtualHosts::new()
+///     .host("example.com", StaticFiles::new("./example.com").unwrap())
+///     .host("example.org", StaticFiles::new("./example.org").unwrap());
+/// ```
+#[derive(Clone)]
+pub struct VirtualHosts {
+    hosts: HashMap<String, StaticFiles>,
+    fallback: Option<StaticFiles>,
+    hash_cache: Arc<HashCache>,
+}
+
+impl VirtualHosts {
+    pub fn new() -> Self {
+        Self {
+            hosts: HashMap::new(),
+            fallback: None,
+            hash_cache: Arc::new(HashCache::default()),
+        }
+    }
+
+    /// Serve requests for given host name (case insensitive, without port) with `files`
+    pub fn host(mut self, host: &str, files: StaticFiles) -> Self {
+        self.hosts
+            .insert(normalize_host(host), self.share_cache(files));
+        self
+    }
+
+    /// Serve requests for unknown hosts with `files`, otherwise they got `404 Not Found`
+    pub fn fallback(mut self, files: StaticFiles) -> Self {
+        self.fallback = Some(self.share_cache(files));
+        self
+    }
+
+    fn share_cache(&self, mut files: StaticFiles) -> StaticFiles {
+        files.hash_cache = self.hash_cache.clone();
+        files
+    }
+
+    fn select(&self, req: &Request) -> Option<&StaticFiles> {
+        let host = req
+            .headers()
+            .get(header::HOST)
+            .and_then(|x| x.to_str().ok())
+            .or_else(|| req.uri().host())
+            .map(normalize_host);
+        host.and_then(|x| self.hosts.get(&x))
+            .or_else(|| self.fallback.as_ref())
+    }
+}
+
+impl Default for VirtualHosts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for VirtualHosts {
+    type Fut = FutureObj<'static, Response>;
+
+    fn call(
+        &self,
+        data: Data,
+        req: Request,
+        params: Option<RouteMatch<'_>>,
+        store: &Store,
+    ) -> Self::Fut {
+        match self.select(&req) {
+            Some(files) => {
+                <StaticFiles as tide::Endpoint<Data, ()>>::call(files, data, req, params, store)
+            }
+            None => FutureObj::new(Box::new(async { ErrorResponse::NotFound.into_response() })),
+        }
+    }
+}
+
+/// Lower case host name without port
+fn normalize_host(host: &str) -> String {
+    let host = host.trim();
+    let host = match host.rfind(':') {
+        // IPv6 literal without port, e.g. `[::1]`
+        Some(index)
+            if host.ends_with(']') || !host[index + 1..].bytes().all(|x| x.is_ascii_digit()) =>
+        {
+            host
+        }
+        Some(index) => &host[..index],
+        None => host,
+    };
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_host() {
+        assert_eq!("example.com", normalize_host("example.com"));
+        assert_eq!("example.com", normalize_host("Example.COM:8080"));
+        assert_eq!("example.com", normalize_host("example.com."));
+        assert_eq!("[::1]", normalize_host("[::1]"));
+        assert_eq!("[::1]", normalize_host("[::1]:8080"));
+    }
+}
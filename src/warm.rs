@@ -0,0 +1,211 @@
+//! Warming of caches before traffic arrives, e.g. right after a deploy
+
+use crate::{path::join_canonical, vfs::Target, watch::snapshot, StaticFiles};
+use crossbeam_channel::unbounded;
+use log::warn;
+use std::{
+    ops::AddAssign,
+    thread::{self, JoinHandle},
+};
+
+/// Outcome of [`StaticFiles::warm`](struct.StaticFiles.html#method.warm)
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct WarmReport {
+    /// files read
+    pub files: usize,
+    /// bytes read
+    pub bytes: u64,
+    /// files which failed to be read, they're logged
+    pub failed: usize,
+}
+
+impl AddAssign for WarmReport {
+    fn add_assign(&mut self, other: Self) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.failed += other.failed;
+    }
+}
+
+impl StaticFiles {
+    /// Read files matching any of `patterns` (e.g. `index.html`, `assets/**/*.js`) into the
+    /// content digest cache, which also brings them into the page cache of the OS, so that first
+    /// requests after a start don't pay for it
+    ///
+    /// Patterns are logical paths where `*` and `?` match within a segment and `**` any number
+    /// of segments. Backends only support patterns without wildcards.
+    pub fn warm<I, S>(&self, patterns: I) -> WarmReport
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut report = WarmReport::default();
+        for target in self.warm_targets(patterns) {
+            report += self.warm_target(&target);
+        }
+        report
+    }
+
+    /// Same as [`warm`](#method.warm), on `concurrency` background threads, the handle returns
+    /// the report once every file is read
+    pub fn warm_in_background<I, S>(
+        &self,
+        patterns: I,
+        concurrency: usize,
+    ) -> JoinHandle<WarmReport>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let (sender, receiver) = unbounded();
+        for target in self.warm_targets(patterns) {
+            sender.send(target).unwrap();
+        }
+        drop(sender);
+        let workers: Vec<_> = (0..concurrency.max(1))
+            .map(|_| {
+                let files = self.clone();
+                let receiver = receiver.clone();
+                thread::spawn(move || {
+                    let mut report = WarmReport::default();
+                    for target in receiver {
+                        report += files.warm_target(&target);
+                    }
+                    report
+                })
+            })
+            .collect();
+        thread::spawn(move || {
+            let mut report = WarmReport::default();
+            for worker in workers {
+                report += worker.join().unwrap_or_default();
+            }
+            report
+        })
+    }
+
+    fn warm_targets<I, S>(&self, patterns: I) -> Vec<Target>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let patterns: Vec<String> = patterns
+            .into_iter()
+            .map(|x| x.as_ref().trim_matches('/').to_string())
+            .collect();
+        let (literal, wildcard): (Vec<_>, Vec<_>) = patterns
+            .into_iter()
+            .partition(|x| !x.contains(|c| c == '*' || c == '?'));
+        let mut paths = literal;
+        if !wildcard.is_empty() {
+            if self.backend.is_some() {
+                warn!("backends can't be listed, skip patterns {:?}", wildcard);
+            } else {
+                paths.extend(
+                    snapshot(&self.root)
+                        .into_iter()
+                        .map(|(path, _)| path)
+                        .filter(|path| wildcard.iter().any(|x| glob_match(x, path))),
+                );
+            }
+        }
+        paths.sort();
+        paths.dedup();
+        paths
+            .into_iter()
+            .filter_map(|path| match &self.backend {
+                None => join_canonical(&self.root, &path)
+                    .filter(|x| x.is_file())
+                    .map(Target::Local),
+                Some(backend) => Some(Target::Backend(backend.clone(), path)),
+            })
+            .collect()
+    }
+
+    fn warm_target(&self, target: &Target) -> WarmReport {
+        let result = target
+            .metadata()
+            .map_err(failure::Error::from)
+            .and_then(|meta| {
+                self.hash_cache
+                    .digests(target, meta.size, meta.modified)
+                    .map(|_| meta.size)
+            });
+        match result {
+            Ok(bytes) => WarmReport {
+                files: 1,
+                bytes,
+                failed: 0,
+            },
+            Err(error) => {
+                warn!("failed to warm {:?}: {}", target.path(), error);
+                WarmReport {
+                    failed: 1,
+                    ..WarmReport::default()
+                }
+            }
+        }
+    }
+}
+
+/// Whether logical `path` matches `pattern`, see [`StaticFiles::warm`]
+pub(crate) fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<_> = pattern.split('/').collect();
+    let path: Vec<_> = path.split('/').collect();
+    match_segments(&pattern, &path)
+}
+
+fn match_segments(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|x| match_segments(rest, &path[x..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((segment, path)) => {
+                match_segment(first.as_bytes(), segment.as_bytes()) && match_segments(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+fn match_segment(pattern: &[u8], segment: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => segment.is_empty(),
+        Some((b'*', rest)) => (0..=segment.len()).any(|x| match_segment(rest, &segment[x..])),
+        Some((b'?', rest)) => !segment.is_empty() && match_segment(rest, &segment[1..]),
+        Some((x, rest)) => segment.first() == Some(x) && match_segment(rest, &segment[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("index.html", "index.html"));
+        assert!(glob_match("*.html", "index.html"));
+        assert!(!glob_match("*.html", "docs/index.html"));
+        assert!(glob_match("**/*.html", "index.html"));
+        assert!(glob_match("**/*.html", "docs/a/index.html"));
+        assert!(glob_match("assets/**", "assets/js/app.js"));
+        assert!(glob_match("assets/?.js", "assets/a.js"));
+        assert!(!glob_match("assets/?.js", "assets/ab.js"));
+        assert!(!glob_match("assets/*", "assets"));
+    }
+
+    #[test]
+    fn test_warm() {
+        let files = StaticFiles::new(env!("CARGO_MANIFEST_DIR")).unwrap();
+        let report = files.warm(vec!["Cargo.toml", "src/range*.rs", "missing.txt"]);
+        assert_eq!(3, report.files);
+        assert_eq!(0, report.failed);
+        assert!(report.bytes > 0);
+
+        let background = files
+            .warm_in_background(vec!["Cargo.toml", "src/range*.rs"], 2)
+            .join()
+            .unwrap();
+        assert_eq!(report, background);
+    }
+}
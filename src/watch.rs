@@ -0,0 +1,281 @@
+//! Notifications of changed files, polled from the file system and published as Server-Sent
+//! Events
+
+use crate::{error::TSFResult, etag::metadata_etag, vfs::Metadata};
+use bytes::Bytes;
+use futures::{
+    channel::mpsc::{channel, Receiver, Sender},
+    future::{self, FutureObj},
+    stream, StreamExt,
+};
+use http::{header, StatusCode};
+use http_service::Body;
+use log::warn;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, Weak},
+    thread,
+    time::{Duration, SystemTime},
+};
+use tide::{configuration::Store, Request, Response, RouteMatch};
+
+/// Events kept for a subscriber not keeping up, later ones are dropped for it
+const SUBSCRIBER_BUFFER: usize = 256;
+
+/// What happened to a file
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// A change of a file below the watched root
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+pub struct ChangeEvent {
+    /// logical path, e.g. `css/site.css`
+    pub path: String,
+    pub kind: ChangeKind,
+    /// `ETag` the file is served with by default
+    /// ([`EtagMode::Metadata`](enum.EtagMode.html#variant.Metadata)), `None` once removed
+    pub etag: Option<String>,
+}
+
+impl ChangeEvent {
+    /// Event of a `text/event-stream`, named `change` with the event as JSON as data
+    pub fn to_sse(&self) -> String {
+        format!(
+            "event: change\ndata: {}\n\n",
+            serde_json::to_string(self).unwrap()
+        )
+    }
+}
+
+/// Endpoint publishing changes of files below a directory as Server-Sent Events, for dashboards
+/// and live-reload clients
+///
+/// The directory is scanned every `interval` by a background thread, which stops once every
+/// clone of this is dropped. Scanning costs a `stat` per file, keep the interval long for large
+/// trees. A subscriber not reading its events misses some once its buffer is full.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use tide_static_file::{ChangeEvents, StaticFiles};
+/// let mut app = tide::App::new(());
+/// let events = ChangeEvents::new("./public", Duration::from_secs(1)).unwrap();
+/// app.at("/_events").get(events);
+/// app.at("/*").get(StaticFiles::new("./public").unwrap());
+/// ```
+#[derive(Clone)]
+pub struct ChangeEvents {
+    subscribers: Arc<Subscribers>,
+}
+
+#[derive(Default)]
+struct Subscribers(Mutex<Vec<Sender<ChangeEvent>>>);
+
+impl Subscribers {
+    /// Send `event` to every subscriber, those gone are removed
+    fn publish(&self, event: &ChangeEvent) {
+        let mut subscribers = self.0.lock().unwrap();
+        let mut kept = Vec::with_capacity(subscribers.len());
+        for mut subscriber in subscribers.drain(..) {
+            match subscriber.try_send(event.clone()) {
+                Err(ref error) if error.is_disconnected() => {}
+                _ => kept.push(subscriber),
+            }
+        }
+        *subscribers = kept;
+    }
+}
+
+impl ChangeEvents {
+    /// Watch files below `root`, scanned every `interval`
+    pub fn new(root: impl AsRef<Path>, interval: Duration) -> TSFResult<Self> {
+        let root = root.as_ref().canonicalize()?;
+        let subscribers = Arc::new(Subscribers::default());
+        let watched = Arc::downgrade(&subscribers);
+        let snapshot = snapshot(&root);
+        thread::Builder::new()
+            .name("tsf-watch".to_string())
+            .spawn(move || watch(&root, interval, snapshot, &watched))?;
+        Ok(Self { subscribers })
+    }
+
+    /// Receive events from now on, e.g. to publish them another way than Server-Sent Events
+    pub fn subscribe(&self) -> Receiver<ChangeEvent> {
+        let (sender, receiver) = channel(SUBSCRIBER_BUFFER);
+        self.subscribers.0.lock().unwrap().push(sender);
+        receiver
+    }
+}
+
+impl<Data: Send + 'static> tide::Endpoint<Data, ()> for ChangeEvents {
+    type Fut = FutureObj<'static, Response>;
+
+    fn call(&self, _: Data, _: Request, _: Option<RouteMatch<'_>>, _: &Store) -> Self::Fut {
+        // a comment first, so that clients see the response before any change
+        let opened = stream::once(future::ready(Bytes::from_static(b": watching\n\n")));
+        let events = self.subscribe().map(|x| Bytes::from(x.to_sse()));
+        let body = opened.chain(events).map(Ok::<_, io::Error>);
+        let response = http::Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::from_stream(body))
+            .unwrap();
+        FutureObj::new(Box::new(async move { response }))
+    }
+}
+
+/// Size and modification time of files by logical path
+pub(crate) type Snapshot = BTreeMap<String, (u64, SystemTime)>;
+
+fn watch(root: &Path, interval: Duration, mut previous: Snapshot, watched: &Weak<Subscribers>) {
+    loop {
+        thread::sleep(interval);
+        let subscribers = match watched.upgrade() {
+            Some(x) => x,
+            None => return,
+        };
+        let current = snapshot(root);
+        for event in changes(&previous, &current) {
+            subscribers.publish(&event);
+        }
+        previous = current;
+    }
+}
+
+/// Files below `root`, symbolic links are not followed
+pub(crate) fn snapshot(root: &Path) -> Snapshot {
+    let mut files = BTreeMap::new();
+    let mut dirs = vec![PathBuf::from(root)];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(x) => x,
+            Err(error) => {
+                warn!("failed to scan {:?}: {}", dir, error);
+                continue;
+            }
+        };
+        for entry in entries.filter_map(Result::ok) {
+            let path = entry.path();
+            match entry.metadata() {
+                Ok(x) if x.file_type().is_dir() => dirs.push(path),
+                Ok(x) if x.file_type().is_file() => {
+                    let logical = path
+                        .strip_prefix(root)
+                        .unwrap()
+                        .iter()
+                        .map(|x| x.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join("/");
+                    let modified = x.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                    files.insert(logical, (x.len(), modified));
+                }
+                _ => {}
+            }
+        }
+    }
+    files
+}
+
+/// Changes from `previous` to `current`, ordered by path
+fn changes(previous: &Snapshot, current: &Snapshot) -> Vec<ChangeEvent> {
+    let etag = |&(size, modified): &(u64, SystemTime)| {
+        let meta = Metadata {
+            size,
+            modified,
+            etag: None,
+        };
+        metadata_etag(&meta).ok()
+    };
+    let mut events = Vec::new();
+    for (path, file) in current {
+        let kind = match previous.get(path) {
+            None => ChangeKind::Created,
+            Some(x) if x != file => ChangeKind::Modified,
+            Some(_) => continue,
+        };
+        events.push(ChangeEvent {
+            path: path.clone(),
+            kind,
+            etag: etag(file),
+        });
+    }
+    for path in previous.keys().filter(|x| !current.contains_key(*x)) {
+        events.push(ChangeEvent {
+            path: path.clone(),
+            kind: ChangeKind::Removed,
+            etag: None,
+        });
+    }
+    events.sort_by(|x, y| x.path.cmp(&y.path));
+    events
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    #[test]
+    fn test_changes() {
+        let time = SystemTime::UNIX_EPOCH + Duration::from_secs(16);
+        let previous: Snapshot = vec![
+            ("a.txt".to_string(), (1, time)),
+            ("b/c.txt".to_string(), (2, time)),
+            ("d.txt".to_string(), (3, time)),
+        ]
+        .into_iter()
+        .collect();
+        let mut current = previous.clone();
+        current.remove("b/c.txt");
+        current.insert("a.txt".to_string(), (4, time));
+        current.insert("b/e.txt".to_string(), (5, time));
+
+        let events = changes(&previous, &current);
+        let summary: Vec<_> = events
+            .iter()
+            .map(|x| (x.path.as_str(), x.kind, x.etag.as_ref().map(String::as_str)))
+            .collect();
+        assert_eq!(
+            vec![
+                ("a.txt", ChangeKind::Modified, Some("10-4")),
+                ("b/c.txt", ChangeKind::Removed, None),
+                ("b/e.txt", ChangeKind::Created, Some("10-5")),
+            ],
+            summary
+        );
+        assert_eq!(
+            "event: change\ndata: {\"path\":\"a.txt\",\"kind\":\"modified\",\"etag\":\"10-4\"}\n\n",
+            events[0].to_sse()
+        );
+        assert!(changes(&current, &current).is_empty());
+    }
+
+    #[test]
+    fn test_subscribe() {
+        let events = ChangeEvents {
+            subscribers: Arc::default(),
+        };
+        let receiver = events.subscribe();
+        drop(events.subscribe());
+        let event = ChangeEvent {
+            path: "a.txt".to_string(),
+            kind: ChangeKind::Created,
+            etag: None,
+        };
+        events.subscribers.publish(&event);
+        assert_eq!(1, events.subscribers.0.lock().unwrap().len());
+        drop(events);
+        let received: Vec<_> = block_on(receiver.collect());
+        assert_eq!(vec![event], received);
+    }
+}